@@ -39,6 +39,7 @@ enum ExitMessage {
 thread_local!(static NETWORK_LISTEN_ADDRESS: RefCell<SocketAddr> = RefCell::new(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0)));
 thread_local!(static SEND_ADDR: RefCell<Option<Sender<SocketAddr>>> = RefCell::new(None));
 thread_local!(static PID1_KEY: RefCell<[u8; 16]> = RefCell::new([0u8; 16]));
+thread_local!(static BOOT_TIME: std::time::Instant = std::time::Instant::now());
 
 #[cfg(test)]
 pub fn set_pid1_key(new_key: [u8; 16]) {
@@ -85,6 +86,11 @@ pub fn current_pid() -> PID {
     crate::arch::process::current_pid()
 }
 
+/// Milliseconds since the kernel was started, used to expire syscall timeouts.
+pub fn elapsed_ms() -> u64 {
+    BOOT_TIME.with(|boot_time| boot_time.elapsed().as_millis() as u64)
+}
+
 /// Each client gets its own connection and its own thread, which is handled here.
 fn handle_connection(
     conn: TcpStream,
@@ -543,7 +549,29 @@ pub fn idle() -> bool {
         }
     }
 
-    while let Ok(msg) = message_receiver.recv() {
+    // Start the clock that syscall timeouts are measured against
+    BOOT_TIME.with(|_| ());
+
+    loop {
+        // Threads that are blocked with a timeout are woken up from here, so don't
+        // wait for a message past the earliest deadline.
+        let next_deadline = SystemServices::with_mut(|ss| {
+            ss.expire_futex_waiters(elapsed_ms());
+            ss.next_futex_deadline()
+        });
+        let msg = if let Some(deadline) = next_deadline {
+            let timeout = std::time::Duration::from_millis(deadline.saturating_sub(elapsed_ms()));
+            match message_receiver.recv_timeout(timeout) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match message_receiver.recv() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            }
+        };
         match msg {
             ThreadMessage::NewConnection(conn, access_key) => {
                 // The new process should already have a PID registered. Convert its access key
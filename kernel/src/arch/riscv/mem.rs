@@ -130,6 +130,66 @@ impl MemoryMapping {
         Ok(())
     }
 
+    /// Create a fresh address space for `pid`. It contains only what the kernel
+    /// needs in order to run the process: the root pagetable at
+    /// `PAGE_TABLE_ROOT_OFFSET`, the pagetables that map the pagetables themselves,
+    /// the process context at `0xff80_1000`, and the kernel's megapage 1023. This
+    /// mirrors the layout the loader creates for processes started at boot.
+    ///
+    /// All of the pages are owned by `pid`, and are released when the process is
+    /// terminated. If this fails, everything allocated so far is released.
+    pub fn allocate(mm: &mut MemoryManager, pid: PID) -> Result<MemoryMapping, xous_kernel::Error> {
+        let result = Self::allocate_inner(mm, pid);
+        if result.is_err() {
+            unsafe { mm.release_all_memory_for_process(pid) };
+        }
+        result
+    }
+
+    fn allocate_inner(mm: &mut MemoryManager, pid: PID) -> Result<MemoryMapping, xous_kernel::Error> {
+        let root = mm.alloc_page(pid)?;
+        // Pagetable that maps the other pagetables into PAGE_TABLE_OFFSET
+        let pagetables = mm.alloc_page(pid)?;
+        // Pagetable that maps the root and the process context
+        let process_tables = mm.alloc_page(pid)?;
+        let context = mm.alloc_page(pid)?;
+
+        let table = |phys: usize| ((phys >> 12) << 10) | MMUFlags::VALID.bits();
+        let page = |phys: usize| {
+            ((phys >> 12) << 10)
+                | (MMUFlags::VALID | MMUFlags::R | MMUFlags::W | MMUFlags::D | MMUFlags::A).bits()
+        };
+        // Every process shares the kernel's megapage 1023, so copy it from the current process
+        let kernel_pages =
+            unsafe { (*(PAGE_TABLE_ROOT_OFFSET as *const RootPageTable)).entries[1023] };
+
+        let pagetables_vpn1 = (PAGE_TABLE_OFFSET >> 22) & ((1 << 10) - 1);
+        let root_vpn1 = (PAGE_TABLE_ROOT_OFFSET >> 22) & ((1 << 10) - 1);
+        init_new_page(
+            mm,
+            root,
+            &[
+                (pagetables_vpn1, table(pagetables)),
+                (root_vpn1, table(process_tables)),
+                (1023, kernel_pages),
+            ],
+        )?;
+        init_new_page(
+            mm,
+            pagetables,
+            &[
+                (pagetables_vpn1, page(pagetables)),
+                (root_vpn1, page(process_tables)),
+            ],
+        )?;
+        init_new_page(mm, process_tables, &[(0, page(root)), (1, page(context))])?;
+        init_new_page(mm, context, &[])?;
+
+        Ok(MemoryMapping {
+            satp: 0x8000_0000 | ((pid.get() as usize) << 22) | (root >> 12),
+        })
+    }
+
     pub fn print_map(&self) {
         println!("Memory Maps for PID {}:", self.get_pid());
         let l1_pt = unsafe { &mut (*(PAGE_TABLE_ROOT_OFFSET as *mut RootPageTable)) };
@@ -185,7 +245,9 @@ impl MemoryMapping {
         // println!("Reserving memory address {:08x} with flags {:?}", addr, flags);
         // Allocate a new level 1 pagetable entry if one doesn't exist.
         if l1_pt.entries[vpn1] & MMUFlags::VALID.bits() == 0 {
-            let pid = crate::arch::current_pid();
+            // This mapping may not belong to the current process yet, e.g. while a
+            // new process is being set up, so charge the pagetable to its owner.
+            let pid = self.get_pid();
             // Allocate a fresh page
             let l0pt_phys = mm.alloc_page(pid)?;
 
@@ -492,6 +554,38 @@ pub fn map_page_inner(
     Ok(())
 }
 
+/// Zero a newly-allocated page that isn't mapped anywhere yet, and fill in the given
+/// `(index, value)` words. The page is mapped into the current address space just
+/// long enough to do this.
+fn init_new_page(
+    mm: &mut MemoryManager,
+    phys: usize,
+    words: &[(usize, usize)],
+) -> Result<(), xous_kernel::Error> {
+    let virt = mm.find_virtual_address(
+        core::ptr::null_mut(),
+        PAGE_SIZE,
+        xous_kernel::MemoryType::Default,
+    )? as usize;
+    map_page_inner(
+        mm,
+        crate::arch::process::current_pid(),
+        phys,
+        virt,
+        MemoryFlags::R | MemoryFlags::W,
+        false,
+    )?;
+    let page = unsafe { &mut (*(virt as *mut LeafPageTable)) };
+    for entry in page.entries.iter_mut() {
+        *entry = 0;
+    }
+    for &(index, value) in words {
+        page.entries[index] = value;
+    }
+    unmap_page_inner(mm, virt)?;
+    Ok(())
+}
+
 /// Get the pagetable entry for a given address, or `Err()` if the address is invalid
 pub fn pagetable_entry(addr: usize) -> Result<&'static mut usize, xous_kernel::Error> {
     if addr & 3 != 0 {
@@ -25,6 +25,15 @@ pub struct Wfi {
     pub base: *mut usize,
 }
 
+pub const TICKTIMER_KERNEL: Ticktimer = Ticktimer {
+    // a read-only view of the ticktimer, which otherwise belongs to the ticktimer server
+    base: 0xffcc_0000 as *mut usize, // see https://github.com/betrusted-io/xous-core/blob/master/docs/memory.md
+};
+
+pub struct Ticktimer {
+    pub base: *mut usize,
+}
+
 pub fn current_pid() -> PID {
     PID::new(satp::read().asid() as _).unwrap()
 }
@@ -45,6 +54,20 @@ pub fn init() {
     let mut wfi_kernel_csr = CSR::new(WFI_KERNEL.base as *mut u32);
     wfi_kernel_csr.wfo(utra::wfi::IGNORE_LOCKED_IGNORE_LOCKED, 1);
 
+    // The ticktimer is claimed by the ticktimer server, so map it without taking
+    // ownership of it. The kernel only ever reads the time from it.
+    MemoryManager::with_mut(|memory_manager| {
+        mem::map_page_inner(
+            memory_manager,
+            PID::new(1).unwrap(),
+            utra::ticktimer::HW_TICKTIMER_BASE,
+            TICKTIMER_KERNEL.base as usize,
+            MemoryFlags::R,
+            false,
+        )
+        .expect("unable to map ticktimer")
+    });
+
     unsafe {
         sie::set_ssoft();
         sie::set_sext();
@@ -52,6 +75,19 @@ pub fn init() {
     rand::init();
 }
 
+/// Milliseconds since the ticktimer was started, used to expire syscall timeouts.
+pub fn elapsed_ms() -> u64 {
+    let ticktimer_kernel_csr = CSR::new(TICKTIMER_KERNEL.base as *mut u32);
+    loop {
+        // TIME1 may roll over between the two reads
+        let high = ticktimer_kernel_csr.r(utra::ticktimer::TIME1);
+        let low = ticktimer_kernel_csr.r(utra::ticktimer::TIME0);
+        if ticktimer_kernel_csr.r(utra::ticktimer::TIME1) == high {
+            return ((high as u64) << 32) | low as u64;
+        }
+    }
+}
+
 /// Put the core to sleep until an interrupt hits. Returns `true`
 /// to indicate the kernel should not exit.
 pub fn idle() -> bool {
@@ -7,7 +7,8 @@ pub const MAX_THREAD: TID = 31;
 pub const EXCEPTION_TID: TID = 1;
 pub const INITIAL_TID: TID = 2;
 pub const IRQ_TID: TID = 0;
-use crate::arch::mem::PAGE_SIZE;
+use crate::arch::mem::{virt_to_phys, MemoryMapping, PAGE_SIZE, USER_AREA_END};
+use crate::mem::MemoryManager;
 use crate::services::ProcessInner;
 use xous_kernel::{ProcessInit, ThreadInit, PID, TID};
 
@@ -497,8 +498,77 @@ impl Process {
         );
     }
 
-    pub fn create(_pid: PID, _init_data: ProcessInit) -> PID {
-        todo!();
+    /// Create a new address space for `pid`, and move the program image described
+    /// by `init_data` into it out of the current process. Returns the new mapping,
+    /// along with the initial thread that should be set up the first time the new
+    /// process is run.
+    ///
+    /// Every page of the image must be backed by RAM that the current process owns
+    /// and isn't lending out. The image is checked up front, so a bad image is
+    /// normally rejected before any of its pages have been taken away.
+    ///
+    /// Pages keep the permissions they have in the current process, so the caller
+    /// decides which of them are writable or executable, e.g. by narrowing them
+    /// with `UpdateMemoryFlags` as `load_program()` does.
+    pub fn create(
+        pid: PID,
+        init_data: ProcessInit,
+    ) -> Result<(MemoryMapping, ThreadInit), xous_kernel::Error> {
+        let text = init_data.text.as_ptr() as usize;
+        let text_len = init_data.text.len();
+        let destination = init_data.text_destination.get();
+        let start = init_data.start.get();
+        let stack = init_data.stack.as_ptr() as usize;
+        let stack_len = init_data.stack.len();
+
+        if (text | text_len | destination | stack | stack_len) & (PAGE_SIZE - 1) != 0 {
+            return Err(xous_kernel::Error::BadAlignment);
+        }
+        // Everything must lie within the user area, and the stack must not overlap the image
+        let within_user_area =
+            |base: usize, len: usize| base.checked_add(len).map_or(false, |end| end <= USER_AREA_END);
+        if !within_user_area(text, text_len)
+            || !within_user_area(destination, text_len)
+            || !within_user_area(stack, stack_len)
+            || (stack < destination + text_len && destination < stack + stack_len)
+            || start < destination
+            || start >= destination + text_len
+        {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+
+        MemoryManager::with_mut(|mm| {
+            for page in (text..text + text_len).step_by(PAGE_SIZE) {
+                let phys = virt_to_phys(page)?;
+                if !mm.is_main_memory(phys as *mut u8) {
+                    return Err(xous_kernel::Error::BadAddress);
+                }
+            }
+
+            let current_pid = current_pid();
+            let current_mapping = MemoryMapping::current();
+            let mapping = MemoryMapping::allocate(mm, pid)?;
+            for offset in (0..text_len).step_by(PAGE_SIZE) {
+                if let Err(e) = mm.move_page(
+                    current_pid,
+                    &current_mapping,
+                    (text + offset) as *mut u8,
+                    pid,
+                    &mapping,
+                    (destination + offset) as *mut u8,
+                ) {
+                    // The pages that were already moved belong to the new process now,
+                    // so they go away with it.
+                    unsafe { mm.release_all_memory_for_process(pid) };
+                    return Err(e);
+                }
+            }
+
+            Ok((
+                mapping,
+                ThreadInit::new(start, init_data.stack, pid.get() as usize, 0, 0, 0),
+            ))
+        })
     }
 
     pub fn destroy(pid: PID) -> Result<(), xous_kernel::Error> {
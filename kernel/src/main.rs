@@ -147,7 +147,8 @@ fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
 #[no_mangle]
 pub extern "C" fn kmain() {
     // Start performing round-robin on all child processes.
-    // Note that at this point, no new direct children of INIT may be created.
+    // New processes created with `CreateProcess` are made direct children of INIT,
+    // so that they get picked up here as well.
     let mut pid = None;
 
     #[cfg(not(any(target_os = "none", target_os = "xous", all(ci, test))))]
@@ -160,6 +161,13 @@ pub extern "C" fn kmain() {
     }
 
     loop {
+        // Time out any `WaitOnAddress` calls that are overdue. This only happens when
+        // control comes back here, so timeouts are as coarse as the preemption timer.
+        // That timer keeps ticking while idle, so the kernel needs no alarm of its own,
+        // and the ticktimer's alarm is left to the ticktimer server.
+        #[cfg(baremetal)]
+        SystemServices::with_mut(|ss| ss.expire_futex_waiters(arch::elapsed_ms()));
+
         pid = next_pid_to_run(pid);
 
         match pid {
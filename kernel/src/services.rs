@@ -17,6 +17,7 @@ use xous_kernel::{
 };
 
 const MAX_SERVER_COUNT: usize = 128;
+const MAX_FUTEX_WAITERS: usize = 128;
 
 pub use crate::arch::process::{INITIAL_TID, MAX_PROCESS_COUNT};
 
@@ -29,6 +30,19 @@ pub struct ExceptionHandler {
     pub sp: usize,
 }
 
+/// A thread that is blocked in `WaitOnAddress`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FutexWaiter {
+    pid: PID,
+    tid: TID,
+
+    /// Address (in program space) that the thread is waiting on
+    addr: usize,
+
+    /// Time, as returned by `arch::elapsed_ms()`, at which the wait times out
+    deadline: Option<u64>,
+}
+
 // fn log_process_update(f: &str, l: u32, process: &Process, old_state: ProcessState) {
 //     if process.pid.get() == 3 {
 //         println!("[{}:{}] Updated PID {:?} state: {:?} -> {:?}", f, l, process.pid, old_state, process.state);
@@ -43,6 +57,10 @@ pub struct SystemServices {
 
     /// A table of all servers in the system
     pub servers: [Option<Server>; MAX_SERVER_COUNT],
+
+    /// Threads that are blocked in `WaitOnAddress`, oldest first. Free slots
+    /// are always at the end.
+    futex_waiters: [Option<FutexWaiter>; MAX_FUTEX_WAITERS],
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -260,6 +278,7 @@ std::thread_local!(static SYSTEM_SERVICES: core::cell::RefCell<SystemServices> =
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
     servers: filled_array![None; 128],
+    futex_waiters: [None; MAX_FUTEX_WAITERS],
 }));
 
 #[cfg(baremetal)]
@@ -276,6 +295,7 @@ static mut SYSTEM_SERVICES: SystemServices = SystemServices {
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
     servers: filled_array![None; 128],
+    futex_waiters: [None; MAX_FUTEX_WAITERS],
 };
 
 impl core::fmt::Debug for Process {
@@ -395,12 +415,35 @@ impl SystemServices {
                 continue;
             }
             let new_pid = pid_from_usize(idx + 1)?;
-            arch::process::Process::create(new_pid, init_process);
-            let ppid = crate::arch::process::current_pid();
-            // println!("Creating new process for PID {} with PPID {}", new_pid, ppid);
-            entry.state = ProcessState::Allocated;
-            entry.ppid = ppid;
-            entry.pid = new_pid;
+
+            // On hardware, the new process gets its own address space and starts
+            // running the image it was handed the first time it gets scheduled.
+            // It is a direct child of PID 1 so that the scheduler in `kmain` picks it up.
+            #[cfg(baremetal)]
+            {
+                let (mapping, thread_init) =
+                    arch::process::Process::create(new_pid, init_process)?;
+                // println!("Creating new process for PID {} with mapping {:?}", new_pid, mapping);
+                *entry = Process {
+                    mapping,
+                    state: ProcessState::Setup(thread_init),
+                    pid: new_pid,
+                    ppid: unsafe { PID::new_unchecked(1) },
+                    current_thread: 0,
+                    previous_thread: 0,
+                    exception_handler: None,
+                };
+            }
+
+            #[cfg(not(baremetal))]
+            {
+                arch::process::Process::create(new_pid, init_process);
+                let ppid = crate::arch::process::current_pid();
+                // println!("Creating new process for PID {} with PPID {}", new_pid, ppid);
+                entry.state = ProcessState::Allocated;
+                entry.ppid = ppid;
+                entry.pid = new_pid;
+            }
             return Ok(new_pid);
         }
         Err(xous_kernel::Error::ProcessNotFound)
@@ -1627,6 +1670,104 @@ impl SystemServices {
         }
     }
 
+    /// Note that the given thread is about to block until `addr` is woken up, or until
+    /// `timeout_ms` milliseconds have passed. A `timeout_ms` of 0 never times out.
+    /// The caller is responsible for actually blocking the thread.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: There are too many threads waiting already
+    pub fn wait_on_address(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        addr: usize,
+        timeout_ms: usize,
+    ) -> Result<(), xous_kernel::Error> {
+        let deadline = if timeout_ms == 0 {
+            None
+        } else {
+            Some(arch::elapsed_ms() + timeout_ms as u64)
+        };
+        let slot = self
+            .futex_waiters
+            .iter_mut()
+            .find(|waiter| waiter.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(FutexWaiter {
+            pid,
+            tid,
+            addr,
+            deadline,
+        });
+        Ok(())
+    }
+
+    /// Wake up at most `count` threads in `pid` that are waiting on `addr`, and
+    /// return how many were woken up.
+    pub fn wake_address(
+        &mut self,
+        pid: PID,
+        addr: usize,
+        count: usize,
+    ) -> Result<usize, xous_kernel::Error> {
+        let mut woken = 0;
+        while woken < count {
+            let idx = match self.futex_waiters.iter().position(|waiter| {
+                matches!(waiter, Some(waiter) if waiter.pid == pid && waiter.addr == addr)
+            }) {
+                Some(idx) => idx,
+                None => break,
+            };
+            let waiter = self.remove_futex_waiter(idx);
+            self.wake_futex_waiter(waiter, xous_kernel::Result::Ok)?;
+            woken += 1;
+        }
+        Ok(woken)
+    }
+
+    /// Wake up every waiting thread whose deadline is at or before `now`, with a
+    /// `Timeout` error.
+    pub fn expire_futex_waiters(&mut self, now: u64) {
+        let mut idx = 0;
+        while let Some(Some(waiter)) = self.futex_waiters.get(idx).copied() {
+            if !matches!(waiter.deadline, Some(deadline) if deadline <= now) {
+                idx += 1;
+                continue;
+            }
+            self.remove_futex_waiter(idx);
+            self.wake_futex_waiter(waiter, xous_kernel::Result::Error(xous_kernel::Error::Timeout))
+                .expect("couldn't wake up timed-out thread");
+        }
+    }
+
+    /// Return the earliest deadline of all waiting threads, if any of them have one.
+    pub fn next_futex_deadline(&self) -> Option<u64> {
+        self.futex_waiters
+            .iter()
+            .flatten()
+            .filter_map(|waiter| waiter.deadline)
+            .min()
+    }
+
+    /// Take the waiter at `idx` out of the table, moving the ones after it down.
+    fn remove_futex_waiter(&mut self, idx: usize) -> FutexWaiter {
+        let waiter = self.futex_waiters[idx].take().unwrap();
+        self.futex_waiters[idx..].rotate_left(1);
+        waiter
+    }
+
+    fn wake_futex_waiter(
+        &mut self,
+        waiter: FutexWaiter,
+        result: xous_kernel::Result,
+    ) -> Result<(), xous_kernel::Error> {
+        self.ready_thread(waiter.pid, waiter.tid)?;
+        #[cfg(not(baremetal))]
+        self.switch_to_thread(waiter.pid, Some(waiter.tid))?;
+        self.set_thread_result(waiter.pid, waiter.tid, result)
+    }
+
     /// Allocate a new server ID for this process and return the address. If the
     /// server table is full, or if there is not enough memory to map the server queue,
     /// return an error.
@@ -2081,6 +2222,16 @@ impl SystemServices {
             }
         }
 
+        // Forget about any of its threads that were waiting on an address.
+        let mut idx = 0;
+        while let Some(Some(waiter)) = self.futex_waiters.get(idx).copied() {
+            if waiter.pid == target_pid {
+                self.remove_futex_waiter(idx);
+            } else {
+                idx += 1;
+            }
+        }
+
         let process = self.get_process_mut(target_pid)?;
         process.activate()?;
         let parent_pid = process.ppid;
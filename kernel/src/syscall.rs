@@ -597,6 +597,63 @@ fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> Sys
     })
 }
 
+fn wait_on_address(
+    pid: PID,
+    tid: TID,
+    addr: MemoryAddress,
+    expected: usize,
+    timeout_ms: usize,
+) -> SysCallResult {
+    if addr.get() & (mem::size_of::<usize>() - 1) != 0 {
+        return Err(xous_kernel::Error::BadAlignment);
+    }
+
+    // The value must be checked before the thread is added to the list of waiters.
+    // Since nothing else can run while the kernel is handling this call, no wakeup
+    // can get lost in between.
+    #[cfg(baremetal)]
+    {
+        if addr.get() >= arch::mem::USER_AREA_END {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        let value = MemoryManager::with_mut(|mm| {
+            mm.ensure_page_exists(addr.get())?;
+            if !arch::mem::page_flags(addr.get())
+                .map(|flags| flags.contains(MemoryFlags::R))
+                .unwrap_or(false)
+            {
+                return Err(xous_kernel::Error::BadAddress);
+            }
+            arch::mem::peek_memory(addr.get() as *mut usize)
+        })?;
+        if value != expected {
+            return Ok(xous_kernel::Result::Ok);
+        }
+    }
+    // The hosted kernel can't see into processes, so the process compares the
+    // value itself, and only makes this call if it matched.
+    #[cfg(not(baremetal))]
+    let _ = expected;
+
+    SystemServices::with_mut(|ss| {
+        ss.wait_on_address(pid, tid, addr.get(), timeout_ms)?;
+
+        // For baremetal targets, switch away from this process.
+        if cfg!(baremetal) {
+            unsafe { SWITCHTO_CALLER = None };
+            let ppid = ss.get_process(pid).expect("Can't get current process").ppid;
+            ss.activate_process_thread(tid, ppid, 0, false)
+                .map(|_| Ok(xous_kernel::Result::ResumeProcess))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        }
+        // For hosted targets, the response is sent once the thread is woken up.
+        else {
+            ss.unschedule_thread(pid, tid)
+                .map(|_| xous_kernel::Result::BlockedProcess)
+        }
+    })
+}
+
 pub fn handle(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallResult {
     #[cfg(feature = "debug-print")]
     print!("KERNEL({}:{}): Syscall {:x?}", pid, tid, call);
@@ -884,6 +941,13 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                 ret
             })
         }
+        SysCall::WaitOnAddress(addr, expected, timeout_ms) => {
+            wait_on_address(pid, tid, addr, expected, timeout_ms)
+        }
+        SysCall::WakeAddress(addr, count) => SystemServices::with_mut(|ss| {
+            ss.wake_address(pid, addr.get(), count)
+                .map(xous_kernel::Result::Scalar1)
+        }),
         SysCall::UpdateMemoryFlags(range, flags, pid) => {
             // We do not yet support modifying flags for other processes.
             if pid.is_some() {
@@ -1177,3 +1177,93 @@ fn process_restart_server() {
 
     main_thread.join().expect("couldn't join kernel process");
 }
+
+/// Test that a thread waiting on an address gets woken up by another thread
+#[test]
+fn wait_on_address_wake() {
+    // Start the kernel in its own thread
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "wait_on_address_wake process",
+        || {
+            use std::sync::atomic::AtomicUsize;
+            use std::sync::Arc;
+            let value = Arc::new(AtomicUsize::new(0));
+
+            let waiter = {
+                let value = value.clone();
+                xous_kernel::create_thread(move || {
+                    while value.load(Ordering::SeqCst) == 0 {
+                        // the wake comes long before this times out, so a lost wakeup fails here
+                        // rather than hanging the test
+                        assert_eq!(xous_kernel::wait_on_address(&value, 0, 5000), Ok(()));
+                    }
+                })
+                .expect("couldn't spawn waiting thread")
+            };
+
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            value.store(1, Ordering::SeqCst);
+            let woken = xous_kernel::wake_address(&value, 1).expect("couldn't wake");
+            assert!(woken <= 1, "woke up {} threads", woken);
+
+            xous_kernel::wait_thread(waiter).expect("couldn't wait for thread");
+        },
+    ))
+    .expect("couldn't start process");
+
+    xous_kernel::wait_process_as_thread(process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that waiting on an address that has already changed returns right away, and
+/// that waking an address nobody waits on is harmless
+#[test]
+fn wait_on_address_mismatch() {
+    // Start the kernel in its own thread
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "wait_on_address_mismatch process",
+        || {
+            let value = std::sync::atomic::AtomicUsize::new(7);
+            xous_kernel::wait_on_address(&value, 6, 0).expect("couldn't wait");
+            assert_eq!(xous_kernel::wake_address(&value, 1), Ok(0));
+        },
+    ))
+    .expect("couldn't start process");
+
+    xous_kernel::wait_process_as_thread(process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that waiting on an address gives up once the timeout passes
+#[test]
+fn wait_on_address_timeout() {
+    // Start the kernel in its own thread
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "wait_on_address_timeout process",
+        || {
+            let value = std::sync::atomic::AtomicUsize::new(0);
+            let start = std::time::Instant::now();
+            assert_eq!(
+                xous_kernel::wait_on_address(&value, 0, 50),
+                Err(xous_kernel::Error::Timeout)
+            );
+            assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+        },
+    ))
+    .expect("couldn't start process");
+
+    xous_kernel::wait_process_as_thread(process).expect("couldn't join process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
@@ -92,6 +92,7 @@ pub fn xous_main(args: TokenStream, input: TokenStream) -> TokenStream {
         #(#attrs)*
         pub #unsafety fn #hash() -> ! {
             xous::init();
+            xous::set_program_name(option_env!("CARGO_BIN_NAME").unwrap_or(env!("CARGO_PKG_NAME")));
             #(#stmts)*
         }
 
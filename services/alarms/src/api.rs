@@ -0,0 +1,45 @@
+pub(crate) const SERVER_NAME_ALARMS: &str = "_RTC Alarm Scheduler_";
+
+/// Alarms are kept in this PDDB dictionary of the `.System` basis, one key per alarm, so they
+/// outlive a reboot.
+pub(crate) const ALARMS_DICT: &str = "sys.alarms";
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum Opcode {
+    /// Set where the alarms of a server are delivered, with an `AlarmHook`
+    Hook,
+    /// Schedule an alarm, described by an `AlarmRequest`
+    Schedule,
+    /// Cancel an alarm by its ID; returns 1 if it was pending
+    Cancel,
+    /// The RTC alarm went off
+    RtcAlarm,
+    /// The PDDB was mounted, so the saved alarms can be loaded
+    Mounted,
+    /// Suspend/resume callback
+    SuspendResume,
+    /// Exits the server
+    Quit,
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct AlarmRequest {
+    /// wall-clock time of the alarm, in seconds since the UNIX epoch
+    pub deadline: u64,
+    /// the server to notify when the alarm goes off; the caller must have hooked it
+    pub server: xous_ipc::String::<64>,
+    /// the opcode of the scalar message sent to `server`
+    pub opcode: u32,
+    /// filled in by the alarm server with the ID of the alarm; 0 if it couldn't be scheduled
+    pub id: u32,
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct AlarmHook {
+    /// a server name the caller registered with the name server
+    pub server: xous_ipc::String::<64>,
+    /// the SID that alarms for `server` are delivered to
+    pub sid: [u32; 4],
+    /// filled in by the alarm server: whether the hook was accepted
+    pub ok: bool,
+}
@@ -0,0 +1,100 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+pub mod api;
+use api::*;
+use xous::{CID, send_message, Message};
+use xous_ipc::Buffer;
+use num_traits::ToPrimitive;
+
+/// Schedules callbacks at wall-clock times, which survive suspend and, once the PDDB is mounted, a reboot.
+///
+/// Alarms are scheduled for a server the calling process registered with the name server, which must
+/// first be hooked with `hook()`. When an alarm goes off, the alarm server sends a non-blocking scalar
+/// message to the SID given there, with the opcode given at scheduling time and these arguments:
+///   - arg1: the ID of the alarm, as returned by `schedule()`
+///   - arg2: the lower 32 bits of the deadline
+///   - arg3: the upper 32 bits of the deadline
+///
+/// Delivery is to the nearest second. An alarm that falls due while the device is off, or while it's
+/// suspended more than 255 seconds ahead of the alarm, is delivered late, after the next boot or resume.
+pub struct Alarms {
+    conn: CID,
+}
+impl Alarms {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_ALARMS).expect("Can't connect to Alarms server");
+        Ok(Alarms {
+            conn
+        })
+    }
+
+    /// Has the alarms for `server_name` delivered to `sid`, which is usually the SID this process got
+    /// when it registered `server_name`. Only the process that registered `server_name` with the name
+    /// server can hook it. Alarms saved across a reboot are held until their server is hooked again, for
+    /// up to an hour past their deadline.
+    pub fn hook(&self, server_name: &str, sid: xous::SID) -> Result<(), xous::Error> {
+        if server_name.len() > 64 {
+            return Err(xous::Error::InvalidString);
+        }
+        let request = AlarmHook {
+            server: xous_ipc::String::<64>::from_str(server_name),
+            sid: sid.to_array(),
+            ok: false,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::Hook.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let response = buf.to_original::<AlarmHook, _>().or(Err(xous::Error::InternalError))?;
+        if response.ok {
+            Ok(())
+        } else {
+            Err(xous::Error::AccessDenied)
+        }
+    }
+
+    /// Schedules an alarm for `deadline`, in seconds since the UNIX epoch, which notifies the server
+    /// registered under `server_name` by sending it `opcode`. `server_name` must have been hooked by
+    /// this process. Returns the ID of the alarm.
+    pub fn schedule(&self, deadline: u64, server_name: &str, opcode: u32) -> Result<u32, xous::Error> {
+        if server_name.len() > 64 {
+            return Err(xous::Error::InvalidString);
+        }
+        let request = AlarmRequest {
+            deadline,
+            server: xous_ipc::String::<64>::from_str(server_name),
+            opcode,
+            id: 0,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::Schedule.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let response = buf.to_original::<AlarmRequest, _>().or(Err(xous::Error::InternalError))?;
+        if response.id != 0 {
+            Ok(response.id)
+        } else {
+            Err(xous::Error::InternalError)
+        }
+    }
+
+    /// Cancels an alarm. Returns `false` if there was no such alarm, for example because it has already
+    /// gone off.
+    pub fn cancel(&self, id: u32) -> Result<bool, xous::Error> {
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Cancel.to_usize().unwrap(), id as usize, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(found) => Ok(found != 0),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for Alarms {
+    fn drop(&mut self) {
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
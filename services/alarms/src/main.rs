@@ -0,0 +1,249 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod schedule;
+use schedule::*;
+
+use num_traits::*;
+use xous::{msg_scalar_unpack, msg_blocking_scalar_unpack};
+use xous_ipc::Buffer;
+use std::thread;
+
+/// The RTC counts down at most this many seconds, so a later alarm is reached in steps.
+const MAX_RTC_SECS: u64 = u8::MAX as u64;
+/// A resume that comes this soon after the wakeup alarm was due is taken to have come from it.
+const HOP_SLACK_SECS: u64 = 5;
+
+#[cfg(any(target_os = "none", target_os = "xous"))]
+mod implementation {
+    use crate::api::*;
+    use num_traits::ToPrimitive;
+
+    pub(crate) struct Rtc {
+        llio: llio::Llio,
+    }
+
+    impl Rtc {
+        pub fn new(xns: &xous_names::XousNames, sid: xous::SID) -> Result<Rtc, xous::Error> {
+            let mut llio = llio::Llio::new(xns);
+            let cid = xous::connect(sid)?;
+            llio.hook_rtc_alarm_callback(Opcode::RtcAlarm.to_u32().unwrap(), cid)?;
+            llio.rtc_alarm_enable(true)?;
+            Ok(Rtc {
+                llio,
+            })
+        }
+        /// Interrupts us `secs` seconds from now, or never if `None`.
+        pub fn set_alarm(&self, secs: Option<u64>) -> Result<(), xous::Error> {
+            match secs {
+                Some(secs) => self.llio.set_rtc_alarm(secs.max(1).min(crate::MAX_RTC_SECS) as u8),
+                None => self.llio.clear_rtc_alarm(),
+            }
+        }
+        /// The RTC alarm doesn't bring the system out of suspend, but the wakeup alarm does. It counts
+        /// at most 255 of its units, and alarms further off than that many seconds are counted in
+        /// minutes, so the wakeup may come early. Returns the number of seconds it is set for, and how
+        /// much earlier than that it can come.
+        pub fn set_wakeup(&self, secs: u64) -> Result<(u64, u64), xous::Error> {
+            if secs <= crate::MAX_RTC_SECS {
+                let secs = secs.max(1);
+                self.llio.set_wakeup_alarm(secs as u8)?;
+                Ok((secs, 0))
+            } else {
+                let minutes = (secs / 60).min(crate::MAX_RTC_SECS);
+                self.llio.set_wakeup_alarm_in(minutes as u8, llio::TimeUnits::Minutes)?;
+                Ok((minutes * 60, 60))
+            }
+        }
+        pub fn clear_wakeup(&self) -> Result<(), xous::Error> {
+            self.llio.clear_wakeup_alarm()
+        }
+    }
+}
+
+// a stub to try to avoid breaking hosted mode for as long as possible.
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod implementation {
+    use crate::api::*;
+    use num_traits::ToPrimitive;
+
+    pub(crate) struct Rtc {
+        sid: xous::SID,
+    }
+
+    impl Rtc {
+        pub fn new(_xns: &xous_names::XousNames, sid: xous::SID) -> Result<Rtc, xous::Error> {
+            Ok(Rtc {
+                sid,
+            })
+        }
+        /// There's no RTC to program in hosted mode, so a thread sleeps in its place. Alarms that
+        /// are superseded still go off, which just causes an extra check of the schedule.
+        pub fn set_alarm(&self, secs: Option<u64>) -> Result<(), xous::Error> {
+            if let Some(secs) = secs {
+                let sid = self.sid;
+                std::thread::spawn(move || {
+                    let tt = ticktimer_server::Ticktimer::new().unwrap();
+                    tt.sleep_ms((secs.max(1) * 1000) as usize).unwrap();
+                    let cid = xous::connect(sid).unwrap();
+                    xous::send_message(cid,
+                        xous::Message::new_scalar(Opcode::RtcAlarm.to_usize().unwrap(), 0, 0, 0, 0)
+                    ).unwrap();
+                    unsafe{xous::disconnect(cid).unwrap();}
+                });
+            }
+            Ok(())
+        }
+        pub fn set_wakeup(&self, secs: u64) -> Result<(u64, u64), xous::Error> {
+            Ok((secs, 0))
+        }
+        pub fn clear_wakeup(&self) -> Result<(), xous::Error> {
+            Ok(())
+        }
+    }
+}
+
+/// Sets the RTC alarm for the next alarm in `schedule` that is due. If that fails, the schedule is
+/// checked again at the next request or resume.
+fn arm(rtc: &implementation::Rtc, schedule: &mut Schedule, now: u64) {
+    if let Err(e) = rtc.set_alarm(schedule.ring(now)) {
+        log::error!("couldn't set RTC alarm: {:?}", e);
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    use crate::implementation::Rtc;
+
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    log::info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let alarms_sid = xns.register_name(api::SERVER_NAME_ALARMS, None).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", alarms_sid);
+
+    let rtc = match Rtc::new(&xns, alarms_sid) {
+        Ok(rtc) => rtc,
+        Err(e) => {
+            log::error!("couldn't hook the RTC alarm, exiting: {:?}", e);
+            xns.unregister_server(alarms_sid).unwrap();
+            xous::destroy_server(alarms_sid).unwrap();
+            xous::terminate_process(1)
+        }
+    };
+    let mut schedule = Schedule::new(&xns);
+
+    // register a suspend/resume listener
+    let sr_cid = xous::connect(alarms_sid).expect("couldn't create suspend callback connection");
+    let mut susres = susres::Susres::new(None, &xns, api::Opcode::SuspendResume as u32, sr_cid).expect("couldn't create suspend/resume object");
+
+    // the saved alarms can only be read once the PDDB is unlocked
+    thread::spawn({
+        let cid = xous::connect(alarms_sid).unwrap();
+        move || {
+            let pddb = pddb::Pddb::new();
+            pddb.is_mounted_blocking(None);
+            xous::send_message(cid,
+                xous::Message::new_scalar(Opcode::Mounted.to_usize().unwrap(), 0, 0, 0, 0)
+            ).expect("couldn't report PDDB mount");
+        }
+    });
+
+    log::trace!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(alarms_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Hook) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<AlarmHook, _>().unwrap();
+                request.ok = match (request.server.as_str(), pid) {
+                    (Ok(server), Some(pid)) if server.len() > 0 =>
+                        schedule.hook(&xns, server, pid, xous::SID::from_array(request.sid)),
+                    _ => false,
+                };
+                buffer.replace(request).expect("couldn't return hook status");
+                // alarms held for this server can go out now
+                arm(&rtc, &mut schedule, now_secs());
+            }
+            Some(Opcode::Schedule) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<AlarmRequest, _>().unwrap();
+                request.id = match (request.server.as_str(), pid) {
+                    (Ok(server), Some(pid)) if schedule.hooked_by(server, pid) =>
+                        schedule.add(request.deadline, server, request.opcode),
+                    _ => 0,
+                };
+                buffer.replace(request).expect("couldn't return alarm ID");
+                arm(&rtc, &mut schedule, now_secs());
+            }
+            Some(Opcode::Cancel) => msg_blocking_scalar_unpack!(msg, id, _, _, _, {
+                let found = schedule.cancel(id as u32);
+                xous::return_scalar(msg.sender, if found { 1 } else { 0 }).expect("couldn't return cancel status");
+                arm(&rtc, &mut schedule, now_secs());
+            }),
+            Some(Opcode::RtcAlarm) => {
+                arm(&rtc, &mut schedule, now_secs());
+            }
+            Some(Opcode::Mounted) => {
+                schedule.mounted();
+                arm(&rtc, &mut schedule, now_secs());
+            }
+            Some(Opcode::SuspendResume) => msg_scalar_unpack!(msg, token, _, _, _, {
+                // wake up for the next alarm. If the wakeup alarm can't count that far, it is set as far
+                // as it goes, and the system is suspended again from there: a hop.
+                let (wakeup, hop) = match schedule.ring(now_secs()).map(|secs| (secs, rtc.set_wakeup(secs))) {
+                    Some((secs, Ok((wakeup, early)))) => {
+                        let at = now_secs() + wakeup;
+                        (true, if wakeup < secs { Some((at - early, at + HOP_SLACK_SECS)) } else { None })
+                    }
+                    Some((_, Err(e))) => {
+                        log::error!("couldn't set wakeup alarm, alarms will be late: {:?}", e);
+                        (false, None)
+                    }
+                    None => (false, None),
+                };
+                susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
+                if wakeup {
+                    if let Err(e) = rtc.clear_wakeup() {
+                        log::error!("couldn't clear wakeup alarm: {:?}", e);
+                    }
+                }
+                let now = now_secs();
+                let due = schedule.due(now);
+                arm(&rtc, &mut schedule, now);
+                if let Some((from, to)) = hop {
+                    // a resume that isn't the hop (e.g. the power button was pressed) is left alone
+                    if !due && now >= from && now <= to {
+                        log::debug!("hopping towards the next alarm");
+                        susres.initiate_suspend().expect("couldn't suspend again");
+                    }
+                }
+            }),
+            Some(Opcode::Quit) => {
+                log::warn!("Quit received, goodbye world!");
+                break;
+            },
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    // clean up our program
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(alarms_sid).unwrap();
+    xous::destroy_server(alarms_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
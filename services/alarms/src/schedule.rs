@@ -0,0 +1,376 @@
+use crate::api::ALARMS_DICT;
+use pddb::{Pddb, PDDB_DEFAULT_SYSTEM_BASIS};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use xous::CID;
+
+/// How long to wait before trying again to deliver an alarm whose server isn't hooked yet, as
+/// happens for saved alarms that come due early in boot.
+pub(crate) const RETRY_SECS: u64 = 10;
+/// An alarm whose server still can't be reached this long after its deadline is dropped.
+const GIVE_UP_SECS: u64 = 3600;
+
+struct Alarm {
+    deadline: u64,
+    server: String,
+    opcode: u32,
+}
+impl Alarm {
+    /// The saved form: the deadline and opcode, little-endian, then the server name.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.deadline.to_le_bytes());
+        data.extend_from_slice(&self.opcode.to_le_bytes());
+        data.extend_from_slice(self.server.as_bytes());
+        data
+    }
+    fn from_bytes(data: &[u8]) -> Option<Alarm> {
+        if data.len() < 12 {
+            return None;
+        }
+        Some(Alarm {
+            deadline: u64::from_le_bytes(data[..8].try_into().unwrap()),
+            opcode: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            server: String::from_utf8(data[12..].to_vec()).ok()?,
+        })
+    }
+}
+
+/// Hands each alarm in `alarms` that is due at `now` to `deliver`, and removes the ones that were
+/// delivered or given up on. Returns their IDs, and how many seconds to wait until the next alarm (or
+/// retry) is due.
+fn ring<F>(alarms: &mut BTreeMap<u32, Alarm>, now: u64, mut deliver: F) -> (Vec<u32>, Option<u64>)
+where
+    F: FnMut(u32, &Alarm) -> bool,
+{
+    let due: Vec<u32> = alarms.iter()
+        .filter(|(_, alarm)| alarm.deadline <= now)
+        .map(|(&id, _)| id)
+        .collect();
+    let mut done = Vec::new();
+    let mut retry = false;
+    for id in due {
+        let alarm = &alarms[&id];
+        if deliver(id, alarm) {
+            log::debug!("alarm {:08x} delivered to {}", id, alarm.server);
+        } else if now - alarm.deadline < GIVE_UP_SECS {
+            retry = true;
+            continue;
+        } else {
+            log::warn!("giving up on alarm {:08x}: {} can't be reached", id, alarm.server);
+        }
+        alarms.remove(&id);
+        done.push(id);
+    }
+    let next = alarms.values()
+        .filter(|alarm| alarm.deadline > now)
+        .map(|alarm| alarm.deadline - now)
+        .min();
+    let next = match (next, retry) {
+        (Some(next), true) => Some(next.min(RETRY_SECS)),
+        (None, true) => Some(RETRY_SECS),
+        (next, false) => next,
+    };
+    (done, next)
+}
+
+/// The servers that alarms can be delivered to, by name, with the connection to the SID that was
+/// handed in for each, and the process that handed it in.
+#[derive(Default)]
+struct Hooks {
+    hooks: HashMap<String, (CID, xous::PID)>,
+}
+impl Hooks {
+    /// Delivers the alarms for `server` to `sid` from now on, connecting to it with `connect`. The
+    /// caller has checked that `pid` registered `server`. Returns the connection of the hook that was
+    /// replaced, if nothing else uses it any more.
+    fn hook<F>(&mut self, server: &str, pid: xous::PID, sid: xous::SID, connect: F) -> Result<Option<CID>, xous::Error>
+    where
+        F: FnOnce(xous::SID) -> Result<CID, xous::Error>,
+    {
+        let cid = connect(sid)?;
+        match self.hooks.insert(String::from(server), (cid, pid)) {
+            Some((old, _)) if old != cid && !self.hooks.values().any(|&(c, _)| c == old) => Ok(Some(old)),
+            _ => Ok(None),
+        }
+    }
+    /// Whether `pid` hooked `server`, and so may schedule alarms for it.
+    fn hooked_by(&self, server: &str, pid: xous::PID) -> bool {
+        matches!(self.hooks.get(server), Some(&(_, p)) if p == pid)
+    }
+    fn cid(&self, server: &str) -> Option<CID> {
+        self.hooks.get(server).map(|&(cid, _)| cid)
+    }
+}
+
+/// Sends `alarm` to the SID its server hooked. An alarm for a server that isn't hooked (yet) is not
+/// delivered.
+fn deliver(hooks: &Hooks, id: u32, alarm: &Alarm) -> bool {
+    let cid = match hooks.cid(&alarm.server) {
+        Some(cid) => cid,
+        None => return false,
+    };
+    match xous::send_message(cid,
+        xous::Message::new_scalar(alarm.opcode as usize,
+            id as usize, alarm.deadline as u32 as usize, (alarm.deadline >> 32) as u32 as usize, 0)
+    ) {
+        Ok(_) => true,
+        Err(e) => {
+            log::warn!("couldn't deliver alarm {:08x} to {}: {:?}", id, alarm.server, e);
+            false
+        }
+    }
+}
+
+fn key_name(id: u32) -> String {
+    format!("{:08x}", id)
+}
+
+/// The pending alarms. They're kept in memory until the PDDB is mounted, and in the PDDB after that.
+pub(crate) struct Schedule {
+    alarms: BTreeMap<u32, Alarm>,
+    pddb: Pddb,
+    mounted: bool,
+    trng: trng::Trng,
+    hooks: Hooks,
+}
+
+impl Schedule {
+    pub(crate) fn new(xns: &xous_names::XousNames) -> Schedule {
+        Schedule {
+            alarms: BTreeMap::new(),
+            pddb: Pddb::new(),
+            mounted: false,
+            trng: trng::Trng::new(xns).expect("couldn't connect to TRNG"),
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Delivers the alarms for `server` to `sid` from now on, if `pid` registered `server` with the
+    /// name server.
+    pub(crate) fn hook(&mut self, xns: &xous_names::XousNames, server: &str, pid: xous::PID, sid: xous::SID) -> bool {
+        match xns.is_registrant(server, pid) {
+            Ok(true) => {}
+            Ok(false) => return false,
+            Err(e) => {
+                log::warn!("couldn't check who registered {}: {:?}", server, e);
+                return false;
+            }
+        }
+        // `try_connect` so a bogus SID is refused instead of waited on
+        match self.hooks.hook(server, pid, sid, xous::try_connect) {
+            Ok(stale) => {
+                if let Some(cid) = stale {
+                    unsafe { xous::disconnect(cid).ok() };
+                }
+                true
+            }
+            Err(e) => {
+                log::warn!("couldn't connect to the alarm SID of {}: {:?}", server, e);
+                false
+            }
+        }
+    }
+
+    /// Whether `pid` may schedule alarms for `server`.
+    pub(crate) fn hooked_by(&self, server: &str, pid: xous::PID) -> bool {
+        self.hooks.hooked_by(server, pid)
+    }
+
+    /// Returns the ID of the new alarm. IDs are random, so that alarms scheduled before the PDDB is
+    /// mounted don't collide with the ones saved in it.
+    pub(crate) fn add(&mut self, deadline: u64, server: &str, opcode: u32) -> u32 {
+        let id = loop {
+            let id = self.trng.get_u32().expect("couldn't get random number");
+            if id != 0 && !self.alarms.contains_key(&id) {
+                break id;
+            }
+        };
+        let alarm = Alarm { deadline, server: String::from(server), opcode };
+        if self.mounted {
+            self.save(id, &alarm);
+        }
+        self.alarms.insert(id, alarm);
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: u32) -> bool {
+        if self.alarms.remove(&id).is_some() {
+            if self.mounted {
+                self.forget(id);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Loads the saved alarms, and saves the ones that were scheduled before the mount.
+    pub(crate) fn mounted(&mut self) {
+        self.mounted = true;
+        let ids: Vec<u32> = self.alarms.keys().cloned().collect();
+        for id in ids {
+            let data = self.alarms[&id].to_bytes();
+            self.write(id, &data);
+        }
+        let keys = self.pddb.list_keys(ALARMS_DICT, Some(PDDB_DEFAULT_SYSTEM_BASIS)).unwrap_or(Vec::new());
+        for key in keys.iter() {
+            let id = match u32::from_str_radix(key, 16) {
+                Ok(id) if id != 0 => id,
+                _ => continue,
+            };
+            let mut data = Vec::new();
+            let alarm = match self.pddb.get(ALARMS_DICT, key, Some(PDDB_DEFAULT_SYSTEM_BASIS), false, false, None, None::<fn()>) {
+                Ok(mut k) => match k.read_to_end(&mut data) {
+                    Ok(_) => Alarm::from_bytes(&data),
+                    Err(_) => None,
+                },
+                Err(_) => None,
+            };
+            match alarm {
+                Some(alarm) => {
+                    self.alarms.insert(id, alarm);
+                }
+                None => {
+                    log::warn!("discarding unreadable alarm {}", key);
+                    self.forget(id);
+                }
+            }
+        }
+        self.pddb.sync().ok();
+        log::info!("{} alarms pending", self.alarms.len());
+    }
+
+    /// Returns `true` if an alarm is due at `now`.
+    pub(crate) fn due(&self, now: u64) -> bool {
+        self.alarms.values().any(|alarm| alarm.deadline <= now)
+    }
+
+    /// Delivers the alarms that are due at `now`. Returns how many seconds to wait until the next
+    /// alarm (or retry) is due, or `None` if there are no alarms left.
+    pub(crate) fn ring(&mut self, now: u64) -> Option<u64> {
+        let hooks = &self.hooks;
+        let (done, next) = ring(&mut self.alarms, now, |id, alarm| deliver(hooks, id, alarm));
+        if self.mounted {
+            for id in done {
+                self.forget(id);
+            }
+        }
+        next
+    }
+
+    fn save(&mut self, id: u32, alarm: &Alarm) {
+        let data = alarm.to_bytes();
+        self.write(id, &data);
+        self.pddb.sync().ok();
+    }
+
+    fn write(&mut self, id: u32, data: &[u8]) {
+        match self.pddb.get(ALARMS_DICT, &key_name(id), Some(PDDB_DEFAULT_SYSTEM_BASIS), true, true, Some(data.len()), None::<fn()>) {
+            Ok(mut key) => {
+                if let Err(e) = key.write_all(data) {
+                    log::error!("couldn't save alarm: {:?}", e);
+                }
+            }
+            Err(e) => log::error!("couldn't create alarm key: {:?}", e),
+        }
+    }
+
+    fn forget(&mut self, id: u32) {
+        self.pddb.delete_key(ALARMS_DICT, &key_name(id), Some(PDDB_DEFAULT_SYSTEM_BASIS))
+            .unwrap_or_else(|e| log::warn!("couldn't delete saved alarm: {:?}", e));
+        self.pddb.sync().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alarm(deadline: u64, server: &str) -> Alarm {
+        Alarm { deadline, server: String::from(server), opcode: 1 }
+    }
+
+    fn alarms(list: &[(u32, u64, &str)]) -> BTreeMap<u32, Alarm> {
+        list.iter().map(|&(id, deadline, server)| (id, alarm(deadline, server))).collect()
+    }
+
+    #[test]
+    fn delivers_due_alarms_and_waits_for_the_next() {
+        let mut pending = alarms(&[(1, 100, "a"), (2, 150, "b"), (3, 1000, "c"), (4, 2000, "d")]);
+        let mut delivered = Vec::new();
+        let (done, next) = ring(&mut pending, 150, |id, _| { delivered.push(id); true });
+        assert_eq!(delivered, [1, 2]);
+        assert_eq!(done, [1, 2]);
+        assert_eq!(next, Some(850));
+        assert_eq!(pending.keys().cloned().collect::<Vec<u32>>(), [3, 4]);
+    }
+
+    #[test]
+    fn no_alarms_left() {
+        let mut pending = alarms(&[(1, 100, "a")]);
+        assert_eq!(ring(&mut pending, 100, |_, _| true), (vec![1], None));
+        assert_eq!(ring(&mut pending, 200, |_, _| true), (vec![], None));
+    }
+
+    #[test]
+    fn undelivered_alarms_are_retried() {
+        let mut pending = alarms(&[(1, 100, "missing"), (2, 5000, "b")]);
+        let (done, next) = ring(&mut pending, 100, |_, alarm| alarm.server != "missing");
+        assert!(done.is_empty());
+        assert_eq!(next, Some(RETRY_SECS));
+        assert!(pending.contains_key(&1));
+
+        // a retry doesn't push back an alarm that is due sooner
+        let mut pending = alarms(&[(1, 100, "missing"), (2, 103, "b")]);
+        let (_, next) = ring(&mut pending, 100, |_, alarm| alarm.server != "missing");
+        assert_eq!(next, Some(3));
+    }
+
+    #[test]
+    fn undeliverable_alarms_are_given_up_on() {
+        let mut pending = alarms(&[(1, 100, "missing")]);
+        let (done, next) = ring(&mut pending, 100 + GIVE_UP_SECS - 1, |_, _| false);
+        assert!(done.is_empty());
+        assert_eq!(next, Some(RETRY_SECS));
+
+        let (done, next) = ring(&mut pending, 100 + GIVE_UP_SECS, |_, _| false);
+        assert_eq!(done, [1]);
+        assert_eq!(next, None);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn hooks_belong_to_their_process() {
+        let (a, b) = (xous::PID::new(2).unwrap(), xous::PID::new(3).unwrap());
+        let sid = xous::SID::from_array([1, 2, 3, 4]);
+        let mut hooks = Hooks::default();
+        assert_eq!(hooks.cid("a"), None);
+        assert!(!deliver(&hooks, 1, &alarm(100, "a")));
+        assert_eq!(hooks.hook("a", a, sid, |s| { assert_eq!(s, sid); Ok(7) }), Ok(None));
+        assert_eq!(hooks.cid("a"), Some(7));
+        assert!(hooks.hooked_by("a", a));
+        assert!(!hooks.hooked_by("a", b));
+        assert!(!hooks.hooked_by("b", a));
+
+        // a SID that can't be reached leaves the hook as it was
+        assert_eq!(hooks.hook("a", a, sid, |_| Err(xous::Error::ServerNotFound)), Err(xous::Error::ServerNotFound));
+        assert_eq!(hooks.cid("a"), Some(7));
+
+        // hooking again hands back the old connection, unless another hook still uses it
+        assert_eq!(hooks.hook("b", b, sid, |_| Ok(7)), Ok(None));
+        assert_eq!(hooks.hook("a", a, sid, |_| Ok(8)), Ok(None));
+        assert_eq!(hooks.hook("b", b, sid, |_| Ok(9)), Ok(Some(7)));
+        assert_eq!(hooks.hook("b", b, sid, |_| Ok(9)), Ok(None));
+    }
+
+    #[test]
+    fn saved_form_round_trips() {
+        let saved = Alarm::from_bytes(&alarm(0x1_2345_6789, "_Some server_").to_bytes()).unwrap();
+        assert_eq!(saved.deadline, 0x1_2345_6789);
+        assert_eq!(saved.opcode, 1);
+        assert_eq!(saved.server, "_Some server_");
+        assert!(Alarm::from_bytes(&[0; 11]).is_none());
+    }
+}
@@ -1,54 +1,343 @@
 #![cfg_attr(not(target_os = "none"), allow(dead_code))]
 
-use codec::FIFO_DEPTH;
+//! Hosted-mode audio backend. There is no codec hardware in hosted mode, so a "clock" thread stands in
+//! for the I2S interrupt: every `FIFO_DEPTH` samples' worth of time it consumes one play frame and produces
+//! one record frame, and fires the `AnotherFrame` callback using the same rules as the hardware handler.
+//!
+//! Played audio is appended to a WAV file, and recorded audio is read out of a WAV file:
+//!   - `XOUS_AUDIO_OUT` sets the output file; defaults to `xous-audio-out.wav` in the working directory
+//!   - `XOUS_AUDIO_IN` sets the input file; if unset (or once the file is exhausted), silence is recorded
+//!
+//! Audio is always 16-bit stereo PCM at 8kHz, as set up by `Setup8kStereo`.
+
+use crate::api::*;
+use num_traits::*;
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 8000;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const WAV_HEADER_LEN: u32 = 44;
+const DEFAULT_OUT_FILE: &str = "xous-audio-out.wav";
+
+static SILENCE: [u32; FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; FIFO_DEPTH];
+
+/// Writes stereo frames to a PCM WAV file. The header sizes are patched after every frame, so the
+/// file is valid even if the emulator is killed mid-stream.
+struct WavWriter {
+    file: File,
+    data_len: u32,
+}
+impl WavWriter {
+    fn create(path: &str) -> std::io::Result<WavWriter> {
+        let mut file = File::create(path)?;
+        let block_align = CHANNELS * (BITS_PER_SAMPLE / 8);
+        let mut header: Vec<u8> = Vec::with_capacity(WAV_HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(WAV_HEADER_LEN - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&CHANNELS.to_le_bytes());
+        header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        header.extend_from_slice(&(SAMPLE_RATE * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        file.write_all(&header)?;
+        Ok(WavWriter { file, data_len: 0 })
+    }
+    fn write_frame(&mut self, frame: &[u32; FIFO_DEPTH]) -> std::io::Result<()> {
+        let mut pcm: Vec<u8> = Vec::with_capacity(FIFO_DEPTH * 4);
+        for &stereo_sample in frame.iter() {
+            // |31 right 16|15 left 0| -> interleaved L, R
+            pcm.extend_from_slice(&((stereo_sample & 0xFFFF) as u16).to_le_bytes());
+            pcm.extend_from_slice(&((stereo_sample >> 16) as u16).to_le_bytes());
+        }
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&pcm)?;
+        self.data_len += pcm.len() as u32;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(WAV_HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(WAV_HEADER_LEN as u64 - 4))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+/// Reads 16-bit PCM frames out of a WAV file. Mono files are duplicated onto both channels; the sample
+/// rate is not converted.
+struct WavReader {
+    reader: BufReader<File>,
+    channels: u16,
+    remaining: u32,
+}
+impl WavReader {
+    fn open(path: &str) -> Result<WavReader, String> {
+        let file = File::open(path).map_err(|e| format!("{:?}", e))?;
+        let mut reader = BufReader::new(file);
+        let mut riff = [0u8; 12];
+        reader.read_exact(&mut riff).map_err(|e| format!("{:?}", e))?;
+        if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+            return Err("not a RIFF/WAVE file".into());
+        }
+        let mut channels: Option<u16> = None;
+        loop {
+            let mut chunk = [0u8; 8];
+            reader.read_exact(&mut chunk).map_err(|_| "no data chunk found".to_string())?;
+            let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            match &chunk[0..4] {
+                b"fmt " => {
+                    let mut fmt = vec![0u8; len as usize];
+                    reader.read_exact(&mut fmt).map_err(|e| format!("{:?}", e))?;
+                    if fmt.len() < 16 {
+                        return Err("truncated fmt chunk".into());
+                    }
+                    let format = u16::from_le_bytes([fmt[0], fmt[1]]);
+                    let ch = u16::from_le_bytes([fmt[2], fmt[3]]);
+                    let rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+                    let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                    if format != 1 || bits != BITS_PER_SAMPLE || (ch != 1 && ch != 2) {
+                        return Err(format!("unsupported format: type {} channels {} bits {}", format, ch, bits));
+                    }
+                    if rate != SAMPLE_RATE {
+                        log::warn!("{} is sampled at {}Hz, but will be played back at {}Hz", path, rate, SAMPLE_RATE);
+                    }
+                    channels = Some(ch);
+                }
+                b"data" => {
+                    match channels {
+                        Some(channels) => return Ok(WavReader { reader, channels, remaining: len }),
+                        None => return Err("data chunk before fmt chunk".into()),
+                    }
+                }
+                _ => {
+                    // chunks are padded to an even length
+                    reader.seek(SeekFrom::Current((len + (len & 1)) as i64)).map_err(|e| format!("{:?}", e))?;
+                }
+            }
+        }
+    }
+    /// Returns `None` once the file is exhausted. A partial last frame is padded with silence.
+    fn read_frame(&mut self) -> Option<[u32; FIFO_DEPTH]> {
+        let block_align = self.channels as u32 * (BITS_PER_SAMPLE / 8) as u32;
+        if self.remaining < block_align {
+            return None;
+        }
+        let mut frame = SILENCE;
+        for stereo_sample in frame.iter_mut() {
+            if self.remaining < block_align {
+                break;
+            }
+            let mut block = [0u8; 4];
+            if self.reader.read_exact(&mut block[..block_align as usize]).is_err() {
+                self.remaining = 0;
+                break;
+            }
+            self.remaining -= block_align;
+            let left = u16::from_le_bytes([block[0], block[1]]) as u32;
+            let right = if self.channels == 2 {
+                u16::from_le_bytes([block[2], block[3]]) as u32
+            } else {
+                left
+            };
+            *stereo_sample = right << 16 | left;
+        }
+        Some(frame)
+    }
+}
+
+/// State shared between the `Codec` and its clock thread, which plays the role of the I2S interrupt.
+struct Stream {
+    play_buffer: FrameRing,
+    play_frames_dropped: u32,
+    rec_buffer: FrameRing,
+    rec_frames_dropped: u32,
+    drain: bool,
+    conn: xous::CID,
+    wav_out: Option<WavWriter>,
+    wav_in: Option<WavReader>,
+}
+
+fn audio_handler(stream: &mut Stream) {
+    // load the play buffer
+    let frame = match stream.play_buffer.dq_frame() {
+        Some(frame) => frame,
+        None => {
+            stream.play_frames_dropped += 1;
+            SILENCE
+        }
+    };
+    if let Some(wav) = stream.wav_out.as_mut() {
+        if let Err(e) = wav.write_frame(&frame) {
+            log::error!("couldn't write audio output, disabling it: {:?}", e);
+            stream.wav_out = None;
+        }
+    }
+
+    // fill the record buffer
+    let rec_buf = match stream.wav_in.as_mut() {
+        Some(wav) => match wav.read_frame() {
+            Some(frame) => frame,
+            None => {
+                log::info!("audio input exhausted, recording silence from here on");
+                stream.wav_in = None;
+                SILENCE
+            }
+        },
+        None => SILENCE,
+    };
+    match stream.rec_buffer.nq_frame(rec_buf) {
+        Ok(()) => {},
+        Err(_buff) => {
+            stream.rec_frames_dropped += 1
+        },
+    }
+
+    // if the buffer is low, let the audio handler know we used up another frame!
+    if stream.play_buffer.readable_count() < 6 && !stream.drain {
+        // there is no hardware FIFO to report on, so the rx counts are always 0. A full queue just means the
+        // server hasn't caught up with the previous callback yet; don't take down the clock thread over it.
+        let _ = xous::try_send_message(stream.conn,
+            xous::Message::new_scalar(Opcode::AnotherFrame.to_usize().unwrap(), 0, 0, 0, 0));
+    }
+}
 
 pub struct Codec {
+    stream: Arc<Mutex<Stream>>,
+    clock_run: Arc<AtomicBool>,
+    clock: Option<JoinHandle<()>>,
+    out_path: String,
+    in_path: Option<String>,
+    powered_on: bool,
+    initialized: bool,
+    live: bool,
+    // to recall values through suspend/resume
+    speaker_gain: f32,
+    headphone_left_gain: f32,
+    headphone_right_gain: f32,
 }
 
 impl Codec {
-    pub fn new(_conn: xous::CID, _xns: &xous_names::XousNames) -> Codec {
+    pub fn new(conn: xous::CID, _xns: &xous_names::XousNames) -> Codec {
+        let out_path = std::env::var("XOUS_AUDIO_OUT").unwrap_or(DEFAULT_OUT_FILE.to_string());
+        let in_path = std::env::var("XOUS_AUDIO_IN").ok();
+        Codec::with_paths(conn, out_path, in_path)
+    }
+
+    fn with_paths(conn: xous::CID, out_path: String, in_path: Option<String>) -> Codec {
         Codec {
+            stream: Arc::new(Mutex::new(Stream {
+                play_buffer: FrameRing::new(),
+                play_frames_dropped: 0,
+                rec_buffer: FrameRing::new(),
+                rec_frames_dropped: 0,
+                drain: false,
+                conn,
+                wav_out: None,
+                wav_in: None,
+            })),
+            clock_run: Arc::new(AtomicBool::new(false)),
+            clock: None,
+            out_path,
+            in_path,
+            powered_on: false,
+            initialized: false,
+            live: false,
+            speaker_gain: -6.0,
+            headphone_left_gain: -15.0,
+            headphone_right_gain: -15.0,
+        }
+    }
+
+    fn start_clock(&mut self) {
+        if self.clock.is_some() {
+            return;
+        }
+        self.clock_run.store(true, Ordering::SeqCst);
+        let stream = self.stream.clone();
+        let run = self.clock_run.clone();
+        self.clock = Some(std::thread::spawn(move || {
+            let period = Duration::from_micros(FIFO_DEPTH as u64 * 1_000_000 / SAMPLE_RATE as u64);
+            // schedule against absolute deadlines, so that the frame rate doesn't drift with OS jitter
+            let mut deadline = Instant::now() + period;
+            while run.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if deadline > now {
+                    std::thread::sleep(deadline - now);
+                }
+                deadline += period;
+                if !run.load(Ordering::SeqCst) {
+                    break;
+                }
+                audio_handler(&mut stream.lock().unwrap());
+            }
+        }));
+    }
+    fn stop_clock(&mut self) {
+        self.clock_run.store(false, Ordering::SeqCst);
+        if let Some(clock) = self.clock.take() {
+            clock.join().expect("audio clock thread panicked");
         }
     }
-    pub fn suspend(&self) {
+
+    pub fn suspend(&mut self) {
+        self.stop_clock();
     }
-    pub fn resume(&self) {
+    pub fn resume(&mut self) {
+        if self.live {
+            self.start_clock();
+        }
     }
     pub fn init(&mut self) {
+        self.initialized = true;
     }
 
-    pub fn nq_play_frame(&mut self, _frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
-        Ok(())
+    pub fn nq_play_frame(&mut self, frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
+        self.stream.lock().unwrap().play_buffer.nq_frame(frame)
     }
     pub fn dq_rec_frame(&mut self) -> Option<[u32; FIFO_DEPTH]> {
-        None
+        self.stream.lock().unwrap().rec_buffer.dq_frame()
     }
     pub fn free_play_frames(&self) -> usize {
-        0
+        self.stream.lock().unwrap().play_buffer.writeable_count()
     }
 
     pub fn can_play(&self) -> bool {
-        false
+        !self.stream.lock().unwrap().play_buffer.is_empty()
     }
 
     pub fn drain(&mut self) {
+        self.stream.lock().unwrap().drain = true;
     }
 
     pub fn available_rec_frames(&self) -> usize {
-        0
+        self.stream.lock().unwrap().rec_buffer.readable_count()
     }
 
-    pub fn power(&mut self, _state: bool) {
+    pub fn power(&mut self, state: bool) {
+        self.powered_on = state;
+        if !state {
+            self.initialized = false;
+        }
     }
 
     pub fn is_on(&self) -> bool {
-        false
+        self.powered_on
     }
     pub fn is_init(&self) -> bool {
-        true
+        self.initialized
     }
     pub fn is_live(&self) -> bool {
-        false
+        self.live
     }
 
     pub fn get_headset_code(&mut self) -> u8 {
@@ -80,17 +369,146 @@ impl Codec {
     pub fn audio_mixer(&mut self) {
     }
 
-    /// set up the betrusted-side signals
+    /// open the WAV files and start the frame clock
     pub fn audio_i2s_start(&mut self) {
+        {
+            let mut stream = self.stream.lock().unwrap();
+            // the output file is created once, and successive streams are appended to it
+            if stream.wav_out.is_none() {
+                match WavWriter::create(&self.out_path) {
+                    Ok(wav) => {
+                        log::info!("writing audio output to {}", self.out_path);
+                        stream.wav_out = Some(wav);
+                    }
+                    Err(e) => log::error!("couldn't create audio output {}: {:?}", self.out_path, e),
+                }
+            }
+            // the input file is replayed from the start of every stream
+            if let Some(path) = &self.in_path {
+                match WavReader::open(path) {
+                    Ok(wav) => {
+                        log::info!("reading audio input from {}", path);
+                        stream.wav_in = Some(wav);
+                    }
+                    Err(e) => log::error!("couldn't open audio input {}: {}", path, e),
+                }
+            }
+            stream.drain = false;
+        }
+        self.live = true;
+        self.start_clock();
     }
 
     pub fn audio_i2s_stop(&mut self) {
+        self.stop_clock();
+        let mut stream = self.stream.lock().unwrap();
+        log::info!("playback stopped. frames dropped: p{} r{}",
+            stream.play_frames_dropped, stream.rec_frames_dropped);
+        stream.play_frames_dropped = 0;
+        stream.rec_frames_dropped = 0;
+        stream.wav_in = None;
+
+        self.live = false;
+        stream.drain = true;
+        stream.play_buffer.clear();
+        stream.rec_buffer.clear();
     }
 
-    pub fn set_speaker_gain_db(&mut self, _gain_db: f32) {
+    pub fn set_speaker_gain_db(&mut self, gain_db: f32) {
+        self.speaker_gain = gain_db;
     }
 
-    pub fn set_headphone_gain_db(&mut self, _gain_db_l: f32, _gain_db_r: f32) {
+    pub fn set_headphone_gain_db(&mut self, gain_db_l: f32, gain_db_r: f32) {
+        self.headphone_left_gain = gain_db_l;
+        self.headphone_right_gain = gain_db_r;
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("xous-codec-{}-{}.wav", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn frame(sample: u32) -> [u32; FIFO_DEPTH] {
+        [sample; FIFO_DEPTH]
+    }
+
+    #[test]
+    fn frames_are_handed_off() {
+        let in_path = temp_path("handoff-in");
+        let out_path = temp_path("handoff-out");
+        let mut wav = WavWriter::create(&in_path).unwrap();
+        wav.write_frame(&frame(0x0002_0001)).unwrap();
+        wav.write_frame(&frame(0x0004_0003)).unwrap();
+        let mut stream = Stream {
+            play_buffer: FrameRing::new(),
+            play_frames_dropped: 0,
+            rec_buffer: FrameRing::new(),
+            rec_frames_dropped: 0,
+            // draining, so that no AnotherFrame callback is sent
+            drain: true,
+            conn: 0,
+            wav_out: Some(WavWriter::create(&out_path).unwrap()),
+            wav_in: Some(WavReader::open(&in_path).unwrap()),
+        };
+        stream.play_buffer.nq_frame(frame(0x1111_2222)).unwrap();
+        stream.play_buffer.nq_frame(frame(0x3333_4444)).unwrap();
+        for _ in 0..3 {
+            audio_handler(&mut stream);
+        }
+
+        // played frames go out in order, then silence once the play buffer runs dry
+        assert_eq!(stream.play_frames_dropped, 1);
+        let mut played = WavReader::open(&out_path).unwrap();
+        assert_eq!(played.read_frame().unwrap()[..], frame(0x1111_2222)[..]);
+        assert_eq!(played.read_frame().unwrap()[..], frame(0x3333_4444)[..]);
+        assert_eq!(played.read_frame().unwrap()[..], SILENCE[..]);
+        assert!(played.read_frame().is_none());
+
+        // recorded frames come from the input, then silence once it is exhausted
+        assert!(stream.wav_in.is_none());
+        assert_eq!(stream.rec_buffer.dq_frame().unwrap()[..], frame(0x0002_0001)[..]);
+        assert_eq!(stream.rec_buffer.dq_frame().unwrap()[..], frame(0x0004_0003)[..]);
+        assert_eq!(stream.rec_buffer.dq_frame().unwrap()[..], SILENCE[..]);
+        assert!(stream.rec_buffer.dq_frame().is_none());
+        std::fs::remove_file(in_path).ok();
+        std::fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn start_and_stop() {
+        let out_path = temp_path("start-stop");
+        let mut codec = Codec::with_paths(0, out_path.clone(), None);
+        // a full play buffer keeps the clock from asking for more frames while the test runs
+        while codec.nq_play_frame(frame(0x1234_5678)).is_ok() {}
+        let queued = codec.free_play_frames();
+
+        codec.audio_i2s_start();
+        assert!(codec.is_live());
+        assert!(codec.clock.is_some());
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(codec.free_play_frames() > queued, "the clock didn't consume any frames");
+        assert!(codec.available_rec_frames() > 0, "the clock didn't record any frames");
+
+        // suspending stops the clock without ending the stream, and resuming picks it up again
+        codec.suspend();
+        assert!(codec.clock.is_none());
+        assert!(codec.is_live());
+        codec.resume();
+        assert!(codec.clock.is_some());
+
+        codec.audio_i2s_stop();
+        assert!(!codec.is_live());
+        assert!(codec.clock.is_none());
+        assert!(!codec.can_play());
+        assert_eq!(codec.available_rec_frames(), 0);
+        // a stopped stream stays stopped through a resume
+        codec.resume();
+        assert!(codec.clock.is_none());
+        std::fs::remove_file(out_path).ok();
+    }
+}
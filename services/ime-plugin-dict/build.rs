@@ -0,0 +1,91 @@
+// Compresses the word lists in `wordlists/` into the form that is baked into the plugin.
+//
+// `<lang>.txt` holds one word per line, most frequent first. It is sorted and front-coded into
+// `<lang>.words`, where each record is:
+//   - a header byte: the number of leading bytes shared with the previous word in the high nibble,
+//     and the word's frequency class (log2 of its rank in the source list) in the low nibble
+//   - the remaining bytes of the word, terminated by a `\n`
+//
+// `<lang>.bigrams.txt` holds pairs of words from `<lang>.txt`, most frequent first. It becomes
+// `<lang>.bigrams`, a list of (u16 LE, u16 LE) indices into the sorted word list, in the same order.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const LOCALES: [&str; 1] = ["en"];
+
+fn read_list(path: &Path) -> Vec<String> {
+    println!("cargo:rerun-if-changed={}", path.display());
+    fs::read_to_string(path)
+        .expect("couldn't read word list")
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| l.len() > 0 && !l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect()
+}
+
+fn frequency_class(rank: usize) -> u8 {
+    let mut class = 0;
+    while (rank + 1) >> (class + 1) != 0 && class < 15 {
+        class += 1;
+    }
+    class as u8
+}
+
+fn front_code(words: &[(String, u8)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut prev = "";
+    for (word, class) in words.iter() {
+        let mut shared = 0;
+        for (a, b) in prev.bytes().zip(word.bytes()) {
+            if a != b || shared == 15 {
+                break;
+            }
+            shared += 1;
+        }
+        // only split words on a character boundary, so each suffix is valid utf-8 on its own
+        while !word.is_char_boundary(shared) {
+            shared -= 1;
+        }
+        out.push((shared as u8) << 4 | class);
+        out.extend_from_slice(word[shared..].as_bytes());
+        out.push(b'\n');
+        prev = word;
+    }
+    out
+}
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    for lang in LOCALES.iter() {
+        let source = read_list(Path::new(&format!("wordlists/{}.txt", lang)));
+        let mut words: Vec<(String, u8)> = Vec::new();
+        for (rank, word) in source.iter().enumerate() {
+            assert!(!word.contains(char::is_whitespace), "{}: words can't contain whitespace: {}", lang, word);
+            if words.iter().any(|(w, _)| w == word) {
+                println!("cargo:warning={}: ignoring duplicate word {}", lang, word);
+                continue;
+            }
+            words.push((word.to_string(), frequency_class(rank)));
+        }
+        words.sort();
+        assert!(words.len() <= u16::MAX as usize, "{}: too many words", lang);
+        let index: HashMap<&str, u16> = words.iter().enumerate().map(|(i, (w, _))| (w.as_str(), i as u16)).collect();
+        fs::write(Path::new(&out_dir).join(format!("{}.words", lang)), front_code(&words)).unwrap();
+
+        let mut bigrams = Vec::new();
+        for pair in read_list(Path::new(&format!("wordlists/{}.bigrams.txt", lang))) {
+            let pair: Vec<&str> = pair.split_whitespace().collect();
+            assert!(pair.len() == 2, "{}: bigrams must be exactly two words: {:?}", lang, pair);
+            for word in pair.iter() {
+                bigrams.extend_from_slice(
+                    &index.get(word).unwrap_or_else(|| panic!("{}: bigram word {} is not in the word list", lang, word)).to_le_bytes()
+                );
+            }
+        }
+        fs::write(Path::new(&out_dir).join(format!("{}.bigrams", lang)), bigrams).unwrap();
+    }
+}
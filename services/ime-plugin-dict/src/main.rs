@@ -0,0 +1,104 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod model;
+use model::Model;
+mod store;
+use store::LearnedStore;
+
+use ime_plugin_api::*;
+
+use log::{error, info};
+
+use xous_ipc::{String, Buffer};
+use num_traits::FromPrimitive;
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    // any app can select this as the predictor for its context, and the IME front end connects to it
+    // and disconnects from it as contexts come and go, so the number of connections isn't limited
+    let ime_dict_sid = xns.register_name(ime_plugin_dict::SERVER_NAME_IME_PLUGIN_DICT, None).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", ime_dict_sid);
+
+    let mut model = Model::new(xous::LANG);
+    // the PDDB is not necessarily mounted yet; learned words are loaded once it is
+    let mut store = LearnedStore::new(xous::LANG);
+
+    // predictions are made a word at a time: the front end sends the partial word typed since the last
+    // whitespace or punctuation with `Input`, and the finished word with `Picked`. Japanese and Chinese
+    // have no word list yet, so for those the model offers nothing.
+    let mytriggers = PredictionTriggers {
+        newline: false,
+        punctuation: true,
+        whitespace: true,
+    };
+
+    info!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(ime_dict_sid).unwrap();
+        log::trace!("received message {:?}", msg);
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Input) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                store.refresh(&mut model);
+                model.set_input(s.as_str());
+            }
+            Some(Opcode::Picked) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                log::trace!("learning word | {}", s.as_str());
+                store.refresh(&mut model);
+                if model.picked(s.as_str()) {
+                    store.changed(&model);
+                }
+            }
+            Some(Opcode::Prediction) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut prediction: Prediction = buffer.to_original::<Prediction, _>().unwrap();
+                log::trace!("querying prediction index {}", prediction.index);
+                if prediction.index == 0 {
+                    // a new round of predictions; pick up any basis changes since the last one
+                    store.refresh(&mut model);
+                }
+                if let Some(word) = model.prediction(prediction.index as usize) {
+                    prediction.string.clear();
+                    for ch in word.chars() {
+                        if prediction.string.push(ch).is_err() {
+                            break;
+                        }
+                    }
+                    prediction.valid = true;
+                } else {
+                    prediction.valid = false;
+                }
+                log::trace!("returning index {} string {:?}", prediction.index, prediction.string);
+
+                buffer.replace(Return::Prediction(prediction)).expect("couldn't return Prediction");
+            }
+            Some(Opcode::Unpick) => {
+                if model.unpick() {
+                    store.changed(&model);
+                }
+            }
+            Some(Opcode::GetPredictionTriggers) => {
+                xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
+            }
+            Some(Opcode::Quit) => {
+                error!("received quit, goodbye!"); break;
+            }
+            None => {error!("unknown Opcode");}
+        }
+    }
+    store.save(&model);
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(ime_dict_sid).unwrap();
+    xous::destroy_server(ime_dict_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
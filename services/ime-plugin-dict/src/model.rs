@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+
+/// Maximum number of predictions offered for any input.
+const MAX_PREDICTIONS: usize = 16;
+/// Words the user picks that aren't in the word list are remembered, up to this many.
+const MAX_LEARNED_WORDS: usize = 512;
+/// Longest word that will be learned, in bytes.
+const MAX_WORD_BYTES: usize = 32;
+/// How much one pick of a word adds to its unigram score. A word list entry scores between 1.0
+/// (most frequent) and ~0.001.
+const LEARNED_UNIGRAM_WEIGHT: f32 = 0.25;
+/// How much one pick of a word pair adds to its bigram score.
+const LEARNED_BIGRAM_WEIGHT: f32 = 0.5;
+/// How strongly the previous word influences the ranking, relative to plain word frequency.
+const BIGRAM_WEIGHT: f32 = 2.0;
+
+fn class_weight(class: u8) -> f32 {
+    1.0 / (1u32 << class) as f32
+}
+
+/// Returns the built-in word list and word pairs for a locale; see `build.rs` for the format.
+///
+/// Japanese and Chinese are left out: their text is entered as kana/kanji and hanzi converted from a
+/// reading, and a list of romaji or pinyin readings would only ever predict the readings themselves.
+/// Until there is a list of entries keyed by reading, those locales get no predictions at all.
+fn builtin_lists(lang: &str) -> Option<(&'static [u8], &'static [u8])> {
+    match lang {
+        "ja" | "zh" => None,
+        // "en", "en-tts", and anything else written with spaces between words
+        _ => Some((include_bytes!(concat!(env!("OUT_DIR"), "/en.words")), include_bytes!(concat!(env!("OUT_DIR"), "/en.bigrams")))),
+    }
+}
+
+struct Word {
+    text: String,
+    /// lower-cased `text`, for matching against input; empty if this slot is free
+    key: String,
+    /// score from the built-in word list; 0.0 for learned words
+    seed: f32,
+    /// number of times the user picked this word
+    learned: u32,
+}
+impl Word {
+    fn unigram(&self) -> f32 {
+        self.seed + self.learned as f32 * LEARNED_UNIGRAM_WEIGHT
+    }
+}
+
+/// Records what the last pick changed, so it can be undone.
+struct Pick {
+    word: usize,
+    prev: Option<usize>,
+}
+
+/// A word-level bigram model, seeded from a built-in word list and adapted by the words the user picks.
+pub(crate) struct Model {
+    words: Vec<Word>,
+    index: HashMap<String, usize>,
+    seed_bigrams: HashMap<(usize, usize), f32>,
+    learned_bigrams: HashMap<(usize, usize), u32>,
+    /// the last word picked, which predicts the next one
+    prev: Option<usize>,
+    /// `false` for locales without a word list, which get no predictions and learn nothing
+    enabled: bool,
+    /// the partial word being typed
+    input: String,
+    last_pick: Option<Pick>,
+    /// predictions for the current input, most likely first
+    ranked: Vec<usize>,
+}
+
+impl Model {
+    pub(crate) fn new(lang: &str) -> Model {
+        let enabled = builtin_lists(lang).is_some();
+        let (word_list, bigram_list) = builtin_lists(lang).unwrap_or((&[], &[]));
+        let mut words = Vec::new();
+        let mut index = HashMap::new();
+        let mut text: Vec<u8> = Vec::new();
+        let mut records = word_list;
+        while records.len() > 0 {
+            let header = records[0];
+            let end = records[1..].iter().position(|&b| b == b'\n').expect("unterminated word list record") + 1;
+            text.truncate((header >> 4) as usize);
+            text.extend_from_slice(&records[1..end]);
+            records = &records[end + 1..];
+
+            let text = String::from(core::str::from_utf8(&text).expect("word list is not valid utf-8"));
+            index.insert(text.to_lowercase(), words.len());
+            words.push(Word {
+                key: text.to_lowercase(),
+                text,
+                seed: class_weight(header & 0xF),
+                learned: 0,
+            });
+        }
+        let mut seed_bigrams = HashMap::new();
+        for (rank, pair) in bigram_list.chunks_exact(4).enumerate() {
+            let first = u16::from_le_bytes([pair[0], pair[1]]) as usize;
+            let second = u16::from_le_bytes([pair[2], pair[3]]) as usize;
+            // same Zipf-like weighting as the word list
+            let class = (63 - ((rank + 1) as u64).leading_zeros()) as u8;
+            seed_bigrams.insert((first, second), class_weight(class));
+        }
+        if enabled {
+            log::info!("loaded {} words and {} word pairs for {}", words.len(), seed_bigrams.len(), lang);
+        } else {
+            log::info!("no word list for {}, predictions are disabled", lang);
+        }
+        let mut model = Model {
+            enabled,
+            words,
+            index,
+            seed_bigrams,
+            learned_bigrams: HashMap::new(),
+            prev: None,
+            input: String::new(),
+            last_pick: None,
+            ranked: Vec::new(),
+        };
+        model.rank();
+        model
+    }
+
+    fn bigram(&self, prev: usize, word: usize) -> f32 {
+        self.seed_bigrams.get(&(prev, word)).copied().unwrap_or(0.0)
+            + self.learned_bigrams.get(&(prev, word)).copied().unwrap_or(0) as f32 * LEARNED_BIGRAM_WEIGHT
+    }
+
+    fn score(&self, word: usize) -> f32 {
+        let mut score = self.words[word].unigram();
+        if let Some(prev) = self.prev {
+            score += BIGRAM_WEIGHT * self.bigram(prev, word);
+        }
+        score
+    }
+
+    fn sort_by_score(&self, candidates: &mut Vec<usize>) {
+        candidates.sort_by(|&a, &b|
+            self.score(b).partial_cmp(&self.score(a)).unwrap_or(core::cmp::Ordering::Equal)
+        );
+    }
+
+    /// Recomputes the predictions for the current input.
+    fn rank(&mut self) {
+        let mut ranked: Vec<usize>;
+        if self.input.len() == 0 {
+            // next-word prediction: words that have followed the previous word first, then the most common words
+            ranked = Vec::new();
+            if let Some(prev) = self.prev {
+                ranked.extend(self.seed_bigrams.keys().chain(self.learned_bigrams.keys())
+                    .filter(|&&(p, _)| p == prev)
+                    .map(|&(_, w)| w));
+                ranked.sort();
+                ranked.dedup();
+                self.sort_by_score(&mut ranked);
+                ranked.truncate(MAX_PREDICTIONS);
+            }
+            let mut common: Vec<usize> = (0..self.words.len())
+                .filter(|&w| self.words[w].key.len() > 0 && !ranked.contains(&w))
+                .collect();
+            self.sort_by_score(&mut common);
+            ranked.extend(common);
+        } else {
+            // completion of the word being typed
+            let input = self.input.to_lowercase();
+            ranked = (0..self.words.len())
+                .filter(|&w| self.words[w].key.starts_with(&input) && self.words[w].key.len() > input.len())
+                .collect();
+            self.sort_by_score(&mut ranked);
+        }
+        ranked.truncate(MAX_PREDICTIONS);
+        self.ranked = ranked;
+    }
+
+    pub(crate) fn set_input(&mut self, input: &str) {
+        self.input.clear();
+        self.input.push_str(input);
+        self.rank();
+    }
+
+    /// Returns the `index`th most likely prediction. A completion keeps the case of what was typed so far.
+    pub(crate) fn prediction(&self, index: usize) -> Option<String> {
+        self.ranked.get(index).map(|&w| {
+            let mut p = self.input.clone();
+            p.extend(self.words[w].text.chars().skip(self.input.chars().count()));
+            p
+        })
+    }
+
+    /// Finds a slot for a word that isn't in the vocabulary, evicting the least used learned word if needed.
+    fn new_word_slot(&mut self) -> usize {
+        if let Some(free) = self.words.iter().position(|w| w.key.len() == 0) {
+            return free;
+        }
+        let learned = self.words.iter().filter(|w| w.seed == 0.0).count();
+        if learned < MAX_LEARNED_WORDS {
+            self.words.push(Word { text: String::new(), key: String::new(), seed: 0.0, learned: 0 });
+            return self.words.len() - 1;
+        }
+        let mut weakest: Option<usize> = None;
+        for (i, w) in self.words.iter().enumerate() {
+            if w.seed == 0.0 && weakest.map_or(true, |k| w.learned < self.words[k].learned) {
+                weakest = Some(i);
+            }
+        }
+        let weakest = weakest.unwrap();
+        self.forget_word(weakest);
+        weakest
+    }
+
+    fn forget_word(&mut self, word: usize) {
+        log::debug!("forgetting {}", self.words[word].text);
+        self.index.remove(&self.words[word].key);
+        self.learned_bigrams.retain(|&(p, w), _| p != word && w != word);
+        if self.prev == Some(word) {
+            self.prev = None;
+        }
+        self.words[word] = Word { text: String::new(), key: String::new(), seed: 0.0, learned: 0 };
+    }
+
+    fn lookup_or_add(&mut self, text: &str) -> usize {
+        let key = text.to_lowercase();
+        if let Some(&word) = self.index.get(&key) {
+            return word;
+        }
+        let word = self.new_word_slot();
+        self.words[word] = Word { text: text.to_string(), key: key.clone(), seed: 0.0, learned: 0 };
+        self.index.insert(key, word);
+        word
+    }
+
+    /// Learns from a word the user entered, and starts predicting the next word.
+    /// Returns `true` if the learned state changed.
+    pub(crate) fn picked(&mut self, text: &str) -> bool {
+        self.input.clear();
+        let learnable = self.enabled
+            && text.len() <= MAX_WORD_BYTES
+            && text.chars().any(|c| c.is_alphabetic())
+            && !text.contains(char::is_whitespace);
+        if !learnable {
+            // numbers, symbols and the like break the chain of words, but aren't worth learning
+            self.prev = None;
+            self.last_pick = None;
+            self.rank();
+            return false;
+        }
+        let word = self.lookup_or_add(text);
+        self.words[word].learned = self.words[word].learned.saturating_add(1);
+        if let Some(prev) = self.prev {
+            let count = self.learned_bigrams.entry((prev, word)).or_insert(0);
+            *count = count.saturating_add(1);
+        }
+        self.last_pick = Some(Pick { word, prev: self.prev });
+        self.prev = Some(word);
+        self.rank();
+        true
+    }
+
+    /// Undoes the last pick. Repeated calls do nothing. Returns `true` if the learned state changed.
+    pub(crate) fn unpick(&mut self) -> bool {
+        let pick = match self.last_pick.take() {
+            Some(pick) => pick,
+            None => return false,
+        };
+        self.words[pick.word].learned = self.words[pick.word].learned.saturating_sub(1);
+        if let Some(prev) = pick.prev {
+            if let Some(count) = self.learned_bigrams.get_mut(&(prev, pick.word)) {
+                *count -= 1;
+                if *count == 0 {
+                    self.learned_bigrams.remove(&(prev, pick.word));
+                }
+            }
+        }
+        self.prev = pick.prev;
+        if self.words[pick.word].seed == 0.0 && self.words[pick.word].learned == 0 {
+            self.forget_word(pick.word);
+        }
+        self.rank();
+        true
+    }
+
+    /// Serializes what was learned from the user. Each line is either `u <count> <word>` or
+    /// `b <count> <word> <next word>`.
+    pub(crate) fn learned_to_bytes(&self) -> Vec<u8> {
+        let mut out = String::new();
+        for w in self.words.iter().filter(|w| w.learned > 0) {
+            out.push_str(&format!("u {} {}\n", w.learned, w.text));
+        }
+        for (&(p, w), &count) in self.learned_bigrams.iter() {
+            out.push_str(&format!("b {} {} {}\n", count, self.words[p].text, self.words[w].text));
+        }
+        out.into_bytes()
+    }
+
+    /// Replaces everything learned from the user with `data`, as produced by `learned_to_bytes()`.
+    pub(crate) fn load_learned(&mut self, data: &[u8]) {
+        for w in 0..self.words.len() {
+            if self.words[w].seed == 0.0 && self.words[w].key.len() > 0 {
+                self.forget_word(w);
+            }
+            self.words[w].learned = 0;
+        }
+        self.learned_bigrams.clear();
+        self.prev = None;
+        self.last_pick = None;
+
+        if !self.enabled {
+            self.rank();
+            return;
+        }
+        let data = String::from_utf8_lossy(data);
+        for line in data.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.as_slice() {
+                ["u", count, text] => {
+                    if let Ok(count) = count.parse::<u32>() {
+                        let word = self.lookup_or_add(text);
+                        self.words[word].learned = count;
+                    }
+                }
+                ["b", count, first, second] => {
+                    if let Ok(count) = count.parse::<u32>() {
+                        let first = self.lookup_or_add(first);
+                        let second = self.lookup_or_add(second);
+                        self.learned_bigrams.insert((first, second), count);
+                    }
+                }
+                _ => log::warn!("ignoring malformed learned word record: {}", line),
+            }
+        }
+        self.rank();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn predictions(model: &Model) -> Vec<String> {
+        (0..MAX_PREDICTIONS).filter_map(|i| model.prediction(i)).collect()
+    }
+
+    #[test]
+    fn completion_keeps_typed_case() {
+        let mut model = Model::new("en");
+        model.set_input("Th");
+        let found = predictions(&model);
+        assert!(found.len() > 0);
+        assert!(found.iter().all(|p| p.starts_with("Th") && p.len() > 2), "{:?}", found);
+    }
+
+    #[test]
+    fn next_word_follows_bigrams() {
+        let mut model = Model::new("en");
+        assert!(model.picked("of"));
+        assert_eq!(model.prediction(0).as_deref(), Some("the"));
+    }
+
+    #[test]
+    fn learns_new_words_and_unpicks() {
+        let mut model = Model::new("en");
+        assert!(model.picked("zyzzyva"));
+        model.set_input("zyz");
+        assert_eq!(predictions(&model), vec!["zyzzyva".to_string()]);
+        assert!(model.unpick());
+        assert!(!model.unpick());
+        model.set_input("zyz");
+        assert!(predictions(&model).is_empty());
+        // numbers aren't learned
+        assert!(!model.picked("12345"));
+    }
+
+    #[test]
+    fn learned_state_round_trips() {
+        let mut model = Model::new("en");
+        for word in ["the", "zyzzyva", "the", "zyzzyva"].iter() {
+            model.picked(word);
+        }
+        let mut saved: Vec<String> = String::from_utf8(model.learned_to_bytes()).unwrap().lines().map(String::from).collect();
+        saved.sort();
+        assert_eq!(saved, vec!["b 1 zyzzyva the", "b 2 the zyzzyva", "u 2 the", "u 2 zyzzyva"]);
+
+        let mut restored = Model::new("en");
+        restored.load_learned(&model.learned_to_bytes());
+        let mut reloaded: Vec<String> = String::from_utf8(restored.learned_to_bytes()).unwrap().lines().map(String::from).collect();
+        reloaded.sort();
+        assert_eq!(reloaded, saved);
+        restored.set_input("zyz");
+        assert_eq!(restored.prediction(0).as_deref(), Some("zyzzyva"));
+    }
+
+    #[test]
+    fn no_predictions_without_a_word_list() {
+        for lang in ["ja", "zh"].iter() {
+            let mut model = Model::new(lang);
+            assert!(model.prediction(0).is_none());
+            assert!(!model.picked("arigatou"));
+            model.set_input("ari");
+            assert!(model.prediction(0).is_none());
+            model.load_learned(b"u 3 arigatou\n");
+            assert!(model.learned_to_bytes().is_empty());
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::model::Model;
+use std::io::Read;
+
+/// Learned words are kept in this dictionary, one key per locale. Writes go to the most recently
+/// unlocked basis, so words learned while a secondary basis is open disappear when it is locked.
+const LEARNED_DICT: &'static str = "ime.dict";
+/// Learned words are written back after this many picks, to spare the flash.
+const SAVE_INTERVAL: u32 = 8;
+
+/// Persists what the `Model` learns in the PDDB.
+pub(crate) struct LearnedStore {
+    pddb: pddb::Pddb,
+    key: String,
+    mounted: bool,
+    /// the open bases that the model's learned state was loaded from; a change triggers a reload
+    bases: Vec<String>,
+    /// picks since the last save
+    unsaved: u32,
+}
+
+impl LearnedStore {
+    pub(crate) fn new(lang: &str) -> LearnedStore {
+        LearnedStore {
+            pddb: pddb::Pddb::new(),
+            key: lang.to_string(),
+            mounted: false,
+            bases: Vec::new(),
+            unsaved: 0,
+        }
+    }
+
+    /// Reloads the learned words if the PDDB was mounted, or if a basis was unlocked or locked, since the
+    /// last call. Unsaved picks are dropped when this happens, as they may belong to a basis that is now locked.
+    pub(crate) fn refresh(&mut self, model: &mut Model) {
+        if !self.mounted {
+            if !self.pddb.is_mounted() {
+                return;
+            }
+            self.mounted = true;
+        }
+        let bases = self.pddb.list_basis();
+        if bases == self.bases {
+            return;
+        }
+        log::debug!("basis set changed, reloading learned words: {:?}", bases);
+        self.bases = bases;
+        self.unsaved = 0;
+        let mut data = Vec::new();
+        match self.pddb.get(LEARNED_DICT, &self.key, None, false, false, None, None::<fn()>) {
+            Ok(mut key) => {
+                if let Err(e) = key.read_to_end(&mut data) {
+                    log::warn!("couldn't read learned words: {:?}", e);
+                    data.clear();
+                }
+            }
+            Err(_) => (), // nothing learned in any open basis yet
+        }
+        model.load_learned(&data);
+    }
+
+    /// Notes that `model` learned something, and saves it if enough has changed.
+    pub(crate) fn changed(&mut self, model: &Model) {
+        self.unsaved += 1;
+        if self.unsaved >= SAVE_INTERVAL {
+            self.save(model);
+        }
+    }
+
+    pub(crate) fn save(&mut self, model: &Model) {
+        if !self.mounted || self.unsaved == 0 {
+            return;
+        }
+        // a transaction replaces the whole record, so a shorter record doesn't leave stale data behind
+        let result = self.pddb.begin(None)
+            .and_then(|mut txn| {
+                txn.write(LEARNED_DICT, &self.key, &model.learned_to_bytes())?;
+                txn.commit()
+            })
+            .and_then(|_| self.pddb.sync());
+        match result {
+            Ok(()) => self.unsaved = 0,
+            Err(e) => log::error!("couldn't save learned words: {:?}", e),
+        }
+    }
+}
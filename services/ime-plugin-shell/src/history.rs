@@ -0,0 +1,418 @@
+use std::io::{Read, Write};
+
+/// Shell history lives in this dictionary. Entries are written to the most recently unlocked basis, and the
+/// PDDB only shows keys from bases that are currently unlocked, so locking a secondary basis hides
+/// the history that was recorded while it was open.
+const HISTORY_DICT: &'static str = "ime.shellhistory";
+/// Lines are truncated to this many bytes, to bound the size of a record.
+const MAX_LINE_BYTES: usize = 256;
+/// Upper bound on the number of lines remembered. When full, the lowest-ranked line is forgotten.
+const MAX_ENTRIES: usize = 128;
+/// The weight of a pick halves after this many newer picks.
+const RECENCY_HALF_LIFE: f32 = 16.0;
+/// Record format: use count (u32 LE), then the sequence number of the last use (u64 LE), then the
+/// line itself. The line is kept in the record rather than in the key name, so that it can be longer
+/// than a key name and no line is ever shown in a key listing.
+const USAGE_LEN: usize = 4 + 8;
+
+/// Returns the name of the key that holds `line`. The name is a hash of the line, so that a line
+/// recorded in several bases has the same key in each of them.
+fn key_name(line: &str) -> String {
+    // 64-bit FNV-1a, which unlike the std hashers is guaranteed to stay the same across builds
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in line.as_bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Usage {
+    count: u32,
+    last_used: u64,
+}
+impl Usage {
+    /// Decodes a record, returning the usage and the line it holds.
+    fn from_record(record: &[u8]) -> Option<(Usage, &str)> {
+        if record.len() <= USAGE_LEN {
+            return None;
+        }
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&record[..4]);
+        let mut last_used = [0u8; 8];
+        last_used.copy_from_slice(&record[4..USAGE_LEN]);
+        let line = std::str::from_utf8(&record[USAGE_LEN..]).ok()?;
+        Some((
+            Usage {
+                count: u32::from_le_bytes(count),
+                last_used: u64::from_le_bytes(last_used),
+            },
+            line,
+        ))
+    }
+    fn to_record(&self, line: &str) -> Vec<u8> {
+        let mut record = Vec::with_capacity(USAGE_LEN + line.len());
+        record.extend_from_slice(&self.count.to_le_bytes());
+        record.extend_from_slice(&self.last_used.to_le_bytes());
+        record.extend_from_slice(line.as_bytes());
+        record
+    }
+}
+
+struct Entry {
+    line: String,
+    usage: Usage,
+}
+
+/// The remembered lines and their ranking, apart from the PDDB records they are kept in.
+struct Lines {
+    entries: Vec<Entry>,
+    /// sequence number for the next pick, used to measure recency
+    seq: u64,
+    /// the current input, against which predictions are prefix-matched
+    input: String,
+    /// predictions for the current input, most likely first
+    ranked: Vec<usize>,
+}
+
+impl Lines {
+    fn new() -> Lines {
+        Lines {
+            entries: Vec::new(),
+            seq: 0,
+            input: String::new(),
+            ranked: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.seq = 0;
+        self.ranked.clear();
+    }
+
+    /// Adds a line read back from the PDDB. A line that is already known (it was recorded in more
+    /// than one basis) keeps its most recent usage.
+    fn load(&mut self, line: &str, usage: Usage) {
+        self.seq = self.seq.max(usage.last_used + 1);
+        match self.entries.iter_mut().find(|e| e.line == line) {
+            Some(entry) => {
+                if usage.last_used > entry.usage.last_used {
+                    entry.usage = usage;
+                }
+            }
+            None => self.entries.push(Entry { line: line.to_string(), usage }),
+        }
+    }
+
+    fn score(&self, usage: &Usage) -> f32 {
+        let age = self.seq.saturating_sub(usage.last_used + 1) as f32;
+        usage.count as f32 * (0.5f32).powf(age / RECENCY_HALF_LIFE)
+    }
+
+    /// Recomputes the predictions for the current input.
+    fn rank(&mut self) {
+        let mut ranked: Vec<usize> = self.entries.iter().enumerate()
+            .filter(|(_, e)| e.line.starts_with(&self.input) && e.line != self.input)
+            .map(|(i, _)| i)
+            .collect();
+        ranked.sort_by(|&a, &b| {
+            let (ea, eb) = (&self.entries[a], &self.entries[b]);
+            self.score(&eb.usage).partial_cmp(&self.score(&ea.usage)).unwrap_or(core::cmp::Ordering::Equal)
+                .then(eb.usage.last_used.cmp(&ea.usage.last_used))
+        });
+        self.ranked = ranked;
+    }
+
+    fn set_input(&mut self, input: &str) {
+        self.input.clear();
+        self.input.push_str(input);
+        self.rank();
+    }
+
+    fn prediction(&self, index: usize) -> Option<&str> {
+        self.ranked.get(index).map(|&i| self.entries[i].line.as_str())
+    }
+
+    /// Counts a pick of `line`. Returns its usage before the pick (`None` if it was new), its usage
+    /// now, and the line that was forgotten to make room for it, if any.
+    fn pick(&mut self, line: &str) -> (Option<Usage>, Usage, Option<String>) {
+        let usage = Usage { count: 1, last_used: self.seq };
+        self.seq += 1;
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.line == line) {
+            let previous = entry.usage;
+            entry.usage = Usage { count: previous.count.saturating_add(1), last_used: usage.last_used };
+            return (Some(previous), entry.usage, None);
+        }
+        let mut evicted = None;
+        if self.entries.len() >= MAX_ENTRIES {
+            let mut weakest = 0;
+            for (i, e) in self.entries.iter().enumerate() {
+                if self.score(&e.usage) < self.score(&self.entries[weakest].usage) {
+                    weakest = i;
+                }
+            }
+            evicted = Some(self.entries.remove(weakest).line);
+        }
+        self.entries.push(Entry { line: line.to_string(), usage });
+        (None, usage, evicted)
+    }
+
+    /// Puts `line` back the way it was before a pick: `previous` is what `pick()` returned for it.
+    fn unpick(&mut self, line: &str, previous: Option<Usage>) {
+        match previous {
+            Some(usage) => {
+                if let Some(entry) = self.entries.iter_mut().find(|e| e.line == line) {
+                    entry.usage = usage;
+                }
+            }
+            None => self.entries.retain(|e| e.line != line),
+        }
+    }
+}
+
+/// Predicts shell input from previously entered lines, ranked by how often and how recently
+/// they were entered.
+pub(crate) struct ShellHistory {
+    pddb: pddb::Pddb,
+    mounted: bool,
+    /// the open bases that `lines` was loaded from; a change triggers a reload
+    bases: Vec<String>,
+    /// the most recently opened basis, where records are written
+    latest: Option<String>,
+    lines: Lines,
+    /// lines picked before the PDDB was mounted; they are committed once it is
+    pending: Vec<String>,
+    /// the line touched by the last pick, and its usage before the pick (`None` if it was new)
+    last_pick: Option<(String, Option<Usage>)>,
+}
+
+impl ShellHistory {
+    pub(crate) fn new() -> ShellHistory {
+        ShellHistory {
+            pddb: pddb::Pddb::new(),
+            mounted: false,
+            bases: Vec::new(),
+            latest: None,
+            lines: Lines::new(),
+            pending: Vec::new(),
+            last_pick: None,
+        }
+    }
+
+    /// Loads the history once the PDDB is mounted. Until then, this costs a message to the PDDB per call;
+    /// afterwards, nothing.
+    pub(crate) fn check_mount(&mut self) {
+        if !self.mounted {
+            self.refresh();
+        }
+    }
+
+    /// Reloads the history if the PDDB was mounted, or if a basis was unlocked or locked, since the last call.
+    /// Bases are rarely locked or unlocked, and mostly from the shell itself, so this only needs to be called
+    /// once per entered line.
+    pub(crate) fn refresh(&mut self) {
+        if !self.mounted {
+            if !self.pddb.is_mounted() {
+                return;
+            }
+            self.mounted = true;
+        }
+        let bases = self.pddb.list_basis();
+        if bases != self.bases {
+            log::debug!("basis set changed, reloading history: {:?}", bases);
+            self.bases = bases;
+            self.latest = self.pddb.latest_basis();
+            self.load();
+            for line in core::mem::replace(&mut self.pending, Vec::new()) {
+                self.picked(&line);
+            }
+            self.last_pick = None;
+            self.lines.rank();
+        }
+    }
+
+    fn load(&mut self) {
+        self.lines.clear();
+        for basis in self.bases.iter() {
+            let keys = match self.pddb.list_keys(HISTORY_DICT, Some(basis)) {
+                Ok(keys) => keys,
+                Err(_) => continue, // no history recorded in this basis yet
+            };
+            for name in keys {
+                let mut record = Vec::new();
+                match self.pddb.get(HISTORY_DICT, &name, Some(basis), false, false, None, None::<fn()>) {
+                    Ok(mut key) => match key.read_to_end(&mut record) {
+                        Ok(_) => match Usage::from_record(&record) {
+                            Some((usage, line)) => self.lines.load(line, usage),
+                            None => log::warn!("skipping corrupt history record {}", name),
+                        },
+                        Err(e) => log::warn!("couldn't read history record {}: {:?}", name, e),
+                    },
+                    Err(e) => log::warn!("couldn't open history record {}: {:?}", name, e),
+                }
+            }
+        }
+        log::debug!("loaded {} history entries", self.lines.entries.len());
+    }
+
+    // The PDDB syncs after every write, so records don't need to be flushed here.
+    fn store(&mut self, line: &str, usage: Usage) {
+        let record = usage.to_record(line);
+        match self.pddb.get(HISTORY_DICT, &key_name(line), self.latest.as_deref(), true, true, Some(record.len()), None::<fn()>) {
+            Ok(mut key) => {
+                if let Err(e) = key.write_all(&record) {
+                    log::error!("couldn't write history record: {:?}", e);
+                }
+            }
+            Err(e) => log::error!("couldn't create history record: {:?}", e),
+        }
+    }
+
+    /// Deletes `line` from every open basis, as it may have been recorded in more than one.
+    fn forget(&mut self, line: &str) {
+        let name = key_name(line);
+        for basis in self.bases.iter() {
+            match self.pddb.delete_key(HISTORY_DICT, &name, Some(basis)) {
+                Ok(()) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => log::warn!("couldn't delete history record from {}: {:?}", basis, e),
+            }
+        }
+    }
+
+    pub(crate) fn set_input(&mut self, input: &str) {
+        self.lines.set_input(input);
+    }
+
+    /// Returns the `index`th most likely completion of the current input.
+    pub(crate) fn prediction(&self, index: usize) -> Option<&str> {
+        self.lines.prediction(index)
+    }
+
+    /// Learns from a line the user entered. This also ends the current input.
+    pub(crate) fn picked(&mut self, line: &str) {
+        let mut end = line.len().min(MAX_LINE_BYTES);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        let line = line[..end].trim_end();
+        if line.len() == 0 {
+            return;
+        }
+        if !self.mounted {
+            self.pending.push(line.to_string());
+            return;
+        }
+        let (previous, usage, evicted) = self.lines.pick(line);
+        if let Some(evicted) = evicted {
+            log::debug!("history full, forgetting {}", evicted);
+            self.forget(&evicted);
+        }
+        self.last_pick = Some((line.to_string(), previous));
+        self.store(line, usage);
+        self.set_input("");
+    }
+
+    /// Undoes the effect of the last pick. Repeated calls do nothing.
+    pub(crate) fn unpick(&mut self) {
+        if !self.mounted {
+            self.pending.pop();
+            return;
+        }
+        if let Some((line, previous)) = self.last_pick.take() {
+            self.lines.unpick(&line, previous);
+            match previous {
+                Some(usage) => self.store(&line, usage),
+                None => self.forget(&line),
+            }
+            self.lines.rank();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(lines: &mut Lines, picks: &[&str]) {
+        for line in picks {
+            lines.pick(line);
+        }
+    }
+
+    fn predictions(lines: &mut Lines, input: &str) -> Vec<String> {
+        lines.set_input(input);
+        let mut predictions = Vec::new();
+        while let Some(line) = lines.prediction(predictions.len()) {
+            predictions.push(line.to_string());
+        }
+        predictions
+    }
+
+    #[test]
+    fn frequent_lines_rank_first() {
+        let mut lines = Lines::new();
+        picks(&mut lines, &["net ping", "net wifi", "net ping", "net ping", "ver"]);
+        assert_eq!(predictions(&mut lines, "net"), ["net ping", "net wifi"]);
+        // the input itself is not a prediction
+        assert_eq!(predictions(&mut lines, "net ping"), Vec::<String>::new());
+        assert_eq!(predictions(&mut lines, ""), ["net ping", "ver", "net wifi"]);
+    }
+
+    #[test]
+    fn old_picks_fade() {
+        let mut lines = Lines::new();
+        picks(&mut lines, &["pddb list", "pddb list", "pddb list"]);
+        for _ in 0..(RECENCY_HALF_LIFE as usize * 4) {
+            lines.pick("ver");
+        }
+        lines.pick("pddb basis");
+        // three picks long ago weigh less than one just now
+        assert_eq!(predictions(&mut lines, "pddb"), ["pddb basis", "pddb list"]);
+    }
+
+    #[test]
+    fn weakest_line_is_evicted() {
+        let mut lines = Lines::new();
+        lines.pick("rarely");
+        for i in 1..MAX_ENTRIES {
+            let line = format!("line {}", i);
+            picks(&mut lines, &[&line, &line]);
+        }
+        assert_eq!(lines.entries.len(), MAX_ENTRIES);
+        let (previous, usage, evicted) = lines.pick("new");
+        assert_eq!(previous, None);
+        assert_eq!(usage.count, 1);
+        assert_eq!(evicted.as_deref(), Some("rarely"));
+        assert_eq!(lines.entries.len(), MAX_ENTRIES);
+        // picking a known line evicts nothing
+        assert_eq!(lines.pick("new").2, None);
+    }
+
+    #[test]
+    fn unpick_restores_usage() {
+        let mut lines = Lines::new();
+        lines.pick("ver");
+        let (previous, _, _) = lines.pick("ver");
+        lines.unpick("ver", previous);
+        assert_eq!(lines.entries[0].usage.count, 1);
+        let (previous, _, _) = lines.pick("echo");
+        lines.unpick("echo", previous);
+        assert_eq!(predictions(&mut lines, ""), ["ver"]);
+    }
+
+    #[test]
+    fn records_hold_the_line() {
+        let usage = Usage { count: 3, last_used: 42 };
+        let line = "a line far too long to be a key name, with ünïcode in it, and then some more words";
+        let record = usage.to_record(line);
+        assert_eq!(Usage::from_record(&record), Some((usage, line)));
+        assert_eq!(Usage::from_record(&record[..USAGE_LEN]), None);
+        // names are fixed-length and don't give the line away
+        assert_eq!(key_name(line).len(), 16);
+        assert_eq!(key_name(line), key_name(line));
+        assert_ne!(key_name(line), key_name("ver"));
+    }
+}
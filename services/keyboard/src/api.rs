@@ -1,4 +1,6 @@
 pub const SERVER_NAME_KBD: &str      = "_Matrix keyboard driver_";
+/// Servers that want the messages of `BindingAction::Message` bindings hook them here.
+pub(crate) const SERVER_NAME_KBD_HOOKS: &str = "_Key binding hooks_";
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct ScanCode {
@@ -19,6 +21,9 @@ pub enum KeyMap {
     Qwertz,
     Dvorak,
     Braille,
+    Colemak,
+    /// a user-defined layout, see `Keyboard::set_custom_keymap()`
+    Custom,
     Undefined,
 }
 impl From<usize> for KeyMap {
@@ -29,6 +34,8 @@ impl From<usize> for KeyMap {
             2 => KeyMap::Qwertz,
             3 => KeyMap::Dvorak,
             4 => KeyMap::Braille,
+            5 => KeyMap::Colemak,
+            6 => KeyMap::Custom,
             _ => KeyMap::Qwerty,
         }
     }
@@ -36,12 +43,14 @@ impl From<usize> for KeyMap {
 impl Into<usize> for KeyMap {
     fn into(self) -> usize {
         match self {
-            // note: these indicese correspond to the position on the keyboard menu
+            // note: these codes are saved in the boot setting in FLASH, so existing codes can't be changed
             KeyMap::Qwerty => 0,
             KeyMap::Azerty => 1,
             KeyMap::Qwertz => 2,
             KeyMap::Dvorak => 3,
             KeyMap::Braille => 4,
+            KeyMap::Colemak => 5,
+            KeyMap::Custom => 6,
             KeyMap::Undefined => 255,
         }
     }
@@ -53,6 +62,15 @@ pub(crate) enum Opcode {
     SelectKeyMap, //(KeyMap),
     GetKeyMap,
 
+    /// set the layout used by `KeyMap::Custom`, as a `String::<4000>` definition
+    SetCustomKeyMap,
+
+    /// add a `KeyBinding`, replacing any binding with the same trigger
+    RegisterBinding,
+
+    /// remove all the `KeyBinding`s
+    ClearBindings,
+
     /// request for ScanCodes
     RegisterListener,
 
@@ -78,6 +96,57 @@ pub(crate) enum Opcode {
     SuspendResume,
 }
 
+/// Most keys that can make up a chord.
+pub const MAX_CHORD_KEYS: usize = 4;
+/// Most characters that a text binding can type.
+pub const MAX_BINDING_TEXT: usize = 512;
+
+/// What has to be pressed to set off a `KeyBinding`. Keys are given as (row, col) positions in the
+/// scan matrix, as used by the layouts in `mappings`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum BindingTrigger {
+    /// all of these keys held down at once; unused entries are (255, 255)
+    Chord([(u8, u8); MAX_CHORD_KEYS]),
+    /// this key held down past the hold delay, in place of its hold character
+    LongPress((u8, u8)),
+}
+
+/// What a `KeyBinding` does when it's set off.
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum BindingAction {
+    /// type out the text, as if it were keyed in
+    Text(xous_ipc::String::<MAX_BINDING_TEXT>),
+    /// send a non-blocking scalar message to the named server, once it has hooked its binding messages
+    /// with `hook_binding_messages()`
+    Message {
+        server: xous_ipc::String::<64>,
+        opcode: u32,
+        args: [u32; 4],
+    },
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct KeyBinding {
+    pub trigger: BindingTrigger,
+    pub action: BindingAction,
+}
+
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum HookOpcode {
+    /// deliver the binding messages of a server to a SID, given in a `BindingHook`
+    Hook,
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct BindingHook {
+    /// a server name the caller registered with the name server
+    pub server: xous_ipc::String::<64>,
+    /// the SID that binding messages for `server` are sent to
+    pub sid: [u32; 4],
+    /// filled in by the keyboard: whether the hook was accepted
+    pub ok: bool,
+}
+
 // this structure is used to register a keyboard listener. Currently, we only accept
 // one trusted listener (enforced by name server and structurally in the code),
 // which is the GAM.
@@ -0,0 +1,281 @@
+use crate::api::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use xous::CID;
+
+/// Most bindings that can be registered at once.
+const MAX_BINDINGS: usize = 32;
+
+/// What the bindings made of a batch of key events.
+#[derive(Default)]
+pub(crate) struct BindingEvents {
+    /// keyups of keys that set off a binding: these shouldn't type anything
+    pub swallowed: Vec<RowCol>,
+    /// the actions of the bindings that were set off
+    pub actions: Vec<BindingAction>,
+    /// a key that belongs to a binding went down, so it shouldn't start repeating
+    pub cancel_repeat: bool,
+}
+
+/// Matches key events against the registered `KeyBinding`s.
+pub(crate) struct Bindings {
+    bindings: Vec<KeyBinding>,
+    /// the keys that are down, and when they went down
+    down: HashMap<RowCol, u64>,
+    /// keys of a chord that was set off, whose keyups have yet to be swallowed
+    fired: HashSet<RowCol>,
+    /// connections to the SIDs that servers hooked for their `BindingAction::Message`s, by server name
+    hooks: Arc<Mutex<HashMap<std::string::String, CID>>>,
+}
+
+impl Bindings {
+    pub(crate) fn new() -> Bindings {
+        Bindings {
+            bindings: Vec::new(),
+            down: HashMap::new(),
+            fired: HashSet::new(),
+            hooks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The hooks, for the thread that takes them.
+    pub(crate) fn hooks(&self) -> Arc<Mutex<HashMap<std::string::String, CID>>> {
+        self.hooks.clone()
+    }
+
+    pub(crate) fn register(&mut self, binding: KeyBinding) {
+        if let Some(existing) = self.bindings.iter_mut().find(|b| b.trigger == binding.trigger) {
+            *existing = binding;
+        } else if self.bindings.len() < MAX_BINDINGS {
+            self.bindings.push(binding);
+        } else {
+            log::warn!("too many key bindings, ignoring {:?}", binding.trigger);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.bindings.clear();
+        self.fired.clear();
+    }
+
+    /// Forgets the keys that are down, for when the key state is thrown away on resume.
+    pub(crate) fn reset(&mut self) {
+        self.down.clear();
+        self.fired.clear();
+    }
+
+    /// `now` and `hold_delay` are in ms. A chord is set off when its keys, and no others, are down; a
+    /// long press when its key comes up after being down for at least `hold_delay`.
+    pub(crate) fn track(&mut self, krs: &KeyRawStates, now: u64, hold_delay: u64) -> BindingEvents {
+        let mut events = BindingEvents::default();
+        for &rc in krs.keydowns.iter() {
+            self.down.insert(rc, now);
+        }
+        if !krs.keydowns.is_empty() {
+            for binding in self.bindings.iter() {
+                match binding.trigger {
+                    BindingTrigger::Chord(keys) => {
+                        let keys: Vec<RowCol> = keys.iter()
+                            .filter(|&&(r, c)| r != 255 && c != 255)
+                            .map(|&(r, c)| RowCol { r, c })
+                            .collect();
+                        if keys.len() == self.down.len() && keys.iter().all(|rc| self.down.contains_key(rc)) {
+                            log::debug!("chord binding {:?} set off", keys);
+                            self.fired.extend(keys);
+                            events.actions.push(binding.action);
+                            events.cancel_repeat = true;
+                        }
+                    }
+                    BindingTrigger::LongPress((r, c)) => {
+                        if krs.keydowns.contains(&RowCol { r, c }) {
+                            events.cancel_repeat = true;
+                        }
+                    }
+                }
+            }
+        }
+        for &rc in krs.keyups.iter() {
+            let pressed = self.down.remove(&rc);
+            if self.fired.remove(&rc) {
+                events.swallowed.push(rc);
+                continue;
+            }
+            if let Some(pressed) = pressed {
+                if now.saturating_sub(pressed) >= hold_delay {
+                    if let Some(binding) = self.bindings.iter().find(|b| b.trigger == BindingTrigger::LongPress((rc.r, rc.c))) {
+                        log::debug!("long press binding {:?} set off", rc);
+                        events.swallowed.push(rc);
+                        events.actions.push(binding.action);
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    /// Sends the scalar message of a `BindingAction::Message` to the SID `server` hooked, if it did.
+    pub(crate) fn send_message(&self, server: &str, opcode: u32, args: &[u32; 4]) {
+        let cid = match self.hooks.lock().unwrap().get(server) {
+            Some(&cid) => cid,
+            None => {
+                log::warn!("key binding for {}, which hasn't hooked its messages", server);
+                return;
+            }
+        };
+        xous::send_message(cid,
+            xous::Message::new_scalar(opcode as usize,
+                args[0] as usize, args[1] as usize, args[2] as usize, args[3] as usize)
+        ).map(|_| ()).unwrap_or_else(|e| log::warn!("key binding couldn't message {}: {:?}", server, e));
+    }
+}
+
+/// Sends the binding messages for `server` to `sid` from now on, connecting to it with `connect`. The
+/// caller has checked that the hooking process registered `server`. Returns the connection of the hook
+/// that was replaced, if nothing else uses it any more.
+pub(crate) fn hook<F>(hooks: &mut HashMap<std::string::String, CID>, server: &str, sid: xous::SID, connect: F) -> Result<Option<CID>, xous::Error>
+where
+    F: FnOnce(xous::SID) -> Result<CID, xous::Error>,
+{
+    let cid = connect(sid)?;
+    match hooks.insert(std::string::String::from(server), cid) {
+        Some(old) if old != cid && !hooks.values().any(|&c| c == old) => Ok(Some(old)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLD_DELAY: u64 = 500;
+
+    fn keys(keys: &[(u8, u8)]) -> Vec<RowCol> {
+        keys.iter().map(|&(r, c)| RowCol::new(r, c)).collect()
+    }
+
+    fn track(bindings: &mut Bindings, down: &[(u8, u8)], up: &[(u8, u8)], now: u64) -> BindingEvents {
+        let mut krs = KeyRawStates::new();
+        krs.keydowns = keys(down);
+        krs.keyups = keys(up);
+        bindings.track(&krs, now, HOLD_DELAY)
+    }
+
+    fn texts(events: &BindingEvents) -> Vec<&str> {
+        events.actions.iter().map(|action| match action {
+            BindingAction::Text(text) => text.as_str().unwrap(),
+            BindingAction::Message { .. } => panic!("unexpected message action"),
+        }).collect()
+    }
+
+    fn text(text: &str) -> BindingAction {
+        BindingAction::Text(xous_ipc::String::<MAX_BINDING_TEXT>::from_str(text))
+    }
+
+    /// a chord of (1,0) and (1,1), and a long press of (2,0)
+    fn bindings() -> Bindings {
+        let mut bindings = Bindings::new();
+        bindings.register(KeyBinding {
+            trigger: BindingTrigger::Chord([(1, 0), (1, 1), (255, 255), (255, 255)]),
+            action: text("chord"),
+        });
+        bindings.register(KeyBinding {
+            trigger: BindingTrigger::LongPress((2, 0)),
+            action: text("long"),
+        });
+        bindings
+    }
+
+    #[test]
+    fn chord_fires_once_all_its_keys_are_down() {
+        let mut bindings = bindings();
+        let events = track(&mut bindings, &[(1, 0)], &[], 0);
+        assert!(events.actions.is_empty());
+        assert!(!events.cancel_repeat);
+
+        let events = track(&mut bindings, &[(1, 1)], &[], 10);
+        assert_eq!(texts(&events), ["chord"]);
+        assert!(events.cancel_repeat);
+
+        // the keyups of the chord don't type anything, nor set it off again
+        let events = track(&mut bindings, &[], &[(1, 0)], 20);
+        assert!(events.actions.is_empty());
+        assert_eq!(events.swallowed, keys(&[(1, 0)]));
+        let events = track(&mut bindings, &[], &[(1, 1)], 30);
+        assert!(events.actions.is_empty());
+        assert_eq!(events.swallowed, keys(&[(1, 1)]));
+    }
+
+    #[test]
+    fn chord_needs_exactly_its_keys() {
+        let mut bindings = bindings();
+        let events = track(&mut bindings, &[(1, 0), (1, 1), (1, 2)], &[], 0);
+        assert!(events.actions.is_empty());
+        let events = track(&mut bindings, &[], &[(1, 0), (1, 1), (1, 2)], 10);
+        assert!(events.swallowed.is_empty());
+
+        // another key held down first
+        track(&mut bindings, &[(3, 3)], &[], 20);
+        let events = track(&mut bindings, &[(1, 0), (1, 1)], &[], 30);
+        assert!(events.actions.is_empty());
+    }
+
+    #[test]
+    fn long_press_fires_after_the_hold_delay() {
+        let mut bindings = bindings();
+        let events = track(&mut bindings, &[(2, 0)], &[], 0);
+        assert!(events.actions.is_empty());
+        assert!(events.cancel_repeat);
+
+        let events = track(&mut bindings, &[], &[(2, 0)], HOLD_DELAY);
+        assert_eq!(texts(&events), ["long"]);
+        assert_eq!(events.swallowed, keys(&[(2, 0)]));
+    }
+
+    #[test]
+    fn short_press_types_as_usual() {
+        let mut bindings = bindings();
+        track(&mut bindings, &[(2, 0)], &[], 0);
+        let events = track(&mut bindings, &[], &[(2, 0)], HOLD_DELAY - 1);
+        assert!(events.actions.is_empty());
+        assert!(events.swallowed.is_empty());
+    }
+
+    #[test]
+    fn registering_a_trigger_again_replaces_its_binding() {
+        let mut bindings = bindings();
+        bindings.register(KeyBinding {
+            trigger: BindingTrigger::LongPress((2, 0)),
+            action: text("replaced"),
+        });
+        track(&mut bindings, &[(2, 0)], &[], 0);
+        let events = track(&mut bindings, &[], &[(2, 0)], HOLD_DELAY);
+        assert_eq!(texts(&events), ["replaced"]);
+    }
+
+    #[test]
+    fn hooking_replaces_the_old_connection() {
+        let sid = xous::SID::from_array([1, 2, 3, 4]);
+        let mut hooks = HashMap::new();
+        assert_eq!(hook(&mut hooks, "a", sid, |s| { assert_eq!(s, sid); Ok(7) }), Ok(None));
+        assert_eq!(hooks.get("a"), Some(&7));
+
+        // a SID that can't be reached leaves the hook as it was
+        assert_eq!(hook(&mut hooks, "a", sid, |_| Err(xous::Error::ServerNotFound)), Err(xous::Error::ServerNotFound));
+        assert_eq!(hooks.get("a"), Some(&7));
+
+        // the old connection is handed back once no hook uses it
+        assert_eq!(hook(&mut hooks, "b", sid, |_| Ok(7)), Ok(None));
+        assert_eq!(hook(&mut hooks, "a", sid, |_| Ok(8)), Ok(None));
+        assert_eq!(hook(&mut hooks, "b", sid, |_| Ok(9)), Ok(Some(7)));
+        assert_eq!(hook(&mut hooks, "b", sid, |_| Ok(9)), Ok(None));
+    }
+
+    #[test]
+    fn reset_forgets_keys_that_are_down() {
+        let mut bindings = bindings();
+        track(&mut bindings, &[(1, 0)], &[], 0);
+        bindings.reset();
+        let events = track(&mut bindings, &[(1, 1)], &[], 10);
+        assert!(events.actions.is_empty());
+    }
+}
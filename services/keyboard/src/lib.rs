@@ -8,6 +8,12 @@ pub use api::*;
 use xous::{send_message, Message};
 use xous_ipc::{Buffer, String};
 
+/// The layout for `KeyMap::Custom` is kept in this PDDB dictionary and key.
+pub const CUSTOM_KEYMAP_DICT: &'static str = "kbd.keymap";
+pub const CUSTOM_KEYMAP_KEY: &'static str = "custom";
+/// Each key in this PDDB dictionary holds one `KeyBinding`, in the form read by `KeyBinding::parse()`.
+pub const BINDINGS_DICT: &'static str = "kbd.bindings";
+
 #[derive(Debug)]
 pub struct Keyboard {
     conn: xous::CID,
@@ -70,6 +76,51 @@ impl Keyboard {
         }
     }
 
+    /// Sets the layout used by `KeyMap::Custom`. See `mappings/custom.rs` for the format of `definition`;
+    /// it's normally kept in the PDDB under `CUSTOM_KEYMAP_DICT`:`CUSTOM_KEYMAP_KEY`.
+    pub fn set_custom_keymap(&self, definition: &str) -> Result<(), xous::Error> {
+        if definition.len() > 4000 {
+            return Err(xous::Error::OutOfMemory);
+        }
+        let s = String::<4000>::from_str(definition);
+        let buf = Buffer::into_buf(s).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SetCustomKeyMap.to_u32().unwrap()).map(|_| ())
+    }
+
+    /// Adds a binding that types out a text snippet or messages a server when a chord or a long press is
+    /// keyed in. A binding with the same trigger is replaced. Bindings aren't applied to the Braille layout,
+    /// which does its own chording.
+    pub fn register_binding(&self, trigger: BindingTrigger, action: BindingAction) -> Result<(), xous::Error> {
+        let binding = KeyBinding { trigger, action };
+        let buf = Buffer::into_buf(binding).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::RegisterBinding.to_u32().unwrap()).map(|_| ())
+    }
+
+    pub fn clear_bindings(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::ClearBindings.to_usize().unwrap(), 0, 0, 0, 0)
+        ).map(|_| ())
+    }
+
+    /// Replaces the registered bindings with the ones in `definitions`, given as (name, definition) pairs
+    /// in the form read by `KeyBinding::parse()`, as they're kept in the PDDB under `BINDINGS_DICT`. A
+    /// definition that can't be parsed or registered is logged and skipped.
+    pub fn load_bindings<'a, I>(&self, definitions: I) -> Result<(), xous::Error>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        self.clear_bindings()?;
+        for (name, definition) in definitions {
+            match KeyBinding::parse(definition) {
+                Some(binding) => if let Err(e) = self.register_binding(binding.trigger, binding.action) {
+                    log::warn!("couldn't register key binding {}: {:?}", name, e);
+                },
+                None => log::warn!("ignoring key binding {}, it couldn't be parsed", name),
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
     pub fn hostmode_inject_key(&self, c: char) {
         send_message(self.conn,
@@ -79,6 +130,110 @@ impl Keyboard {
     }
 }
 
+/// Has the `BindingAction::Message`s for `server_name` sent to `sid`, which is usually the SID this
+/// process got when it registered `server_name`. Only the process that registered `server_name` with the
+/// name server can hook it; until it does, its binding messages are dropped.
+pub fn hook_binding_messages(xns: &xous_names::XousNames, server_name: &str, sid: xous::SID) -> Result<(), xous::Error> {
+    if server_name.len() > 64 {
+        return Err(xous::Error::InvalidString);
+    }
+    let hook = BindingHook {
+        server: String::<64>::from_str(server_name),
+        sid: sid.to_array(),
+        ok: false,
+    };
+    let conn = xns.request_connection_blocking(api::SERVER_NAME_KBD_HOOKS)?;
+    let mut buf = Buffer::into_buf(hook).or(Err(xous::Error::InternalError))?;
+    // the connection isn't torn down, as other threads of this process may share it
+    buf.lend_mut(conn, HookOpcode::Hook.to_u32().unwrap())?;
+    if buf.to_original::<BindingHook, _>().or(Err(xous::Error::InternalError))?.ok {
+        Ok(())
+    } else {
+        Err(xous::Error::AccessDenied)
+    }
+}
+
+fn parse_key(field: &str) -> Option<(u8, u8)> {
+    let mut rc = field.split(',');
+    match (rc.next(), rc.next(), rc.next()) {
+        (Some(r), Some(c), None) => Some((r.parse::<u8>().ok()?, c.parse::<u8>().ok()?)),
+        _ => None,
+    }
+}
+
+impl KeyBinding {
+    /// Parses a binding written as text. The first line is the trigger, either `chord <row>,<col> ...`
+    /// with two to `MAX_CHORD_KEYS` keys, or `longpress <row>,<col>`. The second line is the action,
+    /// either `text` or `message <opcode> [<arg> ...]` with up to four arguments. The rest is the
+    /// text to type (a final newline is dropped), or the name of the server to message. For example:
+    /// ```text
+    /// chord 1,0 1,1
+    /// text
+    /// Hello, world!
+    /// ```
+    pub fn parse(definition: &str) -> Option<KeyBinding> {
+        let mut lines = definition.splitn(3, '\n');
+        let trigger_line = lines.next()?;
+        let action_line = lines.next()?;
+        let payload = lines.next().unwrap_or("");
+
+        let mut fields = trigger_line.split_whitespace();
+        let trigger = match fields.next()? {
+            "chord" => {
+                let mut keys = [(255u8, 255u8); MAX_CHORD_KEYS];
+                let mut count = 0;
+                for field in fields {
+                    if count == MAX_CHORD_KEYS {
+                        return None;
+                    }
+                    keys[count] = parse_key(field)?;
+                    count += 1;
+                }
+                if count < 2 {
+                    return None;
+                }
+                BindingTrigger::Chord(keys)
+            }
+            "longpress" => {
+                let key = parse_key(fields.next()?)?;
+                if fields.next().is_some() {
+                    return None;
+                }
+                BindingTrigger::LongPress(key)
+            }
+            _ => return None,
+        };
+
+        let mut fields = action_line.split_whitespace();
+        let action = match fields.next()? {
+            "text" => {
+                let text = payload.strip_suffix('\n').unwrap_or(payload);
+                if text.len() == 0 || text.len() > MAX_BINDING_TEXT {
+                    return None;
+                }
+                BindingAction::Text(String::<MAX_BINDING_TEXT>::from_str(text))
+            }
+            "message" => {
+                let opcode = fields.next()?.parse::<u32>().ok()?;
+                let mut args = [0u32; 4];
+                for (i, field) in fields.enumerate() {
+                    if i == args.len() {
+                        return None;
+                    }
+                    args[i] = field.parse::<u32>().ok()?;
+                }
+                let server = payload.trim();
+                if server.len() == 0 || server.len() > 64 {
+                    return None;
+                }
+                BindingAction::Message { server: String::<64>::from_str(server), opcode, args }
+            }
+            _ => return None,
+        };
+        Some(KeyBinding { trigger, action })
+    }
+}
+
 use core::sync::atomic::{AtomicU32, Ordering};
 static REFCOUNT: AtomicU32 = AtomicU32::new(0);
 impl Drop for Keyboard {
@@ -89,3 +244,71 @@ impl Drop for Keyboard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_chord_text_binding() {
+        let binding = KeyBinding::parse("chord 1,0 1,1 2,3\ntext\nHello,\nworld!\n").unwrap();
+        assert_eq!(binding.trigger, BindingTrigger::Chord([(1, 0), (1, 1), (2, 3), (255, 255)]));
+        match binding.action {
+            BindingAction::Text(text) => assert_eq!(text.as_str().unwrap(), "Hello,\nworld!"),
+            _ => panic!("expected a text action"),
+        }
+    }
+
+    #[test]
+    fn parses_long_press_message_binding() {
+        let binding = KeyBinding::parse("longpress 4,5\nmessage 7 1 2\n_Some server_\n").unwrap();
+        assert_eq!(binding.trigger, BindingTrigger::LongPress((4, 5)));
+        match binding.action {
+            BindingAction::Message { server, opcode, args } => {
+                assert_eq!(server.as_str().unwrap(), "_Some server_");
+                assert_eq!(opcode, 7);
+                assert_eq!(args, [1, 2, 0, 0]);
+            }
+            _ => panic!("expected a message action"),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_triggers() {
+        for trigger in [
+            "chord 1,0",                   // one key isn't a chord
+            "chord 1,0 1,1 1,2 1,3 1,4",   // too many keys
+            "chord 1,0 1",                 // key without a column
+            "chord 1,0 1,1,2",             // key with too many parts
+            "chord 1,0 a,1",               // row isn't a number
+            "chord 1,0 256,1",             // row out of range
+            "longpress",                   // no key
+            "longpress 1,0 1,1",           // more than one key
+            "tap 1,0",                     // unknown trigger
+            "",
+        ].iter() {
+            assert!(KeyBinding::parse(&format!("{}\ntext\nhi", trigger)).is_none(), "{}", trigger);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_actions() {
+        let too_long = "x".repeat(MAX_BINDING_TEXT + 1);
+        let server_too_long = "s".repeat(65);
+        for action in [
+            "text\n",                      // nothing to type
+            &format!("text\n{}", too_long),
+            "message\nserver",             // no opcode
+            "message x\nserver",           // opcode isn't a number
+            "message 1 1 2 3 4 5\nserver", // too many arguments
+            "message 1 -1\nserver",        // argument isn't a u32
+            "message 1\n",                 // no server
+            &format!("message 1\n{}", server_too_long),
+            "launch\nsomething",           // unknown action
+        ].iter() {
+            assert!(KeyBinding::parse(&format!("chord 1,0 1,1\n{}", action)).is_none(), "{}", action);
+        }
+        // no action line at all
+        assert!(KeyBinding::parse("chord 1,0 1,1").is_none());
+    }
+}
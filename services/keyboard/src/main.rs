@@ -4,6 +4,7 @@
 mod api;
 use api::*;
 mod mappings;
+mod bindings;
 
 use log::info;
 
@@ -68,6 +69,8 @@ mod implementation {
         settings: xous::MemoryRange,
         /// a handle to the spinor block so we can update our settings
         spinor: spinor::Spinor,
+        /// the layout used for `KeyMap::Custom`, once it has been loaded from the PDDB
+        custom: Option<CustomMap>,
     }
 
     fn handle_kbd(_irq_no: usize, arg: *mut usize) {
@@ -184,6 +187,7 @@ mod implementation {
                 debug: 0,
                 settings: setting_page,
                 spinor: spinor::Spinor::new(&xns).unwrap(),
+                custom: None,
             };
 
             xous::claim_interrupt(
@@ -249,6 +253,34 @@ mod implementation {
             self.map = KeyMap::from(code as usize);
             self.map
         }
+        pub(crate) fn set_custom_map(&mut self, map: CustomMap) {
+            self.custom = Some(map);
+        }
+        /// Translates a key position according to the current mapping
+        fn map_key(&self, rc: RowCol) -> ScanCode {
+            match self.map {
+                KeyMap::Qwerty => map_qwerty(rc),
+                KeyMap::Dvorak => map_dvorak(rc),
+                KeyMap::Azerty => map_azerty(rc),
+                KeyMap::Qwertz => map_qwertz(rc),
+                KeyMap::Colemak => map_colemak(rc),
+                // until the custom layout is loaded (it lives in the PDDB, which needs a password
+                // typed in to mount), fall back to QWERTY
+                KeyMap::Custom => match &self.custom {
+                    Some(custom) => custom.map(rc),
+                    None => map_qwerty(rc),
+                },
+                _ => ScanCode {key: None, shift: None, hold: None, alt: None},
+            }
+        }
+        /// Returns `true` if the left shift key is an alt key, as on AZERTY
+        fn alt_shift(&self) -> bool {
+            match self.map {
+                KeyMap::Azerty => true,
+                KeyMap::Custom => self.custom.as_ref().map(|c| c.has_alt()).unwrap_or(false),
+                _ => false,
+            }
+        }
         pub(crate) fn set_repeat(&mut self, rate: u32, delay: u32) {
             self.rate = rate;
             self.delay = delay;
@@ -259,6 +291,13 @@ mod implementation {
         pub(crate) fn get_repeat_check_interval(&self) -> u32 {
             self.rate
         }
+        /// how long, in ms, a key has to be down before its hold meaning applies
+        pub(crate) fn get_hold_delay(&self) -> u32 {
+            self.delay
+        }
+        pub(crate) fn cancel_repeat(&mut self) {
+            self.repeating_key = None;
+        }
 
         pub(crate) fn poll(&mut self) {
             // disable the interrupt while we're polling, to avoid a race condition...
@@ -467,67 +506,61 @@ mod implementation {
 
             // first check for shift and alt keys
             for rc in krs.keydowns.iter() {
-                match self.map {
-                    KeyMap::Azerty => {
-                        if (rc.r == 8) && (rc.c == 5) { // left shift (orange)
-                            if self.alt_up == false {
-                                self.alt_down = true;
-                            } else {
-                                self.alt_up = false;
-                            }
-                        } else if (rc.r == 8) && (rc.c == 9) { // right shift (yellow)
-                            if self.shift_up == false {
-                                self.shift_down = true;
-                            } else {
-                                self.shift_up = false;
-                            }
+                if self.alt_shift() {
+                    if (rc.r == 8) && (rc.c == 5) { // left shift (orange)
+                        if self.alt_up == false {
+                            self.alt_down = true;
+                        } else {
+                            self.alt_up = false;
                         }
-                    },
-                    _ => { // the rest just have one color of shift
-                        if ((rc.r == 8) && (rc.c == 5)) || ((rc.r == 8) && (rc.c == 9)) {
-                            // if the shift key was tapped twice, remove the shift modifier
-                            if self.shift_up == false {
-                                //info!("shift down true");
-                                self.shift_down = true;
-                            } else {
-                                //info!("shift up false");
-                                self.shift_up = false;
-                            }
+                    } else if (rc.r == 8) && (rc.c == 9) { // right shift (yellow)
+                        if self.shift_up == false {
+                            self.shift_down = true;
+                        } else {
+                            self.shift_up = false;
+                        }
+                    }
+                } else { // the rest just have one color of shift
+                    if ((rc.r == 8) && (rc.c == 5)) || ((rc.r == 8) && (rc.c == 9)) {
+                        // if the shift key was tapped twice, remove the shift modifier
+                        if self.shift_up == false {
+                            //info!("shift down true");
+                            self.shift_down = true;
+                        } else {
+                            //info!("shift up false");
+                            self.shift_up = false;
                         }
                     }
                 }
             }
             let mut keyups_noshift: Vec::<RowCol> = Vec::new();
             for &rc in krs.keyups.iter() {
-                match self.map {
-                    KeyMap::Azerty => {
-                        if (rc.r == 8) && (rc.c == 5) { // left shift (orange)
-                            if self.alt_down {
-                                self.alt_up = true;
-                            }
-                            self.alt_down = false;
-                        } else if (rc.r == 8) && (rc.c == 9) { // right shift (yellow)
-                            if self.shift_down {
-                                self.shift_up = true;
-                            }
-                            self.shift_down = false;
-                        } else {
-                            keyups_noshift.push(RowCol{r: rc.r as _, c: rc.c as _});
+                if self.alt_shift() {
+                    if (rc.r == 8) && (rc.c == 5) { // left shift (orange)
+                        if self.alt_down {
+                            self.alt_up = true;
                         }
-                    },
-                    _ => { // the rest just have one color of shift
-                        if ((rc.r == 8) && (rc.c == 5)) || ((rc.r == 8) && (rc.c == 9)) {
-                            // only set the shift-up if we didn't previously clear it with a double-tap of shift
-                            if self.shift_down {
-                                //info!("shift up true");
-                                self.shift_up = true;
-                            }
-                            //info!("shift down false");
-                            self.shift_down = false;
-                        } else {
-                            //info!("adding non-shift entry {:?}", rc);
-                            keyups_noshift.push(RowCol{r: rc.r as _, c: rc.c as _});
+                        self.alt_down = false;
+                    } else if (rc.r == 8) && (rc.c == 9) { // right shift (yellow)
+                        if self.shift_down {
+                            self.shift_up = true;
+                        }
+                        self.shift_down = false;
+                    } else {
+                        keyups_noshift.push(RowCol{r: rc.r as _, c: rc.c as _});
+                    }
+                } else { // the rest just have one color of shift
+                    if ((rc.r == 8) && (rc.c == 5)) || ((rc.r == 8) && (rc.c == 9)) {
+                        // only set the shift-up if we didn't previously clear it with a double-tap of shift
+                        if self.shift_down {
+                            //info!("shift up true");
+                            self.shift_up = true;
                         }
+                        //info!("shift down false");
+                        self.shift_down = false;
+                    } else {
+                        //info!("adding non-shift entry {:?}", rc);
+                        keyups_noshift.push(RowCol{r: rc.r as _, c: rc.c as _});
                     }
                 }
             }
@@ -537,13 +570,7 @@ mod implementation {
                 self.chord_timestamp = self.ticktimer.elapsed_ms();
             }
             for &rc in krs.keydowns.iter() {
-                let code = match self.map {
-                    KeyMap::Qwerty => map_qwerty(rc),
-                    KeyMap::Dvorak => map_dvorak(rc),
-                    KeyMap::Azerty => map_azerty(rc),
-                    KeyMap::Qwertz => map_qwertz(rc),
-                    _ => ScanCode {key: None, shift: None, hold: None, alt: None},
-                };
+                let code = self.map_key(rc);
                 if code.hold == None
                 && !((rc.r == 5) && (rc.c == 2)) // scan code for the menu key
                  { // if there isn't a pre-defined meaning if the key is held *and* it's not the menu key: it's a repeating key
@@ -566,13 +593,7 @@ mod implementation {
 
             for &rc in keyups_noshift.iter() {
                 // info!("interpreting keyups_noshift entry {:?}", rc);
-                let code = match self.map {
-                    KeyMap::Qwerty => map_qwerty(rc),
-                    KeyMap::Dvorak => map_dvorak(rc),
-                    KeyMap::Azerty => map_azerty(rc),
-                    KeyMap::Qwertz => map_qwertz(rc),
-                    _ => ScanCode {key: None, shift: None, hold: None, alt: None},
-                };
+                let code = self.map_key(rc);
                 // delete the key repeat if there is one
                 if code.hold == None {
                     if let Some(key) = code.key {
@@ -584,56 +605,53 @@ mod implementation {
                     }
                 }
 
-                match self.map {
-                    KeyMap::Azerty => {
-                        if self.shift_down || self.shift_up {
-                            if let Some(shiftcode) = code.shift {
-                                ks.push(shiftcode);
-                            } else if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
-                            self.shift_down = false;
-                            self.shift_up = false;
-                        } else if self.alt_down || self.alt_up {
-                            if let Some(altcode) = code.alt {
-                                ks.push(altcode);
-                            } else if let Some(shiftcode) = code.shift {
-                                ks.push(shiftcode);
-                            } else if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
-                            self.alt_down = false;
-                            self.alt_up = false;
-                        } else if hold {
-                            if let Some(holdcode) = code.hold {
-                                ks.push(holdcode);
-                            }
-                        } else {
-                            if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
+                if self.alt_shift() {
+                    if self.shift_down || self.shift_up {
+                        if let Some(shiftcode) = code.shift {
+                            ks.push(shiftcode);
+                        } else if let Some(keycode) = code.key {
+                            ks.push(keycode);
                         }
-                    },
-                    _ => {
-                        if self.shift_down || self.alt_down || self.shift_up || self.alt_up {
-                            if let Some(shiftcode) = code.shift {
-                                ks.push(shiftcode);
-                            } else if let Some(keycode) = code.key {
-                                ks.push(keycode);
-                            }
-                            self.shift_down = false;
-                            self.alt_down = false;
-                            self.shift_up = false;
-                            self.alt_up = false;
-                        } else if hold {
-                            if let Some(holdcode) = code.hold {
-                                ks.push(holdcode);
-                            }
-                        } else {
-                            if let Some(keycode) = code.key {
-                                // info!("appeding normal key '{}'", keycode);
-                                ks.push(keycode);
-                            }
+                        self.shift_down = false;
+                        self.shift_up = false;
+                    } else if self.alt_down || self.alt_up {
+                        if let Some(altcode) = code.alt {
+                            ks.push(altcode);
+                        } else if let Some(shiftcode) = code.shift {
+                            ks.push(shiftcode);
+                        } else if let Some(keycode) = code.key {
+                            ks.push(keycode);
+                        }
+                        self.alt_down = false;
+                        self.alt_up = false;
+                    } else if hold {
+                        if let Some(holdcode) = code.hold {
+                            ks.push(holdcode);
+                        }
+                    } else {
+                        if let Some(keycode) = code.key {
+                            ks.push(keycode);
+                        }
+                    }
+                } else {
+                    if self.shift_down || self.alt_down || self.shift_up || self.alt_up {
+                        if let Some(shiftcode) = code.shift {
+                            ks.push(shiftcode);
+                        } else if let Some(keycode) = code.key {
+                            ks.push(keycode);
+                        }
+                        self.shift_down = false;
+                        self.alt_down = false;
+                        self.shift_up = false;
+                        self.alt_up = false;
+                    } else if hold {
+                        if let Some(holdcode) = code.hold {
+                            ks.push(holdcode);
+                        }
+                    } else {
+                        if let Some(keycode) = code.key {
+                            // info!("appeding normal key '{}'", keycode);
+                            ks.push(keycode);
                         }
                     }
                 }
@@ -690,6 +708,8 @@ mod implementation {
             self.map = map;
         }
         pub fn get_map(&self) -> KeyMap {self.map}
+        pub fn set_custom_map(&mut self, _map: crate::mappings::CustomMap) {
+        }
 
         pub fn update(&self) -> KeyRawStates {
             KeyRawStates::new()
@@ -718,6 +738,10 @@ mod implementation {
         pub(crate) fn get_repeat_check_interval(&self) -> u32 {
             self.rate
         }
+        pub(crate) fn get_hold_delay(&self) -> u32 {
+            self.delay
+        }
+        pub(crate) fn cancel_repeat(&mut self) {}
         pub(crate) fn poll(&mut self) {}
     }
 }
@@ -734,7 +758,7 @@ fn xmain() -> ! {
     //  - GAM
     //  - graphics (if building for hosted mode)
     //  - oqc (for factory test)
-    //  - status sub system (for setting the layout)
+    //  - status sub system (for setting the layout and key bindings)
     #[cfg(any(target_os = "none", target_os = "xous"))]
     let kbd_sid = xns.register_name(api::SERVER_NAME_KBD, Some(3)).expect("can't register server");
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
@@ -764,6 +788,13 @@ fn xmain() -> ! {
     }*/
     let mut esc_index: Option<usize> = None;
     let mut esc_chars = [0u8; 16];
+    let mut bindings = bindings::Bindings::new();
+    // servers hook their binding messages on a server of their own, so that the keyboard only ever
+    // messages a SID handed in by the process that registered the server's name
+    std::thread::spawn({
+        let hooks = bindings.hooks();
+        move || binding_hook_server(hooks)
+    });
 
     log::trace!("starting main loop");
     loop {
@@ -774,6 +805,7 @@ fn xmain() -> ! {
                 kbd.suspend();
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
                 kbd.resume();
+                bindings.reset();
             }),
             Some(Opcode::Vibe) => msg_scalar_unpack!(msg, ena, _,  _,  _, {
                 if ena != 0 { vibe = true }
@@ -817,6 +849,19 @@ fn xmain() -> ! {
                     kbd.get_map().into()
                 ).expect("can't retrieve keymap");
             }),
+            Some(Opcode::SetCustomKeyMap) => {
+                let buffer = unsafe{Buffer::from_memory_message(msg.body.memory_message().unwrap())};
+                let definition = buffer.to_original::<xous_ipc::String::<4000>, _>().unwrap();
+                kbd.set_custom_map(mappings::CustomMap::parse(definition.as_str().unwrap_or("")));
+            }
+            Some(Opcode::RegisterBinding) => {
+                let buffer = unsafe{Buffer::from_memory_message(msg.body.memory_message().unwrap())};
+                let binding = buffer.to_original::<KeyBinding, _>().unwrap();
+                bindings.register(binding);
+            }
+            Some(Opcode::ClearBindings) => msg_scalar_unpack!(msg, _, _, _, _, {
+                bindings.clear();
+            }),
             Some(Opcode::SetRepeat) => msg_scalar_unpack!(msg, rate, delay, _, _, {
                 kbd.set_repeat(rate as u32, delay as u32);
             }),
@@ -922,7 +967,40 @@ fn xmain() -> ! {
                         kbd.track_chord(&rawstates)
                     },
                     _ => {
-                        kbd.track_keys(&rawstates)
+                        let events = bindings.track(&rawstates, ticktimer.elapsed_ms(), kbd.get_hold_delay() as u64);
+                        let mut kc = if events.swallowed.is_empty() {
+                            kbd.track_keys(&rawstates)
+                        } else {
+                            // keys that set off a binding still go through track_keys() to keep the shift and
+                            // repeat state up to date, but whatever they would have typed is thrown away
+                            let mut swallowed = KeyRawStates::new();
+                            let mut passed = KeyRawStates::new();
+                            passed.keydowns.extend_from_slice(&rawstates.keydowns);
+                            for &rc in rawstates.keyups.iter() {
+                                if events.swallowed.contains(&rc) {
+                                    swallowed.keyups.push(rc);
+                                } else {
+                                    passed.keyups.push(rc);
+                                }
+                            }
+                            kbd.track_keys(&swallowed);
+                            kbd.track_keys(&passed)
+                        };
+                        if events.cancel_repeat {
+                            kbd.cancel_repeat();
+                        }
+                        for action in events.actions.iter() {
+                            match action {
+                                BindingAction::Text(text) => {
+                                    // newlines are typed with the enter key, which is a carriage return
+                                    kc.extend(text.as_str().unwrap_or("").chars().map(|c| if c == '\n' { '\r' } else { c }));
+                                }
+                                BindingAction::Message { server, opcode, args } => {
+                                    bindings.send_message(server.as_str().unwrap_or(""), *opcode, args);
+                                }
+                            }
+                        }
+                        kc
                     },
                 };
 
@@ -969,6 +1047,41 @@ fn xmain() -> ! {
     xous::terminate_process(0)
 }
 
+fn binding_hook_server(hooks: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<std::string::String, CID>>>) {
+    let xns = xous_names::XousNames::new().unwrap();
+    let hook_sid = xns.register_name(api::SERVER_NAME_KBD_HOOKS, None).expect("can't register server");
+    loop {
+        let mut msg = xous::receive_message(hook_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(HookOpcode::Hook) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<BindingHook, _>().unwrap();
+                request.ok = match (request.server.as_str(), pid) {
+                    (Ok(server), Some(pid)) if xns.is_registrant(server, pid).unwrap_or(false) => {
+                        // `try_connect` so a bogus SID is refused instead of waited on
+                        match bindings::hook(&mut hooks.lock().unwrap(), server, xous::SID::from_array(request.sid), xous::try_connect) {
+                            Ok(stale) => {
+                                if let Some(cid) = stale {
+                                    unsafe{xous::disconnect(cid).ok();}
+                                }
+                                true
+                            }
+                            Err(e) => {
+                                log::warn!("couldn't connect to the binding SID of {}: {:?}", server, e);
+                                false
+                            }
+                        }
+                    }
+                    _ => false,
+                };
+                buffer.replace(request).expect("couldn't return hook status");
+            }
+            None => log::error!("couldn't convert hook opcode: {:?}", msg),
+        }
+    }
+}
+
 fn esc_match(esc_chars: &[u8]) -> Result<Option<char>, ()> {
     let mut extended = Vec::<u8>::new();
     for (i, &c) in esc_chars.iter().enumerate() {
@@ -8,3 +8,7 @@ mod azerty;
 pub (crate) use azerty::*;
 mod dvorak;
 pub (crate) use dvorak::*;
+mod colemak;
+pub (crate) use colemak::*;
+mod custom;
+pub (crate) use custom::*;
@@ -0,0 +1,141 @@
+use crate::{RowCol, ScanCode};
+use super::map_qwerty;
+use std::collections::HashMap;
+
+/// A user-defined layout. It is written as text, one key per line:
+///
+/// `<row> <col> <key> [<shift> [<hold> [<alt>]]]`
+///
+/// `row` and `col` are the key's position in the scan matrix, as used by the built-in layouts in
+/// this directory. Each character is either written as itself, as `U+` followed by its hex code point
+/// (which is how to write a space, `#`, `-` or a control character), or as `-` for none. Characters
+/// left off the end of a line are none. Blank lines and lines starting with `#` are ignored, and keys
+/// that aren't listed keep their QWERTY meaning. If any key has an `alt` character, the left shift
+/// key becomes an alt key, as it is on AZERTY.
+///
+/// For example, this swaps `q` and `w`, and puts a `€` on the hold layer of `e`:
+/// ```text
+/// # row col key shift hold alt
+/// 1 0 w W ^
+/// 1 1 q Q %
+/// 1 2 e E €
+/// ```
+pub(crate) struct CustomMap {
+    keys: HashMap<RowCol, ScanCode>,
+    has_alt: bool,
+}
+
+fn parse_char(field: &str) -> Result<Option<char>, ()> {
+    if field == "-" {
+        return Ok(None);
+    }
+    if field.starts_with("U+") && field.len() > 2 {
+        let code = u32::from_str_radix(&field[2..], 16).map_err(|_| ())?;
+        return core::char::from_u32(code).map(|c| Some(c)).ok_or(());
+    }
+    let mut chars = field.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Some(c)),
+        _ => Err(()),
+    }
+}
+
+fn parse_line(line: &str) -> Result<(RowCol, ScanCode), ()> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 3 || fields.len() > 6 {
+        return Err(());
+    }
+    let r = fields[0].parse::<u8>().map_err(|_| ())?;
+    let c = fields[1].parse::<u8>().map_err(|_| ())?;
+    let mut chars = [None; 4];
+    for (dest, field) in chars.iter_mut().zip(fields[2..].iter()) {
+        *dest = parse_char(field)?;
+    }
+    Ok((RowCol { r, c }, ScanCode { key: chars[0], shift: chars[1], hold: chars[2], alt: chars[3] }))
+}
+
+impl CustomMap {
+    /// Parses a layout definition. Lines that can't be parsed are logged and skipped.
+    pub(crate) fn parse(definition: &str) -> CustomMap {
+        let mut keys = HashMap::new();
+        let mut has_alt = false;
+        for (number, line) in definition.lines().enumerate() {
+            let line = line.trim();
+            if line.len() == 0 || line.starts_with('#') {
+                continue;
+            }
+            match parse_line(line) {
+                Ok((rc, code)) => {
+                    has_alt |= code.alt.is_some();
+                    keys.insert(rc, code);
+                }
+                Err(()) => log::warn!("ignoring custom keymap line {}: {}", number + 1, line),
+            }
+        }
+        log::info!("custom keymap defines {} keys", keys.len());
+        CustomMap { keys, has_alt }
+    }
+
+    pub(crate) fn map(&self, code: RowCol) -> ScanCode {
+        match self.keys.get(&code) {
+            Some(scancode) => *scancode,
+            None => map_qwerty(code),
+        }
+    }
+
+    pub(crate) fn has_alt(&self) -> bool {
+        self.has_alt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(code: ScanCode) -> [Option<char>; 4] {
+        [code.key, code.shift, code.hold, code.alt]
+    }
+
+    #[test]
+    fn listed_keys_replace_qwerty() {
+        let map = CustomMap::parse("# row col key shift hold alt\n1 0 w W ^\n1 1 q Q %\n1 2 e E €\n");
+        assert_eq!(chars(map.map(RowCol::new(1, 0))), [Some('w'), Some('W'), Some('^'), None]);
+        assert_eq!(chars(map.map(RowCol::new(1, 1))), [Some('q'), Some('Q'), Some('%'), None]);
+        assert_eq!(chars(map.map(RowCol::new(1, 2))), [Some('e'), Some('E'), Some('€'), None]);
+        // keys that aren't listed keep their QWERTY meaning
+        assert_eq!(chars(map.map(RowCol::new(0, 0))), chars(map_qwerty(RowCol::new(0, 0))));
+        assert!(!map.has_alt());
+    }
+
+    #[test]
+    fn escapes_and_missing_characters() {
+        let map = CustomMap::parse("1 0 U+20 U+23 - U+2D\n1 1 a\n");
+        assert_eq!(chars(map.map(RowCol::new(1, 0))), [Some(' '), Some('#'), None, Some('-')]);
+        assert_eq!(chars(map.map(RowCol::new(1, 1))), [Some('a'), None, None, None]);
+        assert!(map.has_alt());
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let map = CustomMap::parse(concat!(
+            "1 0\n",               // too few fields
+            "1 1 a b c d e\n",     // too many fields
+            "x 2 a\n",             // row isn't a number
+            "1 3 ab\n",            // more than one character
+            "1 4 U+D800\n",        // not a character
+            "1 5 U+\n",            // no code point
+            "\n   \n  # comment\n",
+            "1 6 z\n",
+        ));
+        for c in 0..6 {
+            assert_eq!(chars(map.map(RowCol::new(1, c))), chars(map_qwerty(RowCol::new(1, c))));
+        }
+        assert_eq!(chars(map.map(RowCol::new(1, 6))), [Some('z'), None, None, None]);
+    }
+
+    #[test]
+    fn later_lines_win() {
+        let map = CustomMap::parse("1 0 a\n1 0 b\n");
+        assert_eq!(map.map(RowCol::new(1, 0)).key, Some('b'));
+    }
+}
@@ -126,7 +126,7 @@ pub(crate) enum Opcode {
 
     /// sets a wake-up alarm. This forces the SoC into power-on state, if it happens to be off.
     /// primarily used to trigger cold reboots, but could have other reasons
-    SetWakeupAlarm, //(u8, TimeUnits),
+    SetWakeupAlarm, //(u8, TimeUnits as usize),
     /// clear any wakeup alarms that have been set
     ClearWakeupAlarm,
     /// sets an RTC alarm. This just triggers a regular interrupt, no other side-effect
@@ -15,7 +15,7 @@ impl Default for Weekday {
     fn default() -> Self { Weekday::Sunday }
 }
 
-#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum TimeUnits {
     Seconds,
     Minutes,
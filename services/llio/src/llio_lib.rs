@@ -388,6 +388,14 @@ impl Llio {
             Message::new_blocking_scalar(Opcode::SetWakeupAlarm.to_usize().unwrap(), seconds_from_now as _, 0, 0, 0)
         ).map(|_|())
     }
+    /// like `set_wakeup_alarm()`, but counting in `units`, so that the system can be woken up to
+    /// 255 hours from now. The first unit counted may be cut short, so the wakeup can come up to one
+    /// unit early.
+    pub fn set_wakeup_alarm_in(&self, count: u8, units: TimeUnits) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::SetWakeupAlarm.to_usize().unwrap(), count as _, units.to_usize().unwrap(), 0, 0)
+        ).map(|_|())
+    }
     pub fn clear_wakeup_alarm(&self) -> Result<(), xous::Error> {
         send_message(self.conn,
             Message::new_blocking_scalar(Opcode::ClearWakeupAlarm.to_usize().unwrap(), 0, 0, 0, 0)
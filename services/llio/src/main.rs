@@ -423,18 +423,28 @@ fn xmain() -> ! {
                 xous::return_scalar2(msg.sender, is_locked, force_update).expect("couldn't return status");
                 lockstatus_force_update = false;
             }),
-            Some(Opcode::SetWakeupAlarm) => msg_blocking_scalar_unpack!(msg, delay, _, _, _, {
+            Some(Opcode::SetWakeupAlarm) => msg_blocking_scalar_unpack!(msg, delay, units, _, _, {
                 if delay > u8::MAX as usize {
-                    log::error!("Wakeup must be no longer than {} secs in the future", u8::MAX);
+                    log::error!("Wakeup must be no longer than {} units in the future", u8::MAX);
                     xous::return_scalar(msg.sender, 1).expect("couldn't return to caller");
                     continue;
                 }
+                let clk = match FromPrimitive::from_usize(units) {
+                    Some(TimeUnits::Seconds) => TimerClk::CLK_1_S,
+                    Some(TimeUnits::Minutes) => TimerClk::CLK_60_S,
+                    Some(TimeUnits::Hours) => TimerClk::CLK_3600_S,
+                    None => {
+                        log::error!("Unknown wakeup time units {}", units);
+                        xous::return_scalar(msg.sender, 1).expect("couldn't return to caller");
+                        continue;
+                    }
+                };
                 let seconds = delay as u8;
                 wakeup_alarm_enabled = true;
                 // make sure battery switchover is enabled, otherwise we won't keep time when power goes off
                 i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_CONTROL3, &[(Control3::BATT_STD_BL_EN).bits()]).expect("RTC access error");
-                // set clock units to 1 second, output pulse length to ~218ms
-                i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_TIMERB_CLK, &[(TimerClk::CLK_1_S | TimerClk::PULSE_218_MS).bits()]).expect("RTC access error");
+                // set clock units, output pulse length to ~218ms
+                i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_TIMERB_CLK, &[(clk | TimerClk::PULSE_218_MS).bits()]).expect("RTC access error");
                 // program elapsed time
                 i2c.i2c_write(ABRTCMC_I2C_ADR, ABRTCMC_TIMERB, &[seconds]).expect("RTC access error");
                 // enable timerb countdown interrupt, also clears any prior interrupt flag
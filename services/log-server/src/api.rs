@@ -9,6 +9,80 @@ pub struct LogRecord {
     pub level: u32,
     pub args_length: u32,
     pub args: [u8; 3000],
+    /// If the record is lent mutably, the server fills in the `log::LevelFilter` that was set for
+    /// this program with `Opcode::SetLevel`, or `LEVEL_NOT_SET`. It comes last, so that the layout
+    /// of the fields before it is unchanged.
+    pub max_level: u32,
+}
+
+/// Longest program name that the server keeps track of. Longer names are truncated.
+pub const PROGRAM_NAME_LEN: usize = 32;
+
+/// `LogRecord::max_level` when no level was set for the sending program
+pub const LEVEL_NOT_SET: u32 = u32::MAX;
+
+#[repr(C)]
+pub struct ProgramName {
+    pub length: u32,
+    pub name: [u8; PROGRAM_NAME_LEN],
+}
+
+#[repr(C)]
+pub struct SetLevel {
+    pub name_length: u32,
+    pub name: [u8; PROGRAM_NAME_LEN],
+    /// A `log::LevelFilter`, as a `u32`
+    pub level: u32,
+}
+
+pub const RING_CHUNK_LEN: usize = 3072;
+
+#[repr(C)]
+pub struct ReadRing {
+    /// Nonzero to read the copy of the ring that was taken when the last panic finished
+    pub panic_snapshot: u32,
+    /// Offset to read from, counted from the oldest byte in the ring
+    pub offset: u32,
+    /// Filled in by the server: number of bytes currently held by the ring
+    pub total: u32,
+    /// Filled in by the server: number of valid bytes in `data`
+    pub length: u32,
+    pub data: [u8; RING_CHUNK_LEN],
+}
+
+pub const CRASH_MESSAGE_LEN: usize = 1024;
+pub const CRASH_LOG_LEN: usize = 2048;
+
+/// What the server captures about a process that panicked.
+#[repr(C)]
+pub struct CrashRecord {
+    /// Nonzero if this holds a crash: the serial number the server gave it
+    pub valid: u32,
+    pub pid: u32,
+    /// The panicking thread, or 0 if the panic handler didn't say
+    pub tid: u32,
+    pub name_length: u32,
+    pub name: [u8; PROGRAM_NAME_LEN],
+    pub message_length: u32,
+    /// The panic message
+    pub message: [u8; CRASH_MESSAGE_LEN],
+    pub log_length: u32,
+    /// The log output from all processes that led up to the panic, oldest first
+    pub log: [u8; CRASH_LOG_LEN],
+    /// UTC seconds at which the record was saved to the PDDB, or 0 if it hasn't been
+    pub timestamp: u64,
+}
+
+pub fn level_filter_from_u32(level: u32) -> Option<log::LevelFilter> {
+    match level {
+        0 => Some(log::LevelFilter::Off),
+        1 => Some(log::LevelFilter::Error),
+        2 => Some(log::LevelFilter::Warn),
+        3 => Some(log::LevelFilter::Info),
+        4 => Some(log::LevelFilter::Debug),
+        5 => Some(log::LevelFilter::Trace),
+        _ => None,
+    }
 }
 
 #[derive(Debug, PartialEq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
@@ -22,10 +96,34 @@ pub enum Opcode {
     /// A `&[u8]` destined for stderr
     StandardError = 2,
 
-    /// A `xous::StringBuffer` containing this program's name
+    /// A `ProgramName` message, naming the sending program by its binary. Sent before its first `LogRecord`.
     ProgramName = 3,
 
-    /// A panic occurred, and a panic log is forthcoming
+    /// A `SetLevel` message, setting the most verbose level logged by the named program
+    SetLevel = 4,
+
+    /// A `ReadRing` message, copying out part of the RAM log ring
+    ReadRing = 5,
+
+    /// A `CrashRecord` message, copying out the oldest held record whose serial number (in `valid`)
+    /// is above the `valid` of the request. The server holds records until they are acknowledged.
+    ReadCrashRecord = 6,
+
+    /// A blocking scalar that returns once a record with a serial number above `arg1` is held
+    WaitCrashRecord = 7,
+
+    /// A blocking scalar that returns the `log::LevelFilter` set for the sending program, as a
+    /// `u32`, or `LEVEL_NOT_SET`
+    GetLevel = 8,
+
+    /// A scalar that drops the held crash record with serial number `arg1`, once it has been saved
+    AcknowledgeCrashRecord = 9,
+
+    /// A blocking scalar that makes the sender the only process allowed to send `SetLevel` and
+    /// `ReadRing`. Returns 1 if it was granted, or 0 if another process already holds it.
+    ClaimControl = 10,
+
+    /// A panic occurred, and a panic log is forthcoming. `arg1` is the panicking thread's TID, if known.
     PanicStarted = 1000,
 
     /// Log messages of varying size
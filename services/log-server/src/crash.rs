@@ -0,0 +1,136 @@
+use crate::api::CrashRecord;
+use crate::ring::LogRing;
+
+/// Number of finished records held until they are acknowledged. When full, the oldest is dropped.
+const HELD_RECORDS: usize = 4;
+
+const EMPTY: CrashRecord = CrashRecord {
+    valid: 0,
+    pid: 0,
+    tid: 0,
+    name_length: 0,
+    name: [0; crate::api::PROGRAM_NAME_LEN],
+    message_length: 0,
+    message: [0; crate::api::CRASH_MESSAGE_LEN],
+    log_length: 0,
+    log: [0; crate::api::CRASH_LOG_LEN],
+    timestamp: 0,
+};
+
+fn copy(record: &mut CrashRecord, r: &CrashRecord) {
+    record.valid = r.valid;
+    record.pid = r.pid;
+    record.tid = r.tid;
+    record.name_length = r.name_length;
+    record.name.copy_from_slice(&r.name);
+    record.message_length = r.message_length;
+    record.message.copy_from_slice(&r.message);
+    record.log_length = r.log_length;
+    record.log.copy_from_slice(&r.log);
+    record.timestamp = r.timestamp;
+}
+
+/// Builds a `CrashRecord` from the panic messages of a process, and holds finished records in RAM
+/// until whoever saves them acknowledges them. Records that finish before the PDDB is mounted, or
+/// while the process that saves them is down, are kept this way too.
+pub struct CrashRecorder {
+    /// the record being built. The records are boxed, as the server thread's stack is too small for them.
+    record: Box<CrashRecord>,
+    /// the process whose panic is being recorded
+    building: Option<xous::PID>,
+    /// finished records that haven't been acknowledged; a `valid` of 0 marks a free slot
+    held: Vec<CrashRecord>,
+    /// serial number for the next record, stored in its `valid` field
+    next_serial: u32,
+}
+
+impl CrashRecorder {
+    pub fn new() -> CrashRecorder {
+        CrashRecorder {
+            record: Box::new(EMPTY),
+            building: None,
+            held: (0..HELD_RECORDS).map(|_| EMPTY).collect(),
+            next_serial: 1,
+        }
+    }
+
+    /// Starts a new record. If another process's panic is still being recorded, that record is
+    /// finished first: its process may have died before it could say so.
+    pub fn started(&mut self, pid: xous::PID, tid: usize, name: Option<&[u8]>, ring: &LogRing) {
+        if let Some(building) = self.building {
+            if building != pid {
+                self.finished(building);
+            }
+        }
+        self.building = Some(pid);
+        let r = &mut self.record;
+        r.valid = self.next_serial;
+        self.next_serial = self.next_serial.checked_add(1).unwrap_or(1);
+        r.pid = pid.get() as u32;
+        r.tid = tid as u32;
+        let name = name.unwrap_or(&[]);
+        let name_length = name.len().min(r.name.len());
+        r.name[..name_length].copy_from_slice(&name[..name_length]);
+        r.name_length = name_length as u32;
+        r.message_length = 0;
+        r.timestamp = 0;
+
+        // the tail of the log, starting at a line boundary
+        let offset = ring.len().saturating_sub(r.log.len());
+        let mut length = ring.read(offset, &mut r.log);
+        if offset > 0 {
+            if let Some(newline) = r.log[..length].iter().position(|&c| c == b'\n') {
+                r.log.copy_within(newline + 1..length, 0);
+                length -= newline + 1;
+            }
+        }
+        r.log_length = length as u32;
+    }
+
+    pub fn message(&mut self, pid: xous::PID, text: &[u8]) {
+        if self.building != Some(pid) {
+            return;
+        }
+        let r = &mut self.record;
+        let start = r.message_length as usize;
+        let length = text.len().min(r.message.len() - start);
+        r.message[start..start + length].copy_from_slice(&text[..length]);
+        r.message_length += length as u32;
+    }
+
+    pub fn finished(&mut self, pid: xous::PID) {
+        if self.building != Some(pid) {
+            return;
+        }
+        self.building = None;
+        let slot = match self.held.iter().position(|r| r.valid == 0) {
+            Some(free) => free,
+            None => (0..HELD_RECORDS).min_by_key(|&i| self.held[i].valid).unwrap(),
+        };
+        copy(&mut self.held[slot], &self.record);
+    }
+
+    /// Returns `true` if a finished record with a serial number above `after` is held.
+    pub fn held_after(&self, after: u32) -> bool {
+        self.held.iter().any(|r| r.valid > after)
+    }
+
+    /// Copies out the oldest held record with a serial number above `after`, if there is one.
+    /// Otherwise marks `record` invalid.
+    pub fn read(&self, after: u32, record: &mut CrashRecord) {
+        match self.held.iter().filter(|r| r.valid > after).min_by_key(|r| r.valid) {
+            Some(r) => copy(record, r),
+            None => record.valid = 0,
+        }
+    }
+
+    /// Forgets the held record with serial number `serial`, once it has been saved.
+    pub fn acknowledge(&mut self, serial: u32) {
+        if serial == 0 {
+            return;
+        }
+        if let Some(r) = self.held.iter_mut().find(|r| r.valid == serial) {
+            r.valid = 0;
+        }
+    }
+}
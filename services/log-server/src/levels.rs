@@ -0,0 +1,93 @@
+use crate::api::{LEVEL_NOT_SET, PROGRAM_NAME_LEN};
+
+/// Upper bound on the number of programs that can have a level set at the same time.
+const MAX_OVERRIDES: usize = 32;
+/// PIDs are a `u8`, so this covers every process.
+const MAX_PROCESSES: usize = 256;
+
+#[derive(Copy, Clone)]
+struct Name {
+    len: usize,
+    name: [u8; PROGRAM_NAME_LEN],
+}
+impl Name {
+    const EMPTY: Name = Name {
+        len: 0,
+        name: [0; PROGRAM_NAME_LEN],
+    };
+    fn new(name: &[u8]) -> Name {
+        let len = name.len().min(PROGRAM_NAME_LEN);
+        let mut n = Name::EMPTY;
+        n.name[..len].copy_from_slice(&name[..len]);
+        n.len = len;
+        n
+    }
+    fn as_bytes(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+}
+
+/// Tracks the name of each process that logs, and the levels set for programs by name.
+pub struct Levels {
+    /// indexed by PID
+    process_names: Vec<Name>,
+    overrides: [(Name, u32); MAX_OVERRIDES],
+}
+
+impl Levels {
+    pub fn new() -> Levels {
+        Levels {
+            process_names: vec![Name::EMPTY; MAX_PROCESSES],
+            overrides: [(Name::EMPTY, LEVEL_NOT_SET); MAX_OVERRIDES],
+        }
+    }
+
+    pub fn set_process_name(&mut self, pid: xous::PID, name: &[u8]) {
+        self.process_names[pid.get() as usize] = Name::new(name);
+    }
+
+    pub fn process_name(&self, pid: xous::PID) -> Option<&[u8]> {
+        let name = &self.process_names[pid.get() as usize];
+        if name.len == 0 {
+            None
+        } else {
+            Some(name.as_bytes())
+        }
+    }
+
+    /// Sets the level for `program`. `LEVEL_NOT_SET` removes it. Returns `false` if there is
+    /// no room for another program.
+    pub fn set_level(&mut self, program: &[u8], level: u32) -> bool {
+        let program = Name::new(program);
+        if let Some(entry) = self.overrides.iter_mut().find(|(n, _)| n.len != 0 && n.as_bytes() == program.as_bytes()) {
+            if level == LEVEL_NOT_SET {
+                *entry = (Name::EMPTY, LEVEL_NOT_SET);
+            } else {
+                entry.1 = level;
+            }
+            return true;
+        }
+        if level == LEVEL_NOT_SET {
+            return true;
+        }
+        if let Some(entry) = self.overrides.iter_mut().find(|(n, _)| n.len == 0) {
+            *entry = (program, level);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the level set for the program running as `pid`, or `LEVEL_NOT_SET`.
+    pub fn level(&self, pid: xous::PID) -> u32 {
+        let name = match self.process_name(pid) {
+            Some(name) => name,
+            None => return LEVEL_NOT_SET,
+        };
+        self.overrides
+            .iter()
+            .find(|(n, _)| n.len != 0 && n.as_bytes() == name)
+            .map(|(_, level)| *level)
+            .unwrap_or(LEVEL_NOT_SET)
+    }
+}
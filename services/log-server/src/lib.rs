@@ -22,11 +22,22 @@ struct XousLogger {
 }
 
 const BUFFER_SIZE: usize = 4096;
+/// The server is asked for the level set for this program with the first record, and again after
+/// every this many records, so that a change to it is picked up. Records are lent read-only, so
+/// they can't carry the level back.
+const LEVEL_REFRESH_INTERVAL: usize = 32;
 static mut XOUS_LOGGER_BACKING: Option<XousLoggerBacking> = None;
 
 struct XousLoggerBacking<'a> {
     conn: xous::CID,
     buffer: Buffer<'a>,
+    name_sent: bool,
+    /// the level set for this program with `set_level()`, if any
+    level: Option<log::LevelFilter>,
+    /// `log::max_level()` from before a level was set, restored once it is removed again
+    default_level: log::LevelFilter,
+    /// records that reached `log_impl()`, counting towards the next level refresh
+    records: usize,
 }
 
 impl<'a> XousLoggerBacking<'a> {
@@ -34,6 +45,10 @@ impl<'a> XousLoggerBacking<'a> {
         Ok(XousLoggerBacking {
             conn: xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())?,
             buffer: Buffer::new(BUFFER_SIZE),
+            name_sent: false,
+            level: None,
+            default_level: log::LevelFilter::Info,
+            records: 0,
         })
     }
 }
@@ -43,12 +58,75 @@ impl Default for XousLoggerBacking<'_> {
         XousLoggerBacking {
             conn: xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap()).unwrap(),
             buffer: Buffer::new(BUFFER_SIZE),
+            name_sent: false,
+            level: None,
+            default_level: log::LevelFilter::Info,
+            records: 0,
         }
     }
 }
 
 impl XousLoggerBacking<'_> {
+    /// Tells the server which program this is, so that levels set by program name apply to it.
+    fn send_name(&mut self) {
+        let name = xous::program_name().as_bytes();
+        if name.is_empty() {
+            return;
+        }
+        {
+            let program_name = unsafe { &mut *(self.buffer.as_mut_ptr() as *mut api::ProgramName) };
+            let length = name.len().min(program_name.name.len());
+            program_name.name[..length].copy_from_slice(&name[..length]);
+            program_name.length = length as u32;
+        }
+        self.buffer
+            .lend(self.conn, crate::api::Opcode::ProgramName.to_u32().unwrap())
+            .unwrap();
+    }
+
+    /// Applies the level the server holds for this program, as a `u32` (`LEVEL_NOT_SET` if there
+    /// is none).
+    fn apply_level(&mut self, level: u32) {
+        let level = api::level_filter_from_u32(level);
+        if level == self.level {
+            return;
+        }
+        if self.level.is_none() {
+            self.default_level = log::max_level();
+        }
+        self.level = level;
+        // A level quieter than the default is applied here rather than through `log::max_level`, so
+        // that records keep reaching `log_impl()` and a later change to the level is noticed.
+        let filter = match level {
+            Some(level) => level.max(self.default_level),
+            None => self.default_level,
+        };
+        if log::max_level() != filter {
+            log::set_max_level(filter);
+        }
+    }
+
+    fn refresh_level(&mut self) {
+        if let Ok(xous::Result::Scalar1(level)) = xous::send_message(
+            self.conn,
+            xous::Message::new_blocking_scalar(crate::api::Opcode::GetLevel.to_usize().unwrap(), 0, 0, 0, 0),
+        ) {
+            self.apply_level(level as u32);
+        }
+    }
+
     fn log_impl(&mut self, record: &log::Record) {
+        if !self.name_sent {
+            self.send_name();
+            self.name_sent = true;
+        }
+        if self.records % LEVEL_REFRESH_INTERVAL == 0 {
+            self.refresh_level();
+        }
+        self.records = self.records.wrapping_add(1);
+        if self.level.map_or(false, |level| record.level() > level) {
+            return;
+        }
         {
             assert!(core::mem::size_of::<api::LogRecord>() < BUFFER_SIZE);
             let log_record = unsafe { &mut *(self.buffer.as_mut_ptr() as *mut api::LogRecord) };
@@ -142,3 +220,145 @@ pub fn init_wait() -> Result<(), log::SetLoggerError> {
 pub fn resume() {
     unsafe { XOUS_LOGGER_BACKING.as_mut().unwrap().resume() };
 }
+
+fn encode_name(program: &str, name: &mut [u8; api::PROGRAM_NAME_LEN]) -> Result<u32, xous::Error> {
+    let program = program.as_bytes();
+    if program.len() == 0 || program.len() > name.len() {
+        return Err(xous::Error::InvalidString);
+    }
+    name[..program.len()].copy_from_slice(program);
+    Ok(program.len() as u32)
+}
+
+/// Makes the calling process the one that may set levels and read the RAM ring. Only the first
+/// process to ask is granted this, so it has to be asked for while the system boots, before any
+/// untrusted code runs. Fails with `AccessDenied` if another process holds it.
+pub fn claim_control() -> Result<(), xous::Error> {
+    let conn = xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())?;
+    match xous::send_message(
+        conn,
+        xous::Message::new_blocking_scalar(crate::api::Opcode::ClaimControl.to_usize().unwrap(), 0, 0, 0, 0),
+    )? {
+        xous::Result::Scalar1(1) => Ok(()),
+        _ => Err(xous::Error::AccessDenied),
+    }
+}
+
+/// Sets the most verbose level that is logged by `program`, which is the name of its binary (e.g.
+/// `shellchat`). Records above `level` are dropped at once, and the program itself picks up the new
+/// level within the next few records it logs; `None` removes the level again, and the program goes
+/// back to its own filter. Ignored unless the caller holds `claim_control()`.
+pub fn set_level(program: &str, level: Option<log::LevelFilter>) -> Result<(), xous::Error> {
+    let conn = xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())?;
+    let mut buffer = Buffer::new(core::mem::size_of::<api::SetLevel>());
+    {
+        let set_level = unsafe { &mut *(buffer.as_mut_ptr() as *mut api::SetLevel) };
+        set_level.name_length = encode_name(program, &mut set_level.name)?;
+        set_level.level = level.map(|l| l as u32).unwrap_or(api::LEVEL_NOT_SET);
+    }
+    buffer
+        .lend(conn, crate::api::Opcode::SetLevel.to_u32().unwrap())
+        .map(|_| ())
+}
+
+/// Copies log output kept in the server's RAM ring into `data`, starting `offset` bytes after the
+/// oldest byte it holds. If `panic_snapshot` is set, this reads the copy of the ring that was taken
+/// when the last panic finished instead. Returns the number of bytes copied, and the number of bytes
+/// in the ring. Unless the caller holds `claim_control()`, the ring reads as empty.
+pub fn read_ring(panic_snapshot: bool, offset: usize, data: &mut [u8]) -> Result<(usize, usize), xous::Error> {
+    let conn = xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())?;
+    let mut buffer = Buffer::new(core::mem::size_of::<api::ReadRing>());
+    {
+        let read_ring = unsafe { &mut *(buffer.as_mut_ptr() as *mut api::ReadRing) };
+        read_ring.panic_snapshot = if panic_snapshot { 1 } else { 0 };
+        read_ring.offset = offset as u32;
+        read_ring.total = 0;
+        read_ring.length = 0;
+    }
+    buffer.lend_mut(conn, crate::api::Opcode::ReadRing.to_u32().unwrap())?;
+    let read_ring = unsafe { &*(buffer.as_ptr() as *const api::ReadRing) };
+    let length = (read_ring.length as usize).min(data.len()).min(read_ring.data.len());
+    data[..length].copy_from_slice(&read_ring.data[..length]);
+    Ok((length, read_ring.total as usize))
+}
+
+/// Crash records are saved in this dictionary of the `.System` basis, one key per crash, named by
+/// a sequence number.
+pub const CRASHLOG_DICT: &'static str = "sys.crashlog";
+
+impl api::CrashRecord {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const api::CrashRecord as *const u8, core::mem::size_of::<api::CrashRecord>())
+        }
+    }
+
+    /// Recovers a record from `as_bytes()`. Returns `None` if `data` isn't a valid record.
+    pub fn from_bytes(data: &[u8]) -> Option<api::CrashRecord> {
+        if data.len() != core::mem::size_of::<api::CrashRecord>() {
+            return None;
+        }
+        let record = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const api::CrashRecord) };
+        if record.valid == 0
+            || record.name_length as usize > record.name.len()
+            || record.message_length as usize > record.message.len()
+            || record.log_length as usize > record.log.len()
+        {
+            return None;
+        }
+        Some(record)
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_length as usize]).unwrap_or("(invalid name)")
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message[..self.message_length as usize]
+    }
+
+    pub fn log(&self) -> &[u8] {
+        &self.log[..self.log_length as usize]
+    }
+}
+
+/// Reads the oldest crash record held by the server whose serial number (its `valid` field) is above
+/// `after`. Pass 0 to start with the oldest one. Records stay held until `acknowledge_crash_record()`
+/// is called for them.
+pub fn next_crash_record(after: u32) -> Result<Option<api::CrashRecord>, xous::Error> {
+    let conn = xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())?;
+    let mut buffer = Buffer::new(core::mem::size_of::<api::CrashRecord>());
+    {
+        let record = unsafe { &mut *(buffer.as_mut_ptr() as *mut api::CrashRecord) };
+        record.valid = after;
+    }
+    buffer.lend_mut(conn, crate::api::Opcode::ReadCrashRecord.to_u32().unwrap())?;
+    let record = unsafe { core::ptr::read(buffer.as_ptr() as *const api::CrashRecord) };
+    if record.valid == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(record))
+    }
+}
+
+/// Tells the server that the record with serial number `serial` has been saved, so it can stop
+/// holding it.
+pub fn acknowledge_crash_record(serial: u32) -> Result<(), xous::Error> {
+    let conn = xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())?;
+    xous::send_message(
+        conn,
+        xous::Message::new_scalar(crate::api::Opcode::AcknowledgeCrashRecord.to_usize().unwrap(), serial as usize, 0, 0, 0),
+    )
+    .map(|_| ())
+}
+
+/// Blocks until the server holds a crash record with a serial number above `after`. Only one caller
+/// can wait at a time; if a second one starts waiting, the first one returns early.
+pub fn wait_crash_record(after: u32) -> Result<(), xous::Error> {
+    let conn = xous::connect(xous::SID::from_bytes(b"xous-log-server ").unwrap())?;
+    xous::send_message(
+        conn,
+        xous::Message::new_blocking_scalar(crate::api::Opcode::WaitCrashRecord.to_usize().unwrap(), after as usize, 0, 0, 0),
+    )
+    .map(|_| ())
+}
@@ -3,6 +3,12 @@
 
 mod api;
 use api::*;
+mod levels;
+use levels::Levels;
+mod ring;
+use ring::LogRing;
+mod crash;
+use crash::CrashRecorder;
 
 #[cfg(any(target_os = "none", target_os = "xous"))]
 #[macro_use]
@@ -247,8 +253,82 @@ mod implementation {
     }
 }
 
+/// Everything the server keeps between messages.
+struct State {
+    levels: Levels,
+    /// the most recent log output
+    ring: LogRing,
+    /// a copy of `ring` taken when the last panic finished, so later output doesn't push it out
+    panic_ring: LogRing,
+    crash: CrashRecorder,
+    /// a `WaitCrashRecord` caller that is waiting for a record after the given serial number
+    crash_waiter: Option<(xous::MessageSender, u32)>,
+    /// the process that claimed `ClaimControl`, and may set levels and read the rings
+    controller: Option<xous::PID>,
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            levels: Levels::new(),
+            ring: LogRing::new(),
+            panic_ring: LogRing::new(),
+            crash: CrashRecorder::new(),
+            crash_waiter: None,
+            controller: None,
+        }
+    }
+}
+
+/// Sends log output to the console, and keeps a copy in the RAM ring.
+struct LogSink<'a> {
+    output: &'a mut implementation::OutputWriter,
+    ring: &'a mut LogRing,
+}
+
+impl LogSink<'_> {
+    fn putc(&mut self, c: u8) {
+        self.ring.putc(c);
+        self.output.putc(c);
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> core::result::Result<usize, ()> {
+        self.ring.write(buf);
+        self.output.write_all(buf)
+    }
+}
+
+impl Write for LogSink<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.ring.write(s.as_bytes());
+        self.output.write_str(s)
+    }
+}
+
+/// Unpacks the text of a `PanicMessage` into `output_bfr`, and returns its length.
+fn panic_message(msg: &xous::ScalarMessage, output_bfr: &mut [u8; core::mem::size_of::<usize>() * 4]) -> usize {
+    let output_iter = output_bfr.iter_mut();
+
+    // Combine the four arguments to form a single
+    // contiguous buffer. Note: The buffer size will change
+    // depending on the platfor's `usize` length.
+    let arg1_bytes = msg.arg1.to_le_bytes();
+    let arg2_bytes = msg.arg2.to_le_bytes();
+    let arg3_bytes = msg.arg3.to_le_bytes();
+    let arg4_bytes = msg.arg4.to_le_bytes();
+    let input_iter = arg1_bytes
+        .iter()
+        .chain(arg2_bytes.iter())
+        .chain(arg3_bytes.iter())
+        .chain(arg4_bytes.iter());
+    for (dest, src) in output_iter.zip(input_iter) {
+        *dest = *src;
+    }
+    (msg.id - 1100).min(output_bfr.len())
+}
+
 fn handle_scalar(
-    output: &mut implementation::OutputWriter,
+    output: &mut LogSink,
     sender: xous::MessageSender,
     msg: &xous::ScalarMessage,
     sender_pid: xous::PID,
@@ -258,28 +338,8 @@ fn handle_scalar(
         1100 => (),
         1101..=1132 => {
             let mut output_bfr = [0u8; core::mem::size_of::<usize>() * 4];
-            let output_iter = output_bfr.iter_mut();
-
-            // Combine the four arguments to form a single
-            // contiguous buffer. Note: The buffer size will change
-            // depending on the platfor's `usize` length.
-            let arg1_bytes = msg.arg1.to_le_bytes();
-            let arg2_bytes = msg.arg2.to_le_bytes();
-            let arg3_bytes = msg.arg3.to_le_bytes();
-            let arg4_bytes = msg.arg4.to_le_bytes();
-            let input_iter = arg1_bytes
-                .iter()
-                .chain(arg2_bytes.iter())
-                .chain(arg3_bytes.iter())
-                .chain(arg4_bytes.iter());
-            for (dest, src) in output_iter.zip(input_iter) {
-                *dest = *src;
-            }
-            let total_chars = msg.id - 1100;
-            for (idx, c) in output_bfr.iter().enumerate() {
-                if idx >= total_chars {
-                    break;
-                }
+            let total_chars = panic_message(msg, &mut output_bfr);
+            for c in output_bfr[..total_chars].iter() {
                 output.putc(*c);
             }
         }
@@ -300,15 +360,30 @@ fn handle_scalar(
 
 fn handle_opcode(
     output: &mut implementation::OutputWriter,
+    state: &mut State,
     sender: xous::MessageSender,
     opcode: api::Opcode,
     message: &xous::Message,
 ) {
+    let mut output = LogSink {
+        output,
+        ring: &mut state.ring,
+    };
+    // only mutably lent memory can carry a reply back to the sender
+    let writable = matches!(message, xous::Message::MutableBorrow(_));
     if let Some(mem) = message.memory_message() {
         match opcode {
             api::Opcode::LogRecord => {
-                let buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
+                let mut buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
+                let max_level = sender.pid().map(|pid| state.levels.level(pid)).unwrap_or(LEVEL_NOT_SET);
+                if writable {
+                    // a client that lends its records mutably is told which level was set for it
+                    unsafe { (*(buffer.as_mut_ptr() as *mut LogRecord)).max_level = max_level };
+                }
                 let lr = unsafe { &*(buffer.as_ptr() as *const LogRecord) };
+                if max_level != LEVEL_NOT_SET && lr.level > max_level {
+                    return;
+                }
                 let level = if log::Level::Error as u32 == lr.level {
                     "ERR "
                 } else if log::Level::Warn as u32 == lr.level {
@@ -381,13 +456,112 @@ fn handle_opcode(
                 output.write_all(buffer).unwrap();
                 // TODO: If the buffer is mutable, set `length` to 0.
             }
+            api::Opcode::ProgramName => {
+                let buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
+                let pn = unsafe { &*(buffer.as_ptr() as *const ProgramName) };
+                if pn.length as usize > pn.name.len() {
+                    return;
+                }
+                if let Some(pid) = sender.pid() {
+                    state.levels.set_process_name(pid, &pn.name[..pn.length as usize]);
+                }
+            }
+            api::Opcode::SetLevel => {
+                if sender.pid().is_none() || sender.pid() != state.controller {
+                    return;
+                }
+                let buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
+                let sl = unsafe { &*(buffer.as_ptr() as *const SetLevel) };
+                if sl.name_length as usize > sl.name.len() || sl.name_length == 0 {
+                    return;
+                }
+                if sl.level != LEVEL_NOT_SET && level_filter_from_u32(sl.level).is_none() {
+                    return;
+                }
+                let name = &sl.name[..sl.name_length as usize];
+                if !state.levels.set_level(name, sl.level) {
+                    write!(output, "LOG: too many program levels set, ignoring the level for ").ok();
+                    output.write_all(name).ok();
+                    writeln!(output).ok();
+                }
+            }
+            api::Opcode::ReadRing => {
+                if !writable || sender.pid().is_none() || sender.pid() != state.controller {
+                    return;
+                }
+                let mut buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
+                let rr = unsafe { &mut *(buffer.as_mut_ptr() as *mut ReadRing) };
+                let ring = if rr.panic_snapshot != 0 {
+                    &state.panic_ring
+                } else {
+                    &*output.ring
+                };
+                rr.total = ring.len() as u32;
+                rr.length = ring.read(rr.offset as usize, &mut rr.data) as u32;
+            }
+            api::Opcode::ReadCrashRecord => {
+                if !writable {
+                    return;
+                }
+                let mut buffer = unsafe { xous_ipc::Buffer::from_memory_message(mem) };
+                let record = unsafe { &mut *(buffer.as_mut_ptr() as *mut CrashRecord) };
+                let after = record.valid;
+                state.crash.read(after, record);
+            }
             _ => {
                 writeln!(output, "Unhandled opcode").unwrap();
             }
         }
     } else if let Some(scalar) = message.scalar_message() {
         // Scalar message
-        handle_scalar(output, sender, scalar, sender.pid().unwrap());
+        let sender_pid = sender.pid().unwrap();
+        if opcode == api::Opcode::WaitCrashRecord {
+            let after = scalar.arg1 as u32;
+            if state.crash.held_after(after) {
+                xous::return_scalar(sender, 1).ok();
+            } else if let Some((previous, _)) = state.crash_waiter.replace((sender, after)) {
+                // only one waiter is kept; the previous one will just find nothing new
+                xous::return_scalar(previous, 0).ok();
+            }
+            return;
+        }
+        if opcode == api::Opcode::AcknowledgeCrashRecord {
+            state.crash.acknowledge(scalar.arg1 as u32);
+            return;
+        }
+        if opcode == api::Opcode::ClaimControl {
+            if state.controller.is_none() {
+                state.controller = Some(sender_pid);
+            }
+            let granted = state.controller == Some(sender_pid);
+            xous::return_scalar(sender, if granted { 1 } else { 0 }).ok();
+            return;
+        }
+        if opcode == api::Opcode::GetLevel {
+            xous::return_scalar(sender, state.levels.level(sender_pid) as usize).ok();
+            return;
+        }
+        match scalar.id {
+            1000 => state.crash.started(sender_pid, scalar.arg1, state.levels.process_name(sender_pid), &*output.ring),
+            1101..=1132 => {
+                let mut text = [0u8; core::mem::size_of::<usize>() * 4];
+                let length = panic_message(scalar, &mut text);
+                state.crash.message(sender_pid, &text[..length]);
+            }
+            1200 => state.crash.finished(sender_pid),
+            _ => (),
+        }
+        handle_scalar(&mut output, sender, scalar, sender_pid);
+        if scalar.id == 1200 {
+            // keep what led up to the panic, along with the panic message itself
+            state.panic_ring.copy_from(&state.ring);
+        }
+        if let Some((waiter, after)) = state.crash_waiter {
+            if state.crash.held_after(after) {
+                state.crash_waiter = None;
+                xous::return_scalar(waiter, 1).ok();
+            }
+        }
     }
 }
 
@@ -398,6 +572,8 @@ fn reader_thread(arg: usize) {
     writeln!(output, "LOG: Server listening on address {:?}", server_addr).unwrap();
 
     println!("LOG: my PID is {}", xous::process::id());
+    // the rings and crash records are on the heap, so this is small enough for the thread's stack
+    let mut state = State::new();
     let mut counter: usize = 0;
     loop {
         if counter.trailing_zeros() >= 12 {
@@ -408,7 +584,7 @@ fn reader_thread(arg: usize) {
         let envelope = xous::syscall::receive_message(server_addr).expect("couldn't get address");
         let sender = envelope.sender;
         if let Some(opcode) = FromPrimitive::from_usize(envelope.body.id()) {
-            handle_opcode(output, sender, opcode, &envelope.body);
+            handle_opcode(output, &mut state, sender, opcode, &envelope.body);
         } else {
             writeln!(
                 output,
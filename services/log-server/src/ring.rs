@@ -0,0 +1,61 @@
+/// Size of the RAM log ring. Once full, the oldest output is overwritten.
+pub const RING_LEN: usize = 16 * 1024;
+
+/// Keeps the most recent `RING_LEN` bytes of log output, so they can be read back on devices
+/// without a serial cable. The bytes live on the heap, as the server thread's stack is too small
+/// for them.
+pub struct LogRing {
+    buf: Vec<u8>,
+    /// index of the next byte to be written
+    head: usize,
+    /// number of valid bytes
+    len: usize,
+}
+
+impl LogRing {
+    pub fn new() -> LogRing {
+        LogRing {
+            buf: vec![0; RING_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn putc(&mut self, c: u8) {
+        self.buf[self.head] = c;
+        self.head = (self.head + 1) % RING_LEN;
+        if self.len < RING_LEN {
+            self.len += 1;
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for c in data {
+            self.putc(*c);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn copy_from(&mut self, other: &LogRing) {
+        self.buf.copy_from_slice(&other.buf);
+        self.head = other.head;
+        self.len = other.len;
+    }
+
+    /// Copies bytes starting at `offset`, counted from the oldest byte in the ring, into `data`.
+    /// Returns the number of bytes copied.
+    pub fn read(&self, offset: usize, data: &mut [u8]) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let oldest = (self.head + RING_LEN - self.len) % RING_LEN;
+        let count = data.len().min(self.len - offset);
+        for (i, dest) in data[..count].iter_mut().enumerate() {
+            *dest = self.buf[(oldest + offset + i) % RING_LEN];
+        }
+        count
+    }
+}
@@ -67,6 +67,10 @@ pub(crate) const FSCB_FILL_COEFFICIENT: f32 = 0.5;
 #[allow(dead_code)]
 pub(crate) const FSCB_FILL_UNCERTAINTY: f32 = 0.1;
 
+/// Interval at which the ticktimer helper thread checks for bases that have timed out.
+#[allow(dead_code)]
+pub(crate) const BASIS_TIMEOUT_POLL_MS: usize = 1000;
+
 #[allow(dead_code)]
 pub const PDDB_DEFAULT_SYSTEM_BASIS: &'static str = ".System";
 // this isn't an "official" basis, but it is used for the AAD for encrypting the FastSpace structure
@@ -90,6 +94,24 @@ pub(crate) enum Opcode {
     CloseBasis,
     /// warning, the Delete routines have not been well tested
     DeleteBasis,
+    /// Reclaims deleted key slots, small pool blocks and large pool extents in a basis
+    CompactBasis,
+    /// Re-encrypts a secondary basis under a new password
+    ChangeBasisPassword,
+    /// Starts streaming a password-sealed backup archive of a basis; returns the archive header
+    BackupExportStart,
+    /// Returns the next frame of the backup archive being exported
+    BackupExportRead,
+    /// Starts restoring a backup archive into a mounted basis, given the archive header
+    BackupImportStart,
+    /// Feeds the next run of the archive being restored; a zero-length write completes the restore
+    BackupImportWrite,
+    /// Abandons the backup export or import in progress, given its token
+    BackupAbort,
+    /// Stages, commits or aborts an atomic multi-key transaction
+    Transaction,
+    /// Checks the open bases and the free space tracking for consistency, and optionally repairs them
+    Fsck,
     DeleteKey,
     DeleteDict,
     KeyAttributes,
@@ -111,11 +133,18 @@ pub(crate) enum Opcode {
     /// drops any connection state associated with a given key
     KeyDrop,
 
+    /// subscribe to change notifications on a dictionary or key
+    WatchRequest,
+    /// cancel a change notification subscription
+    Unwatch,
+
     /// Menu opcodes
     MenuListBasis,
 
     /// Suspend/resume callback
     SuspendResume,
+    /// Periodic poll from the ticktimer helper thread to expire bases with a `TimeOutSecs` policy
+    BasisTimeoutPoll,
     /// quit the server
     Quit,
     /// Write debug dump (only available in hosted mode)
@@ -128,6 +157,27 @@ pub(crate) enum PollOp {
     Quit,
 }
 
+/// Opcodes for the key change callback server that every `Pddb` object hosts. The PDDB server
+/// sends these to the `cb_sid` that was registered with a `PddbKeyRequest`.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum CbOp {
+    Change,
+    /// a watched dictionary or key changed; arguments are the three words of the watch token, and the `PddbWatchEvent`
+    Watch,
+    Quit
+}
+
+/// Kinds of changes reported to `Pddb::watch_dict()` and `Pddb::watch_key()` subscribers. This is passed as the first
+/// argument of the notification scalar message; the remaining three arguments are the watch token.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PddbWatchEvent {
+    Created,
+    Written,
+    Deleted,
+    /// the basis holding the watched items was locked, so they may no longer be visible
+    BasisLocked,
+}
+
 pub type ApiToken = [u32; 3];
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct PddbBasisList {
@@ -142,6 +192,7 @@ pub enum PddbRequestCode {
     Open,
     Close,
     Delete,
+    Compact,
     NoErr,
     NotMounted,
     NoFreeSpace,
@@ -155,14 +206,15 @@ pub enum PddbRequestCode {
 pub enum BasisRetentionPolicy {
     Persist,
     ClearAfterSleeps(u32),
-    //TimeOutSecs(u32),
+    /// Lock the basis after the given number of seconds without any key or dictionary access
+    TimeOutSecs(u32),
 }
 impl BasisRetentionPolicy {
     pub fn derive_init_state(&self) -> u32 {
         match self {
             BasisRetentionPolicy::Persist => 0,
             BasisRetentionPolicy::ClearAfterSleeps(sleeps) => *sleeps,
-            //BasisRetentionPolicy::TimeOutSecs(secs) => *secs,
+            BasisRetentionPolicy::TimeOutSecs(secs) => *secs,
         }
     }
 }
@@ -173,6 +225,11 @@ pub struct PddbBasisRequest {
     pub policy: Option<BasisRetentionPolicy>,
 }
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbPasswordChange {
+    pub name: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub code: PddbRequestCode,
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct PddbDictRequest {
     pub basis_specified: bool,
     pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
@@ -198,6 +255,120 @@ pub struct PddbKeyRequest {
     pub result: PddbRequestCode,
 }
 
+/// Size of the data window in a `PddbBackupChunk`. Every frame of a backup archive fits in one window.
+#[allow(dead_code)]
+pub(crate) const BACKUP_CHUNK_LEN: usize = 4000;
+/// Size of the plaintext header at the start of a backup archive
+#[allow(dead_code)]
+pub(crate) const BACKUP_HEADER_LEN: usize = 32;
+/// A backup export or import that sees no traffic for this long is abandoned, so a client that
+/// dies mid-stream doesn't lock out backups until the next reboot
+#[allow(dead_code)]
+pub(crate) const BACKUP_INACTIVITY_TIMEOUT_MS: u64 = 60_000;
+/// A transaction that sees no traffic for this long is discarded, so transactions left behind by a client
+/// that died don't hold on to their staged data forever
+#[allow(dead_code)]
+pub(crate) const TXN_INACTIVITY_TIMEOUT_MS: u64 = 60_000;
+/// Most transactions a process can have open at once. Each one can stage up to `TXN_MAX_LEN` bytes in the
+/// PDDB's memory until it is committed or aborted.
+#[allow(dead_code)]
+pub(crate) const TXN_MAX_OPEN_PER_PID: usize = 2;
+/// Most transactions that can be open at once, across all processes
+#[allow(dead_code)]
+pub(crate) const TXN_MAX_OPEN: usize = 8;
+/// A structure for streaming backup archives in and out of the PDDB
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbBackupChunk {
+    /// identifies the backup session; only one export or import can be in progress at a time
+    pub token: [u32; 4],
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    /// number of valid bytes in `data`
+    pub len: u32,
+    pub data: [u8; BACKUP_CHUNK_LEN],
+    pub code: PddbRequestCode,
+}
+
+/// Size of the data window in a `PddbTxnRequest`. Larger values are staged as a write followed by appends.
+#[allow(dead_code)]
+pub(crate) const TXN_CHUNK_LEN: usize = 3072;
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum PddbTxnOp {
+    Begin,
+    /// stages a write that replaces the value of a key
+    Write,
+    /// extends the value of the write that was just staged
+    Append,
+    Delete,
+    Commit,
+    Abort,
+}
+/// A structure for building up and committing transactions
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbTxnRequest {
+    /// identifies the transaction; assigned by the server on `Begin`
+    pub token: ApiToken,
+    pub op: PddbTxnOp,
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    /// number of valid bytes in `data`
+    pub len: u32,
+    pub data: [u8; TXN_CHUNK_LEN],
+    pub code: PddbRequestCode,
+}
+
+/// A structure for subscribing to changes of a dictionary, or of a single key in it
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbWatchRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    /// if false, every key in the dictionary is watched
+    pub key_specified: bool,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    pub cb_sid: [u32; 4],
+    pub token: Option<ApiToken>,
+    pub result: PddbRequestCode,
+}
+
+/// The outcome of a consistency check of the PDDB. Only the bases that are currently open can be checked.
+#[derive(Copy, Clone, Debug, Default)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct FsckReport {
+    /// number of open bases that were checked
+    pub bases: u32,
+    /// number of mapped pages that were checked
+    pub pages: u32,
+    /// page table entries that point at data which does not decrypt
+    pub bad_pages: u32,
+    /// pages that are mapped, but not referenced by the basis root, a dictionary, a key, or the transaction log
+    pub orphan_pages: u32,
+    /// dictionaries whose key count disagrees with their key descriptors, plus bases whose dictionary count
+    /// disagrees with their dictionaries
+    pub bad_counts: u32,
+    /// key descriptors whose data extent is inconsistent, or overlaps that of another key. These are reported, but not repaired.
+    pub bad_keys: u32,
+    /// pages that are in use, but which FastSpace considers to be free
+    pub fscb_conflicts: u32,
+    /// set if repairs were requested and made
+    pub repaired: bool,
+}
+impl FsckReport {
+    /// Total number of problems found
+    pub fn problems(&self) -> u32 {
+        self.bad_pages + self.orphan_pages + self.bad_counts + self.bad_keys + self.fscb_conflicts
+    }
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbFsckRequest {
+    /// if true, problems that can be fixed are fixed; otherwise, they are only reported
+    pub repair: bool,
+    pub report: FsckReport,
+    pub code: PddbRequestCode,
+}
+
 /// Return codes for Read/Write API calls to the main server
 #[repr(u8)]
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
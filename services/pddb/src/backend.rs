@@ -12,6 +12,12 @@ mod types;
 pub use types::*;
 mod bcrypt;
 pub use bcrypt::*;
+mod backup;
+pub(crate) use backup::*;
+mod txn;
+pub(crate) use txn::*;
+mod rekey;
+pub(crate) use rekey::*;
 
 // local to the backend
 mod murmur3;
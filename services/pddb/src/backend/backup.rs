@@ -0,0 +1,463 @@
+use crate::api::*;
+use super::*;
+
+use aes_gcm_siv::{Aes256GcmSiv, Nonce, Key, Tag};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use core::mem::size_of;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::{Result, Error, ErrorKind};
+
+/*
+Backup archive format
+
+A backup archive is a portable copy of the dictionaries and keys of a single basis. Unlike the
+data on the PDDB itself, nothing in an archive is bound to the device that created it: the
+sealing key is derived from a user-chosen backup password and a random salt, both of which
+travel with the archive, so it can be restored onto any device.
+
+The archive is a plaintext header followed by a sequence of sealed frames:
+
+  Header (BACKUP_HEADER_LEN bytes):
+    - magic: "PDBK" (4 bytes)
+    - version: u32
+    - bcrypt cost: u32
+    - salt: 16 bytes
+    - reserved: u32
+
+  Frame (at most BACKUP_CHUNK_LEN bytes):
+    - length of the ciphertext: u32
+    - nonce: 12 bytes
+    - ciphertext + tag: AES-GCM-SIV seal of one record
+
+The sealing key is bcrypt(cost, salt, password), expanded to 256 bits with SHA-512/256; this is
+the same construction used to derive basis keys, except that the salt is random instead of being
+derived from the device's salt base. The AAD of every frame is the header plus the index of the
+frame in the archive, so frames cannot be swapped between archives, re-ordered or dropped
+without detection. The final record is an explicit end-of-archive marker, so truncation is
+detected as well.
+
+Records are a one-byte type tag, followed by:
+  - Basis: the name of the basis the archive was taken from. Always the first record. The name is
+    kept in a sealed record, so an archive does not disclose the existence of a basis to someone
+    who does not know the backup password.
+  - Dict: a dictionary name. Emitted for every dictionary, so empty dictionaries are restored.
+  - Key: the dictionary name, key name, and total length of a key.
+  - KeyData: the next run of data for the most recent Key record.
+  - End: the number of keys in the archive.
+
+Names are encoded as a one-byte length followed by their UTF-8 bytes; integers are little-endian.
+*/
+
+pub(crate) const BACKUP_MAGIC: [u8; 4] = [0x50, 0x44, 0x42, 0x4b]; // "PDBK"
+pub(crate) const BACKUP_VERSION: u32 = 0x00_00_01_00;
+/// overhead of a frame on top of its sealed record: the length field, the nonce and the tag
+const FRAME_OVERHEAD: usize = size_of::<u32>() + size_of::<Nonce>() + size_of::<Tag>();
+/// largest record that fits in a frame of BACKUP_CHUNK_LEN bytes
+const MAX_RECORD_LEN: usize = BACKUP_CHUNK_LEN - FRAME_OVERHEAD;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+enum RecordType {
+    Basis = 1,
+    Dict = 2,
+    Key = 3,
+    KeyData = 4,
+    End = 5,
+}
+impl RecordType {
+    fn from_u8(code: u8) -> Option<RecordType> {
+        match code {
+            1 => Some(RecordType::Basis),
+            2 => Some(RecordType::Dict),
+            3 => Some(RecordType::Key),
+            4 => Some(RecordType::KeyData),
+            5 => Some(RecordType::End),
+            _ => None,
+        }
+    }
+}
+
+/// Derives the archive sealing key from a backup password. Follows the same bcrypt + SHA-512/256
+/// expansion as `PddbOs::basis_derive_key()`, but with the salt stored in the archive header.
+fn backup_derive_key(cost: u32, salt: &[u8; 16], password: &str) -> [u8; AES_KEYSIZE] {
+    use sha2::{FallbackStrategy, Sha512Trunc256};
+    use digest::Digest;
+
+    let mut hashed_password: [u8; 24] = [0; 24];
+    bcrypt(cost, salt, password, &mut hashed_password); // note: this internally makes a copy of the password, and destroys it
+    let mut expander = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+    expander.update(hashed_password);
+    let final_key = expander.finalize();
+    let mut key = [0u8; AES_KEYSIZE];
+    for (&src, dst) in final_key.iter().zip(key.iter_mut()) {
+        *dst = src;
+    }
+    let hp_ptr = hashed_password.as_mut_ptr();
+    for i in 0..hashed_password.len() {
+        unsafe{hp_ptr.add(i).write_volatile(core::mem::zeroed());}
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    key
+}
+
+fn frame_aad(header: &[u8; BACKUP_HEADER_LEN], frame: u64) -> [u8; BACKUP_HEADER_LEN + 8] {
+    let mut aad = [0u8; BACKUP_HEADER_LEN + 8];
+    aad[..BACKUP_HEADER_LEN].copy_from_slice(header);
+    aad[BACKUP_HEADER_LEN..].copy_from_slice(&frame.to_le_bytes());
+    aad
+}
+
+fn push_name(record: &mut Vec::<u8>, name: &str) {
+    assert!(name.len() <= u8::MAX as usize, "name is too long to be archived");
+    record.push(name.len() as u8);
+    record.extend_from_slice(name.as_bytes());
+}
+
+/// A cursor over the fields of a decrypted record.
+struct RecordReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> RecordReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.data.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "backup record is truncated"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn name(&mut self) -> Result<String> {
+        let len = self.bytes(1)?[0] as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::new(ErrorKind::InvalidData, "backup record name is not valid utf-8"))
+    }
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(size_of::<u64>())?.try_into().unwrap()))
+    }
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        self.pos = self.data.len();
+        rest
+    }
+}
+
+enum ExportItem {
+    Basis,
+    Dict(String),
+    Key(String, String),
+    End,
+}
+
+/// Produces a backup archive of a basis, one frame at a time. The list of dictionaries and keys
+/// is captured when the export starts; key data is read out as the frames are generated, so the
+/// archive never has to be held in memory as a whole.
+pub(crate) struct BackupExporter {
+    cipher: Aes256GcmSiv,
+    header: [u8; BACKUP_HEADER_LEN],
+    basis: String,
+    /// index of the next frame to be sealed
+    frame: u64,
+    queue: VecDeque<ExportItem>,
+    /// key whose data is being emitted: (dict, key, offset of the next KeyData record, total length)
+    current: Option<(String, String, usize, usize)>,
+    key_count: u64,
+    header_sent: bool,
+}
+impl BackupExporter {
+    pub(crate) fn new(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis_name: &str, password: &str) -> Result<BackupExporter> {
+        if !basis_cache.basis_list().iter().any(|name| name == basis_name) {
+            return Err(Error::new(ErrorKind::NotFound, "basis is not mounted"));
+        }
+        let mut queue = VecDeque::<ExportItem>::new();
+        queue.push_back(ExportItem::Basis);
+        let mut dicts: Vec::<String> = basis_cache.dict_list(hw, Some(basis_name)).into_iter().collect();
+        dicts.sort();
+        for dict in dicts {
+            let mut keys: Vec::<String> = basis_cache.key_list(hw, &dict, Some(basis_name))?.into_iter().collect();
+            keys.sort();
+            queue.push_back(ExportItem::Dict(dict.to_string()));
+            for key in keys {
+                queue.push_back(ExportItem::Key(dict.to_string(), key));
+            }
+        }
+        queue.push_back(ExportItem::End);
+
+        let mut salt = [0u8; 16];
+        hw.trng_slice(&mut salt);
+        let mut header = [0u8; BACKUP_HEADER_LEN];
+        header[..4].copy_from_slice(&BACKUP_MAGIC);
+        header[4..8].copy_from_slice(&BACKUP_VERSION.to_le_bytes());
+        header[8..12].copy_from_slice(&BCRYPT_COST.to_le_bytes());
+        header[12..28].copy_from_slice(&salt);
+        let key = backup_derive_key(BCRYPT_COST, &salt, password);
+        Ok(BackupExporter {
+            cipher: Aes256GcmSiv::new(Key::from_slice(&key)),
+            header,
+            basis: basis_name.to_string(),
+            frame: 0,
+            queue,
+            current: None,
+            key_count: 0,
+            header_sent: false,
+        })
+    }
+    /// Returns the next piece of the archive: the header on the first call, and then one frame per
+    /// call. Returns `None` once the end-of-archive frame has been produced.
+    pub(crate) fn next_chunk(&mut self, hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<Option<Vec::<u8>>> {
+        if !self.header_sent {
+            self.header_sent = true;
+            return Ok(Some(self.header.to_vec()));
+        }
+        let mut record = Vec::<u8>::new();
+        if let Some((dict, key, offset, len)) = self.current.take() {
+            let chunk_len = (len - offset).min(MAX_RECORD_LEN - 1);
+            let mut data = vec![0u8; chunk_len];
+            let read = basis_cache.key_read(hw, &dict, &key, &mut data, Some(offset), Some(&self.basis))?;
+            if read != chunk_len {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "key changed length during the backup"));
+            }
+            record.push(RecordType::KeyData as u8);
+            record.extend_from_slice(&data);
+            if offset + chunk_len < len {
+                self.current = Some((dict, key, offset + chunk_len, len));
+            }
+        } else {
+            match self.queue.pop_front() {
+                Some(ExportItem::Basis) => {
+                    record.push(RecordType::Basis as u8);
+                    push_name(&mut record, &self.basis);
+                }
+                Some(ExportItem::Dict(dict)) => {
+                    record.push(RecordType::Dict as u8);
+                    push_name(&mut record, &dict);
+                }
+                Some(ExportItem::Key(dict, key)) => {
+                    let len = basis_cache.key_attributes(hw, &dict, &key, Some(&self.basis))?.len;
+                    record.push(RecordType::Key as u8);
+                    push_name(&mut record, &dict);
+                    push_name(&mut record, &key);
+                    record.extend_from_slice(&(len as u64).to_le_bytes());
+                    self.key_count += 1;
+                    if len > 0 {
+                        self.current = Some((dict, key, 0, len));
+                    }
+                }
+                Some(ExportItem::End) => {
+                    record.push(RecordType::End as u8);
+                    record.extend_from_slice(&self.key_count.to_le_bytes());
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(self.seal(hw, &record)))
+    }
+    fn seal(&mut self, hw: &mut PddbOs, record: &[u8]) -> Vec::<u8> {
+        assert!(record.len() <= MAX_RECORD_LEN, "backup record is too large for a frame");
+        let nonce = hw.nonce_gen();
+        let ciphertext = self.cipher.encrypt(
+            &nonce,
+            Payload {
+                aad: &frame_aad(&self.header, self.frame),
+                msg: record,
+            }
+        ).expect("couldn't encrypt backup record");
+        self.frame += 1;
+        let mut frame = Vec::<u8>::with_capacity(FRAME_OVERHEAD + record.len());
+        frame.extend_from_slice(&(ciphertext.len() as u32).to_le_bytes());
+        frame.extend_from_slice(nonce.as_slice());
+        frame.extend_from_slice(&ciphertext);
+        frame
+    }
+}
+
+/// Restores a backup archive into a mounted basis. The archive body can be handed to `write()` in
+/// pieces of any size; its frames are checked and staged in a `Transaction` as they come in, and
+/// nothing is applied to the basis until `finish()` has seen the end of the archive, so a restore
+/// that fails part-way leaves the basis as it was. Keys in the archive replace keys of the same name
+/// in the target basis, and everything else in the target basis is left alone. As a consequence, an
+/// archive can only be restored if its keys fit in one transaction (`TXN_MAX_LEN`).
+pub(crate) struct BackupImporter {
+    cipher: Aes256GcmSiv,
+    header: [u8; BACKUP_HEADER_LEN],
+    /// basis to restore into; `None` restores into the basis the archive was taken from
+    basis: Option<String>,
+    /// index of the next frame to be opened
+    frame: u64,
+    /// archive bytes that don't form a complete frame yet
+    pending: Vec::<u8>,
+    /// the restored keys, staged until the whole archive has checked out. Started by the Basis record.
+    txn: Option<Transaction>,
+    /// every dictionary in the archive, so empty ones are restored too
+    dicts: Vec<String>,
+    /// key whose data is being restored: (dict, key, bytes staged so far, total length)
+    current: Option<(String, String, usize, usize)>,
+    key_count: u64,
+    finished: bool,
+    /// dictionaries created and keys restored, for change notifications
+    changes: Vec<(String, Option<String>, PddbWatchEvent)>,
+}
+impl BackupImporter {
+    pub(crate) fn new(header: &[u8], password: &str, basis_name: Option<&str>) -> Result<BackupImporter> {
+        if header.len() != BACKUP_HEADER_LEN || header[..4] != BACKUP_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a PDDB backup archive"));
+        }
+        if u32::from_le_bytes(header[4..8].try_into().unwrap()) != BACKUP_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported backup archive version"));
+        }
+        // the header isn't authenticated until the first frame is opened, so a cost other than the one
+        // archives are made with could only serve to stall the PDDB in bcrypt, or to weaken the key
+        let cost = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if cost != BCRYPT_COST {
+            return Err(Error::new(ErrorKind::InvalidData, "backup archive has an unsupported bcrypt cost"));
+        }
+        let key = backup_derive_key(cost, header[12..28].try_into().unwrap(), password);
+        Ok(BackupImporter {
+            cipher: Aes256GcmSiv::new(Key::from_slice(&key)),
+            header: header.try_into().unwrap(),
+            basis: basis_name.map(|name| name.to_string()),
+            frame: 0,
+            pending: Vec::<u8>::new(),
+            txn: None,
+            dicts: Vec::new(),
+            current: None,
+            key_count: 0,
+            finished: false,
+            changes: Vec::new(),
+        })
+    }
+    pub(crate) fn write(&mut self, basis_cache: &BasisCache, data: &[u8]) -> Result<()> {
+        self.pending.extend_from_slice(data);
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= size_of::<u32>() {
+            let ct_len = u32::from_le_bytes(self.pending[consumed..consumed + size_of::<u32>()].try_into().unwrap()) as usize;
+            if ct_len < size_of::<Tag>() || ct_len > MAX_RECORD_LEN + size_of::<Tag>() {
+                return Err(Error::new(ErrorKind::InvalidData, "backup archive frame has an invalid length"));
+            }
+            let frame_len = size_of::<u32>() + size_of::<Nonce>() + ct_len;
+            if self.pending.len() - consumed < frame_len {
+                break;
+            }
+            let frame = self.pending[consumed..consumed + frame_len].to_vec();
+            consumed += frame_len;
+            self.open_frame(basis_cache, &frame)?;
+        }
+        self.pending.drain(..consumed);
+        Ok(())
+    }
+    /// Checks that the whole archive was received, and commits the restored data to disk in one
+    /// transaction.
+    pub(crate) fn finish(&mut self, hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+        if !self.finished || !self.pending.is_empty() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "backup archive is truncated"));
+        }
+        let txn = self.txn.take().unwrap(); // the archive starts with a Basis record, or it couldn't have finished
+        let created: Vec<String> = self.dicts.drain(..)
+            .filter(|dict| basis_cache.dict_attributes(hw, dict, Some(&txn.basis)).is_err())
+            .collect();
+        basis_cache.txn_commit(hw, &txn)?;
+        // the transaction created the dictionaries that have keys; the empty ones are left
+        for dict in created.iter() {
+            if basis_cache.dict_attributes(hw, dict, Some(&txn.basis)).is_err() {
+                basis_cache.dict_add(hw, dict, Some(&txn.basis))?;
+            }
+        }
+        for dict in created {
+            self.changes.push((dict, None, PddbWatchEvent::Created));
+        }
+        for op in txn.ops().iter() {
+            self.changes.push((op.dict().to_string(), Some(op.key().to_string()), PddbWatchEvent::Written));
+        }
+        basis_cache.sync(hw, Some(&txn.basis))
+    }
+    /// The basis being restored into, once it is known
+    pub(crate) fn basis(&self) -> Option<&str> {
+        self.basis.as_deref()
+    }
+    /// Returns the dictionaries created and the keys restored, once the restore has been committed
+    pub(crate) fn take_changes(&mut self) -> Vec<(String, Option<String>, PddbWatchEvent)> {
+        std::mem::take(&mut self.changes)
+    }
+    fn open_frame(&mut self, basis_cache: &BasisCache, frame: &[u8]) -> Result<()> {
+        if self.finished {
+            return Err(Error::new(ErrorKind::InvalidData, "data found after the end of the backup archive"));
+        }
+        let nonce = &frame[size_of::<u32>()..size_of::<u32>() + size_of::<Nonce>()];
+        let record = match self.cipher.decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                aad: &frame_aad(&self.header, self.frame),
+                msg: &frame[size_of::<u32>() + size_of::<Nonce>()..],
+            }
+        ) {
+            Ok(record) => record,
+            // the first frame is the first place the password gets checked
+            Err(_) if self.frame == 0 => return Err(Error::new(ErrorKind::PermissionDenied, "incorrect backup password")),
+            Err(_) => return Err(Error::new(ErrorKind::InvalidData, "backup archive is corrupted")),
+        };
+        self.frame += 1;
+        if record.len() == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "empty backup record"));
+        }
+        let record_type = RecordType::from_u8(record[0]).ok_or(Error::new(ErrorKind::InvalidData, "unknown backup record type"))?;
+        let mut reader = RecordReader { data: &record[1..], pos: 0 };
+        if (self.frame == 1) != (record_type == RecordType::Basis) {
+            return Err(Error::new(ErrorKind::InvalidData, "backup archive does not start with a basis record"));
+        }
+        if self.current.is_some() != (record_type == RecordType::KeyData) {
+            return Err(Error::new(ErrorKind::InvalidData, "backup archive key data is out of sequence"));
+        }
+        match record_type {
+            RecordType::Basis => {
+                let name = reader.name()?;
+                if self.basis.is_none() {
+                    self.basis = Some(name);
+                }
+                let basis = self.basis.as_deref().unwrap();
+                if !basis_cache.basis_list().iter().any(|name| name == basis) {
+                    return Err(Error::new(ErrorKind::NotFound, "basis is not mounted"));
+                }
+                log::info!("restoring backup into basis {}", basis);
+                self.txn = Some(Transaction::new(basis));
+            }
+            RecordType::Dict => {
+                let dict = reader.name()?;
+                self.dicts.push(dict);
+            }
+            RecordType::Key => {
+                let dict = reader.name()?;
+                let key = reader.name()?;
+                let len = reader.u64()? as usize;
+                log::debug!("staging {}:{} ({} bytes)", dict, key, len);
+                if len > TXN_MAX_LEN {
+                    return Err(Error::new(ErrorKind::OutOfMemory, "backup archive is too large to restore"));
+                }
+                self.key_count += 1;
+                // the write replaces any existing key of the same name; the data is appended to it
+                self.txn.as_mut().unwrap().write(&dict, &key, &[])?;
+                if len != 0 {
+                    self.current = Some((dict, key, 0, len));
+                }
+            }
+            RecordType::KeyData => {
+                let (dict, key, staged, len) = self.current.take().unwrap();
+                let data = reader.rest();
+                if staged + data.len() > len {
+                    return Err(Error::new(ErrorKind::InvalidData, "backup archive key data is longer than the key"));
+                }
+                self.txn.as_mut().unwrap().append(&dict, &key, data)?;
+                if staged + data.len() < len {
+                    self.current = Some((dict, key, staged + data.len(), len));
+                }
+            }
+            RecordType::End => {
+                if reader.u64()? != self.key_count {
+                    return Err(Error::new(ErrorKind::InvalidData, "backup archive key count mismatch"));
+                }
+                self.finished = true;
+            }
+        }
+        Ok(())
+    }
+}
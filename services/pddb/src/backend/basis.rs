@@ -63,11 +63,18 @@
 /// | 0x0000_003F_80FE_0000  |    - Dict[1] pool = 16MiB                 |
 /// | 0x0000_007E_FE04_0000  |    - Dict[16383] pool                     |
 /// | 0x0000_007E_FF02_0000  |  Unused                                   |
-/// | 0x0000_007F_0000_0000  |  Medium data pool start                   |
-/// |                        |    - TBD                                  |
+/// | 0x0000_007F_0000_0000  |  Medium data pool start  (~127TiB)        |
+/// |                        |    - Dict[0] pool = ~7.9GiB               |
+/// |                        |      - free-list allocated, VPAGE units   |
+/// | 0x0000_0080_FC00_0000  |    - Dict[1] pool = ~7.9GiB               |
+/// | 0x0000_7F7B_0800_0000  |    - Dict[16382] pool                     |
+/// | 0x0000_7F7D_0400_0000  |  Transaction log                          |
+/// |                        |    - header VPAGE, then up to 1024 VPAGEs |
+/// | 0x0000_7F7D_043F_8FE0  |  Re-key record (up to 256 VPAGEs)         |
+/// | 0x0000_7F7D_044F_6FE0  |  Unused                                   |
 /// | 0x0000_FE00_0000_0000  |  Large data pool start  (~16mm TiB)       |
 /// |                        |    - Demand-allocated, bump-pointer       |
-/// |                        |      currently no defrag                  |
+/// |                        |      defragmented by basis compaction     |
 /// ```
 ///
 /// Note that each Basis has its own memory section, and you can have "many" orthogonal Basis without
@@ -76,21 +83,30 @@
 ///
 /// ## Memory Pools
 ///
-/// Key data is split into three categories of sizes: small, medium, and large; but the implementation
-/// currently only deals with small and large keys. The thresholds are subject to tuning, but
-/// roughly speaking, small data are keys <4k bytes; large keys are everything else.
+/// Key data is split into three categories of sizes: small, medium, and large. The thresholds are subject
+/// to tuning, but roughly speaking, small data are keys <4k bytes; medium keys are up to 64 VPAGEs
+/// (~254kiB); large keys are everything else.
 ///
 /// Large keys are the simplest - each key starts at a VPAGE-aligned address, and allocates
 /// up from there. Any unused amount is wasted, but with a ~32k threshold you'll have no worse
 /// than 12.5% unused space, probably closer to ~7%-ish if all your data hovered around the threshold.
-/// The allocation is a simple pointer that just keeps going up. De-allocated space is never defragmented,
-/// and we just rely on the space being "huge" to save us.
+/// The allocation is a simple pointer that just keeps going up. De-allocated space is not reclaimed during
+/// normal operation, and we mostly rely on the space being "huge" to save us; however, a compaction pass
+/// (`Pddb::compact()`) will slide the live large keys back down to the start of the pool, renumber the key
+/// descriptors, and repack the small pools, returning any freed pages to the FastSpace.
 ///
 /// Small keys are kept in VPAGE-sized pools of data, and compacted together in RAM. The initial, naive
 /// implementation simply keeps all small keys in a HashMap in RAM, and when it comes time to sync them
 /// to disk, they are sorted by update count, and written to disk in ascending order.
 ///
-/// Medium keys have a TBD implementation, and are currently directed to the large pool for now.
+/// Medium keys are allocated in whole VPAGEs out of a region that belongs to their dictionary, like the
+/// small pool. Unlike the large pool, the allocator keeps a free-list of de-allocated extents (coalescing
+/// neighbors), so the virtual space is re-used and a dictionary with a lot of churn in its medium keys
+/// does not leak address space. The free-list isn't stored on disk: it's re-derived from the gaps between
+/// the medium keys of a dictionary once all of its key descriptors have been read in. A medium key that
+/// outgrows its reservation is re-allocated, either to a bigger medium extent, or to the large pool if it
+/// no longer fits the medium threshold. If a dictionary's medium region is ever exhausted, new keys
+/// spill over into the large pool.
 ///
 /// ## The Alignment and Serialization Chronicles
 ///
@@ -145,8 +161,11 @@ use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::io::{Result, Error, ErrorKind};
 use std::cmp::Reverse;
 use core::num::NonZeroU32;
+use locales::t;
+use subtle::ConstantTimeEq;
 
 pub(crate) const SMALL_POOL_START: u64 = 0x0000_003F_8000_0000;
+#[allow(dead_code)]
 pub(crate) const SMALL_POOL_END: u64 = 0x0000_007E_FF02_0000;
 pub(crate) const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
 /// we don't want this bigger than VPAGE_SIZE, because a key goal of the small pool is to
@@ -154,6 +173,13 @@ pub(crate) const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
 /// if we made this larger than a VPAGE_SIZE, we don't get much gain in terms of write reduction,
 /// and it greatly complicates the implementation. So, SMALL_CAPACITY should be less than VPAGE_SIZE.
 pub(crate) const SMALL_CAPACITY: usize = VPAGE_SIZE;
+pub(crate) const MEDIUM_POOL_START: u64 = 0x0000_007F_0000_0000;
+/// Size of each dictionary's medium pool region. This is a multiple of VPAGE_SIZE (0xFE0 * 0x20_0000).
+pub(crate) const MEDIUM_POOL_STRIDE: u64 = 0x0000_0001_FC00_0000;
+#[allow(dead_code)]
+pub(crate) const MEDIUM_POOL_END: u64 = 0x0000_7F7D_0400_0000;
+/// Keys whose reservation is too large for the small pool, but at most this size, are stored in the medium pool.
+pub(crate) const MEDIUM_CAPACITY: usize = VPAGE_SIZE * 64;
 pub(crate) const LARGE_POOL_START: u64 = 0x0000_FE00_0000_0000;
 pub(crate) const KEY_MAXCOUNT: usize = 131_071; // 2^17 - 1
 /// This is a size limit on the biggest file you can create. It's currently 32GiB. No, this is not
@@ -274,6 +300,7 @@ impl BasisCache {
         if let Some(basis_index) = self.select_basis(basis_name) {
             let basis = &mut self.cache[basis_index];
             basis.age = basis.age.saturating_add(1);
+            basis.touch(hw);
 
             if basis.ensure_dict_in_cache(hw, name) {
                 return Err(Error::new(ErrorKind::AlreadyExists, "Dictionary already exists"));
@@ -338,6 +365,7 @@ impl BasisCache {
         if basis_name.is_some() {
             if let Some(basis_index) = self.select_basis(basis_name) {
                 let basis = &mut self.cache[basis_index];
+                basis.touch(hw);
                 basis.populate_caches(hw);
                 for (key, dcache) in basis.dicts.iter() {
                     if dcache.flags.valid() {
@@ -363,6 +391,7 @@ impl BasisCache {
         if basis_name.is_some() {
             if let Some(basis_index) = self.select_basis(basis_name) {
                 let basis = &mut self.cache[basis_index];
+                basis.touch(hw);
                 basis.populate_caches(hw);
                 if let Some(dcache) = basis.dicts.get_mut(dict) {
                     dcache.key_list(hw, &basis.v2p_map, &basis.cipher, &mut merge_list);
@@ -398,6 +427,7 @@ impl BasisCache {
 
             basis.age = basis.age.saturating_add(1);
             basis.clean = false;
+            basis.touch(hw);
             basis.dict_delete(hw, dict, paranoid)?;
             basis.basis_sync(hw);
             basis.pt_sync(hw);
@@ -412,6 +442,7 @@ impl BasisCache {
     ) -> Result<usize> {
         if let Some(basis_index) = self.select_basis(basis_name) {
             let basis = &mut self.cache[basis_index];
+            basis.touch(hw);
             if !basis.ensure_dict_in_cache(hw, dict) {
                 return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
             }
@@ -419,7 +450,7 @@ impl BasisCache {
                 if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
                     let kcache = dict_entry.keys.get_mut(key).expect("Entry was assured, but then not there!");
                     // the key exists, *and* there's sufficient space for the data
-                    if kcache.start < SMALL_POOL_END {
+                    if kcache.pool == KeyPool::Small {
                         // small pool fetch
                         // for now, the rule is, if we have a small key, we also have its data in cache.
                         if let KeyCacheData::Small(cache_data) = kcache.data.as_mut().expect("small pool should all have their data 'hot' if the index entry is also in cache") {
@@ -445,7 +476,7 @@ impl BasisCache {
                         if data.len() == 0 { // mostly because i don't want to have to think about this case in the later logic.
                             return Ok(0)
                         }
-                        // large pool fetch
+                        // medium and large pool fetch
                         let mut cur_offset = offset.unwrap_or(0) as u64;
                         let mut blocks_read = 0;
                         loop {
@@ -531,6 +562,7 @@ impl BasisCache {
             if let Some(dict_entry) = basis.dicts.get_mut(dict) {
                 basis.age = basis.age.saturating_add(1);
                 basis.clean = false;
+                basis.last_access = hw.timestamp_now();
                 if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
                     if !paranoid {
                         dict_entry.key_remove(hw, &mut basis.v2p_map, &basis.cipher, key, false);
@@ -606,6 +638,9 @@ impl BasisCache {
                                 // we're definitely going to need another small pool page
                                 pages_needed += 1;
                             }
+                        } else if kcache.pool == KeyPool::Medium && reserved as u64 > kcache.reserved {
+                            // a medium key that outgrows its extent is re-allocated in its entirety
+                            pages_needed += reserved_pages;
                         } else {
                             // it's a large block. see if its address have been mapped
                             // large pool start addresses should always be vpage aligned
@@ -659,6 +694,7 @@ impl BasisCache {
             }
             // refetch the basis here to avoid the re-borrow problem, now that all the potential dict cache mutations are done
             let basis = &mut self.cache[basis_index];
+            basis.touch(hw);
 
             // bumping this every key update affects performance *a lot* -- don't think this is worth it.
             // the bases should only "age" when dicts or keys are modified, not when any data in it is updated for any reason.
@@ -705,6 +741,7 @@ impl BasisCache {
                 if !basis.ensure_dict_in_cache(hw, dict) {
                     continue;
                 } else {
+                    basis.touch(hw);
                     let dict_entry = basis.dicts.get_mut(dict).expect("Entry was assured, but not there!");
                     if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
                         let kcache = match dict_entry.keys.get_mut(key) {
@@ -732,6 +769,7 @@ impl BasisCache {
                 if !basis.ensure_dict_in_cache(hw, dict) {
                     return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
                 } else {
+                    basis.touch(hw);
                     let dict_entry = basis.dicts.get_mut(dict).expect("Entry was assured, but not there!");
                     if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
                         let kcache = dict_entry.keys.get_mut(key).expect("Entry was assured, but then not there!");
@@ -891,8 +929,16 @@ impl BasisCache {
         }
     }
 
-    pub(crate) fn basis_add(&mut self, basis: BasisCacheEntry) {
+    /// Adds a freshly mounted basis to the cache. If the basis holds a transaction log that was left behind
+    /// by an interrupted commit, the transaction is completed or rolled back here, before anyone can see
+    /// the basis. Likewise, an interrupted password change is finished here.
+    pub(crate) fn basis_add(&mut self, hw: &mut PddbOs, basis: BasisCacheEntry) {
+        let name = basis.name.clone();
         self.cache.push(basis);
+        if let Some(basis_index) = self.select_basis(Some(&name)) {
+            rekey_recover(hw, &mut self.cache, basis_index);
+        }
+        self.txn_recover(hw, &name);
     }
 
     pub(crate) fn basis_unmount(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<()> {
@@ -907,8 +953,7 @@ impl BasisCache {
     }
 
     /// note: you can "delete" a basis simply by forgetting its password, but this is more thorough.
-    /// there might also need to be a variant to make which is a "change my password" function, but that is actually
-    /// surprisingly hard.
+    /// To change the password of a basis instead, see `basis_change_password()`.
     pub(crate) fn basis_delete(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<()> {
         if let Some(basis_index) = self.select_basis(Some(basis_name)) {
             let basis = &mut self.cache[basis_index];
@@ -926,6 +971,235 @@ impl BasisCache {
         }
     }
 
+    /// Changes the password of a secondary basis. The basis does not have to be mounted; if it isn't, it is
+    /// unlocked with `old_password` for the duration of the change, and locked again afterwards. Every page of
+    /// the basis is re-encrypted under the new key, and the copy under the old key is destroyed. See rekey.rs
+    /// for how this is made safe against power loss.
+    pub(crate) fn basis_change_password(&mut self, hw: &mut PddbOs, basis_name: &str, old_password: &str, new_password: &str,
+    progress: Option<&modals::Modals>) -> Result<()> {
+        self.basis_rekey(hw, basis_name, old_password, new_password, true, progress)
+    }
+
+    /// Re-keys `basis_name` to `new_password`. Not finishing the job is only useful for testing what happens
+    /// to a password change that is interrupted after its commit point.
+    pub(crate) fn basis_rekey(&mut self, hw: &mut PddbOs, basis_name: &str, old_password: &str, new_password: &str,
+    finish: bool, progress: Option<&modals::Modals>) -> Result<()> {
+        if basis_name == PDDB_DEFAULT_SYSTEM_BASIS {
+            return Err(Error::new(ErrorKind::InvalidInput, "The system basis password can't be changed"));
+        }
+        let old_key = hw.basis_derive_key(basis_name, old_password);
+        let was_open = if let Some(basis_index) = self.select_basis(Some(basis_name)) {
+            if !bool::from(self.cache[basis_index].key.as_slice().ct_eq(&old_key)) {
+                return Err(Error::new(ErrorKind::PermissionDenied, "Old password is incorrect"));
+            }
+            true
+        } else {
+            match self.basis_unlock(hw, basis_name, old_password, BasisRetentionPolicy::Persist) {
+                Some(basis) => self.basis_add(hw, basis),
+                None => return Err(Error::new(ErrorKind::PermissionDenied, "Basis not found, or old password is incorrect")),
+            }
+            false
+        };
+        let basis_index = self.select_basis(Some(basis_name)).expect("basis disappeared while changing its password");
+        {
+            let basis = &mut self.cache[basis_index];
+            basis.touch(hw);
+            basis.sync(hw)?;
+        }
+        let new_key = hw.basis_derive_key(basis_name, new_password);
+        let result = basis_rekey(hw, &mut self.cache, basis_index, &new_key, finish, progress);
+        if !was_open {
+            self.basis_unmount(hw, basis_name)?;
+        }
+        result
+    }
+
+    /// Compacts the named basis, or the latest open basis if `None` is specified. See
+    /// `BasisCacheEntry::dict_compact()` for details on what is reclaimed.
+    pub(crate) fn compact(&mut self, hw: &mut PddbOs, basis_name: Option<&str>, progress: Option<&modals::Modals>) -> Result<()> {
+        // the compaction should only ever free pages, but descriptor and pool rewrites need a little slack
+        if !hw.ensure_fast_space_alloc(2, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space to compact basis"));
+        }
+        if let Some(basis_index) = self.select_basis(basis_name) {
+            let basis = &mut self.cache[basis_index];
+            basis.touch(hw);
+            basis.dict_compact(hw, progress)
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Basis not found"))
+        }
+    }
+
+    /// Checks all the open bases for consistency, optionally repairing them. See `PddbOs::pddb_fsck()` for details.
+    pub(crate) fn fsck(&mut self, hw: &mut PddbOs, repair: bool) -> Result<FsckReport> {
+        if self.cache.len() == 0 {
+            return Err(Error::new(ErrorKind::NotFound, "PDDB is not mounted"));
+        }
+        // flush pending changes, so the caches and the disk agree before they are compared
+        self.sync(hw, None)?;
+        if repair && !hw.ensure_fast_space_alloc(2, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space to repair the PDDB"));
+        }
+        hw.pddb_fsck(&mut self.cache, repair)
+    }
+
+    /// Commits every change in `txn` to its basis, atomically. The transaction is applied to copies of the pages
+    /// it touches, and then switched in through the page table; if the commit is interrupted, it is rolled back or
+    /// forward the next time the basis is mounted. See txn.rs for details.
+    pub(crate) fn txn_commit(&mut self, hw: &mut PddbOs, txn: &Transaction) -> Result<()> {
+        self.txn_commit_until(hw, txn, TxnStage::Done)
+    }
+
+    /// Commits `txn`, but stops at `stage`, leaving the disk as a power loss would. The caches are re-read from disk
+    /// when stopping early, so they describe what a mount would find.
+    pub(crate) fn txn_commit_until(&mut self, hw: &mut PddbOs, txn: &Transaction, stage: TxnStage) -> Result<()> {
+        if txn.ops().len() == 0 {
+            return Ok(());
+        }
+        if !hw.ensure_fast_space_alloc(txn.pages_needed(), &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space to commit transaction"));
+        }
+        let basis_index = self.select_basis(Some(&txn.basis))
+            .ok_or(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))?;
+        {
+            let basis = &mut self.cache[basis_index];
+            basis.touch(hw);
+            // the basis is synced first, so that everything in it is on disk, and nothing outside of the
+            // transaction rides along on its commit
+            basis.sync(hw)?;
+            hw.txn_shadow_begin(TxnShadow::new(&basis.v2p_map));
+        }
+        let applied = self.txn_apply(hw, txn);
+        let shadow = hw.txn_shadow_end().expect("transaction shadow went missing");
+        let (remap, live, frees) = shadow.finish();
+        let body = remap_serialize(&remap);
+        let mut staged = applied;
+        if staged.is_ok() && (shadow.failed() || !hw.fast_space_has_pages(txn_log_pages(body.len()))) {
+            staged = Err(Error::new(ErrorKind::OutOfMemory, "No free space to commit transaction"));
+        }
+        if staged.is_ok() {
+            staged = match self.select_basis(Some(&txn.basis)) {
+                Some(basis_index) => txn_log_stage(hw, &mut self.cache[basis_index], &body),
+                None => Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted.")),
+            };
+        }
+        if staged.is_ok() {
+            let basis_index = self.select_basis(Some(&txn.basis)).unwrap();
+            if stage != TxnStage::Staged {
+                staged = txn_log_seal(hw, &mut self.cache[basis_index], &body);
+            }
+        }
+        if let Err(e) = staged {
+            // nothing on disk refers to the pages the transaction wrote, so rolling back is a matter of
+            // returning them, and forgetting the caches
+            shadow.abandon(hw);
+            self.basis_reload(hw, &txn.basis);
+            return Err(e);
+        }
+        if stage == TxnStage::Staged {
+            self.basis_reload(hw, &txn.basis);
+            return Ok(());
+        }
+        let basis_index = self.select_basis(Some(&txn.basis)).unwrap();
+        // The transaction is committed at this point. If anything below is interrupted, the sealed record is
+        // applied again on the next mount.
+        match stage {
+            TxnStage::Sealed => {
+                self.basis_reload(hw, &txn.basis);
+                return Ok(());
+            }
+            TxnStage::Remapped(count) => {
+                remap_apply(hw, &self.cache[basis_index], &remap[..count.min(remap.len())]);
+                self.basis_reload(hw, &txn.basis);
+                return Ok(());
+            }
+            _ => (),
+        }
+        let basis = &mut self.cache[basis_index];
+        remap_apply(hw, basis, &remap);
+        for (va, mut copy) in live.into_iter() {
+            copy.set_clean(true);
+            copy.set_valid(true);
+            basis.v2p_map.insert(va, copy);
+        }
+        for mut pp in frees.into_iter() {
+            // this includes the pages the copies replaced, which would have been overwritten by an ordinary update
+            let mut noise = [0u8; PAGE_SIZE];
+            hw.trng_slice(&mut noise);
+            hw.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+            hw.fast_space_free(&mut pp);
+        }
+        txn_log_retire(hw, basis);
+        Ok(())
+    }
+
+    /// Applies the operations of a transaction. This is only called with the hardware in shadow mode.
+    fn txn_apply(&mut self, hw: &mut PddbOs, txn: &Transaction) -> Result<()> {
+        let basis_name = Some(txn.basis.as_str());
+        for op in txn.ops().iter() {
+            match op {
+                TxnOp::Write { dict, key, data } => {
+                    // writes replace the whole key, so a shorter value doesn't inherit the tail of the old one
+                    if self.key_attributes(hw, dict, key, basis_name).is_ok() {
+                        self.key_remove(hw, dict, key, basis_name, false)?;
+                    }
+                    self.key_update(hw, dict, key, data, None, None, basis_name, true)?;
+                }
+                TxnOp::Delete { dict, key } => {
+                    match self.key_remove(hw, dict, key, basis_name, false) {
+                        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        self.sync(hw, basis_name)
+    }
+
+    /// Finishes or rolls back a transaction that was interrupted while it was being committed to `basis_name`.
+    fn txn_recover(&mut self, hw: &mut PddbOs, basis_name: &str) {
+        if let Some(basis_index) = self.select_basis(Some(basis_name)) {
+            let mut remapped = false;
+            if let Some(body) = txn_log_read(hw, &self.cache[basis_index]) {
+                match remap_deserialize(&body) {
+                    Ok(remap) => {
+                        log::warn!("Basis {} has an unfinished transaction, completing it", basis_name);
+                        remap_apply(hw, &self.cache[basis_index], &remap);
+                        remapped = true;
+                    }
+                    Err(e) => log::error!("Transaction log of basis {} is corrupt, discarding it: {:?}", basis_name, e),
+                }
+            }
+            // this also cleans up after a log that was never sealed, which rolls back its transaction. The pages
+            // that were freed or abandoned by the transaction are reclaimed by the next full-space sweep.
+            txn_log_retire(hw, &mut self.cache[basis_index]);
+            if remapped {
+                // the caches were read before the page table was updated
+                self.basis_reload(hw, basis_name);
+            }
+        }
+    }
+
+    /// Re-reads a basis from disk, replacing its caches. This is used when the caches no longer describe what
+    /// is on disk, such as after a transaction is abandoned.
+    fn basis_reload(&mut self, hw: &mut PddbOs, basis_name: &str) {
+        if let Some(basis_index) = self.select_basis(Some(basis_name)) {
+            let old = &self.cache[basis_index];
+            let key: [u8; AES_KEYSIZE] = old.key.as_slice().try_into().unwrap();
+            match BasisCacheEntry::mount(hw, basis_name, &key, false, old.policy) {
+                Some(mut basis) => {
+                    basis.policy_state = old.policy_state;
+                    basis.last_access = old.last_access;
+                    self.cache[basis_index] = basis;
+                }
+                None => {
+                    log::error!("Basis {} could not be re-read after a transaction, unmounting it", basis_name);
+                    self.cache.remove(basis_index);
+                }
+            }
+        }
+    }
+
     pub(crate) fn sync(&mut self, hw: &mut PddbOs, basis_name: Option<&str>) -> Result<()> {
         if basis_name.is_some() {
             if let Some(basis_index) = self.select_basis(basis_name) {
@@ -939,7 +1213,9 @@ impl BasisCache {
         Ok(())
     }
 
-    pub(crate) fn suspend(&mut self, hw: &mut PddbOs) {
+    /// Syncs all the bases and locks the ones whose retention policy calls for it. Returns the
+    /// list of bases that were locked, so the caller can notify any clients holding keys in them.
+    pub(crate) fn suspend(&mut self, hw: &mut PddbOs) -> Vec<String> {
         self.sync(hw, None).expect("couldn't sync on suspend");
         let mut lock_list = Vec::<String>::new();
         for basis in self.cache.iter_mut() {
@@ -951,18 +1227,41 @@ impl BasisCache {
                         lock_list.push(basis.name.clone());
                     }
                 }
-                /*
-                BasisRetentionPolicy::TimeOutSecs(secs) => {
-                    if secs < basis.policy_state {
-                        lock_list.push(basis.name.clone());
-                    }
-                }*/
+                // timeouts are handled by `timeout_check()`, which is driven by the ticktimer
+                BasisRetentionPolicy::TimeOutSecs(_) => (),
             }
         }
-        for basis in lock_list {
-            log::info!("unmounting basis on sleep: {}", &basis);
-            self.basis_unmount(hw, &basis).ok();
+        for basis in lock_list.iter() {
+            log::info!("unmounting basis on sleep: {}", basis);
+            self.basis_unmount(hw, basis).ok();
         }
+        lock_list
+    }
+
+    /// Returns true if any of the open bases have a `TimeOutSecs` retention policy.
+    pub(crate) fn has_timeouts(&self) -> bool {
+        self.cache.iter().any(|b| matches!(b.policy, BasisRetentionPolicy::TimeOutSecs(_)))
+    }
+
+    /// Locks any basis whose `TimeOutSecs` policy has elapsed since the last time it was accessed.
+    /// Returns the list of bases that were locked.
+    pub(crate) fn timeout_check(&mut self, hw: &mut PddbOs) -> Vec<String> {
+        let now = hw.timestamp_now();
+        let mut lock_list = Vec::<String>::new();
+        for basis in self.cache.iter_mut() {
+            if let BasisRetentionPolicy::TimeOutSecs(_) = basis.policy {
+                // policy_state holds the timeout in seconds; it is refreshed from the policy on every access
+                let elapsed = now.saturating_sub(basis.last_access);
+                if elapsed >= basis.policy_state as u64 * 1000 {
+                    lock_list.push(basis.name.clone());
+                }
+            }
+        }
+        for basis in lock_list.iter() {
+            log::info!("unmounting basis on timeout: {}", basis);
+            self.basis_unmount(hw, basis).ok();
+        }
+        lock_list
     }
 }
 
@@ -996,12 +1295,14 @@ pub(crate) struct BasisCacheEntry {
     pub v2p_map: HashMap<VirtAddr, PhysPage>,
     /// the last journal rev written to disk
     pub journal: u32,
-    /// current allocation pointer for the "large" pool. This just keeps incrementing "forever".
+    /// current allocation pointer for the "large" pool. This just keeps incrementing until the basis is compacted.
     pub large_alloc_ptr: Option<PageAlignedVa>,
     /// retiention policy
     pub policy: BasisRetentionPolicy,
     // rention state
     pub policy_state: u32,
+    /// time of last access, in systicks. Used to compute `TimeOutSecs` expiry.
+    pub last_access: u64,
 }
 impl BasisCacheEntry {
     /// given a pointer to the hardware, name of the basis, and its cryptographic key, try to derive
@@ -1054,6 +1355,7 @@ impl BasisCacheEntry {
                     large_alloc_ptr: None,
                     policy,
                     policy_state: policy.derive_init_state(),
+                    last_access: hw.timestamp_now(),
                 };
                 if !lazy {
                     bcache.populate_caches(hw);
@@ -1070,6 +1372,10 @@ impl BasisCacheEntry {
             None
         }
     }
+    /// Resets the inactivity countdown for `TimeOutSecs` bases. Call on any key or dictionary access.
+    pub(crate) fn touch(&mut self, hw: &PddbOs) {
+        self.last_access = hw.timestamp_now();
+    }
     /// called during the initial basis scan to track where the large allocation pointer end should be.
     /// basically try to find the maximal extent of already allocated data, and start allocating from there.
     pub(crate) fn large_pool_update(&mut self, maybe_end: u64) {
@@ -1480,9 +1786,200 @@ impl BasisCacheEntry {
     /// Runs through the dictionary listing in a basis and compacts them. Call when the
     /// the dictionary space becomes sufficiently fragmented that accesses are becoming
     /// inefficient.
-    #[allow(dead_code)]
-    pub(crate) fn dict_compact(&self, _basis_name: Option<&str>) -> Result<()> {
-        unimplemented!();
+    ///
+    /// The compaction:
+    ///   - relocates the large keys so they occupy consecutive slots starting at `LARGE_POOL_START`,
+    ///     and resets the `large_alloc_ptr` to just past the last live key
+    ///   - slides the medium keys of every dictionary down to the base of its medium pool region
+    ///   - repacks the small pool of every dictionary, freeing the pool pages that are no longer needed
+    ///   - renumbers the key descriptors so deleted slots are reclaimed, and frees any descriptor pages
+    ///     that are no longer needed
+    ///
+    /// Medium and large key data is not copied: only the virtual-to-physical mappings are moved, which is safe
+    /// because the data page encryption does not bind the virtual address.
+    pub(crate) fn dict_compact(&mut self, hw: &mut PddbOs, progress: Option<&modals::Modals>) -> Result<()> {
+        // make sure everything is in RAM, and that the v2p map has been purged of any freed pages
+        self.populate_caches(hw);
+        self.sync(hw)?;
+
+        let mut dictnames = Vec::<String>::new();
+        for (dict, entry) in self.dicts.iter() {
+            if entry.flags.valid() {
+                dictnames.push(dict.to_string());
+            }
+        }
+        if let Some(modals) = progress {
+            modals.start_progress(t!("pddb.compact", xous::LANG), 0, dictnames.len() as u32 + 1, 0)
+                .expect("couldn't raise progress bar");
+        }
+        self.large_pool_compact();
+        if let Some(modals) = progress {
+            modals.update_progress(1).expect("couldn't update progress bar");
+        }
+        for (i, dict) in dictnames.iter().enumerate() {
+            log::debug!("compacting dict {}", dict);
+            let dict_entry = self.dicts.get_mut(dict).expect("dict disappeared during compaction");
+            dict_entry.medium_pool_compact(&mut self.v2p_map);
+            dict_entry.small_pool_compact(hw, &mut self.v2p_map);
+            if !dict_entry.sync_small_pool(hw, &mut self.v2p_map, &self.cipher) {
+                if let Some(modals) = progress {
+                    modals.finish_progress().expect("couldn't dismiss progress bar");
+                }
+                return Err(Error::new(ErrorKind::OutOfMemory, "Ran out of memory syncing small pool"));
+            }
+            self.dict_rewrite(hw, dict)?;
+            if let Some(modals) = progress {
+                modals.update_progress(i as u32 + 2).expect("couldn't update progress bar");
+            }
+        }
+        self.age = self.age.saturating_add(1);
+        self.clean = false;
+        self.basis_sync(hw);
+        self.pt_sync(hw);
+        if let Some(modals) = progress {
+            modals.finish_progress().expect("couldn't dismiss progress bar");
+        }
+        Ok(())
+    }
+
+    /// Moves every large key into consecutive `LARGE_FILE_MAX_SIZE` slots starting at `LARGE_POOL_START`,
+    /// in order of their current start address. Only the v2p map and the key cache entries are updated;
+    /// the relocated PTEs are marked dirty so they get re-written on the next `pt_sync`, and the key descriptors
+    /// are marked dirty so they get re-written on the next `dict_sync`.
+    fn large_pool_compact(&mut self) {
+        let mut large_keys = Vec::<(u64, String, String)>::new();
+        for (dict_name, dict) in self.dicts.iter() {
+            if dict.flags.valid() {
+                for (key_name, kcache) in dict.keys.iter() {
+                    if kcache.flags.valid() && kcache.pool == KeyPool::Large {
+                        large_keys.push((kcache.start, dict_name.to_string(), key_name.to_string()));
+                    }
+                }
+            }
+        }
+        large_keys.sort();
+        // detach all the pages first, so a relocated key can never land on top of one that hasn't been moved yet
+        let mut relocations = Vec::<(u64, u64, PhysPage)>::new();
+        let mut alloc_ptr = PageAlignedVa::from(LARGE_POOL_START);
+        for (start, dict_name, key_name) in large_keys {
+            let dict = self.dicts.get_mut(&dict_name).expect("dict disappeared during compaction");
+            let kcache = dict.keys.get_mut(&key_name).expect("key disappeared during compaction");
+            for vpage in kcache.large_pool_vpages() {
+                if let Some(pp) = self.v2p_map.remove(&vpage) {
+                    relocations.push((vpage.get(), alloc_ptr.as_u64() + (vpage.get() - start), pp));
+                }
+            }
+            if start != alloc_ptr.as_u64() {
+                log::debug!("relocating {}:{} 0x{:x}->0x{:x}", dict_name, key_name, start, alloc_ptr.as_u64());
+                kcache.start = alloc_ptr.as_u64();
+                kcache.clean = false;
+                dict.clean = false;
+            }
+            alloc_ptr = alloc_ptr + PageAlignedVa::from(LARGE_FILE_MAX_SIZE);
+        }
+        for (old_vaddr, new_vaddr, mut pp) in relocations {
+            if old_vaddr != new_vaddr {
+                pp.set_clean(false);
+            }
+            self.v2p_map.insert(VirtAddr::new(new_vaddr).unwrap(), pp);
+        }
+        self.large_alloc_ptr = Some(alloc_ptr);
+    }
+
+    /// Renumbers the key descriptors of the named dictionary and re-writes its entire descriptor region,
+    /// freeing any trailing descriptor pages that are no longer needed. Unlike `dict_sync`, this does not
+    /// merge into the existing pages, so stale descriptors left in vacated slots are wiped out.
+    fn dict_rewrite(&mut self, hw: &mut PddbOs, name: &str) -> Result<()> {
+        self.last_sync = Some(hw.timestamp_now());
+        if let Some(dict) = self.dicts.get_mut(name) {
+            let dict_offset = VirtAddr::new(dict.index.get() as u64 * DICT_VSIZE).unwrap();
+            let old_pages = dict.key_index_compact();
+            let new_pages = 1 + dict.last_disk_key_index as usize / DK_PER_VPAGE;
+            let dict_name = DictName::try_from_str(name).or(Err(Error::new(ErrorKind::InvalidInput, "dictionary name invalid: invalid utf-8 or length")))?;
+            let dict_disk = Dictionary {
+                flags: dict.flags,
+                age: dict.age,
+                num_keys: dict.key_count,
+                free_key_index: dict.last_disk_key_index,
+                name: dict_name,
+            };
+            // assemble the descriptor pages in RAM
+            let mut dk_vpages = Vec::<DictKeyVpage>::new();
+            for _ in 0..new_pages {
+                dk_vpages.push(DictKeyVpage::default());
+            }
+            let mut dk_entry = DictKeyEntry::default();
+            for (&src, dst) in dict_disk.deref().iter().zip(dk_entry.data.iter_mut()) {
+                *dst = src;
+            }
+            dk_vpages[0].elements[0] = Some(dk_entry);
+            for (key_name, key) in dict.keys.iter_mut() {
+                let mut dk_entry = DictKeyEntry::default();
+                let kn = KeyName::try_from_str(key_name).or(Err(Error::new(ErrorKind::InvalidInput, "key name invalid: invalid utf-8 or length")))?;
+                let key_desc = KeyDescriptor {
+                    start: key.start,
+                    len: key.len,
+                    reserved: key.reserved,
+                    flags: key.flags,
+                    age: key.age,
+                    name: kn,
+                };
+                for (&src, dst) in key_desc.deref().iter().zip(dk_entry.data.iter_mut()) {
+                    *dst = src;
+                }
+                dk_vpages[key.descriptor_vpage_num()].elements[key.descriptor_index.get() as usize % DK_PER_VPAGE] = Some(dk_entry);
+                key.clean = true;
+            }
+            // write them out, re-using the existing physical pages where possible
+            for (vpage_num, dk_vpage) in dk_vpages.iter().enumerate() {
+                let cur_vpage = VirtAddr::new(dict_offset.get() + (vpage_num as u64 * VPAGE_SIZE as u64)).unwrap();
+                let pp = self.v2p_map.entry(cur_vpage).or_insert_with(|| {
+                    let mut ap = hw.try_fast_space_alloc().expect("FastSpace empty");
+                    ap.set_valid(true);
+                    ap
+                });
+                assert!(pp.valid(), "v2p returned an invalid page");
+                let mut page = if let Some(mut data) = hw.data_decrypt_page(&self.cipher, &self.aad, &pp) {
+                    // keep the journal rev, but nothing else
+                    for b in data[size_of::<JournalType>()..].iter_mut() {
+                        *b = 0;
+                    }
+                    data
+                } else {
+                    let mut d = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+                    for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(d[..size_of::<JournalType>()].iter_mut()) {
+                        *dst = src;
+                    }
+                    d
+                };
+                for (index, stride) in page[size_of::<JournalType>()..].chunks_mut(DK_STRIDE).enumerate() {
+                    if let Some(elem) = dk_vpage.elements[index] {
+                        for (&src, dst) in elem.data.iter().zip(stride.iter_mut()) {
+                            *dst = src;
+                        }
+                    }
+                }
+                hw.data_encrypt_and_patch_page(&self.cipher, &self.aad, &mut page, &pp);
+            }
+            // reclaim the descriptor pages that are no longer in use
+            for vpage_num in new_pages..old_pages {
+                let dk_vaddr = VirtAddr::new(dict_offset.get() + (vpage_num as u64 * VPAGE_SIZE as u64)).unwrap();
+                if let Some(pp) = self.v2p_map.get_mut(&dk_vaddr) {
+                    assert!(pp.valid(), "v2p returned an invalid page");
+                    let mut noise = [0u8; PAGE_SIZE];
+                    hw.trng_slice(&mut noise);
+                    hw.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+                    log::trace!("fast_space_free dict_rewrite {} before", pp.journal());
+                    hw.fast_space_free(pp);
+                    assert!(pp.valid() == false, "pp is still marked as valid!");
+                }
+            }
+            log::debug!("rewrote dict {} with {} keys; descriptor pages {}->{}", name, dict.key_count, old_pages, new_pages);
+            dict.clean = true;
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "dict_rewrite called with an invalid dictionary name"))
+        }
     }
 
     /// Syncs *only* the basis header to disk.
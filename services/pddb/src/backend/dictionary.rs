@@ -46,8 +46,8 @@ pub(crate) struct DictCacheEntry {
     /// goes completely empty, the entry should still exist but indicate that it's got space. Thus if a key was found allocated
     /// to the Nth index position, but the previous N-1 positions are empty, the only way we could have gotten there was if we
     /// had allocated lots of small data, filled upo the pool to the Nth position, and then deleted all of that prior data.
-    /// This situation could create pathologies in the memory usage overhead of the small_pool, which until we have a "defrag"
-    /// operation for the small pool, we may just have to live with.
+    /// This situation could create pathologies in the memory usage overhead of the small_pool, which are cleaned up
+    /// by `small_pool_compact()` when the basis is compacted.
     pub(crate) small_pool: Vec<KeySmallPool>,
     /// free space of each small pool element. It's a collection of free space along with the Vec index of the small_pool.
     /// We don't keep the KeySmallPool itself in the small_pool_free directly because it's presumed to be more common
@@ -56,6 +56,9 @@ pub(crate) struct DictCacheEntry {
     /// then we modify the pool item, and then we .push() it back into the heap (or if it doesn't fit at all we allocate a new
     /// entry and return the original item plus the new one to the heap).
     pub(crate) small_pool_free: BinaryHeap<KeySmallPoolOrd>,
+    /// allocator for the medium pool. This is `None` until every key descriptor in the dictionary has been read in,
+    /// because the free space can only be derived from the complete set of medium key extents.
+    pub(crate) medium_pool: Option<KeyMediumPool>,
     /// copy of our AAD, for convenience
     pub(crate) aad: Vec::<u8>,
 }
@@ -78,6 +81,7 @@ impl DictCacheEntry {
             flags: dict.flags,
             small_pool: Vec::<KeySmallPool>::new(),
             small_pool_free: BinaryHeap::<KeySmallPoolOrd>::new(),
+            medium_pool: None,
             aad: my_aad,
         }
     }
@@ -102,13 +106,21 @@ impl DictCacheEntry {
             let mut smallkeys_to_fill = Vec::<String>::new(); // work around inner mutability problem by copying the names of keys to fill
             for (name, key) in self.keys.iter() {
                 if key.flags.valid() {
-                    if key.start + key.reserved > alloc_top.get() {
-                        // if the key is within the large pool space, note its allocation for the basis overall
-                        alloc_top = VirtAddr::new(key.start + key.reserved).unwrap();
-                        // nothing else needs to be done -- we don't pre-cache large key data.
-                    } else {
-                        if key.data.is_none() {
-                            smallkeys_to_fill.push(name.to_string());
+                    match key.pool {
+                        KeyPool::Large => {
+                            if key.start + key.reserved > alloc_top.get() {
+                                // if the key is within the large pool space, note its allocation for the basis overall
+                                alloc_top = VirtAddr::new(key.start + key.reserved).unwrap();
+                            }
+                            // nothing else needs to be done -- we don't pre-cache large key data.
+                        }
+                        KeyPool::Medium => {
+                            // medium key data is not pre-cached, either.
+                        }
+                        KeyPool::Small => {
+                            if key.data.is_none() {
+                                smallkeys_to_fill.push(name.to_string());
+                            }
                         }
                     }
                 }
@@ -118,6 +130,10 @@ impl DictCacheEntry {
             for key_to_fill in smallkeys_to_fill.iter() {
                 self.try_fill_small_key(hw, v2p_map, cipher, &mut data_cache, key_to_fill);
             }
+            // every key is known, so we can derive the medium pool free space if we haven't done so already
+            if self.medium_pool.is_none() {
+                self.rebuild_medium_pool();
+            }
         } else {
             let mut index_cache = PlaintextCache { data: None, tag: None };
             let mut data_cache = PlaintextCache { data: None, tag: None };
@@ -156,6 +172,7 @@ impl DictCacheEntry {
                             descriptor_index: NonZeroU32::new(try_entry as u32).unwrap(),
                             clean: true,
                             data: None,
+                            pool: KeyPool::from_vaddr(keydesc.start),
                         };
                         let kname = std::str::from_utf8(&keydesc.name.data[..keydesc.name.len as usize]).expect("key is not valid utf-8");
                         let key_exists_and_valid =
@@ -165,14 +182,23 @@ impl DictCacheEntry {
                                 false
                             };
                         if !key_exists_and_valid {
+                            let pool = kcache.pool;
                             self.keys.insert(kname.to_string(), kcache);
-                            if keydesc.start + keydesc.reserved > alloc_top.get() {
-                                // if the key is within the large pool space, note its allocation for the basis overall
-                                alloc_top = VirtAddr::new(keydesc.start + keydesc.reserved).unwrap();
-                                // nothing else needs to be done -- we don't pre-cache large key data.
-                            } else {
-                                // try to fill the small key cache entry details
-                                self.try_fill_small_key(hw, v2p_map, cipher, &mut data_cache, &kname);
+                            match pool {
+                                KeyPool::Large => {
+                                    if keydesc.start + keydesc.reserved > alloc_top.get() {
+                                        // if the key is within the large pool space, note its allocation for the basis overall
+                                        alloc_top = VirtAddr::new(keydesc.start + keydesc.reserved).unwrap();
+                                    }
+                                    // nothing else needs to be done -- we don't pre-cache large key data.
+                                }
+                                KeyPool::Medium => {
+                                    // the medium pool allocator is rebuilt once the scan is done
+                                }
+                                KeyPool::Small => {
+                                    // try to fill the small key cache entry details
+                                    self.try_fill_small_key(hw, v2p_map, cipher, &mut data_cache, &kname);
+                                }
                             }
                         } else {
                             log::trace!("fill: entry already present {}", kname);
@@ -187,6 +213,8 @@ impl DictCacheEntry {
 
             // now build the small_pool_free binary heap structure
             self.rebuild_free_pool();
+            // and the medium pool free space
+            self.rebuild_medium_pool();
         }
         alloc_top
     }
@@ -258,6 +286,7 @@ impl DictCacheEntry {
                                 descriptor_index: NonZeroU32::new(try_entry as u32).unwrap(),
                                 clean: true,
                                 data: None,
+                                pool: KeyPool::from_vaddr(keydesc.start),
                             };
                             self.keys.insert(kname.to_string(), kcache);
                             self.try_fill_small_key(hw, v2p_map, cipher, &mut data_cache, &kname);
@@ -289,8 +318,8 @@ impl DictCacheEntry {
                     // fill in the pool with blank entries. In general, we should have a low amount of blank entries, but
                     // one situation where we could get a leak is if we allocate a large amount of small data, and then delete
                     // all but the most recently allocated one, leaving an orphan at a high index, which is then subsequently
-                    // treated as read-only so none of the subsequent write/update ops would have occassion to move it. This would
-                    // need to be remedied with a "defrag" operation, but for now, we don't have that.
+                    // treated as read-only so none of the subsequent write/update ops would have occassion to move it. This is
+                    // remedied by compacting the basis, which repacks the small pool.
                     let ksp = KeySmallPool::new();
                     self.small_pool.push(ksp);
                 }
@@ -359,7 +388,7 @@ impl DictCacheEntry {
             kcache.clean = false;
             // the update isn't going to fit in the reserved space, remove it, and re-insert it with an entirely new entry.
             if kcache.reserved < (data.len() + offset) as u64 {
-                if kcache.start < SMALL_POOL_END {
+                if kcache.pool == KeyPool::Small {
                     // this started life as a small key. the algorithm is to remove and retry upon extend.
                     // allocate a new vector that contains the *entire* data contents (not just the updated portion), and re-insert it
                     let mut update_data = Vec::<u8>::with_capacity(data.len() + offset);
@@ -384,6 +413,19 @@ impl DictCacheEntry {
                     }
                     // and re-add it with the extended data; if it's no longer a small key after this, it'll be handled inside this call.
                     return self.key_update(hw, v2p_map, cipher, name, &update_data, 0, alloc_hint, truncate, large_alloc_ptr);
+                } else if kcache.pool == KeyPool::Medium {
+                    // medium keys live in a fixed-size extent, so they are re-allocated when they outgrow it. Read out the
+                    // existing data, merge in the update, and re-insert the key; it'll land in either a bigger medium extent,
+                    // or the large pool, depending on its new size.
+                    let (start, len) = (kcache.start, kcache.len);
+                    let mut update_data = paged_data_read(hw, v2p_map, cipher, &self.aad, start, len);
+                    while update_data.len() < offset + data.len() {
+                        update_data.push(0);
+                    }
+                    for (&src, dst) in data.iter().zip(update_data[offset..].iter_mut()) { *dst = src };
+                    log::debug!("update/extend: relocating medium key {} with data len {}", name, update_data.len());
+                    self.key_remove(hw, v2p_map, cipher, name, false);
+                    return self.key_update(hw, v2p_map, cipher, name, &update_data, 0, alloc_hint, truncate, large_alloc_ptr);
                 } else {
                     // large data sets will need more physical pages to be allocated for the new file length. It's a hard error
                     // if the requested size goes beyond the pre-allocated virtual memory space limit.
@@ -410,7 +452,7 @@ impl DictCacheEntry {
                 }
             }
             // the key exists, *and* there's sufficient space for the data
-            if kcache.start < SMALL_POOL_END {
+            if kcache.pool == KeyPool::Small {
                 log::debug!("doing data update of {}", name);
                 kcache.age = kcache.age.saturating_add(1);
                 kcache.clean = false;
@@ -439,7 +481,7 @@ impl DictCacheEntry {
                 self.small_pool[pool_index].clean = false;
                 // note: there is no need to update small_pool_free because the reserved size did not change.
             } else {
-                // it's a large key
+                // it's a medium or large key; both are stored in whole pages, so they share the same write path
                 if let Some(_kcd) = &kcache.data {
                     unimplemented!("caching is not yet implemented for large data sets");
                 } else {
@@ -532,7 +574,8 @@ impl DictCacheEntry {
                     } else if truncate {
                         // discard all whole pages after written+offset, and reset the reserved field to the smaller size.
                         let vpage_end_offset = PageAlignedVa::from((written + offset) as u64);
-                        if (vpage_end_offset.as_u64() - kcache.start) > kcache.reserved {
+                        // medium keys keep their reservation, as their extent is owned by the medium pool allocator
+                        if kcache.pool == KeyPool::Large && (vpage_end_offset.as_u64() - kcache.start) > kcache.reserved {
                             for vpage in (vpage_end_offset.as_u64()..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
                                 if let Some(pp) = v2p_map.get_mut(&VirtAddr::new(vpage).unwrap()) {
                                     assert!(pp.valid(), "v2p returned an invalid page");
@@ -619,19 +662,84 @@ impl DictCacheEntry {
                     data: Some(KeyCacheData::Small(KeySmallData{
                         clean: false,
                         data: alloc_data
-                    }))
+                    })),
+                    pool: KeyPool::Small,
                 };
                 self.keys.insert(name.to_string(), kcache);
                 self.key_count += 1;
             } else {
-                log::debug!("creating large key");
-                // it didn't fit in the small pool, stick it in the big pool.
                 let reservation = PageAlignedVa::from(
                     if alloc_hint.unwrap_or(0) > data.len() + offset {
                         alloc_hint.unwrap_or(0)
                     } else {
                         data.len() + offset
                     });
+                // it didn't fit in the small pool, try the medium pool next.
+                let medium_start = if reservation.as_u64() <= MEDIUM_CAPACITY as u64 {
+                    if self.medium_pool.is_none() {
+                        // the free space in the medium pool can only be derived once all the keys are in the cache
+                        self.fill(hw, v2p_map, cipher);
+                    }
+                    self.medium_pool.as_mut().expect("fill() did not rebuild the medium pool").alloc(reservation.as_u64())
+                } else {
+                    None
+                };
+                if let Some(start) = medium_start {
+                    log::debug!("creating medium key {} at 0x{:x}", name, start);
+                    // allocate all the pages for the extent before the key is created, so that running out of space
+                    // or key indices leaves nothing behind
+                    let mut pages = Vec::<PhysPage>::new();
+                    for _ in (start..start + reservation.as_u64()).step_by(VPAGE_SIZE) {
+                        match hw.try_fast_space_alloc() {
+                            Some(pp) => pages.push(pp),
+                            None => break,
+                        }
+                    }
+                    let descriptor_index = if pages.len() * VPAGE_SIZE < reservation.as_usize() {
+                        Err(Error::new(ErrorKind::OutOfMemory, "couldn't allocate memory for medium key"))
+                    } else {
+                        self.get_free_key_index().ok_or(Error::new(ErrorKind::OutOfMemory, "Ran out of key indices in dictionary"))
+                    };
+                    let descriptor_index = match descriptor_index {
+                        Ok(di) => di,
+                        Err(e) => {
+                            for mut pp in pages.into_iter() {
+                                hw.fast_space_free(&mut pp);
+                            }
+                            self.medium_pool.as_mut().unwrap().free(start, reservation.as_u64());
+                            return Err(e);
+                        }
+                    };
+                    let mut kf = KeyFlags(0);
+                    kf.set_valid(true);
+                    let kcache = KeyCacheEntry {
+                        start,
+                        len: (data.len() + offset) as u64,
+                        reserved: reservation.as_u64(),
+                        flags: kf,
+                        age: 0,
+                        descriptor_index,
+                        clean: false,
+                        data: None, // medium keys are not cached
+                        pool: KeyPool::Medium,
+                    };
+                    self.keys.insert(name.to_string(), kcache);
+                    self.key_count += 1;
+                    for (vpage_addr, pp) in (start..start + reservation.as_u64()).step_by(VPAGE_SIZE).zip(pages.into_iter()) {
+                        assert!(pp.valid(), "didn't receive a valid page in medium space alloc");
+                        log::debug!("pp alloc v{:x}->p{:x?}", vpage_addr, pp);
+                        if let Some(old_pp) = v2p_map.insert(VirtAddr::new(vpage_addr).unwrap(), pp) {
+                            // the extent was freed recently, and the removal of its old mapping hasn't been synced yet.
+                            // The old entry is about to be lost from the v2p map, so erase its PTE now.
+                            assert!(!old_pp.valid(), "medium pool allocated an extent that is still in use");
+                            hw.pt_erase(old_pp.page_number());
+                        }
+                    }
+                    // Recurse. The key now exists, so the data is written out in the medium/large update path.
+                    return self.key_update(hw, v2p_map, cipher, name, data, offset, alloc_hint, truncate, large_alloc_ptr);
+                }
+                log::debug!("creating large key");
+                // it didn't fit in the medium pool either, stick it in the big pool.
                 let mut kf = KeyFlags(0);
                 kf.set_valid(true);
                 let descriptor_index = if let Some(di) = self.get_free_key_index() {
@@ -648,6 +756,7 @@ impl DictCacheEntry {
                     descriptor_index,
                     clean: false,
                     data: None, // no caching implemented yet for large keys
+                    pool: KeyPool::Large,
                 };
                 self.keys.insert(name.to_string(), kcache);
                 self.key_count += 1;
@@ -677,6 +786,17 @@ impl DictCacheEntry {
             self.small_pool_free.push(KeySmallPoolOrd{index, avail: ksp.avail})
         }
     }
+    /// Derives the medium pool free space from the extents of the valid medium keys. Only call this when
+    /// every key in the dictionary is in the cache.
+    fn rebuild_medium_pool(&mut self) {
+        let mut extents = Vec::<(u64, u64)>::new();
+        for kcache in self.keys.values() {
+            if kcache.flags.valid() && kcache.pool == KeyPool::Medium {
+                extents.push((kcache.start, kcache.reserved));
+            }
+        }
+        self.medium_pool = Some(KeyMediumPool::from_extents(medium_storage_base_vaddr(self.index), &mut extents));
+    }
     /// Used to remove a key from the dictionary. If you call it with a non-existent key,
     /// the routine has no effect, and does not report an error. Small keys are not immediately
     /// overwritten in paranoid mode, but large keys are.
@@ -715,10 +835,10 @@ impl DictCacheEntry {
                     need_rebuild = true;
 
                 } else {
-                    // handle the large pool case
-                    // mark the entry as invalid and dirty; virtual space is one huge memory leak...
+                    // handle the medium and large pool cases
+                    // mark the entry as invalid and dirty; for large keys, virtual space is one huge memory leak (until compaction)...
                     // ...but we remove the virtual pages from the page pool, effectively reclaiming the physical space.
-                    for vpage in kcache.large_pool_vpages() {
+                    for vpage in kcache.data_vpages() {
                         if let Some(pp) = v2p_map.get_mut(&vpage) {
                             assert!(pp.valid(), "v2p returned an invalid page");
                             { // this slows things down but it prevents data from leaking back into other basis data structures when the sector is re-allocated
@@ -732,6 +852,12 @@ impl DictCacheEntry {
                             assert!(pp.valid() == false, "pp is still marked as valid!");
                         }
                     }
+                    // medium pool virtual space is re-used, so return the extent to the allocator
+                    if kcache.pool == KeyPool::Medium {
+                        if let Some(medium_pool) = self.medium_pool.as_mut() {
+                            medium_pool.free(kcache.start, kcache.reserved);
+                        }
+                    }
                 }
                 need_free_key = Some(kcache.descriptor_index.get());
             }
@@ -856,6 +982,129 @@ impl DictCacheEntry {
         true
     }
 
+    /// Repacks all the valid small keys into as few small pool blocks as possible, using a first-fit
+    /// decreasing strategy. Any blocks at the end of the pool that are no longer needed are erased
+    /// and their physical pages returned to the FastSpace.
+    ///
+    /// All the surviving pools are marked dirty, so `sync_small_pool` should be called right after this
+    /// to write the repacked data out, followed by a `dict_sync` and `pt_sync`.
+    pub(crate) fn small_pool_compact(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>) {
+        let mut small_keys = Vec::<(u64, String)>::new();
+        for ksp in self.small_pool.iter() {
+            for key_name in ksp.contents.iter() {
+                let kcache = self.keys.get(key_name).expect("data record without index");
+                if kcache.flags.valid() {
+                    small_keys.push((kcache.reserved, key_name.to_string()));
+                }
+            }
+        }
+        // largest reservations go first, so the small ones can fill in the gaps at the end
+        small_keys.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut pools = Vec::<KeySmallPool>::new();
+        for (reserved, key_name) in small_keys {
+            let index = if let Some(i) = pools.iter().position(|p| p.avail as u64 >= reserved) {
+                i
+            } else {
+                pools.push(KeySmallPool::new());
+                pools.len() - 1
+            };
+            pools[index].contents.push(key_name.to_string());
+            pools[index].avail -= reserved as u16;
+            // the exact offset within the pool is assigned by sync_small_pool(); just make sure the key resolves to the right pool
+            let kcache = self.keys.get_mut(&key_name).expect("data record without index");
+            kcache.start = small_storage_base_vaddr_from_indices(self.index, index);
+            kcache.clean = false;
+        }
+        log::debug!("small pool compact: {} -> {} pools", self.small_pool.len(), pools.len());
+        // erase & de-allocate the pool pages that are no longer used
+        for index in pools.len()..self.small_pool.len() {
+            let pool_vaddr = VirtAddr::new(small_storage_base_vaddr_from_indices(self.index, index)).unwrap();
+            if let Some(pp) = v2p_map.get_mut(&pool_vaddr) {
+                assert!(pp.valid(), "v2p returned an invalid page");
+                let mut noise = [0u8; PAGE_SIZE];
+                hw.trng_slice(&mut noise);
+                hw.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+                log::trace!("fast_space_free small_pool_compact {} before", pp.journal());
+                hw.fast_space_free(pp);
+                assert!(pp.valid() == false, "pp is still marked as valid!");
+            }
+        }
+        self.small_pool = pools;
+        self.rebuild_free_pool();
+        self.age = self.age.saturating_add(1);
+        self.clean = false;
+    }
+
+    /// Slides all the valid medium keys down against the base of the dictionary's medium pool region, in order
+    /// of their current address, and rebuilds the allocator so it has no free extents. Like the large pool
+    /// compaction, the data is not copied: only the v2p mappings are moved, and the moved PTEs are marked dirty.
+    /// The moved keys are marked dirty, so their descriptors need to be re-written afterwards.
+    pub(crate) fn medium_pool_compact(&mut self, v2p_map: &mut HashMap::<VirtAddr, PhysPage>) {
+        let mut medium_keys = Vec::<(u64, String)>::new();
+        for (key_name, kcache) in self.keys.iter() {
+            if kcache.flags.valid() && kcache.pool == KeyPool::Medium {
+                medium_keys.push((kcache.start, key_name.to_string()));
+            }
+        }
+        medium_keys.sort();
+        // detach all the pages before re-attaching any of them, so no mapping can be clobbered
+        let mut relocations = Vec::<(u64, u64, PhysPage)>::new();
+        let mut alloc_ptr = medium_storage_base_vaddr(self.index);
+        for (start, key_name) in medium_keys {
+            let kcache = self.keys.get_mut(&key_name).expect("key disappeared during compaction");
+            for vpage in kcache.data_vpages() {
+                if let Some(pp) = v2p_map.remove(&vpage) {
+                    relocations.push((vpage.get(), alloc_ptr + (vpage.get() - start), pp));
+                }
+            }
+            if start != alloc_ptr {
+                log::debug!("relocating medium key {} 0x{:x}->0x{:x}", key_name, start, alloc_ptr);
+                kcache.start = alloc_ptr;
+                kcache.clean = false;
+                self.clean = false;
+            }
+            alloc_ptr += PageAlignedVa::from(kcache.reserved).as_u64();
+        }
+        for (old_vaddr, new_vaddr, mut pp) in relocations {
+            if old_vaddr != new_vaddr {
+                pp.set_clean(false);
+            }
+            v2p_map.insert(VirtAddr::new(new_vaddr).unwrap(), pp);
+        }
+        self.rebuild_medium_pool();
+    }
+
+    /// Re-assigns the key descriptor slots so that the valid keys occupy indices 1..=key_count, preserving
+    /// their relative order. Deleted keys are dropped from the cache, as their descriptors are about to be
+    /// overwritten. Returns the number of descriptor vpages that were in use prior to the renumbering, so
+    /// the caller can reclaim the ones that are no longer needed.
+    ///
+    /// Every key is marked dirty; the caller is responsible for re-writing all the descriptor pages.
+    pub(crate) fn key_index_compact(&mut self) -> usize {
+        let old_pages = 1 + self.last_disk_key_index as usize / DK_PER_VPAGE;
+        self.keys.retain(|_, kcache| kcache.flags.valid());
+        let mut order = Vec::<(u32, String)>::new();
+        for (key_name, kcache) in self.keys.iter() {
+            order.push((kcache.descriptor_index.get(), key_name.to_string()));
+        }
+        order.sort();
+        for (i, (_, key_name)) in order.iter().enumerate() {
+            let kcache = self.keys.get_mut(key_name).expect("key disappeared during compaction");
+            kcache.descriptor_index = NonZeroU32::new(i as u32 + 1).unwrap();
+            kcache.clean = false;
+        }
+        self.key_count = order.len() as u32;
+        self.last_disk_key_index = self.key_count + 1;
+        self.free_keys.clear();
+        self.free_keys.push(Reverse(FreeKeyRange{
+            start: self.last_disk_key_index,
+            run: KEY_MAXCOUNT as u32 - 1 - self.last_disk_key_index
+        }));
+        self.age = self.age.saturating_add(1);
+        self.clean = false;
+        old_pages
+    }
+
     /// No data cache to flush yet...large pool caches not implemented!
     pub(crate) fn sync_large_pool(&self) {
     }
@@ -965,6 +1214,8 @@ impl DictCacheEntry {
             name: name.to_string(),
             clean: self.clean,
             small_key_count: self.small_pool.len(),
+            medium_pool_extent: self.medium_pool.as_ref().map_or(0, |mp| mp.extent()),
+            medium_pool_fragmented: self.medium_pool.as_ref().map_or(0, |mp| mp.fragmented_bytes()),
             basis: basis_name.to_string(),
         }
     }
@@ -996,6 +1247,28 @@ pub(crate) fn small_storage_index_from_key(kcache: &KeyCacheEntry, dict_index: N
 pub(crate) fn small_storage_base_vaddr_from_indices(dict_index: NonZeroU32, base_index: usize) -> u64 {
     SMALL_POOL_START + (dict_index.get()-1) as u64 * DICT_VSIZE + base_index as u64 * SMALL_CAPACITY as u64
 }
+/// derive the base virtual address of a dictionary's medium pool region
+pub(crate) fn medium_storage_base_vaddr(dict_index: NonZeroU32) -> u64 {
+    MEDIUM_POOL_START + (dict_index.get()-1) as u64 * MEDIUM_POOL_STRIDE
+}
+/// Reads out the entire contents of a key that is stored in whole pages (a medium or large key). Pages that
+/// were reserved but never written read back as 0's.
+fn paged_data_read(hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv, aad: &[u8],
+    start: u64, len: u64) -> Vec::<u8> {
+    let mut data = Vec::<u8>::with_capacity(len as usize);
+    for vpage_addr in (start..start + len).step_by(VPAGE_SIZE) {
+        let remaining = (start + len - vpage_addr) as usize;
+        let chunk = if remaining < VPAGE_SIZE { remaining } else { VPAGE_SIZE };
+        let pp = v2p_map.get(&VirtAddr::new(vpage_addr).unwrap()).expect("paged key data allocation missing");
+        assert!(pp.valid(), "v2p returned an invalid page");
+        if let Some(page) = hw.data_decrypt_page(cipher, aad, pp) {
+            data.extend_from_slice(&page[size_of::<JournalType>()..size_of::<JournalType>() + chunk]);
+        } else {
+            data.resize(data.len() + chunk, 0);
+        }
+    }
+    data
+}
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 #[repr(C, align(8))]
@@ -1082,6 +1355,10 @@ pub(crate) struct DictAttributes {
     pub(crate) name: String,
     pub(crate) clean: bool,
     pub(crate) small_key_count: usize,
+    /// bytes spanned by the medium pool allocations; 0 if the pool has not been built yet
+    pub(crate) medium_pool_extent: u64,
+    /// bytes lost to holes in the medium pool
+    pub(crate) medium_pool_fragmented: u64,
     pub(crate) basis: String,
 }
 
@@ -8,6 +8,7 @@ use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::SeekFrom;
+use std::convert::TryInto;
 
 // This is considered bad practice for Rust to use a global singleton.
 // However, this hack puts the burden of emulation on the emulator, while
@@ -108,6 +109,37 @@ impl EmuStorage {
         }
         f.flush().unwrap();
     }
+    /// replaces the emulated FLASH with an image previously written by `dump_fs()`. Returns false if the
+    /// image can't be read, or is of the wrong size.
+    pub fn load_fs(&mut self, name: &str) -> bool {
+        let mut memory = Vec::<u8>::with_capacity(PDDB_A_LEN);
+        match File::open(format!("../tools/pddb-images/{}.bin", name)).and_then(|mut f| f.read_to_end(&mut memory)) {
+            Ok(bytes_read) if bytes_read == PDDB_A_LEN => {
+                flashmem().memory.copy_from_slice(&memory);
+                flashmem().disk.seek(SeekFrom::Start(0)).expect("couldn't seek PDDB");
+                flashmem().disk.write_all(&memory).expect("couldn't write PDDB");
+                true
+            }
+            _ => false,
+        }
+    }
+    /// reads back the keys written by `dump_keys()`
+    pub fn load_keys(&self, name: &str) -> Option<Vec<KeyExport>> {
+        let mut data = Vec::<u8>::new();
+        File::open(format!("../tools/pddb-images/{}.key", name)).and_then(|mut f| f.read_to_end(&mut data)).ok()?;
+        if data.len() < 4 {
+            return None;
+        }
+        let count = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        let mut keys = Vec::<KeyExport>::new();
+        for record in data[4..].chunks_exact(64 + 32).take(count) {
+            let mut key = KeyExport { basis_name: [0; 64], key: [0; 32] };
+            key.basis_name.copy_from_slice(&record[..64]);
+            key.key.copy_from_slice(&record[64..]);
+            keys.push(key);
+        }
+        if keys.len() == count {Some(keys)} else {None}
+    }
 }
 
 pub struct HostedSpinor {
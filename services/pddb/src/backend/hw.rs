@@ -112,6 +112,8 @@ pub(crate) struct PddbOs {
     dna: u64,
     /// reference to a TrngPool object that's shared among all the hardware functions
     entropy: Rc<RefCell<TrngPool>>,
+    /// set while a transaction is being applied; see txn.rs
+    txn_shadow: Option<TxnShadow>,
 }
 
 impl PddbOs {
@@ -157,6 +159,7 @@ impl PddbOs {
             fspace_log_len: 0,
             dna: llio.soc_dna().unwrap(),
             entropy: trngpool,
+            txn_shadow: None,
         };
         // emulated
         #[cfg(not(any(target_os = "none", target_os = "xous")))]
@@ -179,6 +182,7 @@ impl PddbOs {
                 fspace_log_len: 0,
                 dna: llio.soc_dna().unwrap(),
                 entropy: trngpool,
+                txn_shadow: None,
             }
         };
         ret
@@ -215,6 +219,29 @@ impl PddbOs {
     }
     #[allow(dead_code)]
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    /// Swaps in an image written by `dbg_dump()`, so that it can be mounted and checked. Returns the keys
+    /// that were dumped with the image; the system basis key is also installed, so `pddb_mount()` works.
+    pub fn dbg_load(&mut self, name: &str) -> Option<Vec::<KeyExport>> {
+        let keys = self.pddb_mr.load_keys(name)?;
+        if !self.pddb_mr.load_fs(name) {
+            return None;
+        }
+        self.fspace_cache = FspaceSet::new();
+        self.fspace_log_addrs = Vec::<PageAlignedPa>::new();
+        self.fspace_log_next_addr = None;
+        self.txn_shadow = None;
+        self.syskey_erase();
+        for key in keys.iter() {
+            if key.basis_name.starts_with(PDDB_DEFAULT_SYSTEM_BASIS.as_bytes())
+            && key.basis_name[PDDB_DEFAULT_SYSTEM_BASIS.len()] == 0 {
+                self.cipher_ecb = Some(Aes256::new(GenericArray::from_slice(&key.key)));
+                self.system_basis_key = Some(key.key);
+            }
+        }
+        Some(keys)
+    }
+    #[allow(dead_code)]
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
     /// used to reset the hardware structure for repeated runs of testing within a single invocation
     pub fn test_reset(&mut self) {
         self.fspace_cache = FspaceSet::new();
@@ -249,6 +276,13 @@ impl PddbOs {
     /// exactly to the first entry in the page table
     pub(crate) fn patch_data(&self, data: &[u8], offset: u32) {
         log::trace!("patch offset: {:x} len: {:x}", offset, data.len());
+        if let Some(shadow) = self.txn_shadow.as_ref() {
+            if shadow.is_original(offset / PAGE_SIZE as u32) {
+                // this is a page being erased before it is freed. It is still part of the basis on disk, so it is
+                // erased when the transaction commits instead.
+                return;
+            }
+        }
         assert!(data.len() + offset as usize <= PDDB_A_LEN - self.data_phys_base.as_usize(), "attempt to store past disk boundary");
         self.spinor.patch(
             self.pddb_mr.as_slice(),
@@ -351,6 +385,10 @@ impl PddbOs {
     /// a *page number* (so a physical address divided by the page size). It's a slightly awkward units, but
     /// it saves a bit of math going back and forth between the native storage formats of the records.
     pub(crate) fn pt_patch_mapping(&mut self, va: VirtAddr, phys_page_num: u32, cipher: &Aes256) {
+        if let Some(shadow) = self.txn_shadow.as_mut() {
+            shadow.map(va, phys_page_num);
+            return;
+        }
         let mut pte = Pte::new(va, PtFlags::CLEAN, Rc::clone(&self.entropy));
         let mut block = Block::from_mut_slice(pte.deref_mut());
         //log::info!("pte pt: {:x?}", block);
@@ -361,10 +399,43 @@ impl PddbOs {
 
     /// erases a page table entry by overwriting it with garbage
     pub(crate) fn pt_erase(&mut self, phys_page_num: u32) {
+        if self.txn_shadow.is_some() {
+            // entries are only erased once the transaction commits; `TxnShadow::finish()` works out which
+            return;
+        }
         let mut eraseblock = [0u8; aes::BLOCK_SIZE];
         self.trng_slice(&mut eraseblock);
         self.patch_pagetable(&eraseblock, phys_page_num * aes::BLOCK_SIZE as u32);
     }
+    /// Checks if the page table entry of `phys_page_num` maps `va` under `cipher`, i.e. if the page currently
+    /// belongs to the basis at that address.
+    pub(crate) fn pt_maps(&self, phys_page_num: u32, va: VirtAddr, cipher: &Aes256) -> bool {
+        let mut block = match self.pt_slot(phys_page_num) {
+            Some(slot) => Block::clone_from_slice(&slot),
+            None => return false,
+        };
+        cipher.decrypt_block(&mut block);
+        match Pte::try_from_slice(block.as_slice()) {
+            Some(pte) => pte.vaddr() == va,
+            None => false,
+        }
+    }
+    /// Returns the page table entry of `phys_page_num` as it is stored, i.e. still encrypted.
+    pub(crate) fn pt_slot(&self, phys_page_num: u32) -> Option<[u8; aes::BLOCK_SIZE]> {
+        let offset = phys_page_num as usize * aes::BLOCK_SIZE;
+        let page_start = offset - offset % PAGE_SIZE;
+        let pt = self.pt_as_slice();
+        if offset + aes::BLOCK_SIZE > pt.len() {
+            return None;
+        }
+        let blank = [0xffu8; aes::BLOCK_SIZE];
+        let pt_page = if pt[page_start..page_start + aes::BLOCK_SIZE] == blank {
+            self.mbbb_retrieve().unwrap_or(&pt[page_start..page_start + PAGE_SIZE])
+        } else {
+            &pt[page_start..page_start + PAGE_SIZE]
+        };
+        pt_page[offset - page_start..offset - page_start + aes::BLOCK_SIZE].try_into().ok()
+    }
     /// Searches the page table for an MBBB slot. This is currently an O(N) search but
     /// in practice for Precursor there are only 8 pages, so it's quite fast on average.
     /// This would want to be optimized or cached for a much larger filesystem.
@@ -1002,11 +1073,20 @@ impl PddbOs {
             }
             if let Some(alloc) = maybe_alloc {
                 assert!(self.fspace_cache.remove(&alloc), "inconsistent state: we found a free page, but later when we tried to update it, it wasn't there!");
+                if let Some(shadow) = self.txn_shadow.as_mut() {
+                    shadow.allocated(alloc);
+                }
             }
             maybe_alloc
         }
     }
     pub fn fast_space_free(&mut self, pp: &mut PhysPage) {
+        if let Some(shadow) = self.txn_shadow.as_mut() {
+            // the page is still mapped on disk until the transaction commits, so it can't be handed out yet
+            shadow.free(pp.clone());
+            pp.set_valid(false);
+            return;
+        }
         self.fast_space_ensure_next_log();
         if !self.fspace_cache.remove(&pp) {
             log::warn!("Freeing a page that's not already in cache: {:x?}", pp);
@@ -1043,6 +1123,14 @@ impl PddbOs {
     /// This is a "look before you leap" function that will potentially pause all system operations
     /// and do a deep scan for space if the required amount is not available.
     pub fn ensure_fast_space_alloc(&mut self, pages: usize, cache: &Vec::<BasisCacheEntry>) -> bool {
+        self.ensure_fast_space_alloc_in_flight(pages, cache, std::iter::empty())
+    }
+    /// Same as `ensure_fast_space_alloc()`, for callers that hold pages which are in use, but not (or no longer)
+    /// in any of the caches, such as the copy a re-key is building. `in_flight` lists their page numbers, so
+    /// that a full-space sweep doesn't hand them out again.
+    pub(crate) fn ensure_fast_space_alloc_in_flight<I>(&mut self, pages: usize, cache: &Vec::<BasisCacheEntry>, in_flight: I) -> bool
+        where I: Iterator<Item = u32>
+    {
         const BUFFER: usize = 1; // a bit of slop in the trigger point
         let has_pages = self.fast_space_has_pages(pages + BUFFER);
         log::trace!("alloc fast_space_len: {}, log_len {}, has {} pages: {}", self.fast_space_len(), self.fspace_log_len, pages + BUFFER, has_pages);
@@ -1053,35 +1141,25 @@ impl PddbOs {
             true
         } else {
             if !has_pages {
+                if self.txn_shadow.is_some() {
+                    // a sweep works from the in-RAM page maps, which don't describe the disk while a transaction is shadowed
+                    log::warn!("Transaction ran out of reserved free space");
+                    return false;
+                }
                 log::warn!("FastSpace alloc forced by lack of free space");
                 // if we're really out of space, do an expensive full-space sweep
-                if let Some(used_pages) = self.pddb_generate_used_map(cache) {
-                    let free_pool = self.fast_space_generate(used_pages);
-                    if free_pool.len() == 0 {
-                        // we're out of free space
-                        false
-                    } else {
-                        let mut fast_space = FastSpace {
-                            free_pool: [PhysPage(0); FASTSPACE_FREE_POOL_LEN],
-                        };
-                        for pp in fast_space.free_pool.iter_mut() {
-                            pp.set_journal(self.trng_u8() % FSCB_JOURNAL_RAND_RANGE)
-                        }
-                        for (&src, dst) in free_pool.iter().zip(fast_space.free_pool.iter_mut()) {
-                            *dst = src;
-                        }
-                        // write just commits a new record to disk, but doesn't update our internal data cache
-                        // this also clears the fast space log.
-                        self.fast_space_write(&fast_space);
-                        // this will ensure the data cache is fully in sync
-                        self.fast_space_read();
-
+                if let Some(mut used_pages) = self.pddb_generate_used_map(cache) {
+                    used_pages.extend(in_flight.map(|page| Reverse(page)));
+                    if self.fast_space_regenerate(used_pages) {
                         // check that we have enough space now -- if not, we're just out of disk space!
                         if self.fast_space_len() > pages {
                             true
                         } else {
                             false
                         }
+                    } else {
+                        // we're out of free space
+                        false
                     }
                 } else {
                     false
@@ -1112,6 +1190,76 @@ impl PddbOs {
         }
     }
 
+    /// Replaces the FastSpace record with a fresh one that is generated from the map of `used_pages`, and
+    /// brings the fspace cache back in sync with it. Returns `false` if there is no free space on the disk.
+    fn fast_space_regenerate(&mut self, used_pages: BinaryHeap<Reverse<u32>>) -> bool {
+        let free_pool = self.fast_space_generate(used_pages);
+        if free_pool.len() == 0 {
+            return false;
+        }
+        let mut fast_space = FastSpace {
+            free_pool: [PhysPage(0); FASTSPACE_FREE_POOL_LEN],
+        };
+        for pp in fast_space.free_pool.iter_mut() {
+            pp.set_journal(self.trng_u8() % FSCB_JOURNAL_RAND_RANGE)
+        }
+        for (&src, dst) in free_pool.iter().zip(fast_space.free_pool.iter_mut()) {
+            *dst = src;
+        }
+        // write just commits a new record to disk, but doesn't update our internal data cache
+        // this also clears the fast space log.
+        self.fast_space_write(&fast_space);
+        // this will ensure the data cache is fully in sync
+        self.fast_space_read();
+        true
+    }
+
+    /// Puts the hardware in shadow mode for applying a transaction; see txn.rs.
+    pub(crate) fn txn_shadow_begin(&mut self, shadow: TxnShadow) {
+        assert!(self.txn_shadow.is_none(), "transactions can't be nested");
+        self.txn_shadow = Some(shadow);
+    }
+    /// Leaves shadow mode, returning what the transaction did.
+    pub(crate) fn txn_shadow_end(&mut self) -> Option<TxnShadow> {
+        self.txn_shadow.take()
+    }
+    /// Reads of an original page that has been copied go to the copy.
+    fn txn_shadow_read_target(&self, pp: &PhysPage) -> PhysPage {
+        match self.txn_shadow.as_ref().and_then(|shadow| shadow.copy_of(pp.page_number())) {
+            Some((copy, _)) => copy,
+            None => *pp,
+        }
+    }
+    /// Writes to an original page go to its copy, which is allocated on the first write. Returns the page to
+    /// write and the journal number the write has to out-rank, or `None` if no copy could be allocated.
+    /// `journal` reads the journal number of the original.
+    fn txn_shadow_write_target<F>(&mut self, pp: &PhysPage, journal: F) -> Option<(PhysPage, JournalType)>
+        where F: FnOnce(&mut Self) -> Option<JournalType>
+    {
+        match self.txn_shadow.as_ref() {
+            Some(shadow) if shadow.is_original(pp.page_number()) => {
+                if let Some(target) = shadow.copy_of(pp.page_number()) {
+                    return Some(target);
+                }
+            }
+            _ => return Some((*pp, 0)),
+        }
+        let floor = journal(self).unwrap_or(0);
+        let copy = self.try_fast_space_alloc();
+        let shadow = self.txn_shadow.as_mut().unwrap();
+        match copy {
+            Some(copy) => {
+                shadow.add_copy(pp.page_number(), copy, floor);
+                Some((copy, floor))
+            }
+            None => {
+                log::error!("Couldn't allocate a copy of page {:x} for a transaction", pp.page_number());
+                shadow.fail();
+                None
+            }
+        }
+    }
+
     pub(crate) fn data_aad(&self, name: &str) -> Vec::<u8> {
         let mut aad = Vec::<u8>::new();
         aad.extend_from_slice(&name.as_bytes());
@@ -1124,6 +1272,7 @@ impl PddbOs {
     /// We don't clip it off because it would require re-allocating a vector, and it's cheaper (although less elegant) to later
     /// just index past it.
     pub(crate) fn data_decrypt_page(&self, cipher: &Aes256GcmSiv, aad: &[u8], page: &PhysPage) -> Option<Vec::<u8>> {
+        let page = &self.txn_shadow_read_target(page);
         let ct_slice = &self.pddb_mr.as_slice()[
             self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE ..
             self.data_phys_base.as_usize() + (page.page_number() as usize + 1) * PAGE_SIZE];
@@ -1164,6 +1313,7 @@ impl PddbOs {
         const KCOM_NONCE_LEN: usize = 32;
         const KCOM_LEN: usize = 32;
         const MAC_LEN: usize = 16;
+        let page = &self.txn_shadow_read_target(page);
         let ct_slice = &self.pddb_mr.as_slice()[
             self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE ..
             self.data_phys_base.as_usize() + (page.page_number() as usize + 1) * PAGE_SIZE];
@@ -1220,7 +1370,14 @@ impl PddbOs {
     /// `data` includes the journal entry on top. The data passed in must be exactly one vpage plus the journal entry
     pub(crate) fn data_encrypt_and_patch_page(&mut self, cipher: &Aes256GcmSiv, aad: &[u8], data: &mut [u8], pp: &PhysPage) {
         assert!(data.len() == VPAGE_SIZE + size_of::<JournalType>(), "did not get a page-sized region to patch");
-        let j = JournalType::from_le_bytes(data[..size_of::<JournalType>()].try_into().unwrap()).saturating_add(1);
+        let (pp, floor) = match self.txn_shadow_write_target(pp,
+            |hw| hw.data_decrypt_page(cipher, aad, pp).map(|d| JournalType::from_le_bytes(d[..size_of::<JournalType>()].try_into().unwrap()))
+        ) {
+            Some(target) => target,
+            None => return,
+        };
+        let pp = &pp;
+        let j = JournalType::from_le_bytes(data[..size_of::<JournalType>()].try_into().unwrap()).max(floor).saturating_add(1);
         for (&src, dst) in j.to_le_bytes().iter().zip(data[..size_of::<JournalType>()].iter_mut()) { *dst = src; }
         let nonce = self.nonce_gen();
         let ciphertext = cipher.encrypt(
@@ -1238,8 +1395,15 @@ impl PddbOs {
     /// which is 4004 bytes total
     pub(crate) fn data_encrypt_and_patch_page_with_commit(&mut self, key: &[u8], aad: &[u8], data: &mut [u8], pp: &PhysPage) {
         assert!(data.len() == KCOM_CT_LEN, "did not get a key-commit sized region to patch");
+        let (pp, floor) = match self.txn_shadow_write_target(pp,
+            |hw| hw.data_decrypt_page_with_commit(key, aad, pp).map(|d| JournalType::from_le_bytes(d[..size_of::<JournalType>()].try_into().unwrap()))
+        ) {
+            Some(target) => target,
+            None => return,
+        };
+        let pp = &pp;
         // updates the journal type
-        let j = JournalType::from_le_bytes(data[..size_of::<JournalType>()].try_into().unwrap()).saturating_add(1);
+        let j = JournalType::from_le_bytes(data[..size_of::<JournalType>()].try_into().unwrap()).max(floor).saturating_add(1);
         for (&src, dst) in j.to_le_bytes().iter().zip(data[..size_of::<JournalType>()].iter_mut()) { *dst = src; }
         // gets the AES-GCM-SIV nonce
         let nonce = self.nonce_gen();
@@ -1677,6 +1841,172 @@ impl PddbOs {
         }
     }
 
+    /// Checks the open bases, and the free space tracking, for consistency. Every problem found is counted in the
+    /// returned report; if `repair` is set, the ones that can be fixed are fixed as follows:
+    ///   - page table entries that point at data which doesn't decrypt, and pages that nothing refers to, are freed
+    ///   - dictionary and basis counts that disagree with the descriptors actually present are rewritten
+    ///   - if the FSCB believes any page that is in use to be free, FastSpace is regenerated from a full-space sweep
+    /// Key descriptors with inconsistent extents are only reported, because there is no telling which key holds
+    /// the correct data.
+    ///
+    /// The caches must be in sync with the disk when this is called. Locked bases can't be checked, and the pages
+    /// they use are only accounted for to the extent that `pddb_get_additional_keys()` is able to find them.
+    pub(crate) fn pddb_fsck(&mut self, cache: &mut Vec::<BasisCacheEntry>, repair: bool) -> Result<FsckReport> {
+        let mut report = FsckReport::default();
+        let mut fixed = false;
+        for basis in cache.iter_mut() {
+            log::info!("fsck: checking basis {}", basis.name);
+            report.bases += 1;
+            // every dictionary and key descriptor has to be in the cache to resolve who owns a page
+            basis.populate_caches(self);
+
+            // 1. check the dictionary and key descriptors, and note which pages they refer to
+            let mut valid_dicts = 0;
+            let mut dict_indices = std::collections::HashSet::<u32>::new();
+            let mut data_vpages = std::collections::HashSet::<VirtAddr>::new();
+            let mut extents = Vec::<(u64, u64)>::new();
+            for (dict_name, dict) in basis.dicts.iter_mut() {
+                if !dict.flags.valid() {
+                    continue;
+                }
+                valid_dicts += 1;
+                dict_indices.insert(dict.index.get());
+                let mut valid_keys = 0;
+                for (key_name, key) in dict.keys.iter() {
+                    if !key.flags.valid() {
+                        continue;
+                    }
+                    valid_keys += 1;
+                    let in_pool = match key.pool {
+                        KeyPool::Small => small_storage_index_from_key(key, dict.index)
+                            .map_or(false, |index| index < dict.small_pool.len()),
+                        KeyPool::Medium => {
+                            let base = medium_storage_base_vaddr(dict.index);
+                            key.start >= base && key.start + key.reserved <= base + MEDIUM_POOL_STRIDE
+                        }
+                        KeyPool::Large => key.reserved <= LARGE_FILE_MAX_SIZE + VPAGE_SIZE as u64,
+                    };
+                    if !in_pool || key.len > key.reserved {
+                        log::warn!("fsck: {}:{}:{} has a bad extent: start {:x}, len {}, reserved {}",
+                            basis.name, dict_name, key_name, key.start, key.len, key.reserved);
+                        report.bad_keys += 1;
+                    } else if key.pool != KeyPool::Small {
+                        data_vpages.extend(key.data_vpages());
+                        extents.push((key.start, key.start + key.reserved));
+                    }
+                }
+                if valid_keys != dict.key_count {
+                    log::warn!("fsck: {}:{} claims {} keys, but has {}", basis.name, dict_name, dict.key_count, valid_keys);
+                    report.bad_counts += 1;
+                    if repair {
+                        dict.key_count = valid_keys;
+                        dict.clean = false;
+                        fixed = true;
+                    }
+                }
+            }
+            if valid_dicts != basis.num_dicts {
+                log::warn!("fsck: {} claims {} dictionaries, but has {}", basis.name, basis.num_dicts, valid_dicts);
+                report.bad_counts += 1;
+                if repair {
+                    basis.num_dicts = valid_dicts;
+                    basis.clean = false;
+                    fixed = true;
+                }
+            }
+            // medium and large keys own their pages outright, so their extents may not overlap
+            extents.sort_unstable();
+            for pair in extents.windows(2) {
+                if pair[0].1 > pair[1].0 {
+                    log::warn!("fsck: {} has overlapping key extents at {:x} and {:x}", basis.name, pair[0].0, pair[1].0);
+                    report.bad_keys += 1;
+                }
+            }
+
+            // 2. check that every mapped page decrypts, and that something refers to it
+            let mut to_free = Vec::<VirtAddr>::new();
+            for (&va, pp) in basis.v2p_map.iter() {
+                if !pp.valid() {
+                    continue;
+                }
+                report.pages += 1;
+                let vaddr = va.get();
+                if vaddr == VPAGE_SIZE as u64 {
+                    // the basis root is committed to the basis key, and it was authenticated when the basis was mounted
+                    continue;
+                }
+                if self.data_decrypt_page(&basis.cipher, &basis.aad, pp).is_none() {
+                    log::warn!("fsck: {} va {:x} maps to page {:x}, which doesn't decrypt", basis.name, vaddr, pp.page_number());
+                    report.bad_pages += 1;
+                    to_free.push(va);
+                    continue;
+                }
+                let owned = if vaddr >= LARGE_POOL_START {
+                    data_vpages.contains(&va)
+                } else if vaddr >= TXN_LOG_START {
+                    // the transaction log and the re-key record, which follows it, are cleaned up by the recovery done
+                    // when the basis is mounted
+                    vaddr < REKEY_RECORD_VA + (REKEY_RECORD_MAX_PAGES * VPAGE_SIZE) as u64
+                } else if vaddr >= MEDIUM_POOL_START {
+                    data_vpages.contains(&va)
+                } else if vaddr >= SMALL_POOL_START {
+                    // small pool pages are shared among the keys of a dictionary, so they are checked at the dictionary level
+                    vaddr < SMALL_POOL_END
+                    && dict_indices.contains(&(((vaddr - SMALL_POOL_START) / SMALL_POOL_STRIDE) as u32 + 1))
+                } else if vaddr >= DICT_VSIZE {
+                    dict_indices.contains(&((vaddr / DICT_VSIZE) as u32))
+                } else {
+                    false
+                };
+                if !owned {
+                    log::warn!("fsck: {} va {:x} (page {:x}) is orphaned", basis.name, vaddr, pp.page_number());
+                    report.orphan_pages += 1;
+                    to_free.push(va);
+                }
+            }
+            if repair {
+                for va in to_free.iter() {
+                    if let Some(pp) = basis.v2p_map.get_mut(va) {
+                        // don't leave stale data behind for when the page is recycled
+                        let mut noise = [0u8; PAGE_SIZE];
+                        self.trng_slice(&mut noise);
+                        self.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+                        self.fast_space_free(pp);
+                        fixed = true;
+                    }
+                }
+                // writes back any corrected counts, and erases the PTEs of the freed pages
+                basis.sync(self)?;
+            }
+        }
+
+        // 3. cross-check the pages in use against the FSCB
+        if let Some(used_pages) = self.pddb_generate_used_map(cache) {
+            let mut probe = PhysPage(0);
+            for &Reverse(page) in used_pages.iter() {
+                probe.set_page_number(page as PhysAddr);
+                if let Some(fs_pp) = self.fspace_cache.get(&probe) {
+                    if fs_pp.space_state() == SpaceState::Free || fs_pp.space_state() == SpaceState::Dirty {
+                        log::warn!("fsck: page {:x} is in use, but FastSpace has it as {:?}", page, fs_pp.space_state());
+                        report.fscb_conflicts += 1;
+                    }
+                }
+            }
+            if repair && report.fscb_conflicts > 0 {
+                if self.fast_space_regenerate(used_pages) {
+                    fixed = true;
+                } else {
+                    log::error!("fsck: couldn't regenerate FastSpace, the disk is full");
+                }
+            }
+        } else {
+            log::warn!("fsck: full-space sweep was cancelled, FastSpace was not checked");
+        }
+        report.repaired = fixed;
+        log::info!("fsck: {:?}", report);
+        Ok(report)
+    }
+
     /// UX function that informs the user of the currently open Basis, and prompts the user to enter passwords
     /// for other basis that may not currently be open. This function is also responsible for validating
     /// that the password is correct by doing a quick scan for "any" PTEs that happen to decrypt to something
@@ -5,6 +5,7 @@ use std::num::NonZeroU32;
 use core::ops::{Deref, DerefMut};
 use std::cmp::Ordering;
 use std::io::{Result, Error, ErrorKind};
+use std::collections::BTreeMap;
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 #[repr(C, align(8))]
@@ -105,8 +106,11 @@ pub(crate) struct KeyCacheEntry {
     /// but if the `data` field is None then there is nothing to in cache to be dirtied.
     pub(crate) clean: bool,
     /// if Some, contains the keys data contents. if None, you must refer to the disk contents to retrieve it.
-    /// Current rule: "small" keys always have their data "hot"; large keys may often not keep their data around.
+    /// Current rule: "small" keys always have their data "hot"; medium and large keys may often not keep their data around.
     pub(crate) data: Option<KeyCacheData>,
+    /// the pool the key's data is allocated in. This is not stored on disk; it's derived from `start` when
+    /// the descriptor is read in, and it must be kept consistent with `start` if the key is relocated.
+    pub(crate) pool: KeyPool,
 }
 impl KeyCacheEntry {
     /// Given a base offset of the dictionary containing the key, compute the starting VirtAddr of the key itself.
@@ -124,8 +128,17 @@ impl KeyCacheEntry {
     }
     /// returns the list of large-pool virtual pages belonging to this entry, if any.
     pub(crate) fn large_pool_vpages(&self) -> Vec::<VirtAddr> {
+        if self.pool == KeyPool::Large {
+            self.data_vpages()
+        } else {
+            Vec::<VirtAddr>::new()
+        }
+    }
+    /// returns the list of virtual pages that hold this entry's data, for keys that are stored in whole
+    /// pages (that is, medium and large keys). Small keys share their pages, so this returns nothing for them.
+    pub(crate) fn data_vpages(&self) -> Vec::<VirtAddr> {
         let mut vpages = Vec::<VirtAddr>::new();
-        if self.start >= LARGE_POOL_START {
+        if self.pool != KeyPool::Small {
             for vbase in (self.start..self.start + self.reserved).step_by(VPAGE_SIZE) {
                 vpages.push(VirtAddr::new((vbase / VPAGE_SIZE as u64) * VPAGE_SIZE as u64).unwrap());
             }
@@ -134,9 +147,32 @@ impl KeyCacheEntry {
     }
 }
 
+/// The pool that a key's data is allocated in.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum KeyPool {
+    /// packed together with other keys into a shared VPAGE
+    Small,
+    /// a run of whole VPAGEs allocated out of the dictionary's medium pool region
+    Medium,
+    /// a run of whole VPAGEs allocated out of the basis-wide large pool
+    Large,
+}
+impl KeyPool {
+    /// Derives the pool from a key's starting virtual address.
+    pub(crate) fn from_vaddr(start: u64) -> KeyPool {
+        if start >= LARGE_POOL_START {
+            KeyPool::Large
+        } else if start >= MEDIUM_POOL_START {
+            KeyPool::Medium
+        } else {
+            KeyPool::Small
+        }
+    }
+}
+
 pub (crate) enum KeyCacheData {
     Small(KeySmallData),
-    // the "Medium" type has a region reserved for it, but we haven't coded a handler for it.
+    // medium keys are read and written directly to disk, like large keys, so they have no cache type.
     #[allow(dead_code)] // Large data caching isn't implemented, so of course, we don't ever create this type
     Large(KeyLargeData),
 }
@@ -197,3 +233,102 @@ impl PartialEq for KeySmallPoolOrd {
         self.avail == other.avail
     }
 }
+
+/// Allocator for a dictionary's medium pool region. Medium keys are allocated in whole VPAGEs, so unlike
+/// the small pool there is no sharing of pages between keys; but unlike the large pool, freed extents
+/// are tracked and re-used, so churn does not leak virtual space. Adjacent free extents are coalesced,
+/// and a free extent that touches the top of the allocated area is returned to the bump region.
+pub(crate) struct KeyMediumPool {
+    /// start of the region owned by this pool
+    base: u64,
+    /// everything from here to the end of the region is unallocated
+    top: u64,
+    /// free extents below `top`, as a map of start address -> length. Always VPAGE-aligned.
+    free: BTreeMap<u64, u64>,
+}
+impl KeyMediumPool {
+    pub(crate) fn new(base: u64) -> KeyMediumPool {
+        assert!(base % VPAGE_SIZE as u64 == 0, "medium pool base is not VPAGE-aligned");
+        KeyMediumPool {
+            base,
+            top: base,
+            free: BTreeMap::new(),
+        }
+    }
+    /// Reconstructs the allocator from the (start, reserved) extents of the keys that are currently
+    /// allocated in the pool. Any gaps between the extents become free space.
+    pub(crate) fn from_extents(base: u64, extents: &mut Vec<(u64, u64)>) -> KeyMediumPool {
+        let mut pool = KeyMediumPool::new(base);
+        extents.sort();
+        for &(start, reserved) in extents.iter() {
+            assert!(start >= pool.top, "medium pool extents overlap");
+            if start > pool.top {
+                pool.free.insert(pool.top, start - pool.top);
+            }
+            pool.top = start + PageAlignedVa::from(reserved).as_u64();
+        }
+        pool
+    }
+    /// Allocates `amount` bytes (rounded up to a whole number of VPAGEs), returning the start address
+    /// of the allocation. Free extents are searched for the best fit before extending the top of the
+    /// pool. Returns `None` if the region is exhausted.
+    pub(crate) fn alloc(&mut self, amount: u64) -> Option<u64> {
+        let amount = PageAlignedVa::from(amount).as_u64();
+        let mut best: Option<(u64, u64)> = None;
+        for (&start, &len) in self.free.iter() {
+            if len >= amount && (best.is_none() || len < best.unwrap().1) {
+                best = Some((start, len));
+                if len == amount {
+                    break;
+                }
+            }
+        }
+        if let Some((start, len)) = best {
+            self.free.remove(&start);
+            if len > amount {
+                self.free.insert(start + amount, len - amount);
+            }
+            Some(start)
+        } else if self.top + amount <= self.base + MEDIUM_POOL_STRIDE {
+            let start = self.top;
+            self.top += amount;
+            Some(start)
+        } else {
+            None
+        }
+    }
+    /// Returns an extent previously handed out by `alloc()` to the pool.
+    pub(crate) fn free(&mut self, start: u64, amount: u64) {
+        let mut start = start;
+        let mut amount = PageAlignedVa::from(amount).as_u64();
+        assert!(start >= self.base && start + amount <= self.top, "medium pool free is out of bounds");
+        // merge with the extent below, if it's adjacent
+        let below = self.free.range(..start).next_back().map(|(&s, &l)| (s, l));
+        if let Some((below_start, below_len)) = below {
+            assert!(below_start + below_len <= start, "double-free in medium pool");
+            if below_start + below_len == start {
+                self.free.remove(&below_start);
+                start = below_start;
+                amount += below_len;
+            }
+        }
+        // merge with the extent above, if it's adjacent
+        if let Some(above_len) = self.free.remove(&(start + amount)) {
+            amount += above_len;
+        }
+        if start + amount == self.top {
+            self.top = start;
+        } else {
+            self.free.insert(start, amount);
+        }
+    }
+    /// Total number of bytes in free extents below the top of the pool. This is the fragmentation
+    /// overhead of the pool: space that is reserved in the virtual map but not used by any key.
+    pub(crate) fn fragmented_bytes(&self) -> u64 {
+        self.free.values().sum()
+    }
+    /// Number of bytes between the base of the pool and the top of the highest allocation.
+    pub(crate) fn extent(&self) -> u64 {
+        self.top - self.base
+    }
+}
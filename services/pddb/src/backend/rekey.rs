@@ -0,0 +1,289 @@
+use crate::api::*;
+use super::*;
+
+use core::mem::size_of;
+use std::convert::TryInto;
+use std::collections::HashMap;
+use std::io::{Result, Error, ErrorKind};
+use aes_gcm_siv::{Aes256GcmSiv, Key};
+use aes_gcm_siv::aead::NewAead;
+use aes::Aes256;
+use aes::cipher::{NewBlockCipher, generic_array::GenericArray};
+use locales::t;
+
+/*
+Basis re-keying
+
+Changing the password of a basis changes its key, and thus every page of the basis -- the page table
+entries, the basis root, the dictionaries and the data -- has to be re-encrypted. This is done copy-first:
+
+  1. Every page other than the basis root is decrypted with the old key, re-encrypted with the new key
+     into a freshly allocated page, and mapped with a page table entry under the new key. Until the root
+     is copied, the new copy can't be mounted, and the basis is still intact under the old key.
+  2. A re-key record is written into the new copy (see the virtual memory layout in basis.rs). It lists
+     the pages of the old copy, so that the job can be finished if it is interrupted.
+  3. The basis root is copied. This is the commit point: from here on, the basis mounts with the new
+     password.
+  4. The pages of the old copy are overwritten with noise, and their page table entries erased. The
+     root goes first, so the old password stops working as early as possible.
+  5. The re-key record is destroyed.
+
+If power is lost before the commit point, the basis is unchanged; the partial copy is unreachable, and
+its pages are reclaimed the next time FastSpace is regenerated. If power is lost after the commit point,
+the re-key record is found the next time the basis is mounted, and whatever is left of the old copy is
+destroyed.
+
+The old key never leaves RAM. Instead, the record holds each page of the old copy together with its page
+table entry, as it was encrypted under the old key. By the time the record is acted on, a page may have
+been reclaimed by a FastSpace regeneration and handed to another basis, which wrote its own entry over
+the old one; so a page is only destroyed if its entry still matches the record.
+
+Pages are allocated one at a time, and FastSpace is regenerated as needed along the way; the pages of the
+copy that aren't in the cache yet, and the pages of the old copy once the cache has switched over, are
+passed to the sweep as in-flight pages, so that it doesn't hand them out again.
+
+  Re-key record, spread over as many VPAGEs as it takes, starting at REKEY_RECORD_VA:
+    - magic: "ReKy" (4 bytes)
+    - number of pages in the old copy (u32)
+    - for each page: page number (u32), encrypted page table entry (16 bytes)
+*/
+
+/// Location of the re-key record; it starts in the VPAGE right after the transaction log.
+pub(crate) const REKEY_RECORD_VA: u64 = 0x0000_7F7D_043F_8FE0;
+/// The record can span this many VPAGEs, which covers a basis of about 50,000 pages.
+pub(crate) const REKEY_RECORD_MAX_PAGES: usize = 256;
+const REKEY_MAGIC: [u8; 4] = [0x52, 0x65, 0x4b, 0x79]; // "ReKy"
+const REKEY_ENTRY_LEN: usize = size_of::<u32>() + aes::BLOCK_SIZE;
+
+fn rekey_root_va() -> VirtAddr {
+    VirtAddr::new(VPAGE_SIZE as u64).unwrap()
+}
+fn rekey_record_va(page: usize) -> VirtAddr {
+    VirtAddr::new(REKEY_RECORD_VA + (page * VPAGE_SIZE) as u64).unwrap()
+}
+
+/// Re-encrypts the basis at `index` in `cache` under `new_key`. If `finish` is false, the pages under the
+/// old key are left behind after the commit point; this is only useful for testing what happens when a
+/// password change is interrupted. The caller is responsible for syncing the basis beforehand.
+pub(crate) fn basis_rekey(hw: &mut PddbOs, cache: &mut Vec::<BasisCacheEntry>, index: usize,
+    new_key: &[u8; AES_KEYSIZE], finish: bool, progress: Option<&modals::Modals>) -> Result<()> {
+    let new_cipher = Aes256GcmSiv::new(Key::from_slice(new_key));
+    let new_cipher_ecb = Aes256::new(GenericArray::from_slice(new_key));
+    let mut old_pages: Vec::<(VirtAddr, PhysPage)> = cache[index].v2p_map.iter()
+        .filter(|(_, pp)| pp.valid())
+        .map(|(&va, &pp)| (va, pp))
+        .collect();
+    // the root is copied last, and destroyed first
+    old_pages.sort_by_key(|(va, _)| *va != rekey_root_va());
+    if rekey_record_pages(old_pages.len()) > REKEY_RECORD_MAX_PAGES {
+        return Err(Error::new(ErrorKind::OutOfMemory, "Basis is too large to re-key"));
+    }
+
+    if let Some(modals) = progress {
+        modals.start_progress(t!("pddb.rekey", xous::LANG), 0, 2 * old_pages.len() as u32 + 1, 0)
+            .expect("couldn't raise progress bar");
+    }
+    let mut new_map = HashMap::<VirtAddr, PhysPage>::new();
+    if let Err(e) = rekey_copy(hw, cache, index, &old_pages, new_key, &new_cipher, &new_cipher_ecb, &mut new_map, progress) {
+        // nothing has been committed, so the partial copy is simply thrown away
+        let mut partial: Vec::<PhysPage> = new_map.values().cloned().collect();
+        for i in 0..partial.len() {
+            hw.ensure_fast_space_alloc_in_flight(1, cache, partial[i..].iter().map(|pp| pp.page_number()));
+            rekey_destroy_page(hw, &mut partial[i]);
+        }
+        if let Some(modals) = progress {
+            modals.finish_progress().expect("couldn't dismiss progress bar");
+        }
+        return Err(e);
+    }
+
+    // committed: switch the cache over to the new copy
+    let basis = &mut cache[index];
+    basis.v2p_map = new_map;
+    basis.key = GenericArray::clone_from_slice(new_key);
+    basis.cipher = new_cipher;
+    basis.cipher_ecb = new_cipher_ecb;
+    if finish {
+        let copied = old_pages.len() as u32 + 1;
+        for step in 0..old_pages.len() {
+            // keeps room in the FSCB log for the frees; the pages of the old copy that remain are no longer in
+            // the cache, so they are passed along in case this forces a sweep.
+            hw.ensure_fast_space_alloc_in_flight(1, cache, old_pages[step..].iter().map(|(_, pp)| pp.page_number()));
+            rekey_destroy_page(hw, &mut old_pages[step].1);
+            if let Some(modals) = progress {
+                modals.update_progress(copied + step as u32 + 1).expect("couldn't update progress bar");
+            }
+        }
+        rekey_record_retire(hw, cache, index);
+    }
+    if let Some(modals) = progress {
+        modals.finish_progress().expect("couldn't dismiss progress bar");
+    }
+    Ok(())
+}
+
+/// Finishes a password change that was interrupted after its commit point, by destroying whatever is left
+/// of the basis under its old key. Does nothing if the basis at `index` has no re-key record.
+pub(crate) fn rekey_recover(hw: &mut PddbOs, cache: &mut Vec::<BasisCacheEntry>, index: usize) {
+    let name = cache[index].name.clone();
+    if !cache[index].v2p_map.contains_key(&rekey_record_va(0)) {
+        return;
+    }
+    if let Some(entries) = rekey_record_read(hw, &cache[index]) {
+        log::warn!("Basis {} was interrupted while changing its password, destroying the copy under the old password", name);
+        for i in 0..entries.len() {
+            let (page, pte) = entries[i];
+            // a page whose entry has changed was reclaimed before the change was interrupted, or since
+            if hw.pt_slot(page) == Some(pte) {
+                hw.ensure_fast_space_alloc_in_flight(1, cache, entries[i..].iter().map(|(page, _)| *page));
+                let mut pp = PhysPage(0);
+                pp.set_page_number(page);
+                pp.set_space_state(SpaceState::Used);
+                rekey_destroy_page(hw, &mut pp);
+            }
+        }
+    } else {
+        log::error!("Re-key record of basis {} is corrupt, discarding it", name);
+    }
+    rekey_record_retire(hw, cache, index);
+}
+
+/// Makes the copy of the basis under the new key, up to and including the commit point. The pages of the copy
+/// are recorded in `new_map` as they are written, so they can be cleaned up if this fails.
+fn rekey_copy(hw: &mut PddbOs, cache: &Vec::<BasisCacheEntry>, index: usize, old_pages: &[(VirtAddr, PhysPage)],
+    new_key: &[u8; AES_KEYSIZE], new_cipher: &Aes256GcmSiv, new_cipher_ecb: &Aes256,
+    new_map: &mut HashMap::<VirtAddr, PhysPage>, progress: Option<&modals::Modals>
+) -> Result<()> {
+    let basis = &cache[index];
+    let mut step = 0;
+    // 1. everything but the root
+    for (va, pp) in old_pages.iter().filter(|(va, _)| *va != rekey_root_va()) {
+        if let Some(mut page) = hw.data_decrypt_page(&basis.cipher, &basis.aad, pp) {
+            let new_pp = rekey_alloc(hw, cache, new_map)?;
+            hw.data_encrypt_and_patch_page(new_cipher, &basis.aad, &mut page, &new_pp);
+            rekey_map(hw, new_map, *va, new_pp, new_cipher_ecb);
+        } else {
+            log::warn!("rekey: {} va {:x} doesn't decrypt, it won't be carried over", basis.name, va);
+        }
+        step += 1;
+        if let Some(modals) = progress {
+            modals.update_progress(step).expect("couldn't update progress bar");
+        }
+    }
+    // 2. the re-key record
+    let mut record = Vec::<u8>::new();
+    record.extend_from_slice(&REKEY_MAGIC);
+    record.extend_from_slice(&(old_pages.len() as u32).to_le_bytes());
+    for (_, pp) in old_pages.iter() {
+        let pte = hw.pt_slot(pp.page_number()).ok_or(Error::new(ErrorKind::InvalidData, "Basis page is outside the page table"))?;
+        record.extend_from_slice(&pp.page_number().to_le_bytes());
+        record.extend_from_slice(&pte);
+    }
+    for (i, chunk) in record.chunks(VPAGE_SIZE).enumerate() {
+        let mut vpage = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+        vpage[..size_of::<JournalType>()].copy_from_slice(&(hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes());
+        vpage[size_of::<JournalType>()..size_of::<JournalType>() + chunk.len()].copy_from_slice(chunk);
+        let record_pp = rekey_alloc(hw, cache, new_map)?;
+        hw.data_encrypt_and_patch_page(new_cipher, &basis.aad, &mut vpage, &record_pp);
+        rekey_map(hw, new_map, rekey_record_va(i), record_pp, new_cipher_ecb);
+    }
+    // 3. the root, which commits the copy
+    let root_pp = old_pages.iter().find(|(va, _)| *va == rekey_root_va()).map(|(_, pp)| *pp)
+        .ok_or(Error::new(ErrorKind::NotFound, "Basis has no root page"))?;
+    let mut root = hw.data_decrypt_page_with_commit(basis.key.as_slice(), &basis.aad, &root_pp)
+        .ok_or(Error::new(ErrorKind::InvalidData, "Basis root did not decrypt"))?;
+    let new_root_pp = rekey_alloc(hw, cache, new_map)?;
+    hw.data_encrypt_and_patch_page_with_commit(new_key, &basis.aad, &mut root, &new_root_pp);
+    rekey_map(hw, new_map, rekey_root_va(), new_root_pp, new_cipher_ecb);
+    if let Some(modals) = progress {
+        modals.update_progress(step + 2).expect("couldn't update progress bar");
+    }
+    Ok(())
+}
+
+/// Number of VPAGEs taken by the re-key record of a basis with `pages` pages.
+fn rekey_record_pages(pages: usize) -> usize {
+    (REKEY_MAGIC.len() + size_of::<u32>() + pages * REKEY_ENTRY_LEN + VPAGE_SIZE - 1) / VPAGE_SIZE
+}
+
+/// Reads back the re-key record of `basis`, as a list of (page number, encrypted page table entry).
+fn rekey_record_read(hw: &mut PddbOs, basis: &BasisCacheEntry) -> Option<Vec::<(u32, [u8; aes::BLOCK_SIZE])>> {
+    let mut record = Vec::<u8>::new();
+    let mut pages = 1;
+    let mut i = 0;
+    while i < pages {
+        let pp = basis.v2p_map.get(&rekey_record_va(i))?;
+        let vpage = hw.data_decrypt_page(&basis.cipher, &basis.aad, pp)?;
+        record.extend_from_slice(&vpage[size_of::<JournalType>()..]);
+        if i == 0 {
+            if record[..REKEY_MAGIC.len()] != REKEY_MAGIC {
+                return None;
+            }
+            let count = u32::from_le_bytes(record[REKEY_MAGIC.len()..REKEY_MAGIC.len() + 4].try_into().unwrap()) as usize;
+            pages = rekey_record_pages(count);
+            if pages > REKEY_RECORD_MAX_PAGES {
+                return None;
+            }
+        }
+        i += 1;
+    }
+    let count = u32::from_le_bytes(record[REKEY_MAGIC.len()..REKEY_MAGIC.len() + 4].try_into().unwrap()) as usize;
+    Some(record[REKEY_MAGIC.len() + 4..].chunks_exact(REKEY_ENTRY_LEN).take(count)
+        .map(|entry| (
+            u32::from_le_bytes(entry[..4].try_into().unwrap()),
+            entry[4..].try_into().unwrap(),
+        ))
+        .collect()
+    )
+}
+
+/// Allocates a page for the new copy, regenerating FastSpace if it has run dry.
+fn rekey_alloc(hw: &mut PddbOs, cache: &Vec::<BasisCacheEntry>, new_map: &HashMap::<VirtAddr, PhysPage>) -> Result<PhysPage> {
+    if !hw.ensure_fast_space_alloc_in_flight(1, cache, new_map.values().map(|pp| pp.page_number())) {
+        return Err(Error::new(ErrorKind::OutOfMemory, "No free space to re-key basis"));
+    }
+    let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "couldn't allocate page for re-keyed basis"))?;
+    pp.set_valid(true);
+    Ok(pp)
+}
+
+/// Writes the page table entry of a page of the new copy, and notes it in `new_map`.
+fn rekey_map(hw: &mut PddbOs, new_map: &mut HashMap::<VirtAddr, PhysPage>, va: VirtAddr, mut pp: PhysPage, cipher_ecb: &Aes256) {
+    hw.pt_patch_mapping(va, pp.page_number(), cipher_ecb);
+    pp.set_clean(true);
+    new_map.insert(va, pp);
+}
+
+/// Overwrites a page with noise, erases its page table entry and returns it to FastSpace.
+fn rekey_destroy_page(hw: &mut PddbOs, pp: &mut PhysPage) {
+    let mut noise = [0u8; PAGE_SIZE];
+    hw.trng_slice(&mut noise);
+    hw.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+    hw.pt_erase(pp.page_number());
+    hw.fast_space_free(pp);
+}
+
+fn rekey_record_retire(hw: &mut PddbOs, cache: &mut Vec::<BasisCacheEntry>, index: usize) {
+    let mut record = Vec::<PhysPage>::new();
+    while let Some(pp) = cache[index].v2p_map.get(&rekey_record_va(record.len())) {
+        record.push(*pp);
+        if record.len() == REKEY_RECORD_MAX_PAGES {
+            break;
+        }
+    }
+    if record.len() == 0 {
+        return;
+    }
+    for pp in record.iter_mut() {
+        hw.ensure_fast_space_alloc(1, cache);
+        rekey_destroy_page(hw, pp);
+    }
+    // drops the freed record from the v2p map
+    let basis = &mut cache[index];
+    for i in 0..record.len() {
+        if let Some(pp) = basis.v2p_map.get_mut(&rekey_record_va(i)) {
+            pp.set_valid(false);
+        }
+    }
+    basis.pt_sync(hw);
+}
@@ -0,0 +1,501 @@
+use crate::api::*;
+use super::*;
+
+use core::mem::size_of;
+use std::convert::TryInto;
+use std::collections::{HashMap, HashSet};
+use std::io::{Result, Error, ErrorKind};
+
+/*
+Transaction commit
+
+A transaction is a list of key writes and deletes that are staged in RAM, and then committed to a
+single basis as a unit. The commit is made atomic by the page table, rather than by replaying the
+operations: every data page is either reachable through a page table entry, or it is not part of
+the basis.
+
+  1. The transaction is applied to the basis with the ordinary key update routines, but with the
+     hardware layer in "shadow" mode (see `TxnShadow`). In shadow mode, a write to a page that was
+     on disk when the transaction started goes to a freshly allocated copy instead, and the copy is
+     given a journal number one higher than the page it replaces. Page table updates and frees are
+     not written out; they are recorded. Pages that are allocated for the first time are written in
+     place, as nothing on disk refers to them yet. The disk as seen through the page table is thus
+     untouched by this step.
+  2. The recorded page table updates are written out as a remap record in the basis' transaction
+     log region (see the virtual memory layout in basis.rs), and the record is sealed.
+  3. The remap record is applied to the page table: each copy is mapped, and then the page it
+     replaces is unmapped. Frees are journaled, and the record is retired.
+
+If power is lost before the record is sealed, nothing refers to the copies, and the transaction is
+rolled back; the pages it allocated are reclaimed by the next full-space sweep. If power is lost
+after it is sealed, the next mount of the basis applies the record again before anyone can see the
+basis, which rolls the transaction forward. Applying a remap record is idempotent. Should both the
+copy and the page it replaces still be mapped, `pt_scan_key()` resolves the conflict in favor of the
+higher journal number, i.e. the copy.
+
+The log region consists of a header VPAGE, followed by up to TXN_LOG_MAX_PAGES VPAGEs of body:
+
+  Header:
+    - magic: "TxLg" (4 bytes)
+    - state: u32 (Sealed or Retired)
+    - number of body pages: u32
+    - reserved: u32
+    - length of the body in bytes: u64
+    - digest: SHA-512/256 of the body (32 bytes)
+
+  Body: a count of page table updates (u32), followed by the updates themselves. Each update is a
+  one-byte type tag (Map or Unmap), the virtual address (u64) and the physical page number (u64).
+  Integers are little-endian.
+
+The commit point is the page table entry of the header. A new header is always written to a freshly
+allocated page, with a journal number one higher than that of the header it replaces, and the
+page table entry of the old header is only erased once the new one is in place. Should both entries
+survive a power loss, `pt_scan_key()` resolves the conflict in favor of the new header. The body pages
+are written before the header, so a sealed header always has its body present; the digest catches
+body pages that were left behind by an earlier, interrupted log.
+*/
+
+/// Start of the transaction log region; the header lives in the first VPAGE.
+pub(crate) const TXN_LOG_START: u64 = 0x0000_7F7D_0400_0000;
+/// Maximum size of a transaction log body, in VPAGEs.
+pub(crate) const TXN_LOG_MAX_PAGES: usize = 1024;
+/// Maximum amount of data a transaction can stage. This bounds the size of a transaction to just under 4MiB.
+pub(crate) const TXN_MAX_LEN: usize = 1024 * VPAGE_SIZE;
+const TXN_MAGIC: [u8; 4] = [0x54, 0x78, 0x4c, 0x67]; // "TxLg"
+const TXN_STATE_SEALED: u32 = 1;
+const TXN_STATE_RETIRED: u32 = 2;
+/// length of the header, as stored after the journal number
+const TXN_HEADER_LEN: usize = 4 + 4 + 4 + 4 + 8 + 32;
+/// length of one page table update in the remap record
+const REMAP_OP_LEN: usize = 1 + 8 + 8;
+
+/// A single change staged in a transaction.
+pub(crate) enum TxnOp {
+    /// replaces the entire contents of a key, creating the key (and its dictionary) if needed
+    Write { dict: String, key: String, data: Vec::<u8> },
+    /// removes a key. Deleting a key that doesn't exist is not an error.
+    Delete { dict: String, key: String },
+}
+impl TxnOp {
+    pub(crate) fn dict(&self) -> &str {
+        match self {
+            TxnOp::Write { dict, .. } => dict,
+            TxnOp::Delete { dict, .. } => dict,
+        }
+    }
+    pub(crate) fn key(&self) -> &str {
+        match self {
+            TxnOp::Write { key, .. } => key,
+            TxnOp::Delete { key, .. } => key,
+        }
+    }
+    fn len(&self) -> usize {
+        match self {
+            TxnOp::Write { dict, key, data } => dict.len() + key.len() + data.len(),
+            TxnOp::Delete { dict, key } => dict.len() + key.len(),
+        }
+    }
+}
+
+/// A set of changes to a single basis that are committed atomically by `BasisCache::txn_commit()`.
+pub(crate) struct Transaction {
+    /// the basis the transaction commits to. This is resolved when the transaction is started, so that a
+    /// transaction can't straddle bases if another basis is opened in the meantime.
+    pub(crate) basis: String,
+    ops: Vec::<TxnOp>,
+    /// amount of data staged, tracked so we can refuse to stage more than a commit can hold
+    len: usize,
+}
+impl Transaction {
+    pub(crate) fn new(basis: &str) -> Self {
+        Transaction {
+            basis: String::from(basis),
+            ops: Vec::new(),
+            len: 0,
+        }
+    }
+    pub(crate) fn ops(&self) -> &[TxnOp] {
+        &self.ops
+    }
+    fn push(&mut self, op: TxnOp) -> Result<()> {
+        if op.dict().len() >= DICT_NAME_LEN || op.key().len() >= KEY_NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, "name is too long"));
+        }
+        if self.len + op.len() > TXN_MAX_LEN {
+            return Err(Error::new(ErrorKind::OutOfMemory, "transaction is too large"));
+        }
+        self.len += op.len();
+        self.ops.push(op);
+        Ok(())
+    }
+    /// Stages a write that replaces the contents of `dict`:`key` with `data`.
+    pub(crate) fn write(&mut self, dict: &str, key: &str, data: &[u8]) -> Result<()> {
+        self.push(TxnOp::Write { dict: String::from(dict), key: String::from(key), data: data.to_vec() })
+    }
+    /// Extends the data of a write that was just staged. This lets values that are too large for a single
+    /// IPC message be staged piece-wise.
+    pub(crate) fn append(&mut self, dict: &str, key: &str, more: &[u8]) -> Result<()> {
+        if self.len + more.len() > TXN_MAX_LEN {
+            return Err(Error::new(ErrorKind::OutOfMemory, "transaction is too large"));
+        }
+        match self.ops.last_mut() {
+            Some(TxnOp::Write { dict: d, key: k, data }) if d.as_str() == dict && k.as_str() == key => {
+                data.extend_from_slice(more);
+                self.len += more.len();
+                Ok(())
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, "append does not follow a write to the same key")),
+        }
+    }
+    /// Stages the removal of `dict`:`key`.
+    pub(crate) fn delete(&mut self, dict: &str, key: &str) -> Result<()> {
+        self.push(TxnOp::Delete { dict: String::from(dict), key: String::from(key) })
+    }
+    /// A generous estimate of the pages a commit of this transaction allocates: the data itself, copies of
+    /// the dictionary, descriptor and small-pool pages each operation touches, a copy of the basis root, and
+    /// the remap record. These have to be on hand before the commit starts, because FastSpace can't be
+    /// regenerated while a transaction is shadowed.
+    pub(crate) fn pages_needed(&self) -> usize {
+        let pages = self.len / VPAGE_SIZE + 4 * self.ops.len() + 4;
+        pages + txn_log_pages(4 + 2 * pages * REMAP_OP_LEN)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+enum RemapOpType {
+    Map = 1,
+    Unmap = 2,
+}
+
+/// A page table update recorded by a transaction.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum RemapOp {
+    /// points `va` at `page`
+    Map { va: VirtAddr, page: PhysAddr },
+    /// erases the page table entry of `page`, if it still maps `va` in this basis. The check matters when
+    /// a record is applied again after a power loss: by then the page may have been freed, and handed to
+    /// another basis.
+    Unmap { va: VirtAddr, page: PhysAddr },
+}
+
+pub(crate) fn remap_serialize(ops: &[RemapOp]) -> Vec::<u8> {
+    let mut body = Vec::<u8>::with_capacity(4 + ops.len() * REMAP_OP_LEN);
+    body.extend_from_slice(&(ops.len() as u32).to_le_bytes());
+    for op in ops.iter() {
+        let (tag, va, page) = match *op {
+            RemapOp::Map { va, page } => (RemapOpType::Map, va, page),
+            RemapOp::Unmap { va, page } => (RemapOpType::Unmap, va, page),
+        };
+        body.push(tag as u8);
+        body.extend_from_slice(&va.get().to_le_bytes());
+        body.extend_from_slice(&(page as u64).to_le_bytes());
+    }
+    body
+}
+
+pub(crate) fn remap_deserialize(body: &[u8]) -> Result<Vec::<RemapOp>> {
+    let truncated = || Error::new(ErrorKind::InvalidData, "remap record is truncated");
+    if body.len() < 4 {
+        return Err(truncated());
+    }
+    let count = u32::from_le_bytes(body[..4].try_into().unwrap()) as usize;
+    let records = &body[4..];
+    if records.len() < count * REMAP_OP_LEN {
+        return Err(truncated());
+    }
+    let mut ops = Vec::<RemapOp>::with_capacity(count);
+    for record in records.chunks_exact(REMAP_OP_LEN).take(count) {
+        let va = VirtAddr::new(u64::from_le_bytes(record[1..9].try_into().unwrap()))
+            .ok_or(Error::new(ErrorKind::InvalidData, "remap record has a null address"))?;
+        let page = u64::from_le_bytes(record[9..17].try_into().unwrap()) as PhysAddr;
+        if record[0] == RemapOpType::Map as u8 {
+            ops.push(RemapOp::Map { va, page });
+        } else if record[0] == RemapOpType::Unmap as u8 {
+            ops.push(RemapOp::Unmap { va, page });
+        } else {
+            return Err(Error::new(ErrorKind::InvalidData, "unknown remap record operation"));
+        }
+    }
+    Ok(ops)
+}
+
+/// Applies a remap record to the page table of `basis`. Only the disk is updated; the caller is
+/// responsible for bringing the in-RAM page map in line.
+pub(crate) fn remap_apply(hw: &mut PddbOs, basis: &BasisCacheEntry, ops: &[RemapOp]) {
+    for op in ops.iter() {
+        match *op {
+            RemapOp::Map { va, page } => hw.pt_patch_mapping(va, page, &basis.cipher_ecb),
+            RemapOp::Unmap { va, page } => {
+                if hw.pt_maps(page, va, &basis.cipher_ecb) {
+                    hw.pt_erase(page);
+                }
+            }
+        }
+    }
+}
+
+/// State of a transaction that is being applied in shadow mode; see the description at the top of this file.
+/// While `PddbOs` holds one of these, writes to the pages in `originals` are redirected to copies, and page
+/// table updates and frees are recorded here instead of being written to disk.
+pub(crate) struct TxnShadow {
+    /// the pages the basis had mapped when the transaction started, with their virtual addresses
+    originals: HashMap::<PhysAddr, (VirtAddr, PhysPage)>,
+    /// copies of original pages, by original page number, along with the journal number of the original
+    copies: HashMap::<PhysAddr, (PhysPage, JournalType)>,
+    /// every page allocated while shadowed, so they can be returned if the transaction is abandoned
+    allocated: Vec::<PhysPage>,
+    /// pages freed while shadowed. They are only journaled as free once the transaction is committed.
+    freed: Vec::<PhysPage>,
+    /// page table entries written while shadowed, in order
+    mapped: Vec::<(VirtAddr, PhysAddr)>,
+    /// set if a copy couldn't be allocated; the transaction can't be committed
+    failed: bool,
+}
+impl TxnShadow {
+    pub(crate) fn new(v2p_map: &HashMap::<VirtAddr, PhysPage>) -> Self {
+        TxnShadow {
+            originals: v2p_map.iter().map(|(&va, &pp)| (pp.page_number(), (va, pp))).collect(),
+            copies: HashMap::new(),
+            allocated: Vec::new(),
+            freed: Vec::new(),
+            mapped: Vec::new(),
+            failed: false,
+        }
+    }
+    pub(crate) fn is_original(&self, page: PhysAddr) -> bool {
+        self.originals.contains_key(&page)
+    }
+    /// The copy of an original page, and the journal number it has to out-rank.
+    pub(crate) fn copy_of(&self, page: PhysAddr) -> Option<(PhysPage, JournalType)> {
+        self.copies.get(&page).copied()
+    }
+    pub(crate) fn add_copy(&mut self, page: PhysAddr, copy: PhysPage, journal: JournalType) {
+        self.copies.insert(page, (copy, journal));
+    }
+    pub(crate) fn allocated(&mut self, pp: PhysPage) {
+        self.allocated.push(pp);
+    }
+    pub(crate) fn free(&mut self, pp: PhysPage) {
+        self.freed.push(pp);
+    }
+    pub(crate) fn map(&mut self, va: VirtAddr, page: PhysAddr) {
+        let page = self.copies.get(&page).map(|(copy, _)| copy.page_number()).unwrap_or(page);
+        self.mapped.push((va, page));
+    }
+    pub(crate) fn fail(&mut self) {
+        self.failed = true;
+    }
+    pub(crate) fn failed(&self) -> bool {
+        self.failed
+    }
+    /// Works out what the transaction does to the page table. Returns the remap record, the copies that
+    /// become part of the basis (by virtual address), and the pages to erase and free once the record is
+    /// applied. Erasing pages that are still mapped on disk is deferred to then, see `PddbOs::patch_data()`.
+    pub(crate) fn finish(&self) -> (Vec::<RemapOp>, Vec::<(VirtAddr, PhysPage)>, Vec::<PhysPage>) {
+        let freed: HashSet::<PhysAddr> = self.freed.iter().map(|pp| pp.page_number()).collect();
+        let mut ops = Vec::<RemapOp>::new();
+        let mut live = Vec::<(VirtAddr, PhysPage)>::new();
+        let mut frees = self.freed.clone();
+        for (&original, &(copy, _)) in self.copies.iter() {
+            let (va, original_pp) = self.originals[&original];
+            if freed.contains(&original) {
+                // the page was written, then let go of; the copy never makes it into the page table
+                frees.push(copy);
+            } else {
+                // map the copy before unmapping the original, so the virtual page is never missing
+                ops.push(RemapOp::Map { va, page: copy.page_number() });
+                ops.push(RemapOp::Unmap { va, page: original });
+                live.push((va, copy));
+                frees.push(original_pp);
+            }
+        }
+        for &(va, page) in self.mapped.iter() {
+            if !freed.contains(&page) {
+                ops.push(RemapOp::Map { va, page });
+            }
+        }
+        for pp in self.freed.iter() {
+            if let Some(&(va, _)) = self.originals.get(&pp.page_number()) {
+                ops.push(RemapOp::Unmap { va, page: pp.page_number() });
+            }
+        }
+        (ops, live, frees)
+    }
+    /// Erases and returns the pages a transaction allocated, after it has been rolled back.
+    pub(crate) fn abandon(self, hw: &mut PddbOs) {
+        for mut pp in self.allocated.into_iter() {
+            let mut noise = [0u8; PAGE_SIZE];
+            hw.trng_slice(&mut noise);
+            hw.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+            hw.fast_space_free(&mut pp);
+        }
+    }
+}
+
+/// How far `BasisCache::txn_commit_until()` takes a commit. Stopping short of `Done` leaves the disk as a power
+/// loss at that point would, which is only useful for testing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum TxnStage {
+    /// the transaction has been shadowed, and its remap record written, but not sealed
+    Staged,
+    /// the remap record is sealed, but none of it has been applied
+    Sealed,
+    /// the given number of page table updates from the remap record have been applied
+    Remapped(usize),
+    Done,
+}
+
+fn txn_digest(body: &[u8]) -> [u8; 32] {
+    use sha2::{FallbackStrategy, Sha512Trunc256};
+    use digest::Digest;
+
+    let mut hasher = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+    hasher.update(body);
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(hasher.finalize().as_slice());
+    digest
+}
+
+fn txn_header_va() -> VirtAddr {
+    VirtAddr::new(TXN_LOG_START).unwrap()
+}
+fn txn_body_va(page: usize) -> VirtAddr {
+    VirtAddr::new(TXN_LOG_START + ((page + 1) * VPAGE_SIZE) as u64).unwrap()
+}
+fn is_txn_body_va(va: &VirtAddr) -> bool {
+    va.get() > TXN_LOG_START && va.get() <= TXN_LOG_START + (TXN_LOG_MAX_PAGES * VPAGE_SIZE) as u64
+}
+
+/// Number of pages that have to be available in FastSpace to log a transaction body of `len` bytes.
+pub(crate) fn txn_log_pages(len: usize) -> usize {
+    // one extra page for the header
+    (len + VPAGE_SIZE - 1) / VPAGE_SIZE + 1
+}
+
+/// Writes the body of a transaction log into the log region. The transaction is not committed until
+/// `txn_log_seal()` is called.
+pub(crate) fn txn_log_stage(hw: &mut PddbOs, basis: &mut BasisCacheEntry, body: &[u8]) -> Result<()> {
+    if body.len() > TXN_LOG_MAX_PAGES * VPAGE_SIZE {
+        return Err(Error::new(ErrorKind::InvalidInput, "transaction is too large"));
+    }
+    // get rid of anything left behind by an earlier log that didn't get retired
+    txn_log_free_body(hw, basis);
+    for (page, chunk) in body.chunks(VPAGE_SIZE).enumerate() {
+        let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "couldn't allocate memory for transaction log"))?;
+        pp.set_valid(true);
+        let mut block = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+        for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(block[..size_of::<JournalType>()].iter_mut()) {
+            *dst = src;
+        }
+        for (&src, dst) in chunk.iter().zip(block[size_of::<JournalType>()..].iter_mut()) {
+            *dst = src;
+        }
+        hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut block, &pp);
+        // the new page is not clean, so its page table entry is written by the pt_sync() below
+        if let Some(old_pp) = basis.v2p_map.insert(txn_body_va(page), pp) {
+            // the previous body page was freed, but its removal hasn't been synced yet
+            hw.pt_erase(old_pp.page_number());
+        }
+    }
+    basis.pt_sync(hw);
+    Ok(())
+}
+
+/// Seals a transaction log whose body has already been written with `txn_log_stage()`. Once this returns,
+/// the transaction will be applied even if power is lost before `BasisCache::txn_commit()` is done with it.
+pub(crate) fn txn_log_seal(hw: &mut PddbOs, basis: &mut BasisCacheEntry, body: &[u8]) -> Result<()> {
+    // the new header has to out-rank the one it replaces in case both page table entries survive
+    let journal = basis.v2p_map.get(&txn_header_va())
+        .and_then(|pp| hw.data_decrypt_page(&basis.cipher, &basis.aad, pp))
+        .map(|page| JournalType::from_le_bytes(page[..size_of::<JournalType>()].try_into().unwrap()))
+        .unwrap_or(hw.trng_u32() % JOURNAL_RAND_RANGE);
+    let mut block = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
+    let mut header = Vec::<u8>::with_capacity(size_of::<JournalType>() + TXN_HEADER_LEN);
+    header.extend_from_slice(&journal.to_le_bytes());
+    header.extend_from_slice(&TXN_MAGIC);
+    header.extend_from_slice(&TXN_STATE_SEALED.to_le_bytes());
+    header.extend_from_slice(&(txn_log_pages(body.len()) as u32 - 1).to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    header.extend_from_slice(&txn_digest(body));
+    for (&src, dst) in header.iter().zip(block.iter_mut()) {
+        *dst = src;
+    }
+
+    let mut pp = hw.try_fast_space_alloc().ok_or(Error::new(ErrorKind::OutOfMemory, "couldn't allocate memory for transaction log"))?;
+    pp.set_valid(true);
+    hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut block, &pp);
+    // this is the commit point
+    hw.pt_patch_mapping(txn_header_va(), pp.page_number(), &basis.cipher_ecb);
+    pp.set_clean(true);
+    // now break the old mapping
+    if let Some(mut old_pp) = basis.v2p_map.insert(txn_header_va(), pp) {
+        hw.pt_erase(old_pp.page_number());
+        if old_pp.valid() {
+            hw.fast_space_free(&mut old_pp);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the body of the transaction log, if the log region holds a sealed transaction that has yet to
+/// be retired.
+pub(crate) fn txn_log_read(hw: &PddbOs, basis: &BasisCacheEntry) -> Option<Vec::<u8>> {
+    let page = basis.v2p_map.get(&txn_header_va())
+        .and_then(|pp| hw.data_decrypt_page(&basis.cipher, &basis.aad, pp))?;
+    let header = &page[size_of::<JournalType>()..size_of::<JournalType>() + TXN_HEADER_LEN];
+    if header[..4] != TXN_MAGIC || u32::from_le_bytes(header[4..8].try_into().unwrap()) != TXN_STATE_SEALED {
+        return None;
+    }
+    let pages = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+    let len = u64::from_le_bytes(header[16..24].try_into().unwrap()) as usize;
+    if pages > TXN_LOG_MAX_PAGES || txn_log_pages(len) != pages + 1 {
+        log::error!("Transaction log header of basis {} is inconsistent, discarding the log", basis.name);
+        return None;
+    }
+    let mut body = Vec::<u8>::with_capacity(pages * VPAGE_SIZE);
+    for index in 0..pages {
+        match basis.v2p_map.get(&txn_body_va(index)).and_then(|pp| hw.data_decrypt_page(&basis.cipher, &basis.aad, pp)) {
+            Some(data) => body.extend_from_slice(&data[size_of::<JournalType>()..]),
+            None => {
+                log::error!("Transaction log of basis {} is missing page {}, discarding the log", basis.name, index);
+                return None;
+            }
+        }
+    }
+    body.truncate(len);
+    if txn_digest(&body) != header[24..56] {
+        log::error!("Transaction log of basis {} does not match its digest, discarding the log", basis.name);
+        return None;
+    }
+    Some(body)
+}
+
+/// Retires the transaction log, once its transaction has been applied (or discarded). The header is kept,
+/// so the next seal can out-rank it, but it is marked as retired; the body pages are freed.
+pub(crate) fn txn_log_retire(hw: &mut PddbOs, basis: &mut BasisCacheEntry) {
+    if let Some(pp) = basis.v2p_map.get(&txn_header_va()).cloned() {
+        if let Some(mut page) = hw.data_decrypt_page(&basis.cipher, &basis.aad, &pp) {
+            let state = size_of::<JournalType>() + 4;
+            if page[state..state + 4] == TXN_STATE_SEALED.to_le_bytes() {
+                page[state..state + 4].copy_from_slice(&TXN_STATE_RETIRED.to_le_bytes());
+                hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut page, &pp);
+            }
+        }
+    }
+    txn_log_free_body(hw, basis);
+}
+
+fn txn_log_free_body(hw: &mut PddbOs, basis: &mut BasisCacheEntry) {
+    let mut freed = false;
+    for (va, pp) in basis.v2p_map.iter_mut() {
+        if is_txn_body_va(va) && pp.valid() {
+            hw.fast_space_free(pp);
+            freed = true;
+        }
+    }
+    if freed {
+        basis.pt_sync(hw);
+    }
+}
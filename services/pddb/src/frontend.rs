@@ -1,2 +1,4 @@
 pub mod pddbkey;
 pub use pddbkey::*;
+pub mod pddbtxn;
+pub use pddbtxn::*;
@@ -0,0 +1,93 @@
+use crate::*;
+use xous::CID;
+use xous_ipc::Buffer;
+
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+
+/// A set of key writes and deletes that are applied to a single basis all at once, or not at all.
+/// Changes are staged in the PDDB server and are not visible to anyone until `commit()` is called.
+/// If the commit is interrupted by a power loss, the transaction is either finished or rolled back
+/// the next time the basis is mounted. Dropping a transaction without committing it aborts it.
+pub struct PddbTransaction {
+    pub(crate) token: ApiToken,
+    pub(crate) conn: CID,
+    pub(crate) finished: bool,
+}
+/// PddbTransactions are created by `Pddb::begin()`
+impl PddbTransaction {
+    /// Stages a write that replaces the entire value of `key_name` with `data`. The key, and its dictionary,
+    /// are created on commit if they don't already exist.
+    pub fn write(&mut self, dict_name: &str, key_name: &str, data: &[u8]) -> Result<()> {
+        let mut chunks = data.chunks(TXN_CHUNK_LEN);
+        self.transact(PddbTxnOp::Write, dict_name, key_name, chunks.next().unwrap_or(&[]))?;
+        for chunk in chunks {
+            self.transact(PddbTxnOp::Append, dict_name, key_name, chunk)?;
+        }
+        Ok(())
+    }
+    /// Stages the deletion of `key_name`. Deleting a key that doesn't exist at commit time is not an error.
+    pub fn delete(&mut self, dict_name: &str, key_name: &str) -> Result<()> {
+        self.transact(PddbTxnOp::Delete, dict_name, key_name, &[])
+    }
+    /// Atomically applies all of the staged changes.
+    pub fn commit(mut self) -> Result<()> {
+        self.finished = true;
+        self.transact(PddbTxnOp::Commit, "", "", &[])
+    }
+    /// Throws away all of the staged changes.
+    pub fn abort(mut self) -> Result<()> {
+        self.finished = true;
+        self.transact(PddbTxnOp::Abort, "", "", &[])
+    }
+
+    fn transact(&mut self, op: PddbTxnOp, dict_name: &str, key_name: &str, data: &[u8]) -> Result<()> {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if key_name.len() > (KEY_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        let mut request = PddbTxnRequest {
+            token: self.token,
+            op,
+            basis_specified: false,
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::new(),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name),
+            len: data.len() as u32,
+            data: [0u8; TXN_CHUNK_LEN],
+            code: PddbRequestCode::Uninit,
+        };
+        request.data[..data.len()].copy_from_slice(data);
+        txn_request(self.conn, request).map(|_| ())
+    }
+}
+
+/// Sends a `PddbTxnRequest` to the server, and returns the token of the transaction on success.
+pub(crate) fn txn_request(conn: CID, request: PddbTxnRequest) -> Result<ApiToken> {
+    let mut buf = Buffer::into_buf(request)
+        .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+    buf.lend_mut(conn, Opcode::Transaction.to_u32().unwrap())
+        .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+    let response = buf.to_original::<PddbTxnRequest, _>().unwrap();
+    match response.code {
+        PddbRequestCode::NoErr => Ok(response.token),
+        PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "basis not found")),
+        PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "transaction not found")),
+        PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "no free space, transaction is too large, or too many transactions are open")),
+        _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+    }
+}
+
+use core::sync::atomic::Ordering;
+impl Drop for PddbTransaction {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.transact(PddbTxnOp::Abort, "", "", &[]).ok();
+        }
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
@@ -19,12 +19,6 @@ use core::sync::atomic::{AtomicU32, Ordering};
 pub(crate) static REFCOUNT: AtomicU32 = AtomicU32::new(0);
 pub(crate) static POLLER_REFCOUNT: AtomicU32 = AtomicU32::new(0);
 
-#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
-enum CbOp {
-    Change,
-    Quit
-}
-
 pub struct PddbMountPoller {
     conn: CID
 }
@@ -81,6 +75,9 @@ pub struct Pddb {
     /// in the case of a basis change. Basis changes are thought to be rare; so, big changes
     /// like this are probably OK.
     keys: Arc<Mutex<HashMap<ApiToken, Box<dyn Fn() + 'static + Send> >>>,
+    /// Dictionary and key watches, mapping the watch token to the connection and opcode that the
+    /// subscriber wants its notifications forwarded to.
+    watches: Arc<Mutex<HashMap<ApiToken, (CID, u32)>>>,
     trng: trng::Trng,
     tt: ticktimer_server::Ticktimer,
 }
@@ -91,8 +88,10 @@ impl Pddb {
         let conn = xns.request_connection_blocking(api::SERVER_NAME_PDDB).expect("can't connect to Pddb server");
         let sid = xous::create_server().unwrap();
         let keys: Arc<Mutex<HashMap<ApiToken, Box<dyn Fn() + 'static + Send> >>> = Arc::new(Mutex::new(HashMap::new()));
+        let watches: Arc<Mutex<HashMap<ApiToken, (CID, u32)>>> = Arc::new(Mutex::new(HashMap::new()));
         let handle = thread::spawn({
             let keys = Arc::clone(&keys);
+            let watches = Arc::clone(&watches);
             let sid = sid.clone();
             move || {
                 loop {
@@ -106,6 +105,16 @@ impl Pddb {
                                 log::warn!("Key changed but no callback was hooked to receive it");
                             }
                         }),
+                        Some(CbOp::Watch) => msg_scalar_unpack!(msg, t0, t1, t2, event, {
+                            let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
+                            if let Some(&(cid, opcode)) = watches.lock().unwrap().get(&token) {
+                                send_message(cid,
+                                    Message::new_scalar(opcode as usize, event, t0, t1, t2)
+                                ).map_err(|e| log::warn!("couldn't forward watch notification: {:?}", e)).ok();
+                            } else {
+                                log::warn!("Watch notification received but no subscriber was registered to receive it");
+                            }
+                        }),
                         Some(CbOp::Quit) => { // blocking scalar
                             xous::return_scalar(msg.sender, 0).unwrap();
                             break;
@@ -121,6 +130,7 @@ impl Pddb {
             cb_sid: sid,
             cb_handle: Some(handle),
             keys,
+            watches,
             trng: trng::Trng::new(&xns).unwrap(),
             tt: ticktimer_server::Ticktimer::new().unwrap(),
         }
@@ -225,6 +235,9 @@ impl Pddb {
             }
         }
     }
+    /// Unlocks a basis, prompting the user for its password. `policy` determines when the basis locks
+    /// itself again; `None` is the same as `BasisRetentionPolicy::Persist`. When a basis locks, every key
+    /// handle's change callback is invoked, and handles bound to that basis return `BrokenPipe` thereafter.
     pub fn unlock_basis(&self, basis_name: &str, policy: Option<BasisRetentionPolicy>) -> Result<()> {
         if basis_name.len() > BASIS_NAME_LEN - 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
@@ -291,6 +304,187 @@ impl Pddb {
             }
         }
     }
+    /// Compacts a basis: deleted key slots are reclaimed, the small data pools are repacked, and the large
+    /// pool is defragmented, returning any freed pages to the free space pool. This is a slow operation that
+    /// shows a progress bar, so it should only be done occasionally, e.g. on devices that have accumulated a
+    /// lot of churn.
+    pub fn compact(&self, basis_name: &str) -> Result<()> {
+        if basis_name.len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        let mgmt = PddbBasisRequest {
+            name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
+            code: PddbRequestCode::Compact,
+            policy: None,
+        };
+        let mut buf = Buffer::into_buf(mgmt).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.conn, Opcode::CompactBasis.to_u32().unwrap()).expect("Couldn't execute CompactBasis opcode");
+        let ret = buf.to_original::<PddbBasisRequest, _>().expect("couldn't restore mgmt structure");
+        match ret.code {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No free space to compact basis")),
+            PddbRequestCode::InternalError => Err(Error::new(ErrorKind::Other, "Internal error compacting basis")),
+            _ => {
+                log::error!("Invalid return code");
+                panic!("Invalid return code");
+            }
+        }
+    }
+    /// Changes the password of a secondary basis. The PDDB prompts the user for the current password, and
+    /// twice for the new one, so the passwords never pass through the caller. The basis need not be unlocked;
+    /// if it is not, it is left locked afterwards. All of the basis is re-encrypted under the new password and
+    /// the copy under the old password is destroyed, so this takes a while on a large basis. An interrupted
+    /// change is completed the next time the basis is unlocked, with the new password.
+    /// The password of the system basis can't be changed this way.
+    pub fn change_basis_password(&self, basis_name: &str) -> Result<()> {
+        if basis_name.len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        let req = PddbPasswordChange {
+            name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(req).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.conn, Opcode::ChangeBasisPassword.to_u32().unwrap()).expect("Couldn't execute ChangeBasisPassword opcode");
+        let ret = buf.to_original::<PddbPasswordChange, _>().expect("couldn't restore password change structure");
+        match ret.code {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Basis not found, old password is incorrect, or entry was cancelled")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No free space to re-encrypt basis")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error changing basis password")),
+        }
+    }
+    /// Checks all the open bases, and the free space tracking, for consistency. If `repair` is true,
+    /// orphaned and undecryptable pages are freed, bad dictionary counts are rewritten and FastSpace is
+    /// regenerated if it's out of sync; otherwise the problems are only reported. Bases that are locked
+    /// are not checked, so unlock everything of interest first. This is a slow operation.
+    pub fn fsck(&self, repair: bool) -> Result<FsckReport> {
+        let req = PddbFsckRequest {
+            repair,
+            report: FsckReport::default(),
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(req).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.conn, Opcode::Fsck.to_u32().unwrap()).expect("Couldn't execute Fsck opcode");
+        let ret = buf.to_original::<PddbFsckRequest, _>().expect("couldn't restore fsck structure");
+        match ret.code {
+            PddbRequestCode::NoErr => Ok(ret.report),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::NotFound, "PDDB is not mounted")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No free space to repair the PDDB")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error checking the PDDB")),
+        }
+    }
+    /// Writes a backup archive of every dictionary and key in `basis_name` to `archive`, returning
+    /// the number of bytes written. The user is prompted for a backup password, which seals the archive.
+    /// The archive is not bound to this device, so it can be restored on another device with `import_basis()`.
+    pub fn export_basis(&mut self, basis_name: &str, archive: &mut impl std::io::Write) -> Result<usize> {
+        if basis_name.len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        let token = [self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap()];
+        let mut opcode = Opcode::BackupExportStart;
+        let mut total = 0;
+        let result = loop {
+            let request = PddbBackupChunk {
+                token,
+                basis_specified: true,
+                basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
+                len: 0,
+                data: [0u8; BACKUP_CHUNK_LEN],
+                code: PddbRequestCode::Uninit,
+            };
+            let response = match self.backup_transact(opcode, request) {
+                Ok(response) => response,
+                Err(e) => break Err(e),
+            };
+            if response.len == 0 {
+                break Ok(total);
+            }
+            if let Err(e) = archive.write_all(&response.data[..response.len as usize]) {
+                break Err(e);
+            }
+            total += response.len as usize;
+            opcode = Opcode::BackupExportRead;
+        };
+        if result.is_err() {
+            // release the server's export state right away, instead of waiting for it to time out
+            self.backup_abort(token);
+        }
+        result
+    }
+    /// Restores a backup archive made by `export_basis()`, prompting the user for the backup password. The
+    /// data is restored into `basis_name`, or if `None`, into the basis the archive was taken from; either way,
+    /// the basis has to be unlocked first. Keys in the archive replace keys with the same name, and any other
+    /// dictionaries and keys in the basis are left alone. Nothing is changed unless the whole archive checks
+    /// out, and the keys in it have to fit in one transaction.
+    pub fn import_basis(&mut self, archive: &mut impl std::io::Read, basis_name: Option<&str>) -> Result<()> {
+        if let Some(name) = basis_name {
+            if name.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+        }
+        let token = [self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap()];
+        let mut request = PddbBackupChunk {
+            token,
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            len: 0,
+            data: [0u8; BACKUP_CHUNK_LEN],
+            code: PddbRequestCode::Uninit,
+        };
+        // the header is sent on its own, so the server can derive the key before any frames arrive
+        archive.read_exact(&mut request.data[..BACKUP_HEADER_LEN])?;
+        request.len = BACKUP_HEADER_LEN as u32;
+        self.backup_transact(Opcode::BackupImportStart, request)?;
+        let result = self.import_frames(archive, basis_name, token);
+        if result.is_err() {
+            self.backup_abort(token);
+        }
+        result
+    }
+    fn import_frames(&self, archive: &mut impl std::io::Read, basis_name: Option<&str>, token: [u32; 4]) -> Result<()> {
+        loop {
+            let mut request = PddbBackupChunk {
+                token,
+                basis_specified: basis_name.is_some(),
+                basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+                len: 0,
+                data: [0u8; BACKUP_CHUNK_LEN],
+                code: PddbRequestCode::Uninit,
+            };
+            // a zero-length read means the archive is finished, and the zero-length write tells the server so
+            let len = archive.read(&mut request.data)?;
+            request.len = len as u32;
+            self.backup_transact(Opcode::BackupImportWrite, request)?;
+            if len == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+    fn backup_abort(&self, token: [u32; 4]) {
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::BackupAbort.to_usize().unwrap(),
+            token[0] as usize, token[1] as usize, token[2] as usize, token[3] as usize)
+        ).expect("couldn't abort backup");
+    }
+    fn backup_transact(&self, opcode: Opcode, request: PddbBackupChunk) -> Result<PddbBackupChunk> {
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, opcode.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbBackupChunk, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(response),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Backup password incorrect, or another backup is in progress")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No free space to restore backup")),
+            PddbRequestCode::InternalError => Err(Error::new(ErrorKind::InvalidData, "Backup archive is invalid or corrupted")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
 
     /// If the `create_*` flags are set, creates the asset if they do not exist, otherwise if false, returns
     /// an error if the asset does not exist.
@@ -448,6 +642,91 @@ impl Pddb {
         }
     }
 
+    /// Starts a transaction on `basis_name`, or the most recently opened basis if `None`. Writes and deletes staged
+    /// in the transaction are applied atomically by `PddbTransaction::commit()`. A process can only have a couple
+    /// of transactions open at once, and fails with `OutOfMemory` past that.
+    pub fn begin(&mut self, basis_name: Option<&str>) -> Result<PddbTransaction> {
+        if basis_name.unwrap_or("").len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        let request = PddbTxnRequest {
+            token: [0; 3],
+            op: PddbTxnOp::Begin,
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::new(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::new(),
+            len: 0,
+            data: [0u8; TXN_CHUNK_LEN],
+            code: PddbRequestCode::Uninit,
+        };
+        let token = txn_request(self.conn, request)?;
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        Ok(PddbTransaction {
+            token,
+            conn: self.conn,
+            finished: false,
+        })
+    }
+
+    /// Subscribes to changes to any key in `dict_name`. Whenever a key in the dictionary is created, written or
+    /// deleted, or the basis holding the dictionary is locked, a non-blocking scalar message with `opcode` is sent
+    /// to `cid`. The first argument of the message is a `PddbWatchEvent`, and the remaining three are the token
+    /// returned by this call, so that several watches can share one opcode. If `basis_name` is `None`, changes in
+    /// any basis are reported. The watch lasts until `unwatch()` is called, or this `Pddb` object is dropped.
+    /// A process can hold a limited number of watches; past that, `PermissionDenied` is returned.
+    pub fn watch_dict(&mut self, dict_name: &str, basis_name: Option<&str>, cid: CID, opcode: u32) -> Result<ApiToken> {
+        self.watch(dict_name, None, basis_name, cid, opcode)
+    }
+    /// Like `watch_dict()`, but only reports changes to a single key.
+    pub fn watch_key(&mut self, dict_name: &str, key_name: &str, basis_name: Option<&str>, cid: CID, opcode: u32) -> Result<ApiToken> {
+        self.watch(dict_name, Some(key_name), basis_name, cid, opcode)
+    }
+    /// Cancels a watch set up with `watch_dict()` or `watch_key()`.
+    pub fn unwatch(&mut self, token: ApiToken) -> Result<()> {
+        if self.watches.lock().unwrap().remove(&token).is_none() {
+            return Err(Error::new(ErrorKind::NotFound, "watch not found"));
+        }
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Unwatch.to_usize().unwrap(), token[0] as usize, token[1] as usize, token[2] as usize, 0)
+        ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        Ok(())
+    }
+    fn watch(&mut self, dict_name: &str, key_name: Option<&str>, basis_name: Option<&str>, cid: CID, opcode: u32) -> Result<ApiToken> {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if key_name.unwrap_or("").len() > (KEY_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        if basis_name.unwrap_or("").len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        let request = PddbWatchRequest {
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            key_specified: key_name.is_some(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name.unwrap_or("")),
+            cb_sid: self.cb_sid.to_array(),
+            token: None,
+            result: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::WatchRequest.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbWatchRequest, _>().unwrap();
+        match (response.result, response.token) {
+            (PddbRequestCode::NoErr, Some(token)) => {
+                self.watches.lock().unwrap().insert(token, (cid, opcode));
+                Ok(token)
+            }
+            (PddbRequestCode::AccessDenied, _) => Err(Error::new(ErrorKind::PermissionDenied, "too many watches")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+
     pub fn sync(&mut self) -> Result<()> {
         let response = send_message(
             self.conn,
@@ -612,6 +891,11 @@ impl Pddb {
 
 impl Drop for Pddb {
     fn drop(&mut self) {
+        // cancel any watches, so the server stops sending notifications to our callback server
+        let tokens: Vec::<ApiToken> = self.watches.lock().unwrap().keys().cloned().collect();
+        for token in tokens {
+            self.unwatch(token).ok();
+        }
         let cid = xous::connect(self.cb_sid).unwrap();
         send_message(cid, Message::new_blocking_scalar(CbOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)).unwrap();
         unsafe{xous::disconnect(cid).ok();}
@@ -372,6 +372,8 @@ mod ux;
 use ux::*;
 mod menu;
 use menu::*;
+mod watch;
+use watch::*;
 
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 mod tests;
@@ -413,6 +415,15 @@ struct TokenRecord {
     pub conn: xous::CID, // callback connection
 }
 
+/// A transaction being staged by a client
+struct TxnRecord {
+    txn: Transaction,
+    /// the process that began the transaction; only it may stage to, commit or abort it
+    pid: Option<xous::PID>,
+    /// when the client last touched the transaction
+    last_activity: u64,
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     log_server::init_wait().unwrap();
@@ -437,6 +448,10 @@ fn xmain() -> ! {
     let mut basis_cache = BasisCache::new();
     // storage for the token lookup: given an ApiToken, return a dict/key/basis set. Basis can be None or specified.
     let mut token_dict = HashMap::<ApiToken, TokenRecord>::new();
+    // storage for dictionary and key watches
+    let mut watch_dict = HashMap::<ApiToken, WatchRecord>::new();
+    // keys written since the last sync, which haven't been reported to watches yet
+    let mut pending_writes = PendingWrites::new();
 
     // mount poller thread
     let is_mounted = Arc::new(AtomicBool::new(false));
@@ -513,11 +528,42 @@ fn xmain() -> ! {
     let mut key_token: Option<[u32; 4]> = None;
     let mut dict_list = Vec::<String>::new(); // storage for dict lists
     let mut dict_token: Option<[u32; 4]> = None;
+    // storage for a backup export or import in progress. Only one can run at a time.
+    let mut backup_token: Option<[u32; 4]> = None;
+    let mut backup_exporter: Option<BackupExporter> = None;
+    let mut backup_importer: Option<BackupImporter> = None;
+    let mut backup_last_activity: u64 = 0;
+    // transactions that are being staged
+    let mut txn_dict = HashMap::<ApiToken, TxnRecord>::new();
 
     // the PDDB resets the hardware RTC to a new random starting point every time it is reformatted
     // it is the only server capable of doing this.
     let time_resetter = xns.request_connection_blocking(crate::TIME_SERVER_PDDB).unwrap();
 
+    // ticktimer-driven poller for bases that lock themselves after a period of inactivity, and for
+    // abandoned backups and transactions. It only bothers the main loop when there is at least one
+    // basis with a `TimeOutSecs` policy open, or a backup or transaction in progress.
+    let has_timeouts = Arc::new(AtomicBool::new(false));
+    let backup_active = Arc::new(AtomicBool::new(false));
+    let txn_active = Arc::new(AtomicBool::new(false));
+    let _ = thread::spawn({
+        let my_cid = my_cid.clone();
+        let has_timeouts = has_timeouts.clone();
+        let backup_active = backup_active.clone();
+        let txn_active = txn_active.clone();
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            loop {
+                tt.sleep_ms(BASIS_TIMEOUT_POLL_MS).unwrap();
+                if has_timeouts.load(Ordering::SeqCst) || backup_active.load(Ordering::SeqCst) || txn_active.load(Ordering::SeqCst) {
+                    send_message(my_cid,
+                        Message::new_scalar(Opcode::BasisTimeoutPoll.to_usize().unwrap(), 0, 0, 0, 0)
+                    ).expect("couldn't send basis timeout poll");
+                }
+            }
+        }
+    });
+
     // register a suspend/resume listener
     let mut susres = susres::Susres::new(Some(susres::SuspendOrder::Early), &xns,
         Opcode::SuspendResume as u32, my_cid).expect("couldn't create suspend/resume object");
@@ -525,9 +571,39 @@ fn xmain() -> ! {
         let mut msg = xous::receive_message(pddb_sid).unwrap();
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
-                basis_cache.suspend(&mut pddb_os);
+                for basis in basis_cache.suspend(&mut pddb_os) {
+                    basis_locked_notify(&mut token_dict, &watch_dict, &mut pending_writes, &basis);
+                }
+                has_timeouts.store(basis_cache.has_timeouts(), Ordering::SeqCst);
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
             }),
+            Some(Opcode::BasisTimeoutPoll) => {
+                for basis in basis_cache.timeout_check(&mut pddb_os) {
+                    basis_locked_notify(&mut token_dict, &watch_dict, &mut pending_writes, &basis);
+                }
+                has_timeouts.store(basis_cache.has_timeouts(), Ordering::SeqCst);
+                if backup_token.is_some()
+                && pddb_os.timestamp_now().saturating_sub(backup_last_activity) >= BACKUP_INACTIVITY_TIMEOUT_MS {
+                    log::warn!("backup abandoned by its client, discarding it");
+                    backup_token = None;
+                    backup_exporter = None;
+                    if let Some(mut importer) = backup_importer.take() {
+                        importer_notify(&watch_dict, &mut importer);
+                    }
+                }
+                backup_active.store(backup_token.is_some(), Ordering::SeqCst);
+                // The kernel doesn't tell servers when a client exits, so a transaction that has been left alone
+                // this long is taken to belong to a process that died before it could commit or abort.
+                let now = pddb_os.timestamp_now();
+                txn_dict.retain(|_, rec| {
+                    let alive = now.saturating_sub(rec.last_activity) < TXN_INACTIVITY_TIMEOUT_MS;
+                    if !alive {
+                        log::warn!("transaction on basis {} abandoned by PID {:?}, discarding it", rec.txn.basis, rec.pid);
+                    }
+                    alive
+                });
+                txn_active.store(txn_dict.len() > 0, Ordering::SeqCst);
+            },
             Some(Opcode::IsMounted) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 if basis_cache.basis_count() > 0 { // if there's anything in the cache, we're mounted.
                     xous::return_scalar(msg.sender, 1).expect("couldn't return scalar");
@@ -635,7 +711,8 @@ fn xmain() -> ! {
                                     &mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8"), pw.as_str().expect("password was not valid utf-8"),
                                     mgmt.policy.unwrap_or(BasisRetentionPolicy::Persist)
                                 ) {
-                                    basis_cache.basis_add(basis);
+                                    basis_cache.basis_add(&mut pddb_os, basis);
+                                    has_timeouts.store(basis_cache.has_timeouts(), Ordering::SeqCst);
                                     finished = true;
                                     mgmt.code = PddbRequestCode::NoErr;
                                 }
@@ -671,7 +748,11 @@ fn xmain() -> ! {
                 match mgmt.code {
                     PddbRequestCode::Close => {
                         match basis_cache.basis_unmount(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8")) {
-                            Ok(_) => mgmt.code = PddbRequestCode::NoErr,
+                            Ok(_) => {
+                                basis_locked_notify(&mut token_dict, &watch_dict, &mut pending_writes, mgmt.name.as_str().unwrap());
+                                has_timeouts.store(basis_cache.has_timeouts(), Ordering::SeqCst);
+                                mgmt.code = PddbRequestCode::NoErr;
+                            }
                             Err(e) => match e.kind() {
                                 ErrorKind::NotFound => mgmt.code = PddbRequestCode::NotFound,
                                 _ => mgmt.code = PddbRequestCode::InternalError,
@@ -703,6 +784,310 @@ fn xmain() -> ! {
                 }
                 buffer.replace(mgmt).unwrap();
             }
+            Some(Opcode::CompactBasis) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut mgmt = buffer.to_original::<PddbBasisRequest, _>().unwrap();
+                match mgmt.code {
+                    PddbRequestCode::Compact => {
+                        match basis_cache.compact(&mut pddb_os, Some(mgmt.name.as_str().expect("name is not valid utf-8")), Some(&modals)) {
+                            Ok(_) => mgmt.code = PddbRequestCode::NoErr,
+                            Err(e) => match e.kind() {
+                                ErrorKind::NotFound => mgmt.code = PddbRequestCode::NotFound,
+                                ErrorKind::OutOfMemory => mgmt.code = PddbRequestCode::NoFreeSpace,
+                                _ => mgmt.code = PddbRequestCode::InternalError,
+                            }
+                        }
+                    }
+                    _ => {
+                        mgmt.code = PddbRequestCode::InternalError;
+                    }
+                }
+                buffer.replace(mgmt).unwrap();
+            }
+            Some(Opcode::ChangeBasisPassword) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbPasswordChange, _>().unwrap();
+                let basis = req.name.as_str().expect("name is not valid utf-8");
+                // the passwords are only ever entered through our own modal, so they never pass through the caller
+                let old_pw = request_prompted_password(pw_cid, &format!("{} {}", t!("pddb.rekey.old", xous::LANG), basis));
+                let new_prompt = format!("{} {}", t!("pddb.rekey.new", xous::LANG), basis);
+                let new_pw = request_prompted_password(pw_cid, &new_prompt);
+                let result = match (old_pw, new_pw) {
+                    (Some(old_pw), Some(new_pw)) if Some(&new_pw) == request_prompted_password(pw_cid, &new_prompt).as_ref() =>
+                        basis_cache.basis_change_password(&mut pddb_os, basis, &old_pw, &new_pw, Some(&modals)),
+                    _ => {
+                        modals.show_notification(t!("pddb.rekey.mismatch", xous::LANG)).expect("couldn't show notification");
+                        Err(std::io::Error::new(ErrorKind::Interrupted, "password entry cancelled"))
+                    }
+                };
+                match result {
+                    Ok(_) => req.code = PddbRequestCode::NoErr,
+                    Err(e) => match e.kind() {
+                        ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                        ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                        ErrorKind::PermissionDenied | ErrorKind::InvalidInput | ErrorKind::Interrupted => req.code = PddbRequestCode::AccessDenied,
+                        _ => req.code = PddbRequestCode::InternalError,
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BackupExportStart) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbBackupChunk, _>().unwrap();
+                req.len = 0;
+                if backup_token.is_some() {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                let basis = req.basis.as_str().expect("name is not valid utf-8");
+                // the backup password is entered twice, as a typo would make the archive unrecoverable
+                let prompt = format!("{} {}", t!("pddb.backup.password", xous::LANG), basis);
+                let pw = request_prompted_password(pw_cid, &prompt);
+                if pw.is_none() || pw != request_prompted_password(pw_cid, &prompt) {
+                    modals.show_notification(t!("pddb.backup.mismatch", xous::LANG)).expect("couldn't show notification");
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                match BackupExporter::new(&mut pddb_os, &mut basis_cache, basis, &pw.unwrap())
+                .and_then(|mut exporter| {
+                    let header = exporter.next_chunk(&mut pddb_os, &mut basis_cache)?.expect("exporter did not produce a header");
+                    Ok((exporter, header))
+                }) {
+                    Ok((exporter, header)) => {
+                        req.data[..header.len()].copy_from_slice(&header);
+                        req.len = header.len() as u32;
+                        backup_token = Some(req.token);
+                        backup_exporter = Some(exporter);
+                        backup_last_activity = pddb_os.timestamp_now();
+                        backup_active.store(true, Ordering::SeqCst);
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => match e.kind() {
+                        ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                        _ => req.code = PddbRequestCode::InternalError,
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BackupExportRead) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbBackupChunk, _>().unwrap();
+                req.len = 0;
+                if backup_token != Some(req.token) || backup_exporter.is_none() {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                backup_last_activity = pddb_os.timestamp_now();
+                match backup_exporter.as_mut().unwrap().next_chunk(&mut pddb_os, &mut basis_cache) {
+                    Ok(Some(frame)) => {
+                        req.data[..frame.len()].copy_from_slice(&frame);
+                        req.len = frame.len() as u32;
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Ok(None) => {
+                        // end of the archive; a zero-length response tells the caller it's done
+                        backup_token = None;
+                        backup_exporter = None;
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => {
+                        log::error!("backup export failed: {:?}", e);
+                        backup_token = None;
+                        backup_exporter = None;
+                        match e.kind() {
+                            ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                            _ => req.code = PddbRequestCode::InternalError,
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BackupImportStart) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbBackupChunk, _>().unwrap();
+                if backup_token.is_some() {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap())
+                } else {
+                    None
+                };
+                if let Some(pw) = request_prompted_password(pw_cid, t!("pddb.backup.restore", xous::LANG)) {
+                    match BackupImporter::new(&req.data[..req.len as usize], &pw, bname) {
+                        Ok(importer) => {
+                            backup_token = Some(req.token);
+                            backup_importer = Some(importer);
+                            backup_last_activity = pddb_os.timestamp_now();
+                            backup_active.store(true, Ordering::SeqCst);
+                            req.code = PddbRequestCode::NoErr;
+                        }
+                        Err(_) => req.code = PddbRequestCode::InternalError,
+                    }
+                } else {
+                    req.code = PddbRequestCode::AccessDenied;
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BackupImportWrite) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbBackupChunk, _>().unwrap();
+                if backup_token != Some(req.token) || backup_importer.is_none() {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                backup_last_activity = pddb_os.timestamp_now();
+                let result = if req.len == 0 {
+                    // end of the archive
+                    backup_token = None;
+                    let mut importer = backup_importer.take().unwrap();
+                    let result = importer.finish(&mut pddb_os, &mut basis_cache);
+                    importer_notify(&watch_dict, &mut importer);
+                    result
+                } else {
+                    backup_importer.as_mut().unwrap().write(&basis_cache, &req.data[..req.len as usize])
+                };
+                match result {
+                    Ok(_) => req.code = PddbRequestCode::NoErr,
+                    Err(e) => {
+                        log::error!("backup import failed: {:?}", e);
+                        backup_token = None;
+                        if let Some(mut importer) = backup_importer.take() {
+                            importer_notify(&watch_dict, &mut importer);
+                        }
+                        match e.kind() {
+                            ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                            ErrorKind::PermissionDenied => req.code = PddbRequestCode::AccessDenied,
+                            ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                            _ => req.code = PddbRequestCode::InternalError,
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::BackupAbort) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, t3, {
+                if backup_token == Some([t0 as u32, t1 as u32, t2 as u32, t3 as u32]) {
+                    log::info!("backup aborted by its client");
+                    backup_token = None;
+                    backup_exporter = None;
+                    if let Some(mut importer) = backup_importer.take() {
+                        importer_notify(&watch_dict, &mut importer);
+                    }
+                }
+                xous::return_scalar(msg.sender, 0).expect("couldn't ack backup abort");
+            }),
+            Some(Opcode::Transaction) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                let dict = req.dict.as_str().expect("dict utf-8 decode error");
+                let key = req.key.as_str().expect("key utf-8 decode error");
+                let pid = msg.sender.pid();
+                let now = pddb_os.timestamp_now();
+                // a transaction can only be used by the process that began it
+                let owned = match txn_dict.get_mut(&req.token) {
+                    Some(rec) if rec.pid == pid => {
+                        rec.last_activity = now;
+                        true
+                    }
+                    _ => false,
+                };
+                let result = match req.op {
+                    PddbTxnOp::Begin => {
+                        // pin the transaction to a basis now, so it commits where it was started
+                        let basis = if req.basis_specified {
+                            let name = req.basis.as_str().unwrap().to_string();
+                            if basis_cache.basis_list().contains(&name) {Some(name)} else {None}
+                        } else {
+                            basis_cache.basis_latest()
+                        };
+                        if txn_dict.len() >= TXN_MAX_OPEN
+                        || txn_dict.values().filter(|rec| rec.pid == pid).count() >= TXN_MAX_OPEN_PER_PID {
+                            Err(std::io::Error::new(ErrorKind::OutOfMemory, "too many open transactions"))
+                        } else if let Some(basis) = basis {
+                            let token: ApiToken = [pddb_os.trng_u32(), pddb_os.trng_u32(), pddb_os.trng_u32()];
+                            txn_dict.insert(token, TxnRecord { txn: Transaction::new(&basis), pid, last_activity: now });
+                            txn_active.store(true, Ordering::SeqCst);
+                            req.token = token;
+                            Ok(())
+                        } else {
+                            Err(std::io::Error::new(ErrorKind::NotFound, "basis not found"))
+                        }
+                    }
+                    PddbTxnOp::Write | PddbTxnOp::Append | PddbTxnOp::Delete => {
+                        if let Some(txn) = txn_dict.get_mut(&req.token).filter(|_| owned).map(|rec| &mut rec.txn) {
+                            let data = &req.data[..req.len as usize];
+                            match req.op {
+                                PddbTxnOp::Write => txn.write(dict, key, data),
+                                PddbTxnOp::Append => txn.append(dict, key, data),
+                                _ => txn.delete(dict, key),
+                            }
+                        } else {
+                            Err(std::io::Error::new(ErrorKind::PermissionDenied, "transaction not found"))
+                        }
+                    }
+                    PddbTxnOp::Commit => {
+                        if let Some(txn) = if owned {txn_dict.remove(&req.token).map(|rec| rec.txn)} else {None} {
+                            let result = basis_cache.txn_commit(&mut pddb_os, &txn);
+                            if result.is_ok() {
+                                for op in txn.ops().iter() {
+                                    let event = match op {
+                                        TxnOp::Write { .. } => PddbWatchEvent::Written,
+                                        TxnOp::Delete { .. } => {
+                                            evict_key_handles(&mut token_dict, op.dict(), Some(op.key()), Some(&txn.basis));
+                                            PddbWatchEvent::Deleted
+                                        }
+                                    };
+                                    watch_notify(&watch_dict, op.dict(), Some(op.key()), Some(&txn.basis), event);
+                                }
+                            }
+                            result
+                        } else {
+                            Err(std::io::Error::new(ErrorKind::PermissionDenied, "transaction not found"))
+                        }
+                    }
+                    PddbTxnOp::Abort => {
+                        if owned {
+                            txn_dict.remove(&req.token);
+                        }
+                        Ok(())
+                    }
+                };
+                match result {
+                    Ok(_) => req.code = PddbRequestCode::NoErr,
+                    Err(e) => {
+                        log::error!("transaction request failed: {:?}", e);
+                        match e.kind() {
+                            ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                            ErrorKind::PermissionDenied => req.code = PddbRequestCode::AccessDenied,
+                            ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                            _ => req.code = PddbRequestCode::InternalError,
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::Fsck) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbFsckRequest, _>().unwrap();
+                match basis_cache.fsck(&mut pddb_os, req.repair) {
+                    Ok(report) => {
+                        req.report = report;
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => match e.kind() {
+                        ErrorKind::NotFound => req.code = PddbRequestCode::NotMounted,
+                        ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                        _ => req.code = PddbRequestCode::InternalError,
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
             Some(Opcode::KeyRequest) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req: PddbKeyRequest = buffer.to_original::<PddbKeyRequest, _>().unwrap();
@@ -741,7 +1126,7 @@ fn xmain() -> ! {
                         match basis_cache.key_update(&mut pddb_os,
                             dict, key, &empty, None, alloc_hint, bname, true
                         ) {
-                            Ok(_) => {},
+                            Ok(_) => watch_notify(&watch_dict, dict, Some(key), bname, PddbWatchEvent::Created),
                             Err(e) => {
                                 log::error!("Couldn't allocate key: {:?}", e);
                                 match e.kind() {
@@ -772,6 +1157,9 @@ fn xmain() -> ! {
             Some(Opcode::KeyDrop) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, _, {
                 let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
                 if let Some(rec) = token_dict.remove(&token) {
+                    if let Some(write) = pending_writes.take_key(&rec.dict, &rec.key, rec.basis.as_deref()) {
+                        PendingWrites::notify(&watch_dict, vec![write]);
+                    }
                     // now check if we can safely disconnect and recycle our connection number.
                     // This is important because we can only have 32 outgoing connections...
                     let mut has_cid = false;
@@ -781,12 +1169,49 @@ fn xmain() -> ! {
                             break;
                         }
                     }
+                    if watch_dict.values().any(|w| w.conn == rec.conn) {
+                        has_cid = true;
+                    }
                     if !has_cid {
                         unsafe{xous::disconnect(rec.conn).expect("couldn't disconnect from callback server")};
                     }
                 }
                 xous::return_scalar(msg.sender, 1).expect("couldn't ack KeyDrop");
             }),
+            Some(Opcode::WatchRequest) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbWatchRequest, _>().unwrap();
+                // `try_connect` so a bogus callback SID is refused instead of waited on
+                let record = match msg.sender.pid() {
+                    Some(owner) => WatchRecord::from_request(&req, owner, &watch_dict, xous::try_connect),
+                    None => Err(PddbRequestCode::InternalError),
+                };
+                match record {
+                    Ok(watch_record) => {
+                        let token: ApiToken = [pddb_os.trng_u32(), pddb_os.trng_u32(), pddb_os.trng_u32()];
+                        watch_dict.insert(token, watch_record);
+                        req.token = Some(token);
+                        req.result = PddbRequestCode::NoErr;
+                    }
+                    Err(code) => {
+                        log::warn!("refusing watch request from {:?}", msg.sender.pid());
+                        req.token = None;
+                        req.result = code;
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::Unwatch) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, _, {
+                let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
+                if let Some(rec) = watch_dict.remove(&token) {
+                    // recycle the callback connection if nobody else is using it
+                    if !watch_dict.values().any(|w| w.conn == rec.conn)
+                    && !token_dict.values().any(|r| r.conn == rec.conn) {
+                        unsafe{xous::disconnect(rec.conn).expect("couldn't disconnect from callback server")};
+                    }
+                }
+                xous::return_scalar(msg.sender, 1).expect("couldn't ack Unwatch");
+            }),
             Some(Opcode::DeleteKey) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req: PddbKeyRequest = buffer.to_original::<PddbKeyRequest, _>().unwrap();
@@ -799,33 +1224,9 @@ fn xmain() -> ! {
                 let key = req.key.as_str().expect("key utf-8 decode error");
                 match basis_cache.key_remove(&mut pddb_os, dict, key, bname, false) {
                     Ok(_) => {
-                        let mut evict_list = Vec::<ApiToken>::new();
-                        // check to see if we need to eliminate any ApiTokens as a result of this.
-                        for (token, rec) in token_dict.iter() {
-                            if (rec.dict == dict) && (rec.key == key) {
-                                // check the basis union rules
-                                let mut matching = false;
-                                if rec.basis.is_none() && bname.is_none() {
-                                    matching = true;
-                                }
-                                if let Some(breq) = bname {
-                                    if rec.basis.is_none() {
-                                        matching = true;
-                                    }
-                                    if let Some(brec) = &rec.basis {
-                                        if brec == breq {
-                                            matching = true;
-                                        }
-                                    }
-                                }
-                                if matching {
-                                    evict_list.push(*token);
-                                }
-                            }
-                        }
-                        for token in evict_list {
-                            token_dict.remove(&token);
-                        }
+                        pending_writes.forget(dict, Some(key), bname);
+                        watch_notify(&watch_dict, dict, Some(key), bname, PddbWatchEvent::Deleted);
+                        evict_key_handles(&mut token_dict, dict, Some(key), bname);
                         req.result = PddbRequestCode::NoErr;
                     }
                     Err(e) => {
@@ -849,33 +1250,9 @@ fn xmain() -> ! {
                 log::debug!("attempting to remove dict {} basis {:?}", dict, bname);
                 match basis_cache.dict_remove(&mut pddb_os, dict, bname, false) {
                     Ok(_) => {
-                        let mut evict_list = Vec::<ApiToken>::new();
-                        // check to see if we need to eliminate any ApiTokens as a result of this.
-                        for (token, rec) in token_dict.iter() {
-                            if rec.dict == dict {
-                                // check the basis union rules
-                                let mut matching = false;
-                                if rec.basis.is_none() && bname.is_none() {
-                                    matching = true;
-                                }
-                                if let Some(breq) = bname {
-                                    if rec.basis.is_none() {
-                                        matching = true;
-                                    }
-                                    if let Some(brec) = &rec.basis {
-                                        if brec == breq {
-                                            matching = true;
-                                        }
-                                    }
-                                }
-                                if matching {
-                                    evict_list.push(*token);
-                                }
-                            }
-                        }
-                        for token in evict_list {
-                            token_dict.remove(&token);
-                        }
+                        pending_writes.forget(dict, None, bname);
+                        watch_notify(&watch_dict, dict, None, bname, PddbWatchEvent::Deleted);
+                        evict_key_handles(&mut token_dict, dict, None, bname);
                         req.result = PddbRequestCode::NoErr;
                     }
                     Err(e) => {
@@ -1069,6 +1446,8 @@ fn xmain() -> ! {
                         false
                     ) {
                         Ok(_) => {
+                            // reported once the data is synced or the handle is closed
+                            pending_writes.mark(&rec.dict, &rec.key, rec.basis.as_deref());
                             pbuf.retcode = PddbRetcode::Ok;
                         }
                         Err(e) => match e.kind() {
@@ -1087,7 +1466,9 @@ fn xmain() -> ! {
                 basis_cache.sync(&mut pddb_os, None).expect("couldn't sync basis");
             }
             Some(Opcode::WriteKeyFlush) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                match basis_cache.sync(&mut pddb_os, None) {
+                let result = basis_cache.sync(&mut pddb_os, None);
+                PendingWrites::notify(&watch_dict, pending_writes.take());
+                match result {
                     Ok(_) => xous::return_scalar(msg.sender, PddbRetcode::Ok.to_usize().unwrap()).unwrap(),
                     Err(e) => match e.kind() {
                         std::io::ErrorKind::OutOfMemory => xous::return_scalar(msg.sender, PddbRetcode::DiskFull.to_usize().unwrap()).unwrap(),
@@ -1118,7 +1499,7 @@ fn xmain() -> ! {
                         basis_cache = BasisCache::new(); // this effectively erases the PDDB from memory
                         if let Some(sys_basis) = pddb_os.pddb_mount() {
                             log::info!("remount successful");
-                            basis_cache.basis_add(sys_basis);
+                            basis_cache.basis_add(&mut pddb_os, sys_basis);
                         } else {
                             log::info!("remount failed");
                         }
@@ -1148,6 +1529,94 @@ fn xmain() -> ! {
     xous::terminate_process(0)
 }
 
+/// Drops the handles that refer to a deleted key, or to every key of a deleted dictionary if `key` is `None`.
+/// A handle opened without naming a basis refers to the key in whichever basis holds it, so it is dropped
+/// no matter which basis the key was deleted from.
+fn evict_key_handles(token_dict: &mut HashMap<ApiToken, TokenRecord>, dict: &str, key: Option<&str>, basis_name: Option<&str>) {
+    token_dict.retain(|_, rec| {
+        let matching = rec.dict == dict
+            && key.map_or(true, |k| rec.key == k)
+            && match (&rec.basis, basis_name) {
+                (None, _) => true,
+                (Some(brec), Some(breq)) => brec == breq,
+                (Some(_), None) => false,
+            };
+        !matching
+    });
+}
+
+/// Called whenever a basis is locked. Every key handle gets a change callback, because the union of
+/// bases visible to handles that didn't specify a basis has changed as well. Handles that were bound
+/// to the locked basis are evicted, so any further access on them fails with `BasisLost`. Watches
+/// covering the basis are told about the lock, but stay registered in case the basis is reopened.
+fn basis_locked_notify(token_dict: &mut HashMap<ApiToken, TokenRecord>, watch_dict: &HashMap<ApiToken, WatchRecord>,
+    pending_writes: &mut PendingWrites, basis_name: &str) {
+    // locking a basis syncs it, so report what was written to it first
+    PendingWrites::notify(watch_dict, pending_writes.take());
+    for (token, rec) in watch_dict.iter() {
+        if rec.basis.is_none() || rec.basis.as_deref() == Some(basis_name) {
+            xous::send_message(rec.conn,
+                Message::new_scalar(CbOp::Watch.to_usize().unwrap(), token[0] as usize, token[1] as usize, token[2] as usize,
+                PddbWatchEvent::BasisLocked.to_usize().unwrap())
+            ).map_err(|e| log::warn!("couldn't send basis lock notification: {:?}", e)).ok();
+        }
+    }
+    let mut evict_list = Vec::<ApiToken>::new();
+    for (token, rec) in token_dict.iter() {
+        xous::send_message(rec.conn,
+            Message::new_scalar(CbOp::Change.to_usize().unwrap(), token[0] as usize, token[1] as usize, token[2] as usize, 0)
+        ).map_err(|e| log::warn!("couldn't send basis lock notification: {:?}", e)).ok();
+        if let Some(name) = &rec.basis {
+            if name == basis_name {
+                evict_list.push(*token);
+            }
+        }
+    }
+    for token in evict_list {
+        if let Some(rec) = token_dict.remove(&token) {
+            // recycle the callback connection if nobody else is using it
+            if !token_dict.values().any(|r| r.conn == rec.conn)
+            && !watch_dict.values().any(|w| w.conn == rec.conn) {
+                unsafe{xous::disconnect(rec.conn).expect("couldn't disconnect from callback server")};
+            }
+        }
+    }
+}
+
+/// Reports what a backup restore has changed. Nothing is changed until the restore is committed, so a
+/// restore that fails or is abandoned reports nothing.
+fn importer_notify(watch_dict: &HashMap<ApiToken, WatchRecord>, importer: &mut BackupImporter) {
+    let basis = importer.basis().map(|b| b.to_string());
+    for (dict, key, event) in importer.take_changes() {
+        watch_notify(watch_dict, &dict, key.as_deref(), basis.as_deref(), event);
+    }
+}
+
+/// Returns the longest prefix of `s` that fits in a `xous_ipc::String::<N>`. `from_str()` would cut
+/// multi-byte characters in half, and then discard the whole string.
+fn truncate_to_boundary(s: &str, n: usize) -> &str {
+    if s.len() <= n {
+        return s;
+    }
+    let mut end = n;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Prompts the user for a password, with `prompt` shown where the basis name normally goes.
+fn request_prompted_password(pw_cid: xous::CID, prompt: &str) -> Option<String> {
+    let request = BasisRequestPassword {
+        db_name: xous_ipc::String::<{crate::api::BASIS_NAME_LEN}>::from_str(truncate_to_boundary(prompt, BASIS_NAME_LEN)),
+        plaintext_pw: None,
+    };
+    let mut buf = Buffer::into_buf(request).unwrap();
+    buf.lend_mut(pw_cid, PwManagerOpcode::RequestPassword.to_u32().unwrap()).unwrap();
+    let ret = buf.to_original::<BasisRequestPassword, _>().unwrap();
+    ret.plaintext_pw.map(|pw| pw.as_str().expect("password was not valid utf-8").to_string())
+}
+
 fn ensure_password(modals: &modals::Modals, pddb_os: &mut PddbOs) -> PasswordState {
     log::info!("Requesting login password");
     loop {
@@ -1184,7 +1653,7 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
     if pw_state == PasswordState::Correct {
         if let Some(sys_basis) = pddb_os.pddb_mount() {
             log::info!("PDDB mount operation finished successfully");
-            basis_cache.basis_add(sys_basis);
+            basis_cache.basis_add(pddb_os, sys_basis);
             return true
         }
     }
@@ -1245,7 +1714,7 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
 
                 if let Some(sys_basis) = pddb_os.pddb_mount() {
                     log::info!("PDDB mount operation finished successfully");
-                    basis_cache.basis_add(sys_basis);
+                    basis_cache.basis_add(pddb_os, sys_basis);
                     true
                 } else {
                     log::error!("Despite formatting, no PDDB was found!");
@@ -1270,7 +1739,7 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
             pddb_os.dbg_dump(Some("full".to_string()), None);
             if let Some(sys_basis) = pddb_os.pddb_mount() {
                 log::info!("PDDB mount operation finished successfully");
-                basis_cache.basis_add(sys_basis);
+                basis_cache.basis_add(pddb_os, sys_basis);
                 true
             } else {
                 log::error!("Despite formatting, no PDDB was found!");
@@ -1299,7 +1768,7 @@ pub(crate) fn manual_testcase(hw: &mut PddbOs) {
     log::info!("Attempting to mount the PDDB");
     if let Some(sys_basis) = hw.pddb_mount() {
         log::info!("PDDB mount operation finished successfully");
-        basis_cache.basis_add(sys_basis);
+        basis_cache.basis_add(hw, sys_basis);
     } else {
         log::info!("PDDB did not mount; did you remember to format the PDDB region?");
     }
@@ -1357,7 +1826,7 @@ pub(crate) fn hw_testcase(pddb_os: &mut PddbOs) {
     let mut basis_cache = BasisCache::new();
     if let Some(sys_basis) = pddb_os.pddb_mount() {
         log::info!("PDDB mount operation finished successfully");
-        basis_cache.basis_add(sys_basis);
+        basis_cache.basis_add(pddb_os, sys_basis);
     } else {
         log::info!("PDDB did not mount; did you remember to format the PDDB region?");
     }
@@ -3,8 +3,8 @@ use rand_chacha::rand_core::RngCore;
 use rand_chacha::rand_core::SeedableRng;
 use crate::*;
 use core::sync::atomic::{AtomicU64, Ordering};
-use std::collections::{BTreeSet, HashSet};
-use std::io::Result;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{Result, ErrorKind};
 
 const UPPER_BOUND: usize = 9000;
 const LOWER_BOUND: usize = 12; // needs to be big enough to compute murmur3 hash + hold checksum
@@ -51,7 +51,7 @@ pub(crate) fn create_basis_testcase(hw: &mut PddbOs, basis_cache: &mut BasisCach
 
     hw.pddb_format(false, None).unwrap();
     let sys_basis = hw.pddb_mount().expect("couldn't mount system basis");
-        basis_cache.basis_add(sys_basis);
+        basis_cache.basis_add(hw, sys_basis);
 
     let num_dicts = maybe_num_dicts.unwrap_or(4);
     let num_keys = maybe_num_keys.unwrap_or(34);
@@ -378,6 +378,288 @@ pub(crate) fn list_all(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
     }
 }
 
+/// Medium pool check: keys between a VPAGE and MEDIUM_CAPACITY in size are allocated out of the
+/// dictionary's medium pool, which re-uses the space of deleted keys. Keys that grow past their
+/// reservation are relocated within the pool, or promoted to the large pool if they outgrow it.
+pub(crate) fn medium_pool_test(hw: &mut PddbOs, basis_cache: &mut BasisCache, maybe_basis: Option<&str>) -> Result<()> {
+    let dictname = "mediumtest";
+    let sizes = [VPAGE_SIZE + 1, VPAGE_SIZE * 2, VPAGE_SIZE * 5 + 100, VPAGE_SIZE * 3, VPAGE_SIZE * 7 - 1, VPAGE_SIZE * 2 + 17];
+    let medium_data = |keynum: usize, len: usize| -> Vec::<u8> {
+        (0..len).map(|i| (keynum * 7 + i) as u8).collect()
+    };
+    let footprint: u64 = sizes.iter().map(|&len| PageAlignedVa::from(len).as_u64()).sum();
+    basis_cache.dict_add(hw, dictname, maybe_basis)?;
+    for (keynum, &len) in sizes.iter().enumerate() {
+        basis_cache.key_update(hw, dictname, &format!("medium{}", keynum), &medium_data(keynum, len),
+            None, None, maybe_basis, false)?;
+    }
+    let da = basis_cache.dict_attributes(hw, dictname, maybe_basis)?;
+    assert!(da.medium_pool_extent == footprint, "medium pool is not densely allocated: 0x{:x} vs 0x{:x}", da.medium_pool_extent, footprint);
+    assert!(da.small_key_count == 0, "medium keys were allocated in the small pool");
+
+    // delete every other key, then put them back: the holes should be filled without growing the pool
+    for keynum in (1..sizes.len()).step_by(2) {
+        basis_cache.key_remove(hw, dictname, &format!("medium{}", keynum), maybe_basis, false)?;
+    }
+    let da = basis_cache.dict_attributes(hw, dictname, maybe_basis)?;
+    assert!(da.medium_pool_fragmented > 0, "deleted medium keys did not leave holes");
+    for keynum in (1..sizes.len()).step_by(2) {
+        basis_cache.key_update(hw, dictname, &format!("medium{}", keynum), &medium_data(keynum, sizes[keynum]),
+            None, None, maybe_basis, false)?;
+    }
+    let da = basis_cache.dict_attributes(hw, dictname, maybe_basis)?;
+    assert!(da.medium_pool_extent == footprint, "medium pool did not re-use freed extents");
+    assert!(da.medium_pool_fragmented == 0, "medium pool has unexpected holes");
+
+    // extend a key past its reservation, forcing a relocation within the medium pool
+    let mut extended = medium_data(0, sizes[0]);
+    let extension = medium_data(100, VPAGE_SIZE * 2);
+    basis_cache.key_update(hw, dictname, "medium0", &extension, Some(sizes[0]), None, maybe_basis, false)?;
+    extended.extend_from_slice(&extension);
+    // grow a key past MEDIUM_CAPACITY, forcing it into the large pool
+    let promoted = medium_data(2, MEDIUM_CAPACITY + VPAGE_SIZE);
+    basis_cache.key_update(hw, dictname, "medium2", &promoted, None, None, maybe_basis, true)?;
+
+    let mut expected = HashMap::<String, Vec::<u8>>::new();
+    for (keynum, &len) in sizes.iter().enumerate() {
+        let data = match keynum {
+            0 => extended.clone(),
+            2 => promoted.clone(),
+            _ => medium_data(keynum, len),
+        };
+        expected.insert(format!("medium{}", keynum), data);
+    }
+    for (key, data) in expected.iter() {
+        let attrs = basis_cache.key_attributes(hw, dictname, key, maybe_basis)?;
+        assert!(attrs.len == data.len(), "medium key {} has the wrong length", key);
+        let mut readback = vec![0u8; attrs.len];
+        basis_cache.key_read(hw, dictname, key, &mut readback, None, maybe_basis)?;
+        assert!(&readback == data, "medium key {} has the wrong data", key);
+    }
+    Ok(())
+}
+
+/// Reads back every key in a basis, so its contents can be compared before and after an operation
+/// that is supposed to preserve them.
+pub(crate) fn snapshot_basis(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis_name: &str) -> HashMap::<String, Vec::<u8>> {
+    let mut snapshot = HashMap::<String, Vec::<u8>>::new();
+    for dict in basis_cache.dict_list(hw, Some(basis_name)).iter() {
+        for key in basis_cache.key_list(hw, dict, Some(basis_name)).unwrap().iter() {
+            let attrs = basis_cache.key_attributes(hw, dict, key, Some(basis_name)).unwrap();
+            let mut data = vec![0u8; attrs.len];
+            basis_cache.key_read(hw, dict, key, &mut data, None, Some(basis_name)).unwrap();
+            snapshot.insert(format!("{}:{}", dict, key), data);
+        }
+    }
+    snapshot
+}
+
+/// Backup check: export a basis to an archive, restore it into another basis, and confirm that every
+/// key came across intact. The archive must be rejected if the password is wrong, or if it has been
+/// truncated or tampered with, and a rejected restore must leave the target basis untouched.
+pub(crate) fn backup_restore_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    src_basis: &str, dst_basis: &str,
+) -> Result<()> {
+    const BACKUP_PW: &'static str = "correct horse battery staple";
+    let mut archive = Vec::<u8>::new();
+    let mut exporter = BackupExporter::new(hw, basis_cache, src_basis, BACKUP_PW)?;
+    while let Some(chunk) = exporter.next_chunk(hw, basis_cache)? {
+        assert!(chunk.len() <= BACKUP_CHUNK_LEN, "backup frame does not fit in an IPC chunk");
+        archive.extend_from_slice(&chunk);
+    }
+    log::info!("backup archive of {} is {} bytes", src_basis, archive.len());
+
+    // restore in odd-sized pieces, so frames straddle the writes
+    let mut importer = BackupImporter::new(&archive[..BACKUP_HEADER_LEN], BACKUP_PW, Some(dst_basis))?;
+    for piece in archive[BACKUP_HEADER_LEN..].chunks(1234) {
+        importer.write(basis_cache, piece)?;
+    }
+    importer.finish(hw, basis_cache)?;
+    let src = snapshot_basis(hw, basis_cache, src_basis);
+    let dst = snapshot_basis(hw, basis_cache, dst_basis);
+    for (key, data) in src.iter() {
+        assert!(dst.get(key) == Some(data), "{} was not restored correctly", key);
+    }
+
+    // take a restored key back out, so that a failed restore which still got applied would show up
+    let removed = src.keys().next().expect("nothing was backed up");
+    let (dict, key) = removed.split_once(':').unwrap();
+    basis_cache.key_remove(hw, dict, key, Some(dst_basis), false)?;
+    let before = snapshot_basis(hw, basis_cache, dst_basis);
+
+    let mut importer = BackupImporter::new(&archive[..BACKUP_HEADER_LEN], "not the password", Some(dst_basis))?;
+    match importer.write(basis_cache, &archive[BACKUP_HEADER_LEN..]) {
+        Err(e) => assert!(e.kind() == ErrorKind::PermissionDenied, "wrong backup password gave the wrong error: {:?}", e),
+        Ok(_) => panic!("backup was restored with the wrong password"),
+    }
+
+    let mut importer = BackupImporter::new(&archive[..BACKUP_HEADER_LEN], BACKUP_PW, Some(dst_basis))?;
+    importer.write(basis_cache, &archive[BACKUP_HEADER_LEN..archive.len() - 1])?;
+    assert!(importer.finish(hw, basis_cache).is_err(), "truncated backup was accepted");
+
+    let mut tampered = archive.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    let mut importer = BackupImporter::new(&tampered[..BACKUP_HEADER_LEN], BACKUP_PW, Some(dst_basis))?;
+    match importer.write(basis_cache, &tampered[BACKUP_HEADER_LEN..]) {
+        Err(e) => assert!(e.kind() == ErrorKind::InvalidData, "tampered backup gave the wrong error: {:?}", e),
+        Ok(_) => panic!("tampered backup was accepted"),
+    }
+    assert!(importer.finish(hw, basis_cache).is_err(), "tampered backup was committed");
+    assert!(snapshot_basis(hw, basis_cache, dst_basis) == before, "a failed restore changed the basis");
+    Ok(())
+}
+
+/// Transaction check: commit a transaction that mixes writes of every pool size with deletes, and confirm
+/// that all of it landed. Then tear commits before their remap record is sealed, after it is sealed, and
+/// part-way through applying it, and check that a remount rolls the first back and the others forward.
+pub(crate) fn transaction_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    basis_name: &str, basis_pw: &str,
+) -> Result<()> {
+    // pick a couple of existing keys to overwrite and delete
+    let mut dicts = BTreeSet::<String>::new();
+    for dict in basis_cache.dict_list(hw, Some(basis_name)) {
+        dicts.insert(dict);
+    }
+    let mut existing: Option<(String, Vec::<String>)> = None;
+    for dict in dicts.iter() {
+        let keys: Vec::<String> = basis_cache.key_list(hw, dict, Some(basis_name))?.into_iter().collect::<BTreeSet<String>>().into_iter().collect();
+        if keys.len() >= 2 {
+            existing = Some((dict.to_string(), keys));
+            break;
+        }
+    }
+    let (dict, keys) = existing.expect("no dictionary with enough keys to run the transaction test");
+
+    let medium = vec![0x5au8; VPAGE_SIZE * 3 + 17];
+    let large: Vec::<u8> = (0..MEDIUM_CAPACITY + 100).map(|i| i as u8).collect();
+    let mut txn = Transaction::new(basis_name);
+    txn.write("txn.a", "small", b"hello world")?;
+    txn.write("txn.a", "medium", &medium)?;
+    txn.write("txn.b", "large", &large)?;
+    txn.write(&dict, &keys[0], b"shorter")?;
+    txn.delete(&dict, &keys[1])?;
+    txn.delete("txn.b", "never existed")?;
+    basis_cache.txn_commit(hw, &txn)?;
+    let committed = snapshot_basis(hw, basis_cache, basis_name);
+    assert!(committed.get("txn.a:small") == Some(&b"hello world".to_vec()), "small write was not committed");
+    assert!(committed.get("txn.a:medium") == Some(&medium), "medium write was not committed");
+    assert!(committed.get("txn.b:large") == Some(&large), "large write was not committed");
+    assert!(committed.get(&format!("{}:{}", dict, keys[0])) == Some(&b"shorter".to_vec()), "overwrite was not committed");
+    assert!(!committed.contains_key(&format!("{}:{}", dict, keys[1])), "delete was not committed");
+
+    let mut torn = Transaction::new(basis_name);
+    torn.write("txn.a", "small", b"torn")?;
+    torn.write("txn.c", "fresh", &medium)?;
+    torn.delete("txn.a", "medium")?;
+
+    log::info!("remounting after a commit that was torn before its remap record was sealed");
+    basis_cache.txn_commit_until(hw, &torn, TxnStage::Staged)?;
+    remount(hw, basis_cache, basis_name, basis_pw);
+    assert!(snapshot_basis(hw, basis_cache, basis_name) == committed, "unsealed transaction was not rolled back");
+
+    // a commit torn right after sealing, and one torn part-way through updating the page table, both roll forward
+    for stage in [TxnStage::Sealed, TxnStage::Remapped(3)].iter() {
+        log::info!("remounting after a commit that was torn at {:?}", stage);
+        basis_cache.txn_commit_until(hw, &torn, *stage)?;
+        remount(hw, basis_cache, basis_name, basis_pw);
+        let replayed = snapshot_basis(hw, basis_cache, basis_name);
+        assert!(replayed.get("txn.a:small") == Some(&b"torn".to_vec()), "sealed transaction was not rolled forward from {:?}", stage);
+        assert!(replayed.get("txn.c:fresh") == Some(&medium), "sealed transaction was not rolled forward from {:?}", stage);
+        assert!(!replayed.contains_key("txn.a:medium"), "sealed transaction was not rolled forward from {:?}", stage);
+        assert!(replayed.get("txn.b:large") == Some(&large), "recovery disturbed a key outside of the transaction");
+        // put things back for the next round
+        let mut undo = Transaction::new(basis_name);
+        undo.write("txn.a", "small", b"hello world")?;
+        undo.write("txn.a", "medium", &medium)?;
+        undo.delete("txn.c", "fresh")?;
+        basis_cache.txn_commit(hw, &undo)?;
+    }
+    Ok(())
+}
+
+fn remount(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis_name: &str, basis_pw: &str) {
+    basis_cache.basis_unmount(hw, basis_name).expect("couldn't unmount basis");
+    let basis = basis_cache.basis_unlock(hw, basis_name, basis_pw, BasisRetentionPolicy::Persist).expect("couldn't remount basis");
+    basis_cache.basis_add(hw, basis);
+}
+
+/// Consistency check: the PDDB should check out clean after all the other tests. Then forge a page table entry
+/// for an orphaned page, and one for a page that doesn't decrypt, into `basis_name`; confirm that fsck finds
+/// both, repairs them, and checks out clean afterwards.
+pub(crate) fn fsck_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    basis_name: &str, basis_pw: &str) -> Result<()> {
+    use aes_gcm_siv::{Aes256GcmSiv, Key};
+    use aes_gcm_siv::aead::NewAead;
+    use aes::Aes256;
+    use aes::cipher::{NewBlockCipher, generic_array::GenericArray};
+
+    let report = basis_cache.fsck(hw, false)?;
+    assert!(report.problems() == 0, "fsck found problems in a consistent PDDB: {:?}", report);
+
+    // the forged entries go into dictionary slots that are never used by the tests
+    let key = hw.basis_derive_key(basis_name, basis_pw);
+    let cipher = Aes256GcmSiv::new(Key::from_slice(&key));
+    let cipher_ecb = Aes256::new(GenericArray::from_slice(&key));
+    let aad = hw.data_aad(basis_name);
+    let orphan = hw.try_fast_space_alloc().expect("no free space to run the fsck test");
+    let mut data = [0u8; VPAGE_SIZE + core::mem::size_of::<JournalType>()];
+    hw.data_encrypt_and_patch_page(&cipher, &aad, &mut data, &orphan);
+    hw.pt_patch_mapping(VirtAddr::new(DICT_MAXCOUNT as u64 * DICT_VSIZE).unwrap(), orphan.page_number(), &cipher_ecb);
+    let garbage = hw.try_fast_space_alloc().expect("no free space to run the fsck test");
+    let mut noise = [0u8; PAGE_SIZE];
+    hw.trng_slice(&mut noise);
+    hw.patch_data(&noise, garbage.page_number() * PAGE_SIZE as u32);
+    hw.pt_patch_mapping(VirtAddr::new((DICT_MAXCOUNT - 1) as u64 * DICT_VSIZE).unwrap(), garbage.page_number(), &cipher_ecb);
+    // remount, so that the forged entries are picked up
+    basis_cache.basis_unmount(hw, basis_name)?;
+    if let Some(basis) = basis_cache.basis_unlock(hw, basis_name, basis_pw, BasisRetentionPolicy::Persist) {
+        basis_cache.basis_add(hw, basis);
+    }
+
+    let report = basis_cache.fsck(hw, false)?;
+    assert!(report.orphan_pages == 1 && report.bad_pages == 1 && !report.repaired, "fsck missed forged pages: {:?}", report);
+    let report = basis_cache.fsck(hw, true)?;
+    assert!(report.repaired, "fsck did not repair forged pages: {:?}", report);
+    let report = basis_cache.fsck(hw, false)?;
+    assert!(report.problems() == 0, "problems remain after fsck repair: {:?}", report);
+    Ok(())
+}
+
+/// Password change check: change the password of `basis_name` while it is mounted, and confirm that its contents
+/// are unchanged, that the new password unlocks it, and that nothing is left under the old password. Then change
+/// it back while it is locked, but stop at the commit point, and confirm that the next mount finishes the job.
+/// The basis is left mounted with `basis_pw`.
+pub(crate) fn rekey_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    basis_name: &str, basis_pw: &str) -> Result<()> {
+    const NEW_PW: &'static str = "a much better password";
+    let old_key = hw.basis_derive_key(basis_name, basis_pw);
+    let new_key = hw.basis_derive_key(basis_name, NEW_PW);
+    let before = snapshot_basis(hw, basis_cache, basis_name);
+
+    assert!(basis_cache.basis_change_password(hw, basis_name, "wrong password", NEW_PW, None).is_err(), "wrong old password was accepted");
+    basis_cache.basis_change_password(hw, basis_name, basis_pw, NEW_PW, None)?;
+    assert!(snapshot_basis(hw, basis_cache, basis_name) == before, "password change altered the basis");
+    assert!(hw.pt_scan_key(&old_key, basis_name).map_or(true, |map| map.len() == 0), "pages remain under the old password");
+    basis_cache.basis_unmount(hw, basis_name)?;
+    assert!(basis_cache.basis_unlock(hw, basis_name, basis_pw, BasisRetentionPolicy::Persist).is_none(), "old password still unlocks the basis");
+    let basis = basis_cache.basis_unlock(hw, basis_name, NEW_PW, BasisRetentionPolicy::Persist).expect("new password doesn't unlock the basis");
+    basis_cache.basis_add(hw, basis);
+    assert!(snapshot_basis(hw, basis_cache, basis_name) == before, "basis changed across a remount after a password change");
+
+    log::info!("interrupting a password change after its commit point");
+    basis_cache.basis_unmount(hw, basis_name)?;
+    basis_cache.basis_rekey(hw, basis_name, NEW_PW, basis_pw, false, None)?;
+    assert!(hw.pt_scan_key(&new_key, basis_name).map_or(false, |map| map.len() > 0), "interrupted password change left nothing to recover");
+    let basis = basis_cache.basis_unlock(hw, basis_name, basis_pw, BasisRetentionPolicy::Persist).expect("couldn't mount basis after interrupted password change");
+    basis_cache.basis_add(hw, basis);
+    assert!(hw.pt_scan_key(&new_key, basis_name).map_or(true, |map| map.len() == 0), "recovery left pages under the superseded password");
+    assert!(snapshot_basis(hw, basis_cache, basis_name) == before, "recovery altered the basis");
+    let report = basis_cache.fsck(hw, false)?;
+    assert!(report.problems() == 0, "fsck found problems after a password change: {:?}", report);
+    Ok(())
+}
+
 /* list of test cases:
     - [done] genenral integrity: allocate 4 dictionaries, each with 34 keys of various sizes ranging from 1k-9k.
     - [done] delete/add consistency: general integrity, delete a dictionary, then add a dictionary.
@@ -388,9 +670,74 @@ pub(crate) fn list_all(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
         note: for faster stress-testing, we dialed the FSCB_PAGES to 4 and the FASTSPACE_PAGES to 1.
     - [done] basis search: create basis A, populate with general integrity. create basis B, add test entries.
         hide basis B, confirm original A; mount basis B, confirm B overlay.
+    - [done] medium pool: allocate keys between a VPAGE and MEDIUM_CAPACITY, punch holes and refill them,
+        relocate a key that outgrows its reservation, and promote a key that outgrows the pool.
+    - [done] compaction: after the deletion torture tests, compact the system basis, confirm the data is unchanged,
+        the key descriptors are dense, and that the result survives a remount.
+    - [done] backup/restore: export the system basis to an archive, restore it into a second basis, and check
+        the contents match. Also check that wrong passwords, truncation and tampering are caught.
+    - [done] transactions: commit a mix of writes and deletes atomically, then confirm that a commit torn before
+        its remap record was sealed rolls back on remount, and ones torn after, or mid-remap, roll forward.
+    - [done] fsck: confirm the PDDB checks out clean after all of the above, then forge an orphaned page and an
+        undecryptable page, and confirm they are found and repaired.
+    - [done] password change: re-key a secondary basis, confirm its contents survive and only the new password
+        opens it; then interrupt a re-key after its commit point and confirm the next mount finishes it.
+    - [done] image fsck: reload every image saved to `tools/pddb-images` during the run, and fsck it.
 */
 
 #[allow(dead_code)]
+/// Reloads each of the images dumped to `tools/pddb-images` during the run, mounts every basis whose key was
+/// dumped with it, and runs fsck over the lot. Any problem fails the run; `tools/pddbci.py` watches for the
+/// "fsck failed" message.
+pub(crate) fn fsck_images(hw: &mut PddbOs, images: &[&str]) -> Result<()> {
+    let mut failed = 0;
+    for &image in images {
+        let keys = match hw.dbg_load(image) {
+            Some(keys) => keys,
+            None => {
+                log::error!("fsck failed: couldn't load image {}", image);
+                failed += 1;
+                continue;
+            }
+        };
+        let mut basis_cache = BasisCache::new();
+        match hw.pddb_mount() {
+            Some(sys_basis) => basis_cache.basis_add(hw, sys_basis),
+            None => {
+                log::error!("fsck failed: couldn't mount the system basis of image {}", image);
+                failed += 1;
+                continue;
+            }
+        }
+        for key in keys.iter() {
+            let name_len = key.basis_name.iter().position(|&c| c == 0).unwrap_or(key.basis_name.len());
+            let name = std::str::from_utf8(&key.basis_name[..name_len]).expect("basis name is not valid utf-8");
+            if name == PDDB_DEFAULT_SYSTEM_BASIS {
+                continue;
+            }
+            match BasisCacheEntry::mount(hw, name, &key.key, false, BasisRetentionPolicy::Persist) {
+                Some(basis) => basis_cache.basis_add(hw, basis),
+                None => {
+                    log::error!("fsck failed: couldn't mount basis {} of image {}", name, image);
+                    failed += 1;
+                }
+            }
+        }
+        let report = basis_cache.fsck(hw, false)?;
+        if report.problems() != 0 {
+            log::error!("fsck failed: image {} has {} problems: {:?}", image, report.problems(), report);
+            failed += 1;
+        } else {
+            log::info!("fsck of image {} is clean ({} bases, {} pages)", image, report.bases, report.pages);
+        }
+    }
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(ErrorKind::Other, format!("fsck failed on {} check(s) of the saved images", failed)))
+    }
+}
+
 pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
     {
         const EXTRA_BASIS: &'static str = "Basis2";
@@ -476,7 +823,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Doing remount disk test");
         let mut basis_cache = BasisCache::new();
         if let Some(sys_basis) = pddb_os.pddb_mount() {
-            basis_cache.basis_add(sys_basis);
+            basis_cache.basis_add(pddb_os, sys_basis);
             list_all(pddb_os, &mut basis_cache);
             pddb_os.dbg_dump(Some("remounte".to_string()), Some(&export));
         }
@@ -484,7 +831,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Mounting the second basis");
         if let Some(basis2) = basis_cache.basis_unlock(pddb_os,
             EXTRA_BASIS, EXTRA_BASIS_PW, BasisRetentionPolicy::Persist) {
-            basis_cache.basis_add(basis2);
+            basis_cache.basis_add(pddb_os, basis2);
         }
         log::set_max_level(log::LevelFilter::Info);
         log::info!("Adding keys to Basis2");
@@ -521,7 +868,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Doing remount disk test part 2");
         let mut basis_cache = BasisCache::new();
         if let Some(sys_basis) = pddb_os.pddb_mount() {
-            basis_cache.basis_add(sys_basis);
+            basis_cache.basis_add(pddb_os, sys_basis);
         }
         let mut remount_list = HashSet::<String>::new();
         for dict in basis_cache.dict_list(pddb_os, None).iter() {
@@ -534,7 +881,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         log::info!("Mounting the second basis again");
         if let Some(basis2) = basis_cache.basis_unlock(pddb_os,
             EXTRA_BASIS, EXTRA_BASIS_PW, BasisRetentionPolicy::Persist) {
-            basis_cache.basis_add(basis2);
+            basis_cache.basis_add(pddb_os, basis2);
         }
         let mut merge2_list = HashSet::<String>::new();
         for dict in basis_cache.dict_list(pddb_os, None).iter() {
@@ -545,6 +892,77 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         assert!(merge2_list.difference(&merge_list).count() == 0, "merged list is different from the original list after remount");
         list_all(pddb_os, &mut basis_cache);
 
+        log::info!("Doing medium pool test");
+        medium_pool_test(pddb_os, &mut basis_cache, Some(PDDB_DEFAULT_SYSTEM_BASIS))?;
+        pddb_os.dbg_dump(Some("mediume".to_string()), Some(&export));
+
+        log::info!("Doing compaction test");
+        let pre_compact = snapshot_basis(pddb_os, &mut basis_cache, PDDB_DEFAULT_SYSTEM_BASIS);
+        basis_cache.compact(pddb_os, Some(PDDB_DEFAULT_SYSTEM_BASIS), None)?;
+        pddb_os.dbg_dump(Some("compacte".to_string()), Some(&export));
+        let post_compact = snapshot_basis(pddb_os, &mut basis_cache, PDDB_DEFAULT_SYSTEM_BASIS);
+        assert!(pre_compact == post_compact, "compaction changed the contents of the basis");
+        for dict in basis_cache.dict_list(pddb_os, Some(PDDB_DEFAULT_SYSTEM_BASIS)).iter() {
+            let da = basis_cache.dict_attributes(pddb_os, dict, Some(PDDB_DEFAULT_SYSTEM_BASIS)).unwrap();
+            assert!(da.free_key_index == da.num_keys + 1, "key descriptors were not compacted in {}", dict);
+            assert!(da.medium_pool_fragmented == 0, "medium pool was not compacted in {}", dict);
+            let mut indices = Vec::<u32>::new();
+            for key in basis_cache.key_list(pddb_os, dict, Some(PDDB_DEFAULT_SYSTEM_BASIS)).unwrap().iter() {
+                indices.push(basis_cache.key_attributes(pddb_os, dict, key, Some(PDDB_DEFAULT_SYSTEM_BASIS)).unwrap().index.get());
+            }
+            indices.sort();
+            assert!(indices.iter().enumerate().all(|(i, &index)| index == i as u32 + 1), "key descriptors are not dense in {}", dict);
+        }
+        basis_cache.basis_unmount(pddb_os, EXTRA_BASIS).unwrap();
+        let mut basis_cache = BasisCache::new();
+        if let Some(sys_basis) = pddb_os.pddb_mount() {
+            basis_cache.basis_add(pddb_os, sys_basis);
+        }
+        let remount_compact = snapshot_basis(pddb_os, &mut basis_cache, PDDB_DEFAULT_SYSTEM_BASIS);
+        assert!(pre_compact == remount_compact, "compacted basis did not survive a remount");
+        if let Some(basis2) = basis_cache.basis_unlock(pddb_os,
+            EXTRA_BASIS, EXTRA_BASIS_PW, BasisRetentionPolicy::Persist) {
+            basis_cache.basis_add(pddb_os, basis2);
+        }
+
+        log::info!("Doing backup/restore test");
+        backup_restore_test(pddb_os, &mut basis_cache, PDDB_DEFAULT_SYSTEM_BASIS, EXTRA_BASIS)?;
+        pddb_os.dbg_dump(Some("restoree".to_string()), Some(&export));
+
+        log::info!("Doing transaction test");
+        transaction_test(pddb_os, &mut basis_cache, EXTRA_BASIS, EXTRA_BASIS_PW)?;
+        pddb_os.dbg_dump(Some("txne".to_string()), Some(&export));
+
+        log::info!("Doing fsck test");
+        fsck_test(pddb_os, &mut basis_cache, EXTRA_BASIS, EXTRA_BASIS_PW)?;
+        pddb_os.dbg_dump(Some("fscke".to_string()), Some(&export));
+
+        log::info!("Doing password change test");
+        rekey_test(pddb_os, &mut basis_cache, EXTRA_BASIS, EXTRA_BASIS_PW)?;
+        pddb_os.dbg_dump(Some("rekeye".to_string()), Some(&export));
+
+        log::info!("Doing basis timeout test");
+        basis_cache.basis_unmount(pddb_os, EXTRA_BASIS).unwrap();
+        if let Some(basis2) = basis_cache.basis_unlock(pddb_os,
+            EXTRA_BASIS, EXTRA_BASIS_PW, BasisRetentionPolicy::TimeOutSecs(2)) {
+            basis_cache.basis_add(pddb_os, basis2);
+        }
+        assert!(basis_cache.has_timeouts(), "basis with a timeout policy was not registered");
+        let tt = ticktimer_server::Ticktimer::new().unwrap();
+        tt.sleep_ms(1500).unwrap();
+        // an access part-way through the countdown should reset the timer
+        basis_cache.dict_list(pddb_os, Some(EXTRA_BASIS));
+        tt.sleep_ms(1500).unwrap();
+        assert!(basis_cache.timeout_check(pddb_os).len() == 0, "basis timed out despite recent access");
+        tt.sleep_ms(1000).unwrap();
+        let locked = basis_cache.timeout_check(pddb_os);
+        assert!(locked.len() == 1 && locked[0] == EXTRA_BASIS, "basis did not time out");
+        assert!(!basis_cache.basis_list().contains(&EXTRA_BASIS.to_string()), "timed out basis is still mounted");
+        assert!(!basis_cache.has_timeouts(), "timeout policy still registered after the basis locked");
+
+        log::info!("Doing fsck of the saved images");
+        fsck_images(pddb_os, &["basecase1e", "dachecke", "patche", "restoree", "txne", "fscke", "rekeye"])?;
+
         log::info!("CI done");
 
         /*
@@ -0,0 +1,262 @@
+use crate::api::*;
+use num_traits::*;
+use xous::Message;
+use std::collections::{BTreeSet, HashMap};
+
+/// Most watches a process can have at once. Each one costs a record here, and a message for every
+/// change it covers.
+pub(crate) const MAX_WATCHES_PER_PID: usize = 32;
+
+pub(crate) struct WatchRecord {
+    pub dict: String,
+    pub key: Option<String>, // None watches every key in the dictionary
+    pub basis: Option<String>,
+    pub conn: xous::CID, // callback connection
+    pub owner: xous::PID, // process that asked for the watch
+}
+impl WatchRecord {
+    /// Makes the record for a watch `owner` asked for with `req`, connecting to its callback server
+    /// with `connect`. Refuses names that don't decode, a callback server that can't be reached, and
+    /// owners that already have `MAX_WATCHES_PER_PID` watches.
+    pub(crate) fn from_request<F>(req: &PddbWatchRequest, owner: xous::PID, watch_dict: &HashMap<ApiToken, WatchRecord>, connect: F)
+    -> Result<WatchRecord, PddbRequestCode>
+    where F: FnOnce(xous::SID) -> Result<xous::CID, xous::Error> {
+        if watch_dict.values().filter(|w| w.owner == owner).count() >= MAX_WATCHES_PER_PID {
+            return Err(PddbRequestCode::AccessDenied);
+        }
+        let dict = req.dict.as_str().or(Err(PddbRequestCode::InternalError))?;
+        let key = if req.key_specified {
+            Some(req.key.as_str().or(Err(PddbRequestCode::InternalError))?)
+        } else {
+            None
+        };
+        let basis = if req.basis_specified {
+            Some(req.basis.as_str().or(Err(PddbRequestCode::InternalError))?)
+        } else {
+            None
+        };
+        let conn = connect(xous::SID::from_array(req.cb_sid)).or(Err(PddbRequestCode::InternalError))?;
+        Ok(WatchRecord {
+            dict: String::from(dict),
+            key: key.map(String::from),
+            basis: basis.map(String::from),
+            conn,
+            owner,
+        })
+    }
+
+    /// Checks if a change to `dict`/`key` in `basis_name` concerns this watch. A `key` of `None` means the
+    /// whole dictionary was affected. Watches that didn't specify a basis see changes in every basis, and
+    /// changes that didn't specify a basis are seen by every watch on the dictionary.
+    pub(crate) fn covers(&self, dict: &str, key: Option<&str>, basis_name: Option<&str>) -> bool {
+        if self.dict != dict {
+            return false;
+        }
+        if let (Some(rkey), Some(key)) = (&self.key, key) {
+            if rkey != key {
+                return false;
+            }
+        }
+        if let (Some(rbasis), Some(basis)) = (&self.basis, basis_name) {
+            if rbasis != basis {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Sends `event` to every watch that covers `dict`/`key` in `basis_name`.
+pub(crate) fn watch_notify(watch_dict: &HashMap<ApiToken, WatchRecord>, dict: &str, key: Option<&str>, basis_name: Option<&str>, event: PddbWatchEvent) {
+    for (token, rec) in watch_dict.iter() {
+        if !rec.covers(dict, key, basis_name) {
+            continue;
+        }
+        xous::send_message(rec.conn,
+            Message::new_scalar(CbOp::Watch.to_usize().unwrap(), token[0] as usize, token[1] as usize, token[2] as usize,
+            event.to_usize().unwrap())
+        ).map_err(|e| log::warn!("couldn't send watch notification: {:?}", e)).ok();
+    }
+}
+
+/// Keys written since the last sync, as (dict, key, basis). A large write arrives as a series of
+/// page-sized `WriteKey` messages, so instead of notifying on each of them, `Written` is sent once
+/// per key when the data is synced, or when the handle that wrote it is closed.
+pub(crate) struct PendingWrites {
+    keys: BTreeSet<(String, String, Option<String>)>,
+}
+impl PendingWrites {
+    pub(crate) fn new() -> Self {
+        PendingWrites { keys: BTreeSet::new() }
+    }
+    pub(crate) fn mark(&mut self, dict: &str, key: &str, basis_name: Option<&str>) {
+        self.keys.insert((dict.to_string(), key.to_string(), basis_name.map(|b| b.to_string())));
+    }
+    /// Drops pending writes that a delete has superseded. A `key` of `None` drops the whole dictionary.
+    pub(crate) fn forget(&mut self, dict: &str, key: Option<&str>, basis_name: Option<&str>) {
+        self.keys.retain(|(d, k, b)|
+            !(d == dict
+            && key.map_or(true, |key| k == key)
+            && (basis_name.is_none() || b.is_none() || b.as_deref() == basis_name))
+        );
+    }
+    /// Removes and returns the pending write of one key, if any
+    pub(crate) fn take_key(&mut self, dict: &str, key: &str, basis_name: Option<&str>) -> Option<(String, String, Option<String>)> {
+        let entry = (dict.to_string(), key.to_string(), basis_name.map(|b| b.to_string()));
+        if self.keys.remove(&entry) {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+    /// Removes and returns every pending write
+    pub(crate) fn take(&mut self) -> Vec<(String, String, Option<String>)> {
+        std::mem::take(&mut self.keys).into_iter().collect()
+    }
+    /// Sends `Written` for the pending writes in `writes`
+    pub(crate) fn notify(watch_dict: &HashMap<ApiToken, WatchRecord>, writes: Vec<(String, String, Option<String>)>) {
+        for (dict, key, basis) in writes {
+            watch_notify(watch_dict, &dict, Some(&key), basis.as_deref(), PddbWatchEvent::Written);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch(dict: &str, key: Option<&str>, basis: Option<&str>) -> WatchRecord {
+        WatchRecord {
+            dict: dict.to_string(),
+            key: key.map(|k| k.to_string()),
+            basis: basis.map(|b| b.to_string()),
+            conn: 0,
+            owner: xous::PID::new(1).unwrap(),
+        }
+    }
+
+    fn request(dict: &str, key: Option<&str>) -> PddbWatchRequest {
+        PddbWatchRequest {
+            basis_specified: false,
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::new(),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict),
+            key_specified: key.is_some(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key.unwrap_or("")),
+            cb_sid: [1, 2, 3, 4],
+            token: None,
+            result: PddbRequestCode::Uninit,
+        }
+    }
+
+    #[test]
+    fn test_watch_request_makes_record() {
+        let owner = xous::PID::new(5).unwrap();
+        let rec = WatchRecord::from_request(&request("wlan.networks", Some("home")), owner, &HashMap::new(), |sid| {
+            assert_eq!(sid, xous::SID::from_array([1, 2, 3, 4]));
+            Ok(7)
+        }).ok().unwrap();
+        assert_eq!((rec.dict.as_str(), rec.key.as_deref(), rec.basis.as_deref()), ("wlan.networks", Some("home"), None));
+        assert_eq!((rec.conn, rec.owner), (7, owner));
+    }
+
+    #[test]
+    fn test_watch_request_unreachable_callback() {
+        let owner = xous::PID::new(5).unwrap();
+        let result = WatchRecord::from_request(&request("wlan.networks", None), owner, &HashMap::new(),
+            |_| Err(xous::Error::ServerNotFound));
+        assert!(matches!(result, Err(PddbRequestCode::InternalError)));
+    }
+
+    #[test]
+    fn test_watch_request_bad_name() {
+        let owner = xous::PID::new(5).unwrap();
+        let mut req = request("wlan.networks", None);
+        req.dict.push_byte(0xff).unwrap(); // not UTF-8
+        let result = WatchRecord::from_request(&req, owner, &HashMap::new(), |_| Ok(7));
+        assert!(matches!(result, Err(PddbRequestCode::InternalError)));
+    }
+
+    #[test]
+    fn test_watches_capped_per_pid() {
+        let owner = xous::PID::new(5).unwrap();
+        let other = xous::PID::new(6).unwrap();
+        let mut watch_dict = HashMap::new();
+        for i in 0..MAX_WATCHES_PER_PID as u32 {
+            let mut rec = watch("d", None, None);
+            rec.owner = owner;
+            watch_dict.insert([i, 0, 0], rec);
+        }
+        let req = request("d", None);
+        assert!(matches!(WatchRecord::from_request(&req, owner, &watch_dict, |_| Ok(7)), Err(PddbRequestCode::AccessDenied)));
+        assert!(WatchRecord::from_request(&req, other, &watch_dict, |_| Ok(7)).is_ok());
+    }
+
+    #[test]
+    fn test_watch_dict_covers_its_keys() {
+        let w = watch("wlan.networks", None, None);
+        assert!(w.covers("wlan.networks", Some("home"), None));
+        assert!(w.covers("wlan.networks", Some("home"), Some("work")));
+        assert!(w.covers("wlan.networks", None, None));
+        assert!(!w.covers("wlan.network", Some("home"), None));
+        assert!(!w.covers("sys.rtc", None, None));
+    }
+
+    #[test]
+    fn test_watch_key_matching() {
+        let w = watch("wlan.networks", Some("home"), None);
+        assert!(w.covers("wlan.networks", Some("home"), None));
+        assert!(!w.covers("wlan.networks", Some("office"), None));
+        // deleting the dictionary deletes the watched key too
+        assert!(w.covers("wlan.networks", None, None));
+    }
+
+    #[test]
+    fn test_watch_basis_matching() {
+        let w = watch("wlan.networks", Some("home"), Some("work"));
+        assert!(w.covers("wlan.networks", Some("home"), Some("work")));
+        assert!(!w.covers("wlan.networks", Some("home"), Some(".System")));
+        // changes made through the union of bases may have landed in the watched basis
+        assert!(w.covers("wlan.networks", Some("home"), None));
+    }
+
+    #[test]
+    fn test_pending_writes_coalesce() {
+        let mut pending = PendingWrites::new();
+        for _ in 0..10 {
+            pending.mark("d", "k", None);
+        }
+        pending.mark("d", "k", Some("work"));
+        pending.mark("d", "other", None);
+        let writes = pending.take();
+        assert_eq!(writes.len(), 3);
+        assert!(pending.take().is_empty());
+    }
+
+    #[test]
+    fn test_pending_writes_take_key() {
+        let mut pending = PendingWrites::new();
+        pending.mark("d", "k", None);
+        pending.mark("d", "other", None);
+        assert!(pending.take_key("d", "k", Some("work")).is_none());
+        assert_eq!(pending.take_key("d", "k", None), Some(("d".to_string(), "k".to_string(), None)));
+        assert!(pending.take_key("d", "k", None).is_none());
+        assert_eq!(pending.take().len(), 1);
+    }
+
+    #[test]
+    fn test_pending_writes_forget() {
+        let mut pending = PendingWrites::new();
+        pending.mark("d", "k", None);
+        pending.mark("d", "j", Some("work"));
+        pending.mark("e", "k", None);
+        pending.forget("d", Some("k"), Some("work"));
+        assert_eq!(pending.take_key("d", "k", None), None);
+        pending.mark("d", "k", Some(".System"));
+        pending.forget("d", None, Some("work"));
+        let writes = pending.take();
+        assert_eq!(writes, vec![
+            ("d".to_string(), "k".to_string(), Some(".System".to_string())),
+            ("e".to_string(), "k".to_string(), None),
+        ]);
+    }
+}
@@ -1,4 +1,5 @@
 pub(crate) const SERVER_NAME_KEYS: &str     = "_Root key server and update manager_";
+pub(crate) const SERVER_NAME_SIGNER: &str   = "_Root key Ed25519 signer_";
 #[allow(dead_code)]
 pub(crate) const SIG_VERSION: u32 = 1;
 
@@ -17,6 +18,9 @@ pub(crate) enum Opcode {
     AesOracle,
     /// initiate key wrapper operation
     AesKwp,
+    /// initiate an Ed25519 signing oracle operation. This is the only opcode of the signer server; the keys
+    /// server only accepts it from the signer server's thread, which has checked the caller's app id.
+    Ed25519Signer,
     /// create new FPGA keys; provisioning requires a slave device to be connected that can run the JTAG sequence
     BbramProvision,
     /// clear a cached password
@@ -39,6 +43,14 @@ pub(crate) enum Opcode {
     UxSignXousPasswordReturn,
     UxSignXousRun,
 
+    /// change the boot or update password
+    UxUpdatePassword,
+    UxUpdatePasswordOldReturn,
+    UxUpdatePasswordNewReturn,
+    UxUpdatePasswordConfirmReturn,
+    UxUpdatePasswordSigningReturn,
+    UxUpdatePasswordRun,
+
     /// Ux AES calls
     UxAesEnsurePassword,
     UxAesPasswordPolicy,
@@ -81,7 +93,7 @@ pub enum PasswordRetentionPolicy {
 /// I don't think it hurts; more importantly, it also prevents an off-the-shelf "hashcat" run from
 /// being used to brute force both passwords in a single go, as the salt has to be (slightly)
 /// recomputed for each type of password.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum PasswordType {
     Boot = 1,
     Update = 2,
@@ -202,4 +214,53 @@ pub (crate) struct KeyWrapper {
     pub result: Option<KeywrapError>,
     // used by the unwrap side
     pub expected_len: u32,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Zeroize, Eq, PartialEq, Copy, Clone)]
+pub enum SignerError {
+    /// Message is too big.
+    TooBig,
+    /// App identifier is empty, too long, or not a server name the caller registered.
+    InvalidAppId,
+    /// The key's password couldn't be confirmed.
+    AuthenticationFailed,
+}
+impl Error for SignerError {}
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            SignerError::TooBig => f.write_str("Message too big"),
+            SignerError::InvalidAppId => f.write_str("Invalid app identifier"),
+            SignerError::AuthenticationFailed => f.write_str("Authentication failed"),
+        }
+    }
+}
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Zeroize, Eq, PartialEq)]
+pub enum SignerOp {
+    /// sign the message, and return the public key
+    Sign,
+    /// only return the public key
+    PublicKey,
+}
+
+/// Maximum size of a message to sign. Like the key wrapper, this is meant for short messages such as
+/// challenges and digests, not bulk data.
+pub(crate) const MAX_SIGN_DATA: usize = 2048;
+/// Maximum length of the app identifier used to derive a signing key: the name of a server the app registered.
+pub const MAX_APP_ID_LEN: usize = 64;
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Zeroize)]
+#[zeroize(drop)]
+pub (crate) struct Ed25519Signer {
+    pub data: [u8; MAX_SIGN_DATA],
+    // used to specify the length of the data used in the fixed-length array above
+    pub len: u32,
+    /// the signing key is derived from the root key and this identifier, which must be the name of a
+    /// server the requesting process registered with xous-names
+    pub app_id: [u8; MAX_APP_ID_LEN],
+    pub app_id_len: u32,
+    pub key_index: u8,
+    pub op: SignerOp,
+    pub signature: [u8; 64],
+    pub public_key: [u8; 32],
+    pub result: Option<SignerError>,
+}
@@ -68,6 +68,9 @@ struct PasswordCache {
     hashed_update_pw_valid: u32,
     fpga_key: [u8; 32],
     fpga_key_valid: u32,
+    /// staging area for the replacement password during a password change; its type is given by `cur_password_type`
+    hashed_new_pw: [u8; 32],
+    hashed_new_pw_valid: u32,
 }
 
 #[repr(C)]
@@ -128,6 +131,15 @@ pub(crate) mod keyrom_config {
     pub const INITIALIZED:         KeyField = KeyField::new(1, 27);
 }
 
+/// Compares two hashed passwords without early exit, so the comparison time doesn't depend on where they differ.
+fn digest_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    let mut diff = 0u8;
+    for (&x, &y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
 /// helper routine that will reverse the order of bits. uses a divide-and-conquer approach.
 pub(crate) fn bitflip(input: &[u8], output: &mut [u8]) {
     assert!((input.len() % 4 == 0) && (output.len() % 4 == 0) && (input.len() == output.len()));
@@ -452,6 +464,39 @@ impl<'a> RootKeys {
         }
     }
 
+    /// Signing oracle: the app key is derived from the root key selected by `key_index`, so the same
+    /// password policy that gates the AES oracle also gates signing.
+    /// ASSUME: the caller has confirmed that the user password is valid and in cache
+    pub fn signer_op(&mut self, signer: &mut Ed25519Signer) {
+        let mut key = match signer.key_index {
+            KeyRomLocs::USER_KEY => {
+                let mut key_enc = self.read_key_256(KeyRomLocs::USER_KEY);
+                let pcache: &PasswordCache = unsafe{& *(self.pass_cache.as_ptr() as *const PasswordCache)};
+                if pcache.hashed_boot_pw_valid == 0 {
+                    self.purge_password(PasswordType::Boot);
+                    log::warn!("boot password isn't valid! Returning bogus results.");
+                }
+                for (key, &pw) in
+                key_enc.iter_mut().zip(pcache.hashed_boot_pw.iter()) {
+                    *key = *key ^ pw;
+                }
+                if self.boot_password_policy == PasswordRetentionPolicy::AlwaysPurge {
+                    self.purge_password(PasswordType::Boot);
+                }
+                key_enc
+            },
+            _ => {
+                self.fake_key[0] = signer.key_index;
+                self.fake_key
+            }
+        };
+        self.compute_key_rollback(&mut key);
+        crate::signer::signer_op(&key, signer);
+        for b in key.iter_mut() {
+            *b = 0;
+        }
+    }
+
     /// returns None if there is an obvious problem with the JTAG interface
     /// otherwise returns the result. "secured" would be the most paranoid setting
     /// which is all the bits burned. There are other combinations that are also
@@ -550,6 +595,16 @@ impl<'a> RootKeys {
         }
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
     }
+    pub fn purge_new_password(&mut self) {
+        unsafe {
+            let pcache_ptr: *mut PasswordCache = self.pass_cache.as_mut_ptr() as *mut PasswordCache;
+            for p in (*pcache_ptr).hashed_new_pw.iter_mut() {
+                *p = 0;
+            }
+            (*pcache_ptr).hashed_new_pw_valid = 0;
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
     pub fn purge_sensitive_data(&mut self) {
         for d in self.sensitive_data.borrow_mut().as_slice_mut::<u32>().iter_mut() {
             *d = 0;
@@ -611,6 +666,7 @@ impl<'a> RootKeys {
                 self.purge_password(PasswordType::Update);
             }
         }
+        self.purge_new_password();
         self.purge_sensitive_data();
     }
     pub fn resume(&mut self) {
@@ -647,6 +703,121 @@ impl<'a> RootKeys {
             log::error!("got an unexpected password from the UX");
             return;
         };
+        let mut digest = self.hash_password(pw, pw_type);
+
+        let pcache_ptr: *mut PasswordCache = self.pass_cache.as_mut_ptr() as *mut PasswordCache;
+        unsafe {
+            match pw_type {
+                PasswordType::Boot => {
+                    for (&src, dst) in digest.iter().zip((*pcache_ptr).hashed_boot_pw.iter_mut()) {
+                        *dst = src;
+                    }
+                    (*pcache_ptr).hashed_boot_pw_valid = 1;
+                }
+                PasswordType::Update => {
+                    for (&src, dst) in digest.iter().zip((*pcache_ptr).hashed_update_pw.iter_mut()) {
+                        *dst = src;
+                    }
+                    (*pcache_ptr).hashed_update_pw_valid = 1;
+                }
+            }
+        }
+        for b in digest.iter_mut() {
+            *b = 0;
+        }
+    }
+
+    /// Hashes a password the same way as `hash_and_save_password`, but stores the result in the staging
+    /// slot for the replacement password. Used by the password change flow, where the old password
+    /// has to remain in the cache until the keys are re-wrapped.
+    pub fn hash_and_save_new_password(&mut self, pw: &str) {
+        let pw_type = if let Some(cur_type) = self.cur_password_type {
+            cur_type
+        } else {
+            log::error!("got an unexpected password from the UX");
+            return;
+        };
+        let mut digest = self.hash_password(pw, pw_type);
+        let pcache: &mut PasswordCache = unsafe{&mut *(self.pass_cache.as_mut_ptr() as *mut PasswordCache)};
+        for (&src, dst) in digest.iter().zip(pcache.hashed_new_pw.iter_mut()) {
+            *dst = src;
+        }
+        pcache.hashed_new_pw_valid = 1;
+        for b in digest.iter_mut() {
+            *b = 0;
+        }
+    }
+
+    /// Checks a re-typed replacement password against the one staged by `hash_and_save_new_password`.
+    /// On mismatch, the staged password is purged, so the user has to enter it again.
+    pub fn confirm_new_password(&mut self, pw: &str) -> bool {
+        let pw_type = if let Some(cur_type) = self.cur_password_type {
+            cur_type
+        } else {
+            log::error!("got an unexpected password from the UX");
+            return false;
+        };
+        let mut digest = self.hash_password(pw, pw_type);
+        let pcache: &PasswordCache = unsafe{& *(self.pass_cache.as_ptr() as *const PasswordCache)};
+        let matches = pcache.hashed_new_pw_valid != 0 && digest_eq(&digest, &pcache.hashed_new_pw);
+        for b in digest.iter_mut() {
+            *b = 0;
+        }
+        if !matches {
+            self.purge_new_password();
+        }
+        matches
+    }
+
+    /// Checks the current password of type `cur_password_type` as part of the password change flow.
+    ///
+    /// The update password is checked against the KEYROM: the decrypted self-signing key has to
+    /// reproduce the self-signing public key. If it does, the password is saved in the cache, as
+    /// it is needed later on to re-patch the gateware.
+    ///
+    /// The boot key has no check value in the KEYROM, so the boot password is instead checked against
+    /// the cached copy, which was validated when the PDDB was unlocked. The caller must ensure the
+    /// boot password is in the cache before asking for it.
+    pub fn verify_password(&mut self, pw: &str) -> bool {
+        let pw_type = if let Some(cur_type) = self.cur_password_type {
+            cur_type
+        } else {
+            log::error!("got an unexpected password from the UX");
+            return false;
+        };
+        let mut digest = self.hash_password(pw, pw_type);
+        let pcache: &mut PasswordCache = unsafe{&mut *(self.pass_cache.as_mut_ptr() as *mut PasswordCache)};
+        let valid = match pw_type {
+            PasswordType::Boot => {
+                if pcache.hashed_boot_pw_valid == 0 {
+                    log::error!("boot password is not in cache, can't verify the old password");
+                    false
+                } else {
+                    digest_eq(&digest, &pcache.hashed_boot_pw)
+                }
+            }
+            PasswordType::Update => {
+                let enc_signing_key = self.read_key_256(KeyRomLocs::SELFSIGN_PRIVKEY);
+                if self.derive_signing_keypair(&enc_signing_key, &digest).is_ok() {
+                    for (&src, dst) in digest.iter().zip(pcache.hashed_update_pw.iter_mut()) {
+                        *dst = src;
+                    }
+                    pcache.hashed_update_pw_valid = 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        for b in digest.iter_mut() {
+            *b = 0;
+        }
+        valid
+    }
+
+    /// Computes `user plaintext -> bcrypt -> sha512trunc256` for a password of type `pw_type`
+    /// using the currently set salt. Caller is responsible for zeroizing the result.
+    fn hash_password(&mut self, pw: &str, pw_type: PasswordType) -> [u8; 32] {
         let mut hashed_password: [u8; 24] = [0; 24];
         let mut salt = self.get_salt();
         // we change the salt ever-so-slightly for every password. This doesn't make any one password more secure;
@@ -667,24 +838,38 @@ impl<'a> RootKeys {
         let mut hasher = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
         hasher.update(hashed_password);
         let digest = hasher.finalize();
+        for b in hashed_password.iter_mut() {
+            *b = 0;
+        }
 
-        let pcache_ptr: *mut PasswordCache = self.pass_cache.as_mut_ptr() as *mut PasswordCache;
-        unsafe {
-            match pw_type {
-                PasswordType::Boot => {
-                    for (&src, dst) in digest.iter().zip((*pcache_ptr).hashed_boot_pw.iter_mut()) {
-                        *dst = src;
-                    }
-                    (*pcache_ptr).hashed_boot_pw_valid = 1;
-                }
-                PasswordType::Update => {
-                    for (&src, dst) in digest.iter().zip((*pcache_ptr).hashed_update_pw.iter_mut()) {
-                        *dst = src;
-                    }
-                    (*pcache_ptr).hashed_update_pw_valid = 1;
-                }
-            }
+        let mut ret: [u8; 32] = [0; 32];
+        ret.copy_from_slice(&digest);
+        ret
+    }
+
+    /// Decrypts an encrypted self-signing root key with a hashed update password, rolls it forward to the
+    /// current anti-rollback state, and checks that it reproduces the self-signing public key in the KEYROM.
+    /// Returns `KeyError` if the password was wrong.
+    fn derive_signing_keypair(&mut self, enc_signing_key: &[u8; 32], hashed_update_pw: &[u8; 32]) -> Result<Keypair, RootkeyResult> {
+        let mut derived_sk: [u8; ed25519_dalek::SECRET_KEY_LENGTH] = [0; ed25519_dalek::SECRET_KEY_LENGTH];
+        for (key, (&enc_key, &pw)) in
+        derived_sk.iter_mut()
+        .zip(enc_signing_key.iter().zip(hashed_update_pw.iter())) {
+            *key = enc_key ^ pw;
+        }
+        self.compute_key_rollback(&mut derived_sk);
+        let sk = SecretKey::from_bytes(&derived_sk);
+        for b in derived_sk.iter_mut() {
+            *b = 0;
         }
+        let sk = sk.map_err(|_| RootkeyResult::KeyError)?;
+        let pk: PublicKey = (&sk).into();
+        if pk.to_bytes() != self.read_key_256(KeyRomLocs::SELFSIGN_PUBKEY) {
+            log::warn!("update password did not reproduce the self-signing public key");
+            return Err(RootkeyResult::KeyError);
+        }
+        // keypair zeroizes on drop
+        Ok(Keypair{public: pk, secret: sk})
     }
 
     /// Reads a 256-bit key at a given index offset
@@ -1446,6 +1631,199 @@ impl<'a> RootKeys {
     }
 
 
+    /// Re-wraps the root keys under a new boot or update password.
+    ///
+    /// Assumes the UX layer has:
+    /// - checked the old password of type `pw_type` with `verify_password()`
+    /// - staged the new password with `hash_and_save_new_password()`
+    /// - put the update password in the cache (it's needed to re-encrypt and re-sign the gateware)
+    ///
+    /// The keys are XOR'd with the password, so the plaintext keys don't change: the loader and kernel
+    /// signatures stay valid, and data wrapped with the user key remains readable. Only the gateware,
+    /// which carries the KEYROM, has to be re-patched and re-signed. A copy of the current gateware is
+    /// made in the staging area before patching, and it is restored if anything fails afterwards.
+    ///
+    /// The new password takes effect once the device is rebooted with the patched KEYROM.
+    pub fn do_update_password(&mut self, rootkeys_modal: &mut Modal, main_cid: xous::CID, pw_type: PasswordType) -> Result<(), RootkeyResult> {
+        // make sure the system is sane
+        self.xous_init_interlock();
+        // the RAM copy of the KEYROM has to stay put until the patch is done
+        self.susres.set_suspendable(false).expect("couldn't block suspend/resume");
+        self.spinor.set_staging_write_protect(true).expect("couldn't protect the staging area");
+
+        // setup Ux
+        let mut progress_action = Slider::new(main_cid, Opcode::UxGutter.to_u32().unwrap(),
+        0, 100, 10, Some("%"), 0, true, true
+        );
+        progress_action.set_is_password(true);
+        rootkeys_modal.modify(
+            Some(ActionType::Slider(progress_action)),
+            Some(t!("rootkeys.pwchange.starting", xous::LANG)), false,
+            None, true, None);
+        rootkeys_modal.activate();
+        xous::yield_slice(); // give some time to the GAM to render
+        let mut pb = ProgressBar::new(rootkeys_modal, &mut progress_action);
+        pb.set_percentage(1);
+
+        let ret = self.update_password_inner(&mut pb, pw_type);
+
+        // the keyrom copy, the replacement password and the update password are always purged: on success, the
+        // cached update password (if it was the one changed) no longer matches the KEYROM after reboot; on failure,
+        // it may have been the reason for the failure.
+        self.purge_sensitive_data();
+        self.purge_new_password();
+        self.purge_password(PasswordType::Update);
+        self.spinor.set_staging_write_protect(false).expect("couldn't un-protect the staging area");
+        self.susres.set_suspendable(true).expect("couldn't re-allow suspend/resume");
+
+        pb.set_percentage(100);
+        self.ticktimer.sleep_ms(250).expect("couldn't show final message");
+
+        ret
+    }
+
+    fn update_password_inner(&mut self, pb: &mut ProgressBar, pw_type: PasswordType) -> Result<(), RootkeyResult> {
+        let pcache: &mut PasswordCache = unsafe{&mut *(self.pass_cache.as_mut_ptr() as *mut PasswordCache)};
+        if pcache.hashed_update_pw_valid == 0 || pcache.hashed_new_pw_valid == 0 {
+            log::error!("update password or replacement password was not set going into the password change routine");
+            return Err(RootkeyResult::KeyError);
+        }
+        if pw_type == PasswordType::Boot && pcache.hashed_boot_pw_valid == 0 {
+            log::error!("boot password was not set going into the password change routine");
+            return Err(RootkeyResult::KeyError);
+        }
+
+        // confirm the update password is correct, and derive the signing key for the gateware
+        let enc_signing_key = self.read_key_256(KeyRomLocs::SELFSIGN_PRIVKEY);
+        let keypair = self.derive_signing_keypair(&enc_signing_key, &pcache.hashed_update_pw)?;
+
+        // decrypt the FPGA key using the (old) update password
+        for (&src, dst) in self.read_key_256(KeyRomLocs::FPGA_KEY).iter().zip(pcache.fpga_key.iter_mut()) {
+            *dst = src;
+        }
+        for (fkey, &pw) in pcache.fpga_key.iter_mut().zip(pcache.hashed_update_pw.iter()) {
+            *fkey = *fkey ^ pw;
+        }
+        pcache.fpga_key_valid = 1;
+        pb.set_percentage(5);
+
+        // stage the keyrom data, and swap the old password for the new one on every key it protects
+        self.populate_sensitive_data();
+        let old_pw: &[u8; 32] = match pw_type {
+            PasswordType::Boot => &pcache.hashed_boot_pw,
+            PasswordType::Update => &pcache.hashed_update_pw,
+        };
+        let rewrap_locs: &[u8] = match pw_type {
+            PasswordType::Boot => &[KeyRomLocs::USER_KEY],
+            PasswordType::Update => &[KeyRomLocs::FPGA_KEY, KeyRomLocs::SELFSIGN_PRIVKEY],
+        };
+        for &loc in rewrap_locs.iter() {
+            for (word, (old, new)) in
+            self.sensitive_data.borrow_mut().as_slice_mut::<u32>()[loc as usize..loc as usize + 256/(size_of::<u32>()*8)].iter_mut()
+            .zip(old_pw.chunks(4).into_iter().zip(pcache.hashed_new_pw.chunks(4).into_iter())) {
+                *word = *word ^ u32::from_be_bytes(old.try_into().unwrap()) ^ u32::from_be_bytes(new.try_into().unwrap());
+            }
+        }
+
+        // sanity check the re-wrapped signing key before committing anything to FLASH
+        if pw_type == PasswordType::Update {
+            let mut staged_signing_key: [u8; 32] = [0; 32];
+            for (dst, &word) in staged_signing_key.chunks_mut(4).into_iter()
+            .zip(self.sensitive_data.borrow_mut().as_slice::<u32>()[KeyRomLocs::SELFSIGN_PRIVKEY as usize..KeyRomLocs::SELFSIGN_PRIVKEY as usize + 256/(size_of::<u32>()*8)].iter()) {
+                dst.copy_from_slice(&word.to_be_bytes());
+            }
+            if self.derive_signing_keypair(&staged_signing_key, &pcache.hashed_new_pw).is_err() {
+                log::error!("re-wrapped signing key failed to verify, aborting before anything is written");
+                return Err(RootkeyResult::IntegrityError);
+            }
+        }
+        #[cfg(feature = "hazardous-debug")]
+        self.debug_staging();
+
+        // make a backup copy of the current gateware to the staging area, so we can roll back if the patch fails.
+        // This overwrites the staging area, so the caller refuses to start while a gateware update is pending.
+        pb.update_text(t!("rootkeys.init.backup_gateware", xous::LANG));
+        pb.rebase_subtask_percentage(5, 30);
+        self.make_gateware_backup(Some(&mut *pb), false)?;
+
+        // from here on the gateware region is modified, so any failure has to restore the backup
+        match self.patch_rewrapped_gateware(pb, &keypair) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                log::error!("password change failed ({:?}), restoring gateware from backup", e);
+                pb.update_text(t!("rootkeys.pwchange.restoring", xous::LANG));
+                pb.set_percentage(0);
+                pb.rebase_subtask_percentage(0, 100);
+                if self.make_gateware_backup(Some(&mut *pb), true).is_err() {
+                    log::error!("couldn't restore the gateware backup, the device may not boot!");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Patches the staged KEYROM into the gateware, using the backup in the staging area as the source,
+    /// then verifies and self-signs the result.
+    fn patch_rewrapped_gateware(&mut self, pb: &mut ProgressBar, keypair: &Keypair) -> Result<(), RootkeyResult> {
+        let pcache: &PasswordCache = unsafe{& *(self.pass_cache.as_ptr() as *const PasswordCache)};
+        assert!(pcache.fpga_key_valid == 1);
+        log::debug!("making source oracle");
+        let mut src_oracle = match BitstreamOracle::new(&pcache.fpga_key, &pcache.fpga_key, self.staging(), self.staging_base()) {
+            Ok(o) => o,
+            Err(e) => {
+                log::error!("couldn't create oracle (most likely FPGA key mismatch): {:?}", e);
+                return Err(e);
+            }
+        };
+        log::debug!("making destination oracle");
+        let mut dst_oracle = match BitstreamOracle::new(&pcache.fpga_key, &pcache.fpga_key, self.gateware(), self.gateware_base()) {
+            Ok(o) => o,
+            Err(e) => {
+                log::error!("couldn't create oracle (most likely FPGA key mismatch): {:?}", e);
+                src_oracle.clear();
+                return Err(e);
+            }
+        };
+        let keysource = dst_oracle.get_original_key_type();
+        dst_oracle.set_target_key_type(keysource);
+
+        pb.update_text(t!("rootkeys.init.patching_keys", xous::LANG));
+        pb.rebase_subtask_percentage(30, 60);
+        let patched = self.gateware_copy_and_patch(&src_oracle, &dst_oracle, Some(&mut *pb))
+        .and_then(|_| {
+            // make a copy of the plaintext metadata and csr records
+            self.spinor.patch(self.gateware(), self.gateware_base(),
+            &self.staging()[METADATA_OFFSET..SELFSIG_OFFSET], METADATA_OFFSET as u32
+            ).map_err(|_| RootkeyResult::FlashError)
+        });
+        if let Err(e) = patched {
+            src_oracle.clear();
+            dst_oracle.clear();
+            return Err(e);
+        }
+
+        // verify that the patch worked
+        pb.update_text(t!("rootkeys.init.verifying_gateware", xous::LANG));
+        pb.rebase_subtask_percentage(60, 90);
+        let verified = self.verify_gateware(&dst_oracle, Some(&mut *pb));
+        src_oracle.clear();
+        dst_oracle.clear();
+        verified?;
+
+        // sign the gateware, commit the signature
+        pb.update_text(t!("rootkeys.init.commit_signatures", xous::LANG));
+        pb.set_percentage(92);
+        let (gateware_sig, gateware_len) = self.sign_gateware(keypair);
+        log::debug!("gateware signature ({}): {:x?}", gateware_len, gateware_sig.to_bytes());
+        self.commit_signature(gateware_sig, gateware_len, SignatureType::Gateware)?;
+
+        pb.set_percentage(96);
+        if !self.verify_gateware_self_signature() {
+            return Err(RootkeyResult::IntegrityError);
+        }
+        Ok(())
+    }
+
     pub fn test(&mut self, rootkeys_modal: &mut Modal, main_cid: xous::CID) -> Result<(), RootkeyResult> {
         let mut progress_action = Slider::new(main_cid, Opcode::UxGutter.to_u32().unwrap(),
         0, 100, 10, Some("%"), 0, true, true
@@ -2157,6 +2535,18 @@ impl<'a> RootKeys {
         sigtype
     }
 
+    /// A gateware update is pending if the staging area holds a validly signed image that we didn't sign
+    /// ourselves (so it's not a backup made by one of our own routines), and whose metadata doesn't match
+    /// the boot gateware (so it hasn't been provisioned yet).
+    pub fn is_gateware_update_pending(&mut self) -> bool {
+        match self.check_gateware_signature(GatewareRegion::Staging) {
+            SignatureResult::ThirdPartyOk | SignatureResult::DevKeyOk => {
+                self.staging()[METADATA_OFFSET..SELFSIG_OFFSET] != self.gateware()[METADATA_OFFSET..SELFSIG_OFFSET]
+            }
+            _ => false,
+        }
+    }
+
     pub fn fetch_gw_metadata(&self, region_enum: GatewareRegion) -> MetadataInFlash {
         let region = match region_enum {
             GatewareRegion::Boot => self.gateware(),
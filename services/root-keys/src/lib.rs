@@ -52,9 +52,17 @@ impl RootKeys {
         Opcode::UxSelfSignXous.to_u32().unwrap()
     }
 
+    /// the menu item payload is the `PasswordType` to change, as its `usize` representation
+    pub fn get_update_password_op(&self) -> u32 {
+        Opcode::UxUpdatePassword.to_u32().unwrap()
+    }
+
     /// this initiates an attempt to update passwords. User must unlock their device first, and can cancel out if not expected.
-    pub fn try_update_password(&mut self, _which: PasswordType) -> Result<(), xous::Error> {
-        unimplemented!();
+    pub fn try_update_password(&mut self, which: PasswordType) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::UxUpdatePassword.to_usize().unwrap(),
+            which.to_usize().unwrap(), 0, 0, 0)
+        ).map(|_| ())
     }
 
     /// checks to see if the KEYROM has been initialized, and if not, generates keys. In the process of doing so, the user will be
@@ -271,6 +279,84 @@ impl Drop for RootKeys {
     }
 }
 
+/// Client of the Ed25519 signing oracle. Unlike `RootKeys`, any process can connect to it, because each
+/// app only ever gets its own key: it is derived from the root key and an app id, which must be the name
+/// of a server the calling process registered with xous-names. The signer checks that with the name
+/// server, so no process can ask for another's key, and the key stays the same across boots and builds.
+/// The private keys never leave the root-keys server.
+///
+/// Names are first come, first served, so an app should register its name before untrusted code runs,
+/// and keep it registered.
+#[derive(Debug)]
+pub struct AppSigner {
+    conn: CID,
+}
+impl AppSigner {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        SIGNER_REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_SIGNER).expect("Can't connect to signer server");
+        Ok(AppSigner { conn })
+    }
+
+    /// Signs `msg` with the Ed25519 key of `app_id`, a server name this process registered. Returns the
+    /// signature and the public key it verifies against.
+    ///
+    /// Use of the key is gated by the same password policy as the AES oracle, so the user may be
+    /// prompted for their password.
+    pub fn sign_ed25519(&self, app_id: &str, msg: &[u8]) -> Result<([u8; 64], [u8; 32]), SignerError> {
+        if msg.len() > api::MAX_SIGN_DATA {
+            // like the key wrapper, the signer is meant for challenges and digests, not bulk data
+            return Err(SignerError::TooBig);
+        }
+        let ret = self.signer_op(SignerOp::Sign, app_id, msg)?;
+        Ok((ret.signature, ret.public_key))
+    }
+    /// Returns the Ed25519 public key of `app_id`, a server name this process registered, e.g. to register it
+    /// with a remote service before signing.
+    pub fn ed25519_public_key(&self, app_id: &str) -> Result<[u8; 32], SignerError> {
+        let ret = self.signer_op(SignerOp::PublicKey, app_id, &[])?;
+        Ok(ret.public_key)
+    }
+    fn signer_op(&self, op: SignerOp, app_id: &str, msg: &[u8]) -> Result<Ed25519Signer, SignerError> {
+        if app_id.len() == 0 || app_id.len() > api::MAX_APP_ID_LEN {
+            return Err(SignerError::InvalidAppId);
+        }
+        let mut alloc = Ed25519Signer {
+            data: [0u8; MAX_SIGN_DATA],
+            len: msg.len() as u32,
+            app_id: [0u8; MAX_APP_ID_LEN],
+            app_id_len: app_id.len() as u32,
+            key_index: 0, // chosen by the server
+            op,
+            signature: [0u8; 64],
+            public_key: [0u8; 32],
+            result: Some(SignerError::AuthenticationFailed), // initialize to a default value that throws an error if it wasn't modified by the recipient
+        };
+        for (&src, dst) in msg.iter().zip(alloc.data.iter_mut()) {
+            *dst = src;
+        }
+        for (&src, dst) in app_id.as_bytes().iter().zip(alloc.app_id.iter_mut()) {
+            *dst = src;
+        }
+        let mut buf = Buffer::into_buf(alloc).or(Err(SignerError::AuthenticationFailed))?;
+        buf.lend_mut(self.conn, Opcode::Ed25519Signer.to_u32().unwrap()).or(Err(SignerError::AuthenticationFailed))?;
+        let ret = buf.to_original::<Ed25519Signer, _>().unwrap();
+        match ret.result {
+            None => Ok(ret),
+            Some(err) => Err(err),
+        }
+    }
+}
+
+static SIGNER_REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for AppSigner {
+    fn drop(&mut self) {
+        if SIGNER_REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
+
 impl BlockCipher for RootKeys {
     type BlockSize = U16;   // 128-bit cipher
     // we have to manually match this to PAR_BLOCKS!!
@@ -26,6 +26,7 @@ use std::str;
 mod implementation;
 #[cfg(any(target_os = "none", target_os = "xous"))]
 use implementation::*;
+mod signer;
 /// used by the bbram helper/console protocol to indicate the start of a console message
 const CONSOLE_SENTINEL: &'static str = "CONS_SENTINEL|";
 
@@ -135,6 +136,17 @@ mod implementation {
         pub fn hash_and_save_password(&mut self, pw: &str) {
             log::info!("got password plaintext: {}", pw);
         }
+        pub fn hash_and_save_new_password(&mut self, pw: &str) {
+            log::info!("got new password plaintext: {}", pw);
+        }
+        pub fn confirm_new_password(&mut self, pw: &str) -> bool {
+            log::info!("got new password confirmation plaintext: {}", pw);
+            true
+        }
+        pub fn verify_password(&mut self, pw: &str) -> bool {
+            log::info!("got old password plaintext: {}", pw);
+            true
+        }
         pub fn set_ux_password_type(&mut self, cur_type: Option<PasswordType>) {
             self.password_type = cur_type;
         }
@@ -173,7 +185,11 @@ mod implementation {
         pub fn do_sign_xous(&mut self, rootkeys_modal: &mut Modal, main_cid: xous::CID) -> Result<(), RootkeyResult> {
             self.fake_progress(rootkeys_modal, main_cid, t!("rootkeys.init.signing_kernel", xous::LANG))
         }
+        pub fn do_update_password(&mut self, rootkeys_modal: &mut Modal, main_cid: xous::CID, _pw_type: PasswordType) -> Result<(), RootkeyResult> {
+            self.fake_progress(rootkeys_modal, main_cid, t!("rootkeys.pwchange.starting", xous::LANG))
+        }
         pub fn purge_password(&mut self, _ptype: PasswordType) {}
+        pub fn purge_new_password(&mut self) {}
         pub fn purge_user_password(&mut self, _ptype: AesRootkeyType) {}
 
         pub fn get_ux_password_type(&self) -> Option<PasswordType> {self.password_type}
@@ -198,6 +214,9 @@ mod implementation {
         pub fn is_pcache_boot_password_valid(&self) -> bool {
             true
         }
+        pub fn is_gateware_update_pending(&mut self) -> bool {
+            false
+        }
         pub fn fpga_key_source(&self) -> FpgaKeySource {
             FpgaKeySource::Efuse
         }
@@ -257,6 +276,12 @@ mod implementation {
                 }
             }
         }
+        pub fn signer_op(&mut self, signer: &mut Ed25519Signer) {
+            // fake a "well known" root key by just expanding the index into a trivial key
+            let mut key = [0 as u8; 32];
+            key[0] = signer.key_index;
+            crate::signer::signer_op(&key, signer);
+        }
         pub fn kwp_op(&mut self, kwp: &mut KeyWrapper) {
             let keywrapper = Aes256KeyWrap::new(&[0u8; 32]);
             match kwp.op {
@@ -314,6 +339,10 @@ fn xmain() -> ! {
           3. PDDB
     */
     let keys_sid = xns.register_name(api::SERVER_NAME_KEYS, Some(3)).expect("can't register server");
+    // the signer is open to all: it only ever uses the keys of apps the caller registered the names of
+    let signer_sid = xns.register_name(api::SERVER_NAME_SIGNER, None).expect("can't register signer server");
+    let signer_cid = xous::connect(keys_sid).expect("couldn't connect the signer server");
+    std::thread::spawn(move || signer::signer_server(signer_sid, signer_cid));
 
     let mut keys = RootKeys::new();
     log::info!("Boot FPGA key source: {:?}", keys.fpga_key_source());
@@ -394,6 +423,8 @@ fn xmain() -> ! {
 
     let mut reboot_initiated = false;
     let mut aes_sender: Option<xous::MessageSender> = None;
+    // tracks which password is being changed, as the password type of the UX changes over the course of the flow
+    let mut pw_change_type: Option<PasswordType> = None;
     loop {
         let mut msg = xous::receive_message(keys_sid).unwrap();
         log::debug!("message: {:?}", msg);
@@ -852,6 +883,215 @@ fn xmain() -> ! {
                     }
                 }
             }
+            Some(Opcode::UxUpdatePassword) => msg_scalar_unpack!(msg, pw_type_code, _, _, _, {
+                // overall flow:
+                //  - confirm that the user wants to proceed
+                //  - prompt for the old password, and check it
+                //  - prompt for the new password, twice
+                //  - for boot password changes, prompt for the update password (needed to re-sign the gateware)
+                //  - re-wrap the keys and patch the gateware
+                //  - reboot, so the new KEYROM takes effect
+                let pw_type: PasswordType = match FromPrimitive::from_usize(pw_type_code) {
+                    Some(pt) => pt,
+                    None => {
+                        log::error!("got an invalid password type for update: {}", pw_type_code);
+                        continue;
+                    }
+                };
+                if !keys.is_initialized() {
+                    modals.show_notification(t!("rootkeys.pwchange.not_init", xous::LANG)).expect("modals error");
+                    continue;
+                }
+                if keys.is_gateware_update_pending() {
+                    // the password change stages a backup of the gateware, which would overwrite the update
+                    modals.show_notification(t!("rootkeys.pwchange.update_pending", xous::LANG)).expect("modals error");
+                    continue;
+                }
+                if pw_type == PasswordType::Boot && !keys.is_pcache_boot_password_valid() {
+                    // the boot key has no check value in the KEYROM; the old boot password is checked against the
+                    // copy that was validated when the PDDB was unlocked.
+                    modals.show_notification(t!("rootkeys.pwchange.unlock_first", xous::LANG)).expect("modals error");
+                    continue;
+                }
+                modals.add_list_item(t!("rootkeys.confirm.yes", xous::LANG)).expect("modals error");
+                modals.add_list_item(t!("rootkeys.confirm.no", xous::LANG)).expect("modals error");
+                match modals.get_radiobutton(t!("rootkeys.pwchange.confirm", xous::LANG)) {
+                    Ok(response) => {
+                        if response == t!("rootkeys.confirm.no", xous::LANG) {
+                            continue;
+                        } else if response != t!("rootkeys.confirm.yes", xous::LANG) {
+                            log::error!("Got unexpected response: {:?}", response);
+                            continue;
+                        } else {
+                            // do nothing, this is the forward path
+                        }
+                    }
+                    _ => {
+                        log::error!("get_radiobutton failed");
+                        continue;
+                    }
+                }
+                pw_change_type = Some(pw_type);
+                keys.set_ux_password_type(Some(pw_type));
+                let prompt = match pw_type {
+                    PasswordType::Boot => t!("rootkeys.pwchange.old_boot", xous::LANG),
+                    PasswordType::Update => t!("rootkeys.pwchange.old_update", xous::LANG),
+                };
+                password_action.set_action_opcode(Opcode::UxUpdatePasswordOldReturn.to_u32().unwrap());
+                rootkeys_modal.modify(
+                    Some(ActionType::TextEntry(password_action)),
+                    Some(prompt), false,
+                    None, true, None
+                );
+                #[cfg(feature="tts")]
+                tts.tts_blocking(prompt).unwrap();
+                rootkeys_modal.activate();
+            }),
+            Some(Opcode::UxUpdatePasswordOldReturn) => {
+                let mut buf = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let mut plaintext_pw = buf.to_original::<gam::modal::TextEntryPayload, _>().unwrap();
+
+                let valid = keys.verify_password(plaintext_pw.as_str());
+                plaintext_pw.volatile_clear(); // ensure the data is destroyed after sending to the keys enclave
+                buf.volatile_clear();
+
+                let pw_type = if let Some(pt) = pw_change_type {
+                    pt
+                } else {
+                    log::error!("got an old password without a password change in progress");
+                    keys.set_ux_password_type(None);
+                    continue;
+                };
+                if !valid {
+                    pw_change_type = None;
+                    keys.set_ux_password_type(None);
+                    modals.show_notification(t!("rootkeys.pwchange.wrong_password", xous::LANG)).expect("modals error");
+                    continue;
+                }
+                let prompt = match pw_type {
+                    PasswordType::Boot => t!("rootkeys.pwchange.new_boot", xous::LANG),
+                    PasswordType::Update => t!("rootkeys.pwchange.new_update", xous::LANG),
+                };
+                password_action.set_action_opcode(Opcode::UxUpdatePasswordNewReturn.to_u32().unwrap());
+                rootkeys_modal.modify(
+                    Some(ActionType::TextEntry(password_action)),
+                    Some(prompt), false,
+                    None, true, None
+                );
+                #[cfg(feature="tts")]
+                tts.tts_blocking(prompt).unwrap();
+                rootkeys_modal.activate();
+            }
+            Some(Opcode::UxUpdatePasswordNewReturn) => {
+                let mut buf = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let mut plaintext_pw = buf.to_original::<gam::modal::TextEntryPayload, _>().unwrap();
+
+                keys.hash_and_save_new_password(plaintext_pw.as_str());
+                plaintext_pw.volatile_clear(); // ensure the data is destroyed after sending to the keys enclave
+                buf.volatile_clear();
+
+                password_action.set_action_opcode(Opcode::UxUpdatePasswordConfirmReturn.to_u32().unwrap());
+                rootkeys_modal.modify(
+                    Some(ActionType::TextEntry(password_action)),
+                    Some(t!("rootkeys.pwchange.confirm_new", xous::LANG)), false,
+                    None, true, None
+                );
+                #[cfg(feature="tts")]
+                tts.tts_blocking(t!("rootkeys.pwchange.confirm_new", xous::LANG)).unwrap();
+                rootkeys_modal.activate();
+            }
+            Some(Opcode::UxUpdatePasswordConfirmReturn) => {
+                let mut buf = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let mut plaintext_pw = buf.to_original::<gam::modal::TextEntryPayload, _>().unwrap();
+
+                let matches = keys.confirm_new_password(plaintext_pw.as_str());
+                plaintext_pw.volatile_clear(); // ensure the data is destroyed after sending to the keys enclave
+                buf.volatile_clear();
+
+                if !matches {
+                    // the staged password was purged; ask for the new password again
+                    modals.show_notification(t!("rootkeys.pwchange.mismatch", xous::LANG)).expect("modals error");
+                    let prompt = match pw_change_type {
+                        Some(PasswordType::Boot) => t!("rootkeys.pwchange.new_boot", xous::LANG),
+                        _ => t!("rootkeys.pwchange.new_update", xous::LANG),
+                    };
+                    password_action.set_action_opcode(Opcode::UxUpdatePasswordNewReturn.to_u32().unwrap());
+                    rootkeys_modal.modify(
+                        Some(ActionType::TextEntry(password_action)),
+                        Some(prompt), false,
+                        None, true, None
+                    );
+                    #[cfg(feature="tts")]
+                    tts.tts_blocking(prompt).unwrap();
+                    rootkeys_modal.activate();
+                    continue;
+                }
+
+                if keys.is_pcache_update_password_valid() {
+                    send_message(main_cid,
+                        xous::Message::new_scalar(Opcode::UxUpdatePasswordRun.to_usize().unwrap(), 0, 0, 0, 0)
+                    ).expect("couldn't initiate password change");
+                } else {
+                    keys.set_ux_password_type(Some(PasswordType::Update));
+                    password_action.set_action_opcode(Opcode::UxUpdatePasswordSigningReturn.to_u32().unwrap());
+                    rootkeys_modal.modify(
+                        Some(ActionType::TextEntry(password_action)),
+                        Some(t!("rootkeys.get_signing_password", xous::LANG)), false,
+                        None, true, None
+                    );
+                    #[cfg(feature="tts")]
+                    tts.tts_blocking(t!("rootkeys.get_signing_password", xous::LANG)).unwrap();
+                    rootkeys_modal.activate();
+                }
+            }
+            Some(Opcode::UxUpdatePasswordSigningReturn) => {
+                let mut buf = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let mut plaintext_pw = buf.to_original::<gam::modal::TextEntryPayload, _>().unwrap();
+
+                keys.hash_and_save_password(plaintext_pw.as_str());
+                plaintext_pw.volatile_clear(); // ensure the data is destroyed after sending to the keys enclave
+                buf.volatile_clear();
+
+                send_message(main_cid,
+                    xous::Message::new_scalar(Opcode::UxUpdatePasswordRun.to_usize().unwrap(), 0, 0, 0, 0)
+                ).expect("couldn't initiate password change");
+            }
+            Some(Opcode::UxUpdatePasswordRun) => msg_scalar_unpack!(msg, _, _, _, _, {
+                keys.set_ux_password_type(None);
+                let pw_type = if let Some(pt) = pw_change_type.take() {
+                    pt
+                } else {
+                    log::error!("password change run requested without a password change in progress");
+                    keys.purge_new_password();
+                    continue;
+                };
+
+                let result = keys.do_update_password(&mut rootkeys_modal, main_cid, pw_type);
+                // the stop emoji, when sent to the slider action bar in progress mode, will cause it to close and relinquish focus
+                rootkeys_modal.key_event(['🛑', '\u{0000}', '\u{0000}', '\u{0000}']);
+
+                match result {
+                    Ok(_) => {
+                        modals.show_notification(t!("rootkeys.pwchange.finished", xous::LANG)).expect("modals error");
+                        // the new password only takes effect once the patched KEYROM is loaded
+                        send_message(main_cid,
+                            xous::Message::new_scalar(Opcode::UxTryReboot.to_usize().unwrap(), 0, 0, 0, 0)
+                        ).expect("couldn't initiate dialog box");
+                    }
+                    Err(RootkeyResult::AlignmentError) => {
+                        modals.show_notification(t!("rootkeys.init.fail_alignment", xous::LANG)).expect("modals error");
+                    }
+                    Err(RootkeyResult::KeyError) => {
+                        modals.show_notification(t!("rootkeys.init.fail_key", xous::LANG)).expect("modals error");
+                    }
+                    Err(RootkeyResult::IntegrityError) => {
+                        modals.show_notification(t!("rootkeys.init.fail_verify", xous::LANG)).expect("modals error");
+                    }
+                    Err(RootkeyResult::FlashError) => {
+                        modals.show_notification(t!("rootkeys.init.fail_burn", xous::LANG)).expect("modals error");
+                    }
+                }
+            }),
             Some(Opcode::UxAesEnsurePassword) => msg_blocking_scalar_unpack!(msg, key_index, _, _, _, {
                 if key_index as u8 == AesRootkeyType::User0.to_u8().unwrap() {
                     if keys.is_pcache_boot_password_valid() {
@@ -976,6 +1216,19 @@ fn xmain() -> ! {
                 keys.kwp_op(&mut kwp);
                 buffer.replace(kwp).unwrap();
             }
+            Some(Opcode::Ed25519Signer) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut signer = buffer.to_original::<Ed25519Signer, _>().unwrap();
+                // only the signer server's thread can vouch for the caller's PID
+                if msg.sender.pid().map(|pid| pid.get() as u32) != Some(xous::process::id()) {
+                    log::error!("Ed25519Signer request didn't come through the signer server");
+                    signer.result = Some(SignerError::AuthenticationFailed);
+                    buffer.replace(signer).unwrap();
+                    continue;
+                }
+                keys.signer_op(&mut signer);
+                buffer.replace(signer).unwrap();
+            }
 
             Some(Opcode::BbramProvision) => {
                 modals.show_notification(t!("rootkeys.bbram.confirm", xous::LANG)).expect("modals error");
@@ -0,0 +1,172 @@
+//! Ed25519 signing oracle. Each app gets its own signing key, derived from a root key and the name
+//! of a server the app registered with xous-names, so the private keys never have to leave the
+//! root-keys server, and no process can sign with another's key. Unlike PIDs, which depend on load
+//! order, the name stays the same across boots and builds, and the name server vouches for it.
+//!
+//! The scalar multiplications in key derivation and signing are dispatched by the `betrusted`
+//! backend of curve25519-dalek to the `engine-25519` hardware accelerator.
+
+use crate::api::*;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use sha2::{FallbackStrategy, Sha512Trunc256};
+use digest::Digest;
+
+/// Domain separator for app signing keys, so they can never collide with a key derived from the
+/// same root key for a different purpose.
+const APP_KEY_DOMAIN: &[u8] = b"xous rootkeys ed25519 app key v1";
+
+/// Derives the Ed25519 keypair for the app known by the server name `app_id` from a (rolled-back) root key.
+fn derive_app_keypair(root_key: &[u8; 32], app_id: &[u8]) -> Keypair {
+    // for such a small hash, software is the most performant choice
+    let mut hasher = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+    hasher.update(APP_KEY_DOMAIN);
+    hasher.update(root_key);
+    hasher.update(app_id);
+    let digest = hasher.finalize();
+    let sk = SecretKey::from_bytes(&digest).expect("couldn't construct secret key");
+    let pk: PublicKey = (&sk).into();
+    // keypair zeroizes on drop
+    Keypair{public: pk, secret: sk}
+}
+
+/// Performs the operation requested by `signer` with the app key derived from `root_key`. The signer
+/// server has already checked that the caller registered `signer.app_id`.
+/// Results are written back into `signer`.
+pub(crate) fn signer_op(root_key: &[u8; 32], signer: &mut Ed25519Signer) {
+    if signer.app_id_len == 0 || signer.app_id_len as usize > MAX_APP_ID_LEN {
+        signer.result = Some(SignerError::InvalidAppId);
+        return;
+    }
+    if signer.len as usize > MAX_SIGN_DATA {
+        signer.result = Some(SignerError::TooBig);
+        return;
+    }
+    let keypair = derive_app_keypair(root_key, &signer.app_id[..signer.app_id_len as usize]);
+    signer.public_key = keypair.public.to_bytes();
+    match signer.op {
+        SignerOp::Sign => {
+            signer.signature = keypair.sign(&signer.data[..signer.len as usize]).to_bytes();
+        }
+        SignerOp::PublicKey => (),
+    }
+    signer.result = None;
+}
+
+/// Serves the signer server, which unlike the keys server takes connections from any process. Each
+/// request is checked with the name server, to make sure its sender registered the server name it
+/// asks for a key for, then against the boot password policy, and then forwarded to the keys
+/// server, which holds the root key.
+pub(crate) fn signer_server(signer_sid: xous::SID, keys_cid: xous::CID) {
+    use num_traits::*;
+    use xous_ipc::Buffer;
+    let xns = xous_names::XousNames::new().unwrap();
+    loop {
+        let mut msg = xous::receive_message(signer_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Ed25519Signer) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut signer = buffer.to_original::<Ed25519Signer, _>().unwrap();
+                signer.key_index = AesRootkeyType::User0.to_u8().unwrap();
+                let app_id = signer.app_id.get(..signer.app_id_len as usize).unwrap_or(&[]);
+                let registered = match (msg.sender.pid(), core::str::from_utf8(app_id)) {
+                    (Some(pid), Ok(name)) if !name.is_empty() => xns.is_registrant(name, pid).unwrap_or(false),
+                    _ => false,
+                };
+                if !registered {
+                    log::warn!("signer request for an app id its sender didn't register, refusing");
+                    signer.result = Some(SignerError::InvalidAppId);
+                    buffer.replace(signer).unwrap();
+                    continue;
+                }
+                signer.result = Some(SignerError::AuthenticationFailed);
+                let ensured = xous::send_message(keys_cid,
+                    xous::Message::new_blocking_scalar(Opcode::UxAesEnsurePassword.to_usize().unwrap(),
+                    AesRootkeyType::User0.to_usize().unwrap(), 0, 0, 0)
+                );
+                if let Ok(xous::Result::Scalar1(1)) = ensured {
+                    let mut fwd = Buffer::into_buf(signer).expect("couldn't forward signer request");
+                    fwd.lend_mut(keys_cid, Opcode::Ed25519Signer.to_u32().unwrap()).expect("couldn't forward signer request");
+                    signer = fwd.to_original::<Ed25519Signer, _>().unwrap();
+                } else {
+                    log::error!("couldn't confirm the boot password, refusing to sign");
+                }
+                buffer.replace(signer).unwrap();
+            }
+            _ => log::error!("unknown signer opcode: {:?}", msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Verifier;
+
+    fn request(op: SignerOp, app_id: &[u8], data: &[u8]) -> Ed25519Signer {
+        let mut signer = Ed25519Signer {
+            data: [0u8; MAX_SIGN_DATA],
+            len: data.len() as u32,
+            app_id: [0u8; MAX_APP_ID_LEN],
+            app_id_len: app_id.len() as u32,
+            key_index: 0,
+            op,
+            signature: [0u8; 64],
+            public_key: [0u8; 32],
+            result: Some(SignerError::AuthenticationFailed),
+        };
+        signer.data[..data.len()].copy_from_slice(data);
+        signer.app_id[..app_id.len()].copy_from_slice(app_id);
+        signer
+    }
+
+    #[test]
+    fn test_app_key_is_stable() {
+        let a = derive_app_keypair(&[1u8; 32], b"vault");
+        let b = derive_app_keypair(&[1u8; 32], b"vault");
+        assert_eq!(a.public, b.public);
+    }
+
+    #[test]
+    fn test_app_keys_are_distinct() {
+        let vault = derive_app_keypair(&[1u8; 32], b"vault").public;
+        assert_ne!(vault, derive_app_keypair(&[1u8; 32], b"vault2").public);
+        assert_ne!(vault, derive_app_keypair(&[2u8; 32], b"vault").public);
+    }
+
+    #[test]
+    fn test_sign_verifies_under_returned_key() {
+        let mut signer = request(SignerOp::Sign, b"vault", b"challenge");
+        signer_op(&[1u8; 32], &mut signer);
+        assert_eq!(signer.result, None);
+        assert_eq!(signer.public_key, derive_app_keypair(&[1u8; 32], b"vault").public.to_bytes());
+        let public = PublicKey::from_bytes(&signer.public_key).unwrap();
+        let signature = ed25519_dalek::Signature::from(signer.signature);
+        assert!(public.verify(b"challenge", &signature).is_ok());
+        assert!(public.verify(b"challenge!", &signature).is_err());
+
+        let mut lookup = request(SignerOp::PublicKey, b"vault", &[]);
+        signer_op(&[1u8; 32], &mut lookup);
+        assert_eq!(lookup.result, None);
+        assert_eq!(lookup.public_key, signer.public_key);
+    }
+
+    #[test]
+    fn test_rejects_bad_app_id() {
+        let mut empty = request(SignerOp::Sign, b"", b"challenge");
+        signer_op(&[1u8; 32], &mut empty);
+        assert_eq!(empty.result, Some(SignerError::InvalidAppId));
+
+        let mut long = request(SignerOp::Sign, b"vault", b"challenge");
+        long.app_id_len = MAX_APP_ID_LEN as u32 + 1;
+        signer_op(&[1u8; 32], &mut long);
+        assert_eq!(long.result, Some(SignerError::InvalidAppId));
+    }
+
+    #[test]
+    fn test_rejects_too_big() {
+        let mut big = request(SignerOp::Sign, b"vault", b"challenge");
+        big.len = MAX_SIGN_DATA as u32 + 1;
+        signer_op(&[1u8; 32], &mut big);
+        assert_eq!(big.result, Some(SignerError::TooBig));
+    }
+}
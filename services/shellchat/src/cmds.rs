@@ -98,6 +98,10 @@ mod wlan;     use wlan::*;
 mod jtag_cmd; use jtag_cmd::*;
 mod net_cmd;  use net_cmd::*;
 mod pddb_cmd; use pddb_cmd::*;
+mod log_cmd;  use log_cmd::*;
+mod crashlog; use crashlog::*;
+mod bind;     use bind::*;
+mod names;    use names::*;
 
 #[cfg(feature="tts")]
 mod tts;
@@ -138,6 +142,9 @@ pub struct CmdEnv {
     jtag_cmd: JtagCmd,
     net_cmd: NetCmd,
     pddb_cmd: PddbCmd,
+    crashlog_cmd: CrashLog,
+    log_cmd: LogCmd,
+    bind_cmd: Bind,
     wlan_cmd: Wlan,
 
     #[cfg(feature="tts")]
@@ -206,6 +213,9 @@ impl CmdEnv {
             jtag_cmd: JtagCmd::new(&xns),
             net_cmd: NetCmd::new(&xns),
             pddb_cmd: PddbCmd::new(&xns),
+            crashlog_cmd: CrashLog::new(),
+            log_cmd: LogCmd::new(),
+            bind_cmd: Bind::new(),
             wlan_cmd: Wlan::new(),
 
             #[cfg(feature="tts")]
@@ -229,6 +239,7 @@ impl CmdEnv {
         let mut backlight_cmd = Backlight{};
         let mut accel_cmd = Accel{};
         let mut console_cmd = Console{};
+        let mut names_cmd = Names{};
         let commands: &mut [& mut dyn ShellCmdApi] = &mut [
             ///// 4. add your command to this array, so that it can be looked up and dispatched
             &mut echo_cmd,
@@ -252,6 +263,10 @@ impl CmdEnv {
             &mut self.jtag_cmd,
             &mut self.net_cmd,
             &mut self.pddb_cmd,
+            &mut self.log_cmd,
+            &mut self.crashlog_cmd,
+            &mut self.bind_cmd,
+            &mut names_cmd,
 
             #[cfg(feature="tts")]
             &mut self.tts_cmd,
@@ -0,0 +1,106 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+use std::io::{Read, Write};
+use keyboard::{KeyBinding, BINDINGS_DICT};
+
+/// Edits the key bindings kept in the PDDB; the status bar watches them and registers them with the
+/// keyboard. A binding is given on one line, in the form read by `KeyBinding::parse()` with `|` in place
+/// of its line breaks, e.g. `bind set hello chord 1,0 1,1 | text | Hello, world!`
+pub struct Bind {
+    pddb: pddb::Pddb,
+}
+impl Bind {
+    pub fn new() -> Bind {
+        Bind {
+            pddb: pddb::Pddb::new(),
+        }
+    }
+
+    fn load(&mut self, name: &str) -> Option<std::string::String> {
+        let mut definition = std::string::String::new();
+        match self.pddb.get(BINDINGS_DICT, name, None, false, false, None, None::<fn()>) {
+            Ok(mut key) => key.read_to_string(&mut definition).ok()?,
+            Err(_) => return None,
+        };
+        Some(definition)
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for Bind {
+    cmd_api!(bind); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "bind options: list, show [name], set [name] [trigger] | [action] | [text or server], delete [name]";
+
+        let mut tokens = args.as_str().unwrap().splitn(3, ' ');
+
+        if let Some(sub_cmd) = tokens.next() {
+            match sub_cmd {
+                "list" | "" => {
+                    let mut names = self.pddb.list_keys(BINDINGS_DICT, None).unwrap_or(Vec::new());
+                    names.sort();
+                    if names.len() == 0 {
+                        write!(ret, "No key bindings").unwrap();
+                    }
+                    for name in names.iter() {
+                        let definition = self.load(name).unwrap_or_default();
+                        if write!(ret, "{}: {}\n", name, definition.lines().next().unwrap_or("")).is_err() {
+                            break;
+                        }
+                    }
+                }
+                "show" => {
+                    match tokens.next().and_then(|name| self.load(name)) {
+                        Some(definition) => write!(ret, "{}", definition.replace('\n', " | ")).ok(), // truncate if needed
+                        None => write!(ret, "No such key binding").ok(),
+                    };
+                }
+                "set" => {
+                    let (name, line) = match (tokens.next(), tokens.next()) {
+                        (Some(name), Some(line)) if name.len() > 0 => (name, line),
+                        _ => {
+                            write!(ret, "{}", helpstring).unwrap();
+                            return Ok(Some(ret));
+                        }
+                    };
+                    let definition = line.splitn(3, '|').map(|l| l.trim()).collect::<Vec<&str>>().join("\n");
+                    if KeyBinding::parse(&definition).is_none() {
+                        write!(ret, "Couldn't parse key binding: {}", line).ok();
+                        return Ok(Some(ret));
+                    }
+                    // replace, rather than write over, any binding of the same name
+                    self.pddb.delete_key(BINDINGS_DICT, name, None).ok();
+                    match self.pddb.get(BINDINGS_DICT, name, None, true, true, Some(definition.len()), None::<fn()>) {
+                        Ok(mut key) => match key.write_all(definition.as_bytes()) {
+                            Ok(()) => {
+                                self.pddb.sync().ok();
+                                write!(ret, "Key binding {} set", name).unwrap();
+                            }
+                            Err(e) => write!(ret, "Couldn't write key binding: {:?}", e).unwrap(),
+                        },
+                        Err(e) => write!(ret, "Couldn't create key binding: {:?}", e).unwrap(),
+                    }
+                }
+                "delete" => {
+                    let name = tokens.next().unwrap_or("");
+                    match self.pddb.delete_key(BINDINGS_DICT, name, None) {
+                        Ok(()) => {
+                            self.pddb.sync().ok();
+                            write!(ret, "Key binding {} deleted", name).unwrap();
+                        }
+                        Err(e) => write!(ret, "Couldn't delete key binding {}: {:?}", name, e).unwrap(),
+                    }
+                }
+                _ => {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+
+        } else {
+            write!(ret, "{}", helpstring).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+use std::io::Read;
+use log_server::{api::CrashRecord, CRASHLOG_DICT};
+use pddb::PDDB_DEFAULT_SYSTEM_BASIS;
+
+pub struct CrashLog {
+    pddb: pddb::Pddb,
+}
+impl CrashLog {
+    pub fn new() -> CrashLog {
+        CrashLog {
+            pddb: pddb::Pddb::new(),
+        }
+    }
+
+    /// Returns the names of the saved records, oldest first.
+    fn list(&mut self) -> Vec<std::string::String> {
+        let mut keys = self.pddb.list_keys(CRASHLOG_DICT, Some(PDDB_DEFAULT_SYSTEM_BASIS)).unwrap_or(Vec::new());
+        keys.sort();
+        keys
+    }
+
+    fn load(&mut self, key: &str) -> Option<CrashRecord> {
+        let mut data = Vec::new();
+        match self.pddb.get(CRASHLOG_DICT, key, Some(PDDB_DEFAULT_SYSTEM_BASIS), false, false, None, None::<fn()>) {
+            Ok(mut k) => k.read_to_end(&mut data).ok()?,
+            Err(_) => return None,
+        };
+        CrashRecord::from_bytes(&data)
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for CrashLog {
+    cmd_api!(crashlog); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "crashlog options: list, show [n], log [n], clear";
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+
+        if let Some(sub_cmd) = tokens.next() {
+            match sub_cmd {
+                "list" | "" => {
+                    // records that haven't been saved yet are only held in RAM by the log server
+                    let mut after = 0;
+                    let mut held = 0;
+                    while let Ok(Some(record)) = log_server::next_crash_record(after) {
+                        after = record.valid;
+                        held += 1;
+                        let message = std::string::String::from_utf8_lossy(record.message());
+                        if write!(ret, "unsaved: {} (PID {}) {}\n",
+                            record.name(), record.pid, message.lines().next().unwrap_or("")
+                        ).is_err() {
+                            break;
+                        }
+                    }
+                    let keys = self.list();
+                    if keys.len() == 0 && held == 0 {
+                        write!(ret, "No crashes recorded").unwrap();
+                    }
+                    for key in keys.iter() {
+                        if let Some(record) = self.load(key) {
+                            let message = std::string::String::from_utf8_lossy(record.message());
+                            if write!(ret, "{}: {} (PID {}) {}\n",
+                                key.parse::<u32>().unwrap_or(0), record.name(), record.pid,
+                                message.lines().next().unwrap_or("")
+                            ).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+                "show" | "log" => {
+                    // the most recent record unless one is named
+                    let key = match tokens.next() {
+                        Some(n) => match n.parse::<u32>() {
+                            Ok(n) => Some(format!("{:08}", n)),
+                            Err(_) => None,
+                        },
+                        None => self.list().pop(),
+                    };
+                    let record = match key.as_ref().and_then(|k| self.load(k)) {
+                        Some(record) => record,
+                        None => {
+                            write!(ret, "No such crash record").unwrap();
+                            return Ok(Some(ret));
+                        }
+                    };
+                    if sub_cmd == "show" {
+                        write!(ret, "{} PID {}", record.name(), record.pid).unwrap();
+                        if record.tid != 0 {
+                            write!(ret, " TID {}", record.tid).unwrap();
+                        }
+                        if record.timestamp != 0 {
+                            if let Some(dt) = chrono::NaiveDateTime::from_timestamp_opt(record.timestamp as i64, 0) {
+                                write!(ret, " at {} UTC", dt.format("%Y-%m-%d %H:%M:%S")).unwrap();
+                            }
+                        }
+                        write!(ret, "\n{}", std::string::String::from_utf8_lossy(record.message())).ok(); // truncate if needed
+                    } else {
+                        // as much of the end of the log as fits
+                        let log = record.log();
+                        let start = log.len().saturating_sub(1000);
+                        write!(ret, "{}", std::string::String::from_utf8_lossy(&log[start..])).ok(); // truncate if needed
+                    }
+                }
+                "clear" => {
+                    match self.pddb.delete_dict(CRASHLOG_DICT, Some(PDDB_DEFAULT_SYSTEM_BASIS)) {
+                        Ok(()) => {
+                            self.pddb.sync().ok();
+                            write!(ret, "Crash records cleared").unwrap();
+                        }
+                        Err(e) => write!(ret, "Couldn't clear crash records: {:?}", e).unwrap(),
+                    }
+                }
+                _ => {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+
+        } else {
+            write!(ret, "{}", helpstring).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
@@ -8,6 +8,7 @@ pub struct Keys {
     testing_range: xous::MemoryRange,
     spinor: spinor::Spinor,
     rootkeys: root_keys::RootKeys,
+    signer: root_keys::AppSigner,
 }
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -15,6 +16,7 @@ pub struct Keys {
 pub struct Keys {
     spinor: spinor::Spinor,
     rootkeys: root_keys::RootKeys,
+    signer: root_keys::AppSigner,
 }
 #[cfg(feature="spinortest")]
 const TEST_SIZE: usize = 0x4000;
@@ -47,12 +49,14 @@ impl Keys {
             testing_range,
             spinor,
             rootkeys: root_keys::RootKeys::new(&xns, Some(AesRootkeyType::User0)).expect("couldn't allocate rootkeys API"),
+            signer: root_keys::AppSigner::new(&xns).expect("couldn't allocate signer API"),
         };
 
         #[cfg(not(feature="spinortest"))]
         let keys = Keys {
             spinor,
             rootkeys: root_keys::RootKeys::new(&xns, Some(AesRootkeyType::User0)).expect("couldn't allocate rootkeys API"),
+            signer: root_keys::AppSigner::new(&xns).expect("couldn't allocate signer API"),
         };
         keys
     }
@@ -64,7 +68,7 @@ impl<'a> ShellCmdApi<'a> for Keys {
     fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
-        let helpstring = "keys [usblock] [usbunlock] [pddbrecycle]";
+        let helpstring = "keys [usblock] [usbunlock] [pddbrecycle] [sign]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -106,6 +110,26 @@ impl<'a> ShellCmdApi<'a> for Keys {
                         write!(ret, "aes test failed").unwrap();
                     }
                 }
+                "sign" => {
+                    use ed25519_dalek::{PublicKey, Signature, Verifier};
+                    let msg = "whiskey made me do it";
+                    // the key belongs to the server name shellchat registered
+                    match self.signer.sign_ed25519(crate::SERVER_NAME_SHELLCHAT, msg.as_bytes()) {
+                        Ok((sig, pubkey)) => {
+                            let pk = PublicKey::from_bytes(&pubkey).expect("public key was not valid");
+                            let sig = Signature::new(sig);
+                            let pubkey_again = self.signer.ed25519_public_key(crate::SERVER_NAME_SHELLCHAT);
+                            if pk.verify(msg.as_bytes(), &sig).is_ok() && pubkey_again == Ok(pubkey) {
+                                write!(ret, "ed25519 sign test passed").unwrap();
+                            } else {
+                                write!(ret, "ed25519 sign test failed").unwrap();
+                            }
+                        }
+                        Err(e) => {
+                            write!(ret, "ed25519 sign error: {:?}", e).unwrap();
+                        }
+                    }
+                }
                 "usblock" => {
                     env.llio.debug_usb(Some(true)).unwrap();
                     write!(ret, "USB debug port locked out; one word at 0x80000000 is disclosable via USB.").unwrap();
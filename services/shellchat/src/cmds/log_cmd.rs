@@ -0,0 +1,98 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+/// Bytes of log output shown per page, leaving room in the response for the header line.
+const PAGE_LEN: usize = 900;
+
+#[derive(Debug)]
+pub struct LogCmd {
+}
+impl LogCmd {
+    /// Claims control of the log server, which only the first process to ask gets. This is created
+    /// while the shell boots, so that no untrusted code can get there first.
+    pub fn new() -> LogCmd {
+        if log_server::claim_control().is_err() {
+            log::error!("couldn't claim control of the log server, so log levels can't be set or the log read back");
+        }
+        LogCmd {}
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for LogCmd {
+    cmd_api!(log); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "log options: level [program] [off|error|warn|info|debug|trace|default], dump [page], panic [page]";
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+
+        if let Some(sub_cmd) = tokens.next() {
+            match sub_cmd {
+                "level" => {
+                    let program = tokens.next();
+                    let level = match tokens.next() {
+                        Some("off") => Some(Some(log::LevelFilter::Off)),
+                        Some("error") => Some(Some(log::LevelFilter::Error)),
+                        Some("warn") => Some(Some(log::LevelFilter::Warn)),
+                        Some("info") => Some(Some(log::LevelFilter::Info)),
+                        Some("debug") => Some(Some(log::LevelFilter::Debug)),
+                        Some("trace") => Some(Some(log::LevelFilter::Trace)),
+                        Some("default") => Some(None),
+                        _ => None,
+                    };
+                    match (program, level) {
+                        (Some(program), Some(level)) => {
+                            match log_server::set_level(program, level) {
+                                Ok(()) => match level {
+                                    Some(level) => write!(ret, "{} log level set to {}", program, level).unwrap(),
+                                    None => write!(ret, "{} log level no longer set", program).unwrap(),
+                                },
+                                Err(e) => write!(ret, "Couldn't set log level: {:?}", e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "{}", helpstring).unwrap(),
+                    }
+                }
+                "dump" | "panic" => {
+                    // page 0 is the most recent output, page 1 the output before that, and so on
+                    let page = match tokens.next() {
+                        Some(page) => match page.parse::<usize>() {
+                            Ok(page) => page,
+                            Err(_) => {
+                                write!(ret, "{}", helpstring).unwrap();
+                                return Ok(Some(ret));
+                            }
+                        },
+                        None => 0,
+                    };
+                    let panic_snapshot = sub_cmd == "panic";
+                    let mut data = [0u8; PAGE_LEN];
+                    // the first read only finds out how much there is
+                    let (_, total) = log_server::read_ring(panic_snapshot, usize::MAX, &mut data)?;
+                    if total == 0 {
+                        write!(ret, "No log output recorded").unwrap();
+                        return Ok(Some(ret));
+                    }
+                    let end = total.saturating_sub(page * PAGE_LEN);
+                    if end == 0 {
+                        write!(ret, "Only {} pages recorded", (total + PAGE_LEN - 1) / PAGE_LEN).unwrap();
+                        return Ok(Some(ret));
+                    }
+                    let start = end.saturating_sub(PAGE_LEN);
+                    let (len, _) = log_server::read_ring(panic_snapshot, start, &mut data[..end - start])?;
+                    write!(ret, "Log bytes {}-{} of {}:\n", start, start + len, total).unwrap();
+                    write!(ret, "{}", std::string::String::from_utf8_lossy(&data[..len])).ok(); // truncate if needed
+                }
+                _ => {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+
+        } else {
+            write!(ret, "{}", helpstring).unwrap();
+        }
+        Ok(Some(ret))
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+/// Servers shown per page; each takes a line, and the PID lists can make the lines long.
+const PAGE_LEN: usize = 8;
+
+/// The throwaway server registered by `names auth`.
+const AUTH_TEST_SERVER: &str = "_names auth test_";
+
+/// Checks an Ed25519 signature for `register_name_authenticated`. The curve arithmetic runs on
+/// the 25519 engine, through our fork of curve25519-dalek.
+pub fn ed25519_verify(pubkey: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    use ed25519_dalek::{PublicKey, Signature};
+    match PublicKey::from_bytes(pubkey) {
+        Ok(public) => public.verify_strict(message, &Signature::from(*signature)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Signs a challenge for `request_authenticated_connection`.
+pub fn ed25519_signer(keypair: &ed25519_dalek::Keypair) -> impl FnOnce(&[u8]) -> [u8; 64] + '_ {
+    use ed25519_dalek::Signer;
+    move |message| keypair.sign(message).to_bytes()
+}
+
+#[derive(Debug)]
+pub struct Names {
+}
+
+impl<'a> ShellCmdApi<'a> for Names {
+    cmd_api!(names); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "names [page], names [server]: list servers as name conns/limit [+auth conns] pids\nnames auth: self-test authenticated connections";
+
+        if args.as_str().unwrap().trim() == "auth" {
+            self.auth_test(env, &mut ret)?;
+            return Ok(Some(ret));
+        }
+
+        let servers = match env.xns.directory() {
+            Ok(servers) => servers,
+            Err(xous::Error::AccessDenied) => {
+                write!(ret, "Name server introspection is disabled on this build").unwrap();
+                return Ok(Some(ret));
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+        let (page, filter) = match tokens.next() {
+            Some("help") => {
+                write!(ret, "{}", helpstring).unwrap();
+                return Ok(Some(ret));
+            }
+            Some(arg) if !arg.is_empty() => match arg.parse::<usize>() {
+                Ok(page) => (page, None),
+                Err(_) => (0, Some(arg)),
+            },
+            _ => (0, None),
+        };
+
+        let matching: Vec<&xous_names::api::ServerEntry> = servers.iter()
+            .filter(|s| filter.map_or(true, |f| s.name.as_str().unwrap_or("").contains(f)))
+            .collect();
+        let pages = (matching.len() + PAGE_LEN - 1) / PAGE_LEN;
+        if matching.is_empty() {
+            write!(ret, "No matching servers").unwrap();
+            return Ok(Some(ret));
+        }
+        if page >= pages {
+            write!(ret, "Only {} pages of servers", pages).unwrap();
+            return Ok(Some(ret));
+        }
+        write!(ret, "{} servers, page {} of {}:\n", matching.len(), page, pages).unwrap();
+        for server in matching.iter().skip(page * PAGE_LEN).take(PAGE_LEN) {
+            write!(ret, "{} {}/", server.name, server.conns).ok();
+            match server.conn_limit {
+                Some(limit) => write!(ret, "{}", limit).ok(),
+                None => write!(ret, "-").ok(),
+            };
+            if server.authenticated {
+                write!(ret, " +auth {}", server.auth_conns).ok();
+            }
+            write!(ret, " [").ok();
+            for (i, pid) in server.pids().enumerate() {
+                if i > 0 {
+                    write!(ret, ",").ok();
+                }
+                write!(ret, "{}", pid).ok();
+            }
+            if server.pids_truncated {
+                write!(ret, ",...").ok();
+            }
+            write!(ret, "]\n").ok(); // truncate if needed
+        }
+        Ok(Some(ret))
+    }
+}
+
+impl Names {
+    /// Registers a server that only takes authenticated connections, and checks that the name
+    /// server hands one out for a signature under the allowed key, and for nothing else.
+    fn auth_test(&mut self, env: &mut CommonEnv, ret: &mut String::<1024>) -> Result<(), xous::Error> {
+        use core::fmt::Write;
+        use ed25519_dalek::{Keypair, Signer};
+        let allowed = Keypair::generate(&mut env.trng);
+        let stranger = Keypair::generate(&mut env.trng);
+        let sid = env.xns.register_name_authenticated(
+            AUTH_TEST_SERVER, Some(0), vec![allowed.public.to_bytes()], ed25519_verify)?;
+
+        let mut passed = true;
+        match env.xns.request_authenticated_connection(
+            AUTH_TEST_SERVER, stranger.public.to_bytes(), ed25519_signer(&stranger)) {
+            Err(xous::Error::AccessDenied) => write!(ret, "Unlisted key refused\n").unwrap(),
+            r => { passed = false; write!(ret, "Unlisted key: expected AccessDenied, got {:?}\n", r).unwrap() }
+        }
+        match env.xns.request_authenticated_connection(
+            AUTH_TEST_SERVER, allowed.public.to_bytes(), |_| allowed.sign(b"not the challenge").to_bytes()) {
+            Err(xous::Error::AccessDenied) => write!(ret, "Bad signature refused\n").unwrap(),
+            r => { passed = false; write!(ret, "Bad signature: expected AccessDenied, got {:?}\n", r).unwrap() }
+        }
+        match env.xns.request_authenticated_connection(
+            AUTH_TEST_SERVER, allowed.public.to_bytes(), ed25519_signer(&allowed)) {
+            Ok(cid) => {
+                write!(ret, "Allowed key connected\n").unwrap();
+                unsafe { xous::disconnect(cid).ok(); }
+            }
+            Err(e) => { passed = false; write!(ret, "Allowed key: connection failed with {:?}\n", e).unwrap() }
+        }
+        env.xns.unregister_server(sid)?;
+        xous::destroy_server(sid).ok();
+        write!(ret, "{}", if passed { "Authentication self-test passed" } else { "Authentication self-test FAILED" }).unwrap();
+        Ok(())
+    }
+}
@@ -3,6 +3,34 @@ use xous_ipc::String;
 #[allow(unused_imports)]
 use std::io::{Write, Read, Seek, SeekFrom};
 
+use std::net::TcpStream;
+
+/// Sends a backup archive to the host as length-prefixed blocks, ended by an empty block. A
+/// connection that drops without the empty block is a failed backup, so the receiver (see
+/// tools/pddb_backup.py) knows not to replace the previous archive with it.
+struct FramedWriter {
+    stream: TcpStream,
+}
+impl FramedWriter {
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.stream.write_all(&0u32.to_le_bytes())?;
+        self.stream.flush()
+    }
+}
+impl Write for FramedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        self.stream.write_all(&(buf.len() as u32).to_le_bytes())?;
+        self.stream.write_all(buf)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
 pub struct PddbCmd {
     pddb: pddb::Pddb,
 }
@@ -21,9 +49,9 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
         #[cfg(not(feature="pddbtest"))]
-        let helpstring = "pddb [basislist] [dictlist] [keylist] [query] [dictdelete] [keydelete]";
+        let helpstring = "pddb [basislist] [dictlist] [keylist] [query] [dictdelete] [keydelete] [backup] [restore] [passwd] [fsck]";
         #[cfg(feature="pddbtest")]
-        let helpstring = "pddb [basislist] [dictlist] [keylist] [query] [dictdelete] [keydelete] [test]";
+        let helpstring = "pddb [basislist] [dictlist] [keylist] [query] [dictdelete] [keydelete] [backup] [restore] [passwd] [fsck] [test]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
         if let Some(sub_cmd) = tokens.next() {
@@ -168,6 +196,71 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                         Err(_) => write!(ret, "Error encountered listing dictionaries").ok().unwrap_or(()),
                     }
                 }
+                "backup" => {
+                    // archives are streamed straight to a host, so they never land on the same flash as the basis,
+                    // and nothing in the system basis records which bases exist
+                    match (tokens.next(), tokens.next(), tokens.next().and_then(|p| p.parse::<u16>().ok())) {
+                        (Some(basis), Some(host), Some(port)) => {
+                            match TcpStream::connect((host, port)) {
+                                Ok(stream) => {
+                                    let mut archive = FramedWriter { stream };
+                                    match self.pddb.export_basis(basis, &mut archive).and_then(|len| archive.finish().map(|_| len)) {
+                                        Ok(len) => write!(ret, "Backed up {} to {}:{} ({} bytes)", basis, host, port, len).unwrap(),
+                                        Err(e) => write!(ret, "Backup of {} failed: {:?}", basis, e).unwrap(),
+                                    }
+                                }
+                                Err(e) => write!(ret, "Couldn't connect to {}:{}: {:?}", host, port, e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "pddb backup [basis] [host] [port]").unwrap(),
+                    }
+                }
+                "restore" => {
+                    match (tokens.next(), tokens.next(), tokens.next().and_then(|p| p.parse::<u16>().ok())) {
+                        (Some(basis), Some(host), Some(port)) => {
+                            match TcpStream::connect((host, port)) {
+                                Ok(mut stream) => {
+                                    match self.pddb.import_basis(&mut stream, Some(basis)) {
+                                        Ok(_) => write!(ret, "Restored {} from {}:{}", basis, host, port).unwrap(),
+                                        Err(e) => write!(ret, "Restore of {} failed: {:?}", basis, e).unwrap(),
+                                    }
+                                }
+                                Err(e) => write!(ret, "Couldn't connect to {}:{}: {:?}", host, port, e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "pddb restore [basis] [host] [port]").unwrap(),
+                    }
+                }
+                "passwd" => {
+                    // the passwords are entered in the PDDB's own prompts, never on the command line
+                    if let Some(basis) = tokens.next() {
+                        match self.pddb.change_basis_password(basis) {
+                            Ok(_) => write!(ret, "Password of {} changed", basis).unwrap(),
+                            Err(e) => write!(ret, "Password change of {} failed: {:?}", basis, e).unwrap(),
+                        }
+                    } else {
+                        write!(ret, "pddb passwd [basis]").unwrap();
+                    }
+                }
+                "fsck" => {
+                    let repair = tokens.next() == Some("repair");
+                    match self.pddb.fsck(repair) {
+                        Ok(report) => {
+                            write!(ret, "Checked {} bases, {} pages\n", report.bases, report.pages).unwrap();
+                            write!(ret, "Undecryptable pages: {}\nOrphaned pages: {}\n", report.bad_pages, report.orphan_pages).unwrap();
+                            write!(ret, "Bad counts: {}\nBad keys: {}\nFSCB conflicts: {}\n",
+                                report.bad_counts, report.bad_keys, report.fscb_conflicts).unwrap();
+                            if report.repaired {
+                                write!(ret, "Repairs made").unwrap();
+                            } else if report.problems() != 0 && !repair {
+                                write!(ret, "Run `pddb fsck repair` to fix").unwrap();
+                            } else {
+                                write!(ret, "No repairs made").unwrap();
+                            }
+                        }
+                        Err(e) => write!(ret, "fsck failed: {:?}", e).unwrap(),
+                    }
+                }
                 // note that this feature only works in hosted mode
                 #[cfg(feature="pddbtest")]
                 "test" => {
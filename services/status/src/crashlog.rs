@@ -0,0 +1,80 @@
+/// Saves the crash records captured by the log server into the `.System` basis, so that a panic can
+/// be looked into after a reboot with the shellchat `crashlog` command.
+use std::thread;
+use std::io::Write;
+use pddb::Pddb;
+
+/// Upper bound on the number of crash records kept. When full, the oldest record is deleted.
+const MAX_CRASH_RECORDS: usize = 8;
+
+pub fn start_crash_persister() {
+    thread::spawn({
+        move || {
+            let mut pddb = Pddb::new();
+            // records that come in before the mount are held by the log server until we save them
+            pddb.is_mounted_blocking(None);
+            loop {
+                // start from the oldest held record each time, so that a record that couldn't be
+                // saved before is tried again
+                let mut after = 0;
+                loop {
+                    match log_server::next_crash_record(after) {
+                        Ok(Some(mut record)) => {
+                            after = record.valid;
+                            if save(&mut pddb, &mut record) {
+                                log_server::acknowledge_crash_record(record.valid)
+                                    .unwrap_or_else(|e| log::warn!("couldn't acknowledge crash record: {:?}", e));
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            log::error!("couldn't read crash record: {:?}", e);
+                            break;
+                        }
+                    }
+                }
+                log_server::wait_crash_record(after).expect("couldn't wait for crash records");
+            }
+        }
+    });
+}
+
+/// Saves `record`, and returns `true` if it was written out.
+fn save(pddb: &mut Pddb, record: &mut log_server::api::CrashRecord) -> bool {
+    log::info!("saving crash record for {} (PID {})", record.name(), record.pid);
+    record.timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut sequence: Vec<u32> = pddb.list_keys(log_server::CRASHLOG_DICT, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS))
+        .unwrap_or(Vec::new())
+        .iter()
+        .filter_map(|k| k.parse::<u32>().ok())
+        .collect();
+    sequence.sort();
+    let next = sequence.last().map(|&n| n + 1).unwrap_or(0);
+    while sequence.len() >= MAX_CRASH_RECORDS {
+        let oldest = sequence.remove(0);
+        pddb.delete_key(log_server::CRASHLOG_DICT, &format!("{:08}", oldest), Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS))
+            .unwrap_or_else(|e| log::warn!("couldn't delete old crash record: {:?}", e));
+    }
+
+    let data = record.as_bytes();
+    match pddb.get(
+        log_server::CRASHLOG_DICT, &format!("{:08}", next), Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS),
+        true, true, Some(data.len()), None::<fn()>
+    ) {
+        Ok(mut key) => {
+            if let Err(e) = key.write_all(data) {
+                log::error!("couldn't write crash record: {:?}", e);
+                return false;
+            }
+        }
+        Err(e) => {
+            log::error!("couldn't create crash record: {:?}", e);
+            return false;
+        }
+    }
+    pddb.sync().is_ok()
+}
@@ -44,6 +44,22 @@ pub fn create_kbd_menu(status_conn: xous::CID, kbd_mgr: xous::SID) -> MenuMatic
         action_payload: MenuPayload::Scalar([code as u32, 0, 0, 0]),
         close_on_select: true,
     });
+    let code: usize = KeyMap::Colemak.into();
+    menu_items.push(MenuItem {
+        name: xous_ipc::String::from_str("Colemak"),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::SetKeyboard.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([code as u32, 0, 0, 0]),
+        close_on_select: true,
+    });
+    let code: usize = KeyMap::Custom.into();
+    menu_items.push(MenuItem {
+        name: xous_ipc::String::from_str("Custom"),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::SetKeyboard.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([code as u32, 0, 0, 0]),
+        close_on_select: true,
+    });
     #[cfg(feature="tts")]
     {
         let code: usize = KeyMap::Braille.into();
@@ -58,3 +74,59 @@ pub fn create_kbd_menu(status_conn: xous::CID, kbd_mgr: xous::SID) -> MenuMatic
 
     menu_matic(menu_items, gam::KBD_MENU_NAME, Some(kbd_mgr)).expect("couldn't create MenuMatic manager")
 }
+
+/// Returns the position of `map` in the keyboard menu.
+pub fn kbd_menu_index(map: KeyMap) -> usize {
+    match map {
+        KeyMap::Qwerty => 0,
+        KeyMap::Azerty => 1,
+        KeyMap::Qwertz => 2,
+        KeyMap::Dvorak => 3,
+        KeyMap::Colemak => 4,
+        KeyMap::Custom => 5,
+        KeyMap::Braille => 6,
+        _ => 0,
+    }
+}
+
+/// Loads the `KeyMap::Custom` layout from the PDDB into the keyboard. The PDDB must be mounted.
+pub fn load_custom_keymap(kbd: &keyboard::Keyboard) {
+    use std::io::Read;
+    let mut pddb = pddb::Pddb::new();
+    match pddb.get(keyboard::CUSTOM_KEYMAP_DICT, keyboard::CUSTOM_KEYMAP_KEY, None, false, false, None, None::<fn()>) {
+        Ok(mut key) => {
+            let mut definition = std::string::String::new();
+            match key.read_to_string(&mut definition) {
+                Ok(_) => if let Err(e) = kbd.set_custom_keymap(&definition) {
+                    // e.g. a layout too long to send; an empty layout leaves every key on QWERTY
+                    log::warn!("couldn't set custom keymap, falling back to QWERTY: {:?}", e);
+                    kbd.set_custom_keymap("").ok();
+                },
+                Err(e) => log::warn!("couldn't read custom keymap: {:?}", e),
+            }
+        }
+        Err(_) => log::debug!("no custom keymap defined"),
+    }
+}
+
+/// Registers the key bindings kept in the PDDB with the keyboard, replacing any registered before.
+/// The PDDB must be mounted.
+pub fn load_key_bindings(kbd: &keyboard::Keyboard) {
+    use std::io::Read;
+    let mut pddb = pddb::Pddb::new();
+    let names = pddb.list_keys(keyboard::BINDINGS_DICT, None).unwrap_or(Vec::new());
+    let mut definitions = Vec::new();
+    for name in names.iter() {
+        let mut definition = std::string::String::new();
+        match pddb.get(keyboard::BINDINGS_DICT, name, None, false, false, None, None::<fn()>) {
+            Ok(mut key) => match key.read_to_string(&mut definition) {
+                Ok(_) => definitions.push((name.as_str(), definition)),
+                Err(e) => log::warn!("couldn't read key binding {}: {:?}", name, e),
+            },
+            Err(e) => log::warn!("couldn't open key binding {}: {:?}", name, e),
+        }
+    }
+    if let Err(e) = kbd.load_bindings(definitions.iter().map(|(name, definition)| (*name, definition.as_str()))) {
+        log::warn!("couldn't load key bindings: {:?}", e);
+    }
+}
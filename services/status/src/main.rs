@@ -9,6 +9,9 @@ mod kbdmenu;
 use kbdmenu::*;
 mod app_autogen;
 mod time;
+mod tz;
+mod ntp;
+mod crashlog;
 
 use com::api::*;
 use core::fmt::Write;
@@ -63,6 +66,8 @@ pub(crate) enum StatusOpcode {
     BatteryDisconnect,
     /// for returning wifi stats
     WifiStats,
+    /// the key bindings in the PDDB changed
+    KeyBindingsChanged,
     Quit,
 }
 
@@ -109,6 +114,8 @@ fn xmain() -> ! {
     // this kicks off the thread that services the `libstd` calls for time-related things.
     // we want this started really early, because it sanity checks the RTC and a bunch of other stuff.
     time::start_time_server();
+    // saves the record of any panic to the PDDB, once it is mounted
+    crashlog::start_crash_persister();
 
     let xns = xous_names::XousNames::new().unwrap();
     // 1 connection exactly -- from the GAM to set our canvas GID
@@ -327,6 +334,9 @@ fn xmain() -> ! {
     let time_sid = xous::create_server().unwrap();
     let time_cid = xous::connect(time_sid).unwrap();
     time::start_time_ux(time_sid);
+    // keeps the clock in step with NTP whenever there is a network
+    let ntp_status = Arc::new(Mutex::new(ntp::NtpStatus::default()));
+    ntp::start_ntp_sync(ntp_status.clone());
     // this is used by the main loop to get the localtime to show on the status bar
     let mut localtime = llio::LocalTime::new();
     // used to hide time when the PDDB is not mounted
@@ -341,6 +351,9 @@ fn xmain() -> ! {
     let kbd_mgr = xous::create_server().unwrap();
     let kbd_menumatic = create_kbd_menu(xous::connect(status_sid).unwrap(), kbd_mgr);
     let kbd = keyboard::Keyboard::new(&xns).unwrap();
+    let mut kbd_settings_loaded = false;
+    // watches the key bindings in the PDDB, so that edits (e.g. from the shellchat `bind` command) reach the keyboard
+    let mut _bindings_watch: Option<pddb::Pddb> = None;
 
     log::debug!("subscribe to wifi updates");
     netmgr.wifi_state_subscribe(cb_cid, StatusOpcode::WifiStats.to_u32().unwrap()).unwrap();
@@ -426,8 +439,22 @@ fn xmain() -> ! {
                 };
                 wifi_status = WlanStatus::from_ipc(buffer.to_original::<com::WlanStatusIpc, _>().unwrap());
             },
+            Some(StatusOpcode::KeyBindingsChanged) => msg_scalar_unpack!(msg, _event, _, _, _, {
+                load_key_bindings(&kbd);
+            }),
             Some(StatusOpcode::Pump) => {
                 let elapsed_time = ticktimer.elapsed_ms();
+                if !kbd_settings_loaded && pddb_poller.is_mounted_nonblocking() {
+                    // the custom layout and key bindings can only be loaded once the PDDB is unlocked
+                    load_custom_keymap(&kbd);
+                    load_key_bindings(&kbd);
+                    let mut pddb = pddb::Pddb::new();
+                    match pddb.watch_dict(keyboard::BINDINGS_DICT, None, cb_cid, StatusOpcode::KeyBindingsChanged.to_u32().unwrap()) {
+                        Ok(_) => _bindings_watch = Some(pddb),
+                        Err(e) => log::warn!("couldn't watch key bindings, edits apply after a reboot: {:?}", e),
+                    }
+                    kbd_settings_loaded = true;
+                }
                 { // update the CPU load bar
                     let mut draw_list = GamObjectList::new(status_gid);
                     draw_list.push(GamObjectType::Rect(cpuload_rect)).unwrap();
@@ -536,6 +563,13 @@ fn xmain() -> ! {
                             timestr
                         )
                         .unwrap();
+                        if ntp_status.lock().unwrap().is_stale(elapsed_time) {
+                            write!(
+                                &mut uptime_tv,
+                                "{}",
+                                t!("stats.ntp_stale", xous::LANG)
+                            ).unwrap();
+                        }
                     } else {
                         if pddb_poller.is_mounted_nonblocking() {
                             write!(
@@ -608,13 +642,19 @@ fn xmain() -> ! {
                 log::debug!("getting keyboard map");
                 let map = kbd.get_keymap().expect("couldn't get key mapping");
                 log::info!("setting keymap index to {:?}", map);
-                kbd_menumatic.set_index(map.into());
+                kbd_menumatic.set_index(kbd_menu_index(map));
                 log::debug!("raising keyboard menu");
                 ticktimer.sleep_ms(100).ok(); // yield for a moment to allow the previous menu to close
                 gam.raise_menu(gam::KBD_MENU_NAME).expect("couldn't raise keyboard layout submenu");
             },
             Some(StatusOpcode::SetKeyboard) => msg_scalar_unpack!(msg, code, _, _, _, {
                 let map = keyboard::KeyMap::from(code);
+                if let keyboard::KeyMap::Custom = map {
+                    // pick up any edits to the layout since it was last loaded
+                    if pddb_poller.is_mounted_nonblocking() {
+                        load_custom_keymap(&kbd);
+                    }
+                }
                 kbd.set_keymap(map).expect("couldn't set keyboard mapping");
             }),
             Some(StatusOpcode::SwitchToShellchat) => {
@@ -0,0 +1,332 @@
+/// Keeps the wall clock in step with NTP.
+///
+/// Once the PDDB is mounted and `net` reports a DHCP-bound link, the servers listed in the
+/// `ntp_servers` key of the `sys.rtc` dictionary are queried, and again every few hours after that.
+/// The key holds one `host` or `host:port` per line; if it is missing or empty, a default list is
+/// used. The answers are sanity-checked against each other before the time server is asked to
+/// adjust the clock: ordinary drift is slewed in gradually, so time never jumps, and only an
+/// error larger than `SLEW_LIMIT_MS` (e.g. a clock that was never set) is stepped.
+///
+/// The outcome of the last attempt is kept in an `NtpStatus`, which the status bar uses to flag a
+/// clock that NTP hasn't been able to confirm.
+use std::thread;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use num_traits::*;
+use xous::Message;
+use sntpc::{Error, NtpContext, NtpTimestampGenerator, NtpUdpSocket, Result};
+use crate::time::{TimeOp, TIME_SERVER_DICT, TIME_SERVER_PUBLIC};
+
+/// Key in `TIME_SERVER_DICT` with the NTP servers to use, one per line.
+pub(crate) const TIME_SERVER_NTP_SERVERS: &str = "ntp_servers";
+/// Used when no servers are configured.
+const DEFAULT_NTP_SERVERS: [&str; 2] = ["time.google.com", "pool.ntp.org"];
+
+/// Time between successful syncs.
+const RESYNC_INTERVAL_MS: u64 = 6 * 3600 * 1000;
+/// Time before trying again after a failed sync.
+const RETRY_INTERVAL_MS: u64 = 10 * 60 * 1000;
+/// How often the sync schedule is checked.
+const POLL_INTERVAL_MS: usize = 60 * 1000;
+/// The status bar flags the clock once a sync has been attempted, but none succeeded for this long.
+const STALE_MS: u64 = 24 * 3600 * 1000;
+
+/// At most this many servers are asked for the time on each sync.
+const MAX_SAMPLES: usize = 3;
+/// Answers from different servers that are further apart than this are treated as disagreeing.
+const AGREEMENT_MS: i64 = 2000;
+/// Offsets smaller than this are within the noise of the measurement, and are left alone.
+const NOISE_MS: i64 = 50;
+/// Offsets up to this size are slewed; larger ones are stepped, which needs two agreeing servers.
+const SLEW_LIMIT_MS: i64 = 15 * 60 * 1000;
+/// No NTP answer can be earlier than this (2022-01-01 00:00 UTC).
+const MIN_VALID_UTC_MS: i64 = 1_640_995_200_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum NtpState {
+    /// No sync has been attempted yet, e.g. the network hasn't come up
+    Idle,
+    Synced,
+    /// No server could be reached, or the clock couldn't be adjusted; retried at the next sync
+    Failed,
+    /// Servers answered, but the answers didn't pass the sanity checks
+    Rejected,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct NtpStatus {
+    pub state: NtpState,
+    /// ticktimer time of the last successful sync
+    pub last_sync_ms: Option<u64>,
+}
+impl Default for NtpStatus {
+    fn default() -> Self {
+        NtpStatus { state: NtpState::Idle, last_sync_ms: None }
+    }
+}
+impl NtpStatus {
+    /// True if a sync has been attempted, but NTP hasn't confirmed the time for a while.
+    pub(crate) fn is_stale(&self, now_ms: u64) -> bool {
+        self.state != NtpState::Idle
+        && self.last_sync_ms.map_or(true, |t| now_ms.saturating_sub(t) > STALE_MS)
+    }
+}
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+enum NtpOp {
+    /// a `WlanStatusIpc` from the net manager
+    WifiStats,
+    /// check whether a sync is due
+    Pump,
+}
+
+#[derive(Copy, Clone, Default)]
+struct StdTimestampGen {
+    duration: std::time::Duration,
+}
+impl NtpTimestampGenerator for StdTimestampGen {
+    fn init(&mut self) {
+        self.duration = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+    }
+
+    fn timestamp_sec(&self) -> u64 {
+        self.duration.as_secs()
+    }
+
+    fn timestamp_subsec_micros(&self) -> u32 {
+        self.duration.subsec_micros()
+    }
+}
+
+#[derive(Debug)]
+struct UdpSocketWrapper(UdpSocket);
+
+impl NtpUdpSocket for UdpSocketWrapper {
+    fn send_to<T: ToSocketAddrs>(
+        &self,
+        buf: &[u8],
+        addr: T,
+    ) -> Result<usize> {
+        match self.0.send_to(buf, addr) {
+            Ok(usize) => Ok(usize),
+            Err(_) => Err(Error::Network),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        match self.0.recv_from(buf) {
+            Ok((size, addr)) => Ok((size, addr)),
+            Err(_) => Err(Error::Network),
+        }
+    }
+}
+
+/// Reads the configured NTP servers out of the PDDB, falling back to the defaults.
+pub(crate) fn servers(pddb: &mut pddb::Pddb) -> Vec<String> {
+    let mut list = String::new();
+    if let Ok(mut key) = pddb.get(
+        TIME_SERVER_DICT,
+        TIME_SERVER_NTP_SERVERS,
+        None, false, false,
+        None,
+        None::<fn()>
+    ) {
+        key.read_to_string(&mut list).ok();
+    }
+    let servers: Vec<String> = list
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect();
+    if servers.is_empty() {
+        DEFAULT_NTP_SERVERS.iter().map(|s| s.to_string()).collect()
+    } else {
+        servers
+    }
+}
+
+/// Asks one server how far the clock is off. Returns the ms to add to the current time to get
+/// UTC. The offset is worked out from all four NTP timestamps, so the time the packets spent on
+/// the network cancels out instead of leaving the clock late by it.
+pub(crate) fn get_offset_ms(server: &str, trng: &trng::Trng) -> Result<i64> {
+    let addr = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:123", server)
+    };
+    let local_port = (trng.get_u32().unwrap() % 16384 + 49152) as u16;
+    let socket_addr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), local_port);
+    let socket = UdpSocket::bind(socket_addr).map_err(|_| Error::Network)?;
+    log::debug!("NTP rx socket created {:?}", socket);
+    socket
+        .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+        .map_err(|_| Error::Network)?;
+    let ntp_context = NtpContext::new(StdTimestampGen::default());
+    let time = sntpc::get_time(addr.as_str(), UdpSocketWrapper(socket), ntp_context)?;
+    log::info!("Got NTP time from {}: {}.{}, offset {} us, round trip {} us",
+        server, time.sec(), time.sec_fraction(), time.offset(), time.roundtrip());
+    Ok(time.offset() / 1000)
+}
+
+pub(crate) fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Measures how far the clock is off, as the median of the offsets reported by the servers that
+/// answered. Returns the offset and the number of answers that agree with it, or `None` unless
+/// most of the answers agree.
+fn consensus(offsets: &mut [i64]) -> Option<(i64, usize)> {
+    if offsets.is_empty() {
+        return None;
+    }
+    offsets.sort_unstable();
+    let median = offsets[offsets.len() / 2];
+    let agreeing = offsets.iter().filter(|&&o| (o - median).abs() <= AGREEMENT_MS).count();
+    if agreeing * 2 > offsets.len() {
+        Some((median, agreeing))
+    } else {
+        None
+    }
+}
+
+fn sync(servers: &[String], trng: &trng::Trng, timeserver_cid: xous::CID) -> NtpState {
+    let mut offsets = Vec::new();
+    for server in servers {
+        match get_offset_ms(server, trng) {
+            Ok(offset_ms) if now_ms() + offset_ms < MIN_VALID_UTC_MS => {
+                log::warn!("ignoring implausible time {} from {}", (now_ms() + offset_ms) / 1000, server);
+            }
+            Ok(offset_ms) => {
+                offsets.push(offset_ms);
+                if offsets.len() == MAX_SAMPLES {
+                    break;
+                }
+            }
+            Err(e) => log::info!("NTP query to {} failed: {:?}", server, e),
+        }
+    }
+    if offsets.is_empty() {
+        return NtpState::Failed;
+    }
+    let (offset_ms, agreeing) = match consensus(&mut offsets) {
+        Some(c) => c,
+        None => {
+            log::warn!("NTP servers disagree, not adjusting the clock: {:?}", offsets);
+            return NtpState::Rejected;
+        }
+    };
+    log::info!("clock is off by {} ms ({} of {} servers agree)", offset_ms, agreeing, offsets.len());
+    if offset_ms.abs() < NOISE_MS {
+        // nothing to do
+    } else if offset_ms.abs() <= SLEW_LIMIT_MS {
+        if let Err(e) = xous::send_message(timeserver_cid,
+            Message::new_scalar(
+                TimeOp::SlewUtcTimeMs.to_usize().unwrap(),
+                (offset_ms >> 32) as usize,
+                (offset_ms & 0xFFFF_FFFF) as usize,
+                0, 0,
+            )
+        ) {
+            // retry at the next sync rather than taking the thread down
+            log::error!("couldn't slew time: {:?}", e);
+            return NtpState::Failed;
+        }
+    } else {
+        // a single server can't be trusted to move the clock by this much, unless it's the only one
+        if agreeing < 2 && servers.len() > 1 {
+            log::warn!("refusing to step the clock by {} s on the word of one server", offset_ms / 1000);
+            return NtpState::Rejected;
+        }
+        let utc_ms = now_ms() + offset_ms;
+        log::info!("stepping the clock to {}", utc_ms / 1000);
+        if let Err(e) = xous::send_message(timeserver_cid,
+            Message::new_scalar(
+                TimeOp::SetUtcTimeMs.to_usize().unwrap(),
+                ((utc_ms as u64) >> 32) as usize,
+                (utc_ms as u64 & 0xFFFF_FFFF) as usize,
+                0, 0,
+            )
+        ) {
+            log::error!("couldn't set time: {:?}", e);
+            return NtpState::Failed;
+        }
+    }
+    NtpState::Synced
+}
+
+pub(crate) fn start_ntp_sync(status: Arc<Mutex<NtpStatus>>) {
+    thread::spawn({
+        move || {
+            let xns = xous_names::XousNames::new().unwrap();
+            let trng = trng::Trng::new(&xns).unwrap();
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            let timeserver_cid = xous::connect(xous::SID::from_bytes(TIME_SERVER_PUBLIC).unwrap()).unwrap();
+            let ntp_sid = xous::create_server().unwrap();
+            let ntp_cid = xous::connect(ntp_sid).unwrap();
+
+            // the server list and the UTC offset both live in the PDDB
+            let mut pddb = pddb::Pddb::new();
+            pddb.is_mounted_blocking(None);
+
+            let mut netmgr = net::NetManager::new();
+            netmgr.wifi_state_subscribe(ntp_cid, NtpOp::WifiStats.to_u32().unwrap()).unwrap();
+            thread::spawn({
+                let tt = ticktimer_server::Ticktimer::new().unwrap();
+                move || {
+                    loop {
+                        tt.sleep_ms(POLL_INTERVAL_MS).unwrap();
+                        xous::send_message(ntp_cid,
+                            Message::new_scalar(NtpOp::Pump.to_usize().unwrap(), 0, 0, 0, 0)
+                        ).expect("couldn't pump NTP sync");
+                    }
+                }
+            });
+
+            let mut bound = false;
+            let mut next_sync_ms = 0;
+            loop {
+                let msg = xous::receive_message(ntp_sid).unwrap();
+                match FromPrimitive::from_usize(msg.body.id()) {
+                    Some(NtpOp::WifiStats) => {
+                        let buffer = unsafe {
+                            xous_ipc::Buffer::from_memory_message(msg.body.memory_message().unwrap())
+                        };
+                        let wifi_status = com::WlanStatus::from_ipc(buffer.to_original::<com::WlanStatusIpc, _>().unwrap());
+                        let now_bound = wifi_status.ipv4.dhcp == com_rs_ref::DhcpState::Bound;
+                        if now_bound && !bound {
+                            // a new lease could be a different network, so check the time right away
+                            log::debug!("DHCP bound, scheduling NTP sync");
+                            next_sync_ms = tt.elapsed_ms();
+                        }
+                        bound = now_bound;
+                    }
+                    Some(NtpOp::Pump) => (),
+                    None => {
+                        log::error!("NTP sync thread received unknown opcode: {:?}", msg);
+                        continue;
+                    }
+                }
+                if bound && tt.elapsed_ms() >= next_sync_ms {
+                    let state = sync(&servers(&mut pddb), &trng, timeserver_cid);
+                    let now = tt.elapsed_ms();
+                    let mut ntp_status = status.lock().unwrap();
+                    ntp_status.state = state;
+                    if state == NtpState::Synced {
+                        ntp_status.last_sync_ms = Some(now);
+                        next_sync_ms = now + RESYNC_INTERVAL_MS;
+                    } else {
+                        log::info!("NTP sync failed: {:?}", state);
+                        next_sync_ms = now + RETRY_INTERVAL_MS;
+                    }
+                }
+            }
+        }
+    });
+}
@@ -33,9 +33,6 @@ use locales::t;
 use chrono::prelude::*;
 use xous::Message;
 use gam::modal::*;
-// ntp imports
-use sntpc::{Error, NtpContext, NtpTimestampGenerator, NtpUdpSocket, Result};
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
 
 /// This is a "well known name" used by `libstd` to connect to the time server
 /// Even thought it is "public" nobody connects to it directly, they connect to it via `libstd`
@@ -48,6 +45,9 @@ pub(crate) const TIME_SERVER_DICT: &'static str = "sys.rtc";
 const TIME_SERVER_UTC_OFFSET: &'static str = "utc_offset";
 /// This is the offset from UTC to the display time zone. This can vary when the user changes time zones.
 pub(crate) const TIME_SERVER_TZ_OFFSET: &'static str = "tz_offset";
+/// The name of the display time zone in `tz::ZONES`. When set, this overrides `tz_offset`, and the offset
+/// follows the daylight saving time rules of the zone.
+pub(crate) const TIME_SERVER_TZ_NAME: &'static str = "tz_name";
 
 #[allow(dead_code)]
 const CTL3: usize = 0;
@@ -84,6 +84,10 @@ pub(crate) enum TimeOp {
     WallClockTimeInit = 6,
     /// Self-poll for PDDB mount
     PddbMountPoll = 7,
+    /// Adjusts UTC time by the provided number of ms, gradually. Only used by the NTP sync, not `libstd`.
+    SlewUtcTimeMs = 8,
+    /// Sets the time zone to the provided index into `tz::ZONES`. Not used by `libstd`.
+    SetTimeZone = 9,
 }
 
 /// Do not modify the discriminants in this structure. They are used in `libstd` directly.
@@ -99,46 +103,25 @@ pub(crate) enum TimeUxOp {
     SetTimeZone,
     Quit,
 }
-#[derive(Copy, Clone, Default)]
-struct StdTimestampGen {
-    duration: std::time::Duration,
+/// Rate at which a correction is slewed into the clock: 1ms of correction per this many ms of time.
+const SLEW_RATE: i64 = 20;
+
+/// A correction to the UTC offset that is worked into the clock gradually, so that time doesn't jump.
+#[derive(Default)]
+struct Slew {
+    /// the whole correction, in ms
+    total_ms: i64,
+    /// ticktimer time at which the correction started
+    start_tt_ms: u64,
 }
-impl NtpTimestampGenerator for StdTimestampGen {
-    fn init(&mut self) {
-        self.duration = std::time::SystemTime::now()
-            .duration_since(std::time::SystemTime::UNIX_EPOCH)
-            .unwrap();
-    }
-
-    fn timestamp_sec(&self) -> u64 {
-        self.duration.as_secs()
-    }
-
-    fn timestamp_subsec_micros(&self) -> u32 {
-        self.duration.subsec_micros()
-    }
-}
-
-
-#[derive(Debug)]
-struct UdpSocketWrapper(UdpSocket);
-
-impl NtpUdpSocket for UdpSocketWrapper {
-    fn send_to<T: ToSocketAddrs>(
-        &self,
-        buf: &[u8],
-        addr: T,
-    ) -> Result<usize> {
-        match self.0.send_to(buf, addr) {
-            Ok(usize) => Ok(usize),
-            Err(_) => Err(Error::Network),
-        }
-    }
-
-    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
-        match self.0.recv_from(buf) {
-            Ok((size, addr)) => Ok((size, addr)),
-            Err(_) => Err(Error::Network),
+impl Slew {
+    /// The part of the correction that has been applied by ticktimer time `now_ms`.
+    fn applied(&self, now_ms: u64) -> i64 {
+        let budget = now_ms.saturating_sub(self.start_tt_ms) as i64 / SLEW_RATE;
+        if self.total_ms >= 0 {
+            self.total_ms.min(budget)
+        } else {
+            self.total_ms.max(-budget)
         }
     }
 }
@@ -265,10 +248,13 @@ pub fn start_time_server() {
             if tz_key.read(&mut tz_buf).unwrap_or(0) == 8 {
                 tz_offset_ms = i64::from_le_bytes(tz_buf);
             }
+            let mut zone_handle = Pddb::new();
+            let mut zone = read_zone(&mut zone_handle);
             log::debug!("offset_key: {}", utc_offset_ms / 1000);
             log::debug!("tz_key: {}", tz_offset_ms / 1000);
             log::debug!("start_rtc_secs: {}", start_rtc_secs);
             log::debug!("start_tt_ms: {}", start_tt_ms);
+            let mut slew = Slew::default();
             loop {
                 let msg = xous::receive_message(pub_sid).unwrap();
                 match FromPrimitive::from_usize(msg.body.id()) {
@@ -287,10 +273,16 @@ pub fn start_time_server() {
                         start_tt_ms = tt.elapsed_ms();
                     },
                     Some(TimeOp::GetUtcTimeMs) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                        let now_ms = tt.elapsed_ms();
+                        if slew.total_ms != 0 && slew.applied(now_ms) == slew.total_ms {
+                            utc_offset_ms += slew.total_ms;
+                            slew = Slew::default();
+                        }
                         let t =
                             start_rtc_secs as i64 * 1000i64
-                            + (tt.elapsed_ms() - start_tt_ms) as i64
-                            + utc_offset_ms;
+                            + (now_ms - start_tt_ms) as i64
+                            + utc_offset_ms
+                            + slew.applied(now_ms);
                         assert!(t > 0, "time result is negative, this is an error");
                         log::trace!("utc ms {}", t);
                         xous::return_scalar2(msg.sender,
@@ -299,12 +291,21 @@ pub fn start_time_server() {
                         ).expect("couldn't respond to GetUtcTimeMs");
                     }),
                     Some(TimeOp::GetLocalTimeMs) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                        log::trace!("current offset {}", (start_rtc_secs as i64 * 1000i64 + (tt.elapsed_ms() - start_tt_ms) as i64) / 1000);
+                        let now_ms = tt.elapsed_ms();
+                        log::trace!("current offset {}", (start_rtc_secs as i64 * 1000i64 + (now_ms - start_tt_ms) as i64) / 1000);
+                        if slew.total_ms != 0 && slew.applied(now_ms) == slew.total_ms {
+                            utc_offset_ms += slew.total_ms;
+                            slew = Slew::default();
+                        }
                         let t =
                             start_rtc_secs as i64 * 1000i64
-                            + (tt.elapsed_ms() - start_tt_ms) as i64
+                            + (now_ms - start_tt_ms) as i64
                             + utc_offset_ms
-                            + tz_offset_ms;
+                            + slew.applied(now_ms);
+                        let t = t + match zone {
+                            Some(rule) => rule.offset_at(t / 1000) as i64 * 1000,
+                            None => tz_offset_ms,
+                        };
                         assert!(t > 0, "time result is negative, this is an error");
                         log::trace!("local since epoch {}", t / 1000);
                         xous::return_scalar2(msg.sender,
@@ -323,11 +324,26 @@ pub fn start_time_server() {
                             utc_time_ms -
                             (start_rtc_secs as i64) * 1000;
                         utc_offset_ms = offset;
+                        // an explicit time overrides any correction in progress
+                        slew = Slew::default();
                         offset_key.seek(SeekFrom::Start(0)).expect("couldn't seek");
                         log::info!("setting offset to {} secs", offset / 1000);
                         assert_eq!(offset_key.write(&offset.to_le_bytes()).unwrap_or(0), 8, "couldn't commit UTC time offset to PDDB");
                         offset_key.flush().expect("couldn't flush PDDB");
                     }),
+                    Some(TimeOp::SlewUtcTimeMs) => xous::msg_scalar_unpack!(msg, delta_hi_ms, delta_lo_ms, _, _, {
+                        let delta_ms = ((delta_hi_ms as i64) << 32) | (delta_lo_ms as i64);
+                        // keep whatever part of a previous correction was already applied, and start over from there
+                        let now_ms = tt.elapsed_ms();
+                        utc_offset_ms += slew.applied(now_ms);
+                        slew = Slew { total_ms: delta_ms, start_tt_ms: now_ms };
+                        // the target is committed right away, so a reboot part way through lands on the corrected time
+                        let target_ms = utc_offset_ms + delta_ms;
+                        log::info!("slewing UTC offset by {} ms", delta_ms);
+                        offset_key.seek(SeekFrom::Start(0)).expect("couldn't seek");
+                        assert_eq!(offset_key.write(&target_ms.to_le_bytes()).unwrap_or(0), 8, "couldn't commit UTC time offset to PDDB");
+                        offset_key.flush().expect("couldn't flush PDDB");
+                    }),
                     Some(TimeOp::SetTzOffsetMs) => xous::msg_scalar_unpack!(msg, tz_hi_ms, tz_lo_ms, _, _, {
                         let tz_ms = ((tz_hi_ms as i64) << 32) | (tz_lo_ms as i64);
                         // sanity check with very broad bounds: I don't know of any time zones that are more than +/2 days from UTC
@@ -342,10 +358,41 @@ pub fn start_time_server() {
                             log::info!("setting tz offset to {} secs", tz_ms / 1000);
                             assert_eq!(tz_key.write(&tz_ms.to_le_bytes()).unwrap_or(0), 8, "couldn't commit TZ time offset to PDDB");
                             tz_key.flush().expect("couldn't flush PDDB");
+                            // a fixed offset replaces any named zone
+                            if zone.take().is_some() {
+                                zone_handle.delete_key(TIME_SERVER_DICT, TIME_SERVER_TZ_NAME, None).ok();
+                            }
+                        }
+                    }),
+                    Some(TimeOp::SetTimeZone) => xous::msg_scalar_unpack!(msg, index, _, _, _, {
+                        match crate::tz::zone(index) {
+                            Some(rule) => {
+                                let name = crate::tz::ZONES[index].0;
+                                log::info!("setting time zone to {}", name);
+                                // delete first, because a shorter name would leave the end of the old one behind
+                                zone_handle.delete_key(TIME_SERVER_DICT, TIME_SERVER_TZ_NAME, None).ok();
+                                // the zone still applies until reboot if it can't be saved, e.g. the PDDB isn't mounted
+                                match zone_handle.get(
+                                    TIME_SERVER_DICT,
+                                    TIME_SERVER_TZ_NAME,
+                                    None, true, true,
+                                    Some(32),
+                                    None::<fn()>
+                                ) {
+                                    Ok(mut name_key) => {
+                                        if let Err(e) = name_key.write_all(name.as_bytes()).and_then(|_| name_key.flush()) {
+                                            log::error!("couldn't commit TZ name to PDDB: {:?}", e);
+                                        }
+                                    }
+                                    Err(e) => log::error!("couldn't open TZ name key: {:?}", e),
+                                }
+                                zone = Some(rule);
+                            }
+                            None => log::warn!("Requested time zone {} doesn't exist, ignoring!", index),
                         }
                     }),
                     Some(TimeOp::WallClockTimeInit) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                        if utc_offset_ms == 0 || tz_offset_ms == 0 {
+                        if utc_offset_ms == 0 || (tz_offset_ms == 0 && zone.is_none()) {
                             xous::return_scalar(msg.sender, 0).unwrap();
                         } else {
                             xous::return_scalar(msg.sender, 1).unwrap();
@@ -448,6 +495,26 @@ pub fn start_time_server() {
     });
 }
 
+/// Looks up the time zone saved in the PDDB, if any.
+fn read_zone(pddb: &mut Pddb) -> Option<crate::tz::Rule> {
+    let mut name_key = pddb.get(
+        TIME_SERVER_DICT,
+        TIME_SERVER_TZ_NAME,
+        None, false, false,
+        None,
+        None::<fn()>
+    ).ok()?;
+    let mut name = String::new();
+    name_key.read_to_string(&mut name).ok()?;
+    match crate::tz::find(&name) {
+        Some(index) => crate::tz::zone(index),
+        None => {
+            log::warn!("unknown time zone {:?}, falling back to the fixed offset", name);
+            None
+        }
+    }
+}
+
 #[allow(dead_code)]
 fn is_rtc_invalid(settings: &[u8]) -> bool {
     ((settings[CTL3] & 0xE0) != (Control3::BATT_STD_BL_EN).bits()) // power switchover setting should be initialized
@@ -497,41 +564,28 @@ pub fn start_time_ux(sid: xous::SID) {
                             continue;
                         }
                         let mut tz_set_handle = pddb::Pddb::new();
-                        let mut tz_set = false;
-                        let mut tz_offset_ms = 0i64;
-                        let maybe_tz_set_key = tz_set_handle.get(
-                            TIME_SERVER_DICT,
-                            TIME_SERVER_TZ_OFFSET,
-                            None, false, false,
-                            None,
-                            None::<fn()>
-                        ).ok();
-                        if let Some(mut tz_set_key) = maybe_tz_set_key {
-                            let mut tz_buf = [0u8; 8];
-                            if tz_set_key.read(&mut tz_buf).unwrap_or(0) == 8 {
-                                tz_offset_ms = i64::from_le_bytes(tz_buf);
-                                tz_set = true;
+                        let mut local_zone = read_zone(&mut tz_set_handle).map(LocalZone::Named);
+                        if local_zone.is_none() {
+                            let maybe_tz_set_key = tz_set_handle.get(
+                                TIME_SERVER_DICT,
+                                TIME_SERVER_TZ_OFFSET,
+                                None, false, false,
+                                None,
+                                None::<fn()>
+                            ).ok();
+                            if let Some(mut tz_set_key) = maybe_tz_set_key {
+                                let mut tz_buf = [0u8; 8];
+                                if tz_set_key.read(&mut tz_buf).unwrap_or(0) == 8 {
+                                    local_zone = Some(LocalZone::Fixed(i64::from_le_bytes(tz_buf)));
+                                }
                             }
                         }
                         // note that we don't do an "else" here because we also want to catch the case of
                         // a key exists, but nothing was written to it (length of key was 0 or inappropriate)
-                        if !tz_set {
-                            let tz = modals.get_text(
-                                t!("rtc.timezone", xous::LANG),
-                                Some(tz_ux_validator), None
-                            ).expect("couldn't get timezone").as_str()
-                            .parse::<f32>().expect("pre-validated input failed to re-parse!");
-                            log::info!("got tz offset {}", tz);
-                            tz_offset_ms = (tz * 3600.0 * 1000.0) as i64;
-                            xous::send_message(timeserver_cid,
-                                Message::new_scalar(
-                                    crate::time::TimeOp::SetTzOffsetMs.to_usize().unwrap(),
-                                    (tz_offset_ms >> 32) as usize,
-                                    (tz_offset_ms & 0xFFFF_FFFF) as usize,
-                                    0, 0,
-                                )
-                            ).expect("couldn't set timezone");
-                        }
+                        let local_zone = match local_zone {
+                            Some(zone) => zone,
+                            None => pick_time_zone(&modals, timeserver_cid),
+                        };
 
                         // see if we want to try to use NTP or not
                         modals.add_list_item(t!("pddb.yes", xous::LANG)).expect("couldn't build radio item list");
@@ -546,35 +600,29 @@ pub fn start_time_ux(sid: xous::SID) {
                             _ => log::error!("get_radiobutton failed"),
                         }
                         if try_ntp {
-                            let local_port = (trng.get_u32().unwrap() % 16384 + 49152) as u16;
-                            let socket_addr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), local_port);
-                            let socket = UdpSocket::bind(socket_addr).expect("Unable to create UDP socket");
-                            log::debug!("NTP rx socket created {:?}", socket);
-                            socket
-                                .set_read_timeout(Some(std::time::Duration::from_secs(2)))
-                                .expect("Unable to set UDP socket read timeout");
-                            let sock_wrapper = UdpSocketWrapper(socket);
-                            let ntp_context = NtpContext::new(StdTimestampGen::default());
-                            let result = sntpc::get_time("time.google.com:123", sock_wrapper, ntp_context);
-                            match result {
-                                Ok(time) => {
-                                    log::info!("Got NTP time: {}.{}", time.sec(), time.sec_fraction());
-                                    let current_time = Utc.ymd(1970, 1, 1).and_hms(0, 0, 0) + chrono::Duration::seconds(time.sec() as i64);
-                                    log::info!("Setting UTC time: {:?}", current_time.to_string());
-                                    xous::send_message(timeserver_cid,
-                                        Message::new_scalar(
-                                            crate::time::TimeOp::SetUtcTimeMs.to_usize().unwrap(),
-                                            ((current_time.timestamp_millis() as u64) >> 32) as usize,
-                                            (current_time.timestamp_millis() as u64 & 0xFFFF_FFFF) as usize,
-                                            0, 0,
-                                        )
-                                    ).expect("couldn't set time");
-                                    continue;
+                            let mut ntp_time_ms = None;
+                            for server in crate::ntp::servers(&mut tz_set_handle) {
+                                match crate::ntp::get_offset_ms(&server, &trng) {
+                                    Ok(offset_ms) => {
+                                        ntp_time_ms = Some(crate::ntp::now_ms() + offset_ms);
+                                        break;
+                                    }
+                                    Err(err) => log::info!("Err from {}: {:?}", server, err),
                                 }
-                                Err(err) => {
-                                    log::info!("Err: {:?}", err);
-                                    modals.show_notification(t!("rtc.ntp_fail", xous::LANG)).expect("couldn't show NTP error");
-                                },
+                            }
+                            if let Some(time_ms) = ntp_time_ms {
+                                log::info!("Setting UTC time: {}", time_ms / 1000);
+                                xous::send_message(timeserver_cid,
+                                    Message::new_scalar(
+                                        crate::time::TimeOp::SetUtcTimeMs.to_usize().unwrap(),
+                                        ((time_ms as u64) >> 32) as usize,
+                                        (time_ms as u64 & 0xFFFF_FFFF) as usize,
+                                        0, 0,
+                                    )
+                                ).expect("couldn't set time");
+                                continue;
+                            } else {
+                                modals.show_notification(t!("rtc.ntp_fail", xous::LANG)).expect("couldn't show NTP error");
                             }
                         }
 
@@ -628,13 +676,14 @@ pub fn start_time_ux(sid: xous::SID) {
                         log::debug!("got seconds {}", secs);
 
                         log::info!("Setting time: {}/{}/{} {}:{}:{}", months, days, years, hours, mins, secs);
-                        let new_dt = chrono::FixedOffset::east((tz_offset_ms / 1000) as i32).ymd(years as i32 + 2000, months as u32, days as u32)
-                        .and_hms(hours as u32, mins as u32, secs as u32);
+                        let local_secs = chrono::NaiveDate::from_ymd(years as i32 + 2000, months as u32, days as u32)
+                        .and_hms(hours as u32, mins as u32, secs as u32).timestamp();
+                        let utc_ms = (local_secs - local_zone.offset_at_local(local_secs)) * 1000;
                         xous::send_message(timeserver_cid,
                             Message::new_scalar(
                                 crate::time::TimeOp::SetUtcTimeMs.to_usize().unwrap(),
-                                ((new_dt.timestamp_millis() as u64) >> 32) as usize,
-                                (new_dt.timestamp_millis() as u64 & 0xFFFF_FFFF) as usize,
+                                ((utc_ms as u64) >> 32) as usize,
+                                (utc_ms as u64 & 0xFFFF_FFFF) as usize,
                                 0, 0,
                             )
                         ).expect("couldn't set time");
@@ -644,21 +693,7 @@ pub fn start_time_ux(sid: xous::SID) {
                             modals.show_notification(t!("stats.please_mount", xous::LANG)).expect("couldn't show notification");
                             continue;
                         }
-                        let tz = modals.get_text(
-                            t!("rtc.timezone", xous::LANG),
-                            Some(tz_ux_validator), None
-                        ).expect("couldn't get timezone").as_str()
-                        .parse::<f32>().expect("pre-validated input failed to re-parse!");
-                        log::info!("got tz offset {}", tz);
-                        let tzoff_ms = (tz * 3600.0 * 1000.0) as i64;
-                        xous::send_message(timeserver_cid,
-                            Message::new_scalar(
-                                crate::time::TimeOp::SetTzOffsetMs.to_usize().unwrap(),
-                                (tzoff_ms >> 32) as usize,
-                                (tzoff_ms & 0xFFFF_FFFF) as usize,
-                                0, 0,
-                            )
-                        ).expect("couldn't set timezone");
+                        pick_time_zone(&modals, timeserver_cid);
                     }),
                     Some(TimeUxOp::Quit) => {
                         xous::return_scalar(msg.sender, 0).unwrap();
@@ -675,6 +710,85 @@ pub fn start_time_ux(sid: xous::SID) {
 }
 
 // RTC Ux helper functions
+/// Number of zones listed on each page of the time zone picker, which is about what fits on the screen.
+const TZ_PAGE_SIZE: usize = 8;
+
+/// The local time zone, as picked by the user.
+enum LocalZone {
+    Named(crate::tz::Rule),
+    /// offset from UTC in ms
+    Fixed(i64),
+}
+impl LocalZone {
+    /// The offset from UTC in seconds that applies to the local time `local_secs`.
+    fn offset_at_local(&self, local_secs: i64) -> i64 {
+        match self {
+            LocalZone::Named(rule) => rule.offset_at_local(local_secs) as i64,
+            LocalZone::Fixed(offset_ms) => offset_ms / 1000,
+        }
+    }
+}
+
+/// Asks the user for a time zone, first by region and then by name, or for a fixed offset from UTC,
+/// and hands the answer to the time server.
+fn pick_time_zone(modals: &modals::Modals, timeserver_cid: xous::CID) -> LocalZone {
+    use crate::tz::{ZONES, city_of, region_of, regions};
+    let regions = regions();
+    for region in regions.iter() {
+        modals.add_list_item(region).expect("couldn't build radio item list");
+    }
+    modals.add_list_item(t!("rtc.tz_fixed", xous::LANG)).expect("couldn't build radio item list");
+    let region = modals.get_radiobutton(t!("rtc.tz_region", xous::LANG)).expect("couldn't get time zone region");
+
+    if regions.contains(&region.as_str()) {
+        let zones: Vec<usize> = (0..ZONES.len()).filter(|&i| region_of(ZONES[i].0) == region).collect();
+        let pages: Vec<&[usize]> = zones.chunks(TZ_PAGE_SIZE).collect();
+        let mut page = 0;
+        let index = loop {
+            if zones.len() == 1 {
+                break zones[0];
+            }
+            for &i in pages[page].iter() {
+                modals.add_list_item(&city_of(ZONES[i].0)).expect("couldn't build radio item list");
+            }
+            if pages.len() > 1 {
+                modals.add_list_item(t!("rtc.tz_more", xous::LANG)).expect("couldn't build radio item list");
+            }
+            let city = modals.get_radiobutton(t!("rtc.tz_zone", xous::LANG)).expect("couldn't get time zone");
+            if let Some(&i) = pages[page].iter().find(|&&i| city_of(ZONES[i].0) == city) {
+                break i;
+            }
+            // "more" wraps around to the first page after the last one
+            page = (page + 1) % pages.len();
+        };
+        log::info!("got time zone {}", ZONES[index].0);
+        xous::send_message(timeserver_cid,
+            Message::new_scalar(
+                crate::time::TimeOp::SetTimeZone.to_usize().unwrap(),
+                index, 0, 0, 0,
+            )
+        ).expect("couldn't set time zone");
+        LocalZone::Named(crate::tz::zone(index).expect("compiled-in time zone didn't parse"))
+    } else {
+        let tz = modals.get_text(
+            t!("rtc.timezone", xous::LANG),
+            Some(tz_ux_validator), None
+        ).expect("couldn't get timezone").as_str()
+        .parse::<f32>().expect("pre-validated input failed to re-parse!");
+        log::info!("got tz offset {}", tz);
+        let tzoff_ms = (tz * 3600.0 * 1000.0) as i64;
+        xous::send_message(timeserver_cid,
+            Message::new_scalar(
+                crate::time::TimeOp::SetTzOffsetMs.to_usize().unwrap(),
+                (tzoff_ms >> 32) as usize,
+                (tzoff_ms & 0xFFFF_FFFF) as usize,
+                0, 0,
+            )
+        ).expect("couldn't set timezone");
+        LocalZone::Fixed(tzoff_ms)
+    }
+}
+
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub(crate) enum ValidatorOp {
     UxMonth,
@@ -0,0 +1,522 @@
+//! A compiled-in subset of the IANA time zone database.
+//!
+//! Each zone is stored as the POSIX TZ string that `zic` writes at the end of the zone's TZif
+//! file, which describes the rules that are in force today: a standard offset, and optionally a
+//! daylight saving offset with the rules for when it starts and ends. Historical transitions are
+//! not kept, so local times before a zone last changed its rules come out with today's rules.
+//!
+//! The time server keeps the name of the selected zone in the `tz_name` key of `sys.rtc`, and
+//! works out the offset for each instant it is asked about, so clocks change over to and from
+//! daylight saving time by themselves.
+
+/// Zone names and their rules, grouped by region. The region is the part of the name before the
+/// first `/`, and is what the zone picker shows first.
+pub(crate) const ZONES: &[(&str, &str)] = &[
+    ("Africa/Abidjan", "GMT0"),
+    ("Africa/Accra", "GMT0"),
+    ("Africa/Addis_Ababa", "EAT-3"),
+    ("Africa/Algiers", "CET-1"),
+    ("Africa/Cairo", "EET-2EEST,M4.5.5/0,M10.5.4/24"),
+    ("Africa/Casablanca", "<+01>-1"),
+    ("Africa/Johannesburg", "SAST-2"),
+    ("Africa/Khartoum", "CAT-2"),
+    ("Africa/Kinshasa", "WAT-1"),
+    ("Africa/Lagos", "WAT-1"),
+    ("Africa/Maputo", "CAT-2"),
+    ("Africa/Nairobi", "EAT-3"),
+    ("Africa/Tunis", "CET-1"),
+    ("America/Anchorage", "AKST9AKDT,M3.2.0,M11.1.0"),
+    ("America/Argentina/Buenos_Aires", "<-03>3"),
+    ("America/Bogota", "<-05>5"),
+    ("America/Caracas", "<-04>4"),
+    ("America/Chicago", "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Denver", "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Guatemala", "CST6"),
+    ("America/Halifax", "AST4ADT,M3.2.0,M11.1.0"),
+    ("America/Havana", "CST5CDT,M3.2.0/0,M11.1.0/1"),
+    ("America/Jamaica", "EST5"),
+    ("America/Lima", "<-05>5"),
+    ("America/Los_Angeles", "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Mexico_City", "CST6"),
+    ("America/Montevideo", "<-03>3"),
+    ("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Nuuk", "<-02>2<-01>,M3.5.0/-1,M10.5.0/0"),
+    ("America/Panama", "EST5"),
+    ("America/Phoenix", "MST7"),
+    ("America/Puerto_Rico", "AST4"),
+    ("America/Regina", "CST6"),
+    ("America/Santiago", "<-04>4<-03>,M9.1.6/24,M4.1.6/24"),
+    ("America/Sao_Paulo", "<-03>3"),
+    ("America/St_Johns", "NST3:30NDT,M3.2.0,M11.1.0"),
+    ("America/Toronto", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Vancouver", "PST8PDT,M3.2.0,M11.1.0"),
+    ("Asia/Almaty", "<+05>-5"),
+    ("Asia/Baghdad", "<+03>-3"),
+    ("Asia/Bangkok", "<+07>-7"),
+    ("Asia/Beirut", "EET-2EEST,M3.5.0/0,M10.5.0/0"),
+    ("Asia/Colombo", "<+0530>-5:30"),
+    ("Asia/Dhaka", "<+06>-6"),
+    ("Asia/Dubai", "<+04>-4"),
+    ("Asia/Ho_Chi_Minh", "<+07>-7"),
+    ("Asia/Hong_Kong", "HKT-8"),
+    ("Asia/Jakarta", "WIB-7"),
+    ("Asia/Jerusalem", "IST-2IDT,M3.4.4/26,M10.5.0"),
+    ("Asia/Kabul", "<+0430>-4:30"),
+    ("Asia/Karachi", "PKT-5"),
+    ("Asia/Kathmandu", "<+0545>-5:45"),
+    ("Asia/Kolkata", "IST-5:30"),
+    ("Asia/Kuala_Lumpur", "<+08>-8"),
+    ("Asia/Manila", "PST-8"),
+    ("Asia/Novosibirsk", "<+07>-7"),
+    ("Asia/Riyadh", "<+03>-3"),
+    ("Asia/Seoul", "KST-9"),
+    ("Asia/Shanghai", "CST-8"),
+    ("Asia/Singapore", "<+08>-8"),
+    ("Asia/Taipei", "CST-8"),
+    ("Asia/Tashkent", "<+05>-5"),
+    ("Asia/Tehran", "<+0330>-3:30"),
+    ("Asia/Tokyo", "JST-9"),
+    ("Asia/Vladivostok", "<+10>-10"),
+    ("Asia/Yangon", "<+0630>-6:30"),
+    ("Asia/Yekaterinburg", "<+05>-5"),
+    ("Atlantic/Azores", "<-01>1<+00>,M3.5.0/0,M10.5.0/1"),
+    ("Atlantic/Bermuda", "AST4ADT,M3.2.0,M11.1.0"),
+    ("Atlantic/Canary", "WET0WEST,M3.5.0/1,M10.5.0"),
+    ("Atlantic/Cape_Verde", "<-01>1"),
+    ("Atlantic/Reykjavik", "GMT0"),
+    ("Australia/Adelaide", "ACST-9:30ACDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Brisbane", "AEST-10"),
+    ("Australia/Darwin", "ACST-9:30"),
+    ("Australia/Hobart", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Lord_Howe", "<+1030>-10:30<+11>-11,M10.1.0,M4.1.0"),
+    ("Australia/Melbourne", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Perth", "AWST-8"),
+    ("Australia/Sydney", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Europe/Amsterdam", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Athens", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Brussels", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Bucharest", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Budapest", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Copenhagen", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Dublin", "IST-1GMT0,M10.5.0,M3.5.0/1"),
+    ("Europe/Helsinki", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Istanbul", "<+03>-3"),
+    ("Europe/Kyiv", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Lisbon", "WET0WEST,M3.5.0/1,M10.5.0"),
+    ("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Madrid", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Minsk", "<+03>-3"),
+    ("Europe/Moscow", "MSK-3"),
+    ("Europe/Oslo", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Paris", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Prague", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Riga", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Rome", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Sofia", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Stockholm", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Tallinn", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Vienna", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Vilnius", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Warsaw", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Zurich", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Pacific/Auckland", "NZST-12NZDT,M9.5.0,M4.1.0/3"),
+    ("Pacific/Chatham", "<+1245>-12:45<+1345>,M9.5.0/2:45,M4.1.0/3:45"),
+    ("Pacific/Fiji", "<+12>-12"),
+    ("Pacific/Guam", "ChST-10"),
+    ("Pacific/Honolulu", "HST10"),
+    ("Pacific/Kiritimati", "<+14>-14"),
+    ("Pacific/Noumea", "<+11>-11"),
+    ("Pacific/Pago_Pago", "SST11"),
+    ("Pacific/Port_Moresby", "<+10>-10"),
+    ("Pacific/Tahiti", "<-10>10"),
+    ("Pacific/Tongatapu", "<+13>-13"),
+    ("UTC", "UTC0"),
+];
+
+/// The index of the zone called `name`.
+pub(crate) fn find(name: &str) -> Option<usize> {
+    ZONES.iter().position(|&(n, _)| n == name)
+}
+
+/// The rules of the zone at `index` in `ZONES`.
+pub(crate) fn zone(index: usize) -> Option<Rule> {
+    ZONES.get(index).and_then(|&(_, rule)| Rule::parse(rule))
+}
+
+/// The regions, in the order they appear in `ZONES`. Zones without a region are their own region.
+pub(crate) fn regions() -> Vec<&'static str> {
+    let mut regions: Vec<&'static str> = Vec::new();
+    for &(name, _) in ZONES.iter() {
+        let region = region_of(name);
+        if !regions.contains(&region) {
+            regions.push(region);
+        }
+    }
+    regions
+}
+
+pub(crate) fn region_of(name: &str) -> &str {
+    name.split('/').next().unwrap_or(name)
+}
+
+/// The name of a zone without its region, in the form shown to the user: "Argentina/Buenos Aires".
+pub(crate) fn city_of(name: &str) -> String {
+    match name.find('/') {
+        Some(i) => name[i + 1..].replace('_', " "),
+        None => name.to_string(),
+    }
+}
+
+/// The date a transition falls on, in one of the three forms POSIX allows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Date {
+    /// `Jn`: day of year 1-365, never counting February 29
+    Julian(u16),
+    /// `n`: day of year 0-365, counting February 29
+    Ordinal(u16),
+    /// `Mm.w.d`: day `d` (0 is Sunday) of week `w` (1-5, where 5 is the last) of month `m`
+    MonthWeekDay(u8, u8, u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Transition {
+    date: Date,
+    /// seconds after local midnight; may be negative or more than a day
+    time: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Dst {
+    /// seconds east of UTC
+    offset: i32,
+    /// in local standard time
+    start: Transition,
+    /// in local daylight saving time
+    end: Transition,
+}
+
+/// The rules of one zone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Rule {
+    /// seconds east of UTC
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+impl Rule {
+    /// Parses a POSIX TZ string, e.g. "CET-1CEST,M3.5.0,M10.5.0/3". Note that POSIX offsets count
+    /// hours west of UTC, so they have the opposite sign of the usual "UTC+1".
+    pub(crate) fn parse(s: &str) -> Option<Rule> {
+        let mut p = Parser { s: s.as_bytes() };
+        p.name()?;
+        let std_offset = -p.time()?;
+        if p.s.is_empty() {
+            return Some(Rule { std_offset, dst: None });
+        }
+        p.name()?;
+        let offset = if p.peek() == Some(b',') { std_offset + 3600 } else { -p.time()? };
+        p.expect(b',')?;
+        let start = p.transition()?;
+        p.expect(b',')?;
+        let end = p.transition()?;
+        if !p.s.is_empty() {
+            return None;
+        }
+        Some(Rule { std_offset, dst: Some(Dst { offset, start, end }) })
+    }
+
+    /// The offset from UTC in seconds, east positive, at `utc_secs` seconds since EPOCH.
+    pub(crate) fn offset_at(&self, utc_secs: i64) -> i32 {
+        let dst = match self.dst {
+            Some(dst) => dst,
+            None => return self.std_offset,
+        };
+        let year = civil_from_days((utc_secs + self.std_offset as i64).div_euclid(86400)).0;
+        let start = dst.start.local_secs(year) - self.std_offset as i64;
+        let end = dst.end.local_secs(year) - dst.offset as i64;
+        let in_dst = if start < end {
+            start <= utc_secs && utc_secs < end
+        } else {
+            // the southern hemisphere, where daylight saving time spans the new year
+            !(end <= utc_secs && utc_secs < start)
+        };
+        if in_dst { dst.offset } else { self.std_offset }
+    }
+
+    /// The offset from UTC in seconds, east positive, that applies to the local time `local_secs`.
+    /// Local times that are skipped or repeated when the clocks change resolve to one side of the
+    /// change.
+    pub(crate) fn offset_at_local(&self, local_secs: i64) -> i32 {
+        self.offset_at(local_secs - self.std_offset as i64)
+    }
+}
+
+impl Transition {
+    /// The local time of the transition in `year`, as seconds since EPOCH.
+    fn local_secs(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        let days = match self.date {
+            Date::Julian(n) => {
+                let leap_day = if is_leap(year) && n >= 60 { 1 } else { 0 };
+                jan1 + n as i64 - 1 + leap_day
+            }
+            Date::Ordinal(n) => jan1 + n as i64,
+            Date::MonthWeekDay(month, week, weekday) => {
+                let first = days_from_civil(year, month as u32, 1);
+                // 1970-01-01 was a Thursday
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (weekday as i64 - first_weekday).rem_euclid(7) + (week as i64 - 1) * 7;
+                while day >= first + days_in_month(year, month as u32) {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        days * 86400 + self.time as i64
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.s.first().copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        if self.peek() == Some(c) {
+            self.s = &self.s[1..];
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// A zone abbreviation: three or more letters, or anything between `<` and `>`.
+    fn name(&mut self) -> Option<()> {
+        let len = if self.peek() == Some(b'<') {
+            self.s.iter().position(|&c| c == b'>')? + 1
+        } else {
+            self.s.iter().position(|c| !c.is_ascii_alphabetic()).unwrap_or(self.s.len())
+        };
+        if len < 3 {
+            return None;
+        }
+        self.s = &self.s[len..];
+        Some(())
+    }
+
+    fn number(&mut self) -> Option<i32> {
+        let len = self.s.iter().position(|c| !c.is_ascii_digit()).unwrap_or(self.s.len());
+        if len == 0 || len > 3 {
+            return None;
+        }
+        let n = std::str::from_utf8(&self.s[..len]).ok()?.parse().ok()?;
+        self.s = &self.s[len..];
+        Some(n)
+    }
+
+    /// `[+-]hh[:mm[:ss]]`, in seconds.
+    fn time(&mut self) -> Option<i32> {
+        let sign = match self.peek() {
+            Some(b'-') => { self.s = &self.s[1..]; -1 }
+            Some(b'+') => { self.s = &self.s[1..]; 1 }
+            _ => 1,
+        };
+        let mut secs = self.number()? * 3600;
+        if self.expect(b':').is_some() {
+            secs += self.number()? * 60;
+            if self.expect(b':').is_some() {
+                secs += self.number()?;
+            }
+        }
+        Some(sign * secs)
+    }
+
+    fn transition(&mut self) -> Option<Transition> {
+        let date = match self.peek()? {
+            b'J' => {
+                self.s = &self.s[1..];
+                match self.number()? {
+                    n @ 1..=365 => Date::Julian(n as u16),
+                    _ => return None,
+                }
+            }
+            b'M' => {
+                self.s = &self.s[1..];
+                let month = self.number()?;
+                self.expect(b'.')?;
+                let week = self.number()?;
+                self.expect(b'.')?;
+                let weekday = self.number()?;
+                if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+                    return None;
+                }
+                Date::MonthWeekDay(month as u8, week as u8, weekday as u8)
+            }
+            _ => match self.number()? {
+                n @ 0..=365 => Date::Ordinal(n as u16),
+                _ => return None,
+            },
+        };
+        let time = if self.expect(b'/').is_some() { self.time()? } else { 2 * 3600 };
+        Some(Transition { date, time })
+    }
+}
+
+fn is_leap(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> i64 {
+    match month {
+        2 => if is_leap(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since EPOCH of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The (year, month, day) of a number of days since EPOCH.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i64, month: u32, day: u32, hour: i64, min: i64) -> i64 {
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + min * 60
+    }
+
+    fn offset(name: &str, utc_secs: i64) -> i32 {
+        zone(find(name).unwrap()).unwrap().offset_at(utc_secs)
+    }
+
+    #[test]
+    fn all_zones_parse() {
+        for &(name, rule) in ZONES.iter() {
+            assert!(Rule::parse(rule).is_some(), "{} has a bad rule: {}", name, rule);
+        }
+    }
+
+    #[test]
+    fn civil_dates() {
+        for days in [-1, 0, 59, 60, 10957, 19782, 2932896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn new_york() {
+        // 2024: second Sunday of March 2:00 EST, first Sunday of November 2:00 EDT
+        assert_eq!(offset("America/New_York", utc(2024, 1, 15, 12, 0)), -5 * 3600);
+        assert_eq!(offset("America/New_York", utc(2024, 3, 10, 6, 59)), -5 * 3600);
+        assert_eq!(offset("America/New_York", utc(2024, 3, 10, 7, 0)), -4 * 3600);
+        assert_eq!(offset("America/New_York", utc(2024, 11, 3, 5, 59)), -4 * 3600);
+        assert_eq!(offset("America/New_York", utc(2024, 11, 3, 6, 0)), -5 * 3600);
+    }
+
+    #[test]
+    fn europe() {
+        // last Sunday of March and October, at 01:00 UTC everywhere in the EU
+        assert_eq!(offset("Europe/Berlin", utc(2024, 3, 31, 0, 59)), 3600);
+        assert_eq!(offset("Europe/Berlin", utc(2024, 3, 31, 1, 0)), 7200);
+        assert_eq!(offset("Europe/Athens", utc(2024, 10, 27, 0, 59)), 3 * 3600);
+        assert_eq!(offset("Europe/Athens", utc(2024, 10, 27, 1, 0)), 2 * 3600);
+        assert_eq!(offset("Europe/London", utc(2024, 7, 1, 12, 0)), 3600);
+        // Irish "standard" time is summer time, with a negative offset in winter
+        assert_eq!(offset("Europe/Dublin", utc(2024, 7, 1, 12, 0)), 3600);
+        assert_eq!(offset("Europe/Dublin", utc(2024, 1, 1, 12, 0)), 0);
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        // Sydney leaves daylight saving time on the first Sunday of April, at 3:00 AEDT
+        assert_eq!(offset("Australia/Sydney", utc(2024, 1, 1, 0, 0)), 11 * 3600);
+        assert_eq!(offset("Australia/Sydney", utc(2024, 4, 6, 15, 59)), 11 * 3600);
+        assert_eq!(offset("Australia/Sydney", utc(2024, 4, 6, 16, 0)), 10 * 3600);
+        assert_eq!(offset("Australia/Sydney", utc(2024, 10, 5, 15, 59)), 10 * 3600);
+        assert_eq!(offset("Australia/Sydney", utc(2024, 10, 5, 16, 0)), 11 * 3600);
+        assert_eq!(offset("Australia/Lord_Howe", utc(2024, 7, 1, 0, 0)), 10 * 3600 + 1800);
+        assert_eq!(offset("Australia/Lord_Howe", utc(2024, 12, 1, 0, 0)), 11 * 3600);
+        assert_eq!(offset("Pacific/Chatham", utc(2024, 12, 1, 0, 0)), 13 * 3600 + 45 * 60);
+    }
+
+    #[test]
+    fn unusual_transition_times() {
+        // Nuuk changes at -1:00 on the last Sunday of March, i.e. 22:00 local on Saturday
+        assert_eq!(offset("America/Nuuk", utc(2024, 3, 30, 23, 59)), -2 * 3600);
+        assert_eq!(offset("America/Nuuk", utc(2024, 3, 31, 1, 0)), -3600);
+        // Jerusalem starts on the Friday before the last Sunday of March: M3.4.4/26 is 02:00 Friday
+        assert_eq!(offset("Asia/Jerusalem", utc(2024, 3, 28, 23, 59)), 2 * 3600);
+        assert_eq!(offset("Asia/Jerusalem", utc(2024, 3, 29, 0, 0)), 3 * 3600);
+        assert_eq!(offset("Asia/Kolkata", utc(2024, 6, 1, 0, 0)), 5 * 3600 + 1800);
+    }
+
+    #[test]
+    fn rules() {
+        assert_eq!(Rule::parse("UTC0"), Some(Rule { std_offset: 0, dst: None }));
+        assert_eq!(
+            Rule::parse("<+0545>-5:45"),
+            Some(Rule { std_offset: 5 * 3600 + 45 * 60, dst: None })
+        );
+        assert_eq!(
+            Rule::parse("EST5EDT,J60/1:30,300"),
+            Some(Rule {
+                std_offset: -5 * 3600,
+                dst: Some(Dst {
+                    offset: -4 * 3600,
+                    start: Transition { date: Date::Julian(60), time: 5400 },
+                    end: Transition { date: Date::Ordinal(300), time: 7200 },
+                }),
+            })
+        );
+        assert_eq!(Rule::parse("E5"), None);
+        assert_eq!(Rule::parse("EST5EDT"), None);
+        assert_eq!(Rule::parse("EST5EDT,M13.1.0,M11.1.0"), None);
+        assert_eq!(Rule::parse("EST5EDT,M3.2.0,M11.1.0junk"), None);
+    }
+
+    #[test]
+    fn local_times() {
+        let berlin = zone(find("Europe/Berlin").unwrap()).unwrap();
+        // noon local in winter and summer
+        assert_eq!(berlin.offset_at_local(utc(2024, 1, 10, 12, 0)), 3600);
+        assert_eq!(berlin.offset_at_local(utc(2024, 7, 10, 12, 0)), 7200);
+    }
+
+    #[test]
+    fn picker_names() {
+        assert_eq!(regions()[0], "Africa");
+        assert!(regions().contains(&"UTC"));
+        assert_eq!(region_of("America/Argentina/Buenos_Aires"), "America");
+        assert_eq!(city_of("America/Argentina/Buenos_Aires"), "Argentina/Buenos Aires");
+        assert_eq!(city_of("UTC"), "UTC");
+    }
+}
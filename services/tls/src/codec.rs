@@ -0,0 +1,85 @@
+//! Helpers for the length-prefixed big-endian encodings used by TLS.
+
+use crate::Error;
+
+/// Reads fields from the front of a byte slice.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.data.len() {
+            return Err(Error::Decode);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub(crate) fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+    pub(crate) fn u24(&mut self) -> Result<usize, Error> {
+        let b = self.bytes(3)?;
+        Ok((b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+    /// A vector with a one byte length prefix
+    pub(crate) fn vec8(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+    /// A vector with a two byte length prefix
+    pub(crate) fn vec16(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+    /// A vector with a three byte length prefix
+    pub(crate) fn vec24(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u24()?;
+        self.bytes(len)
+    }
+    /// Fails unless everything has been read.
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Decode)
+        }
+    }
+}
+
+pub(crate) fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_be_bytes());
+}
+
+pub(crate) fn put_u24(out: &mut Vec<u8>, v: usize) {
+    out.extend_from_slice(&(v as u32).to_be_bytes()[1..]);
+}
+
+/// Appends `body` with a one byte length prefix.
+pub(crate) fn put_vec8(out: &mut Vec<u8>, body: &[u8]) {
+    out.push(body.len() as u8);
+    out.extend_from_slice(body);
+}
+
+/// Appends `body` with a two byte length prefix.
+pub(crate) fn put_vec16(out: &mut Vec<u8>, body: &[u8]) {
+    put_u16(out, body.len() as u16);
+    out.extend_from_slice(body);
+}
+
+/// Appends `body` with a three byte length prefix.
+pub(crate) fn put_vec24(out: &mut Vec<u8>, body: &[u8]) {
+    put_u24(out, body.len());
+    out.extend_from_slice(body);
+}
@@ -0,0 +1,80 @@
+//! The key exchange groups: x25519, and secp256r1 for peers that only do the mandatory group.
+
+use crate::Error;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rand_core::{CryptoRng, RngCore};
+
+pub(crate) const SECP256R1: u16 = 0x0017;
+pub(crate) const X25519: u16 = 0x001d;
+
+/// The groups spoken, most preferred first. A client sends a share for the first, and makes a
+/// second one if the server asks for it with a HelloRetryRequest.
+pub(crate) const GROUPS: [u16; 2] = [X25519, SECP256R1];
+
+/// An ephemeral secret for one key exchange.
+pub(crate) enum KeyShare {
+    X25519(x25519_dalek::EphemeralSecret),
+    Secp256r1(p256::SecretKey),
+}
+
+impl KeyShare {
+    /// `group` must be one of `GROUPS`.
+    pub(crate) fn new<R: RngCore + CryptoRng>(group: u16, rng: &mut R) -> KeyShare {
+        match group {
+            X25519 => KeyShare::X25519(x25519_dalek::EphemeralSecret::new(&mut *rng)),
+            SECP256R1 => loop {
+                // p256 wants a newer rand_core than the rest of the crate, so draw the scalar
+                // ourselves; all but a vanishing fraction of 32-byte strings are valid
+                let mut scalar = [0u8; 32];
+                rng.fill_bytes(&mut scalar);
+                if let Ok(secret) = p256::SecretKey::from_be_bytes(&scalar) {
+                    break KeyShare::Secp256r1(secret);
+                }
+            },
+            _ => panic!("unsupported group {:#x}", group),
+        }
+    }
+
+    pub(crate) fn group(&self) -> u16 {
+        match self {
+            KeyShare::X25519(_) => X25519,
+            KeyShare::Secp256r1(_) => SECP256R1,
+        }
+    }
+
+    /// The share to send: a raw x25519 point, or an uncompressed secp256r1 point.
+    pub(crate) fn public(&self) -> Vec<u8> {
+        match self {
+            KeyShare::X25519(secret) => x25519_dalek::PublicKey::from(secret).as_bytes().to_vec(),
+            KeyShare::Secp256r1(secret) => secret.public_key().to_encoded_point(false).as_bytes().to_vec(),
+        }
+    }
+
+    /// The shared secret with the peer's share, which must be in the same group.
+    pub(crate) fn agree(self, peer: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            KeyShare::X25519(secret) => {
+                if peer.len() != 32 {
+                    return Err(Error::IllegalParameter);
+                }
+                let mut point = [0u8; 32];
+                point.copy_from_slice(peer);
+                let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(point));
+                // a low-order peer share gives an all-zero result
+                if shared.as_bytes().iter().all(|&b| b == 0) {
+                    return Err(Error::IllegalParameter);
+                }
+                Ok(shared.as_bytes().to_vec())
+            }
+            KeyShare::Secp256r1(secret) => {
+                // only the uncompressed form is allowed (RFC 8446 section 4.2.8.2)
+                if peer.len() != 65 || peer[0] != 4 {
+                    return Err(Error::IllegalParameter);
+                }
+                let point = p256::PublicKey::from_sec1_bytes(peer).map_err(|_| Error::IllegalParameter)?;
+                let shared = p256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), point.as_affine());
+                Ok(shared.raw_secret_bytes().to_vec())
+            }
+        }
+    }
+}
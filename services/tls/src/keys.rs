@@ -0,0 +1,158 @@
+//! The TLS 1.3 key schedule (RFC 8446 section 7) for TLS_AES_128_GCM_SHA256.
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+
+pub(crate) const HASH_LEN: usize = 32;
+pub(crate) const KEY_LEN: usize = 16;
+pub(crate) const IV_LEN: usize = 12;
+
+pub(crate) type Secret = [u8; HASH_LEN];
+
+/// HKDF-Expand-Label
+pub(crate) fn expand_label(secret: &[u8], label: &str, context: &[u8], out: &mut [u8]) {
+    let mut info = Vec::with_capacity(4 + 6 + label.len() + context.len());
+    info.extend_from_slice(&(out.len() as u16).to_be_bytes());
+    info.push((6 + label.len()) as u8);
+    info.extend_from_slice(b"tls13 ");
+    info.extend_from_slice(label.as_bytes());
+    info.push(context.len() as u8);
+    info.extend_from_slice(context);
+    Hkdf::<Sha256>::from_prk(secret).expect("secret is one hash long")
+        .expand(&info, out).expect("output is short enough");
+}
+
+/// Derive-Secret, given the hash of the messages
+pub(crate) fn derive_secret(secret: &Secret, label: &str, transcript_hash: &[u8]) -> Secret {
+    let mut out = [0u8; HASH_LEN];
+    expand_label(secret, label, transcript_hash, &mut out);
+    out
+}
+
+fn extract(salt: &Secret, ikm: &[u8]) -> Secret {
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), ikm);
+    let mut out = [0u8; HASH_LEN];
+    out.copy_from_slice(&prk);
+    out
+}
+
+fn empty_hash() -> [u8; HASH_LEN] {
+    Sha256::digest(&[]).into()
+}
+
+/// Computes the handshake secret from the (EC)DHE shared secret. No PSKs are used, so the early
+/// secret is always the same.
+pub(crate) fn handshake_secret(shared_secret: &[u8]) -> Secret {
+    let early_secret = extract(&[0u8; HASH_LEN], &[0u8; HASH_LEN]);
+    let derived = derive_secret(&early_secret, "derived", &empty_hash());
+    extract(&derived, shared_secret)
+}
+
+pub(crate) fn master_secret(handshake_secret: &Secret) -> Secret {
+    let derived = derive_secret(handshake_secret, "derived", &empty_hash());
+    extract(&derived, &[0u8; HASH_LEN])
+}
+
+/// The key and IV for one direction of traffic.
+pub(crate) struct TrafficKeys {
+    pub key: [u8; KEY_LEN],
+    pub iv: [u8; IV_LEN],
+}
+
+impl TrafficKeys {
+    pub(crate) fn new(traffic_secret: &Secret) -> TrafficKeys {
+        let mut keys = TrafficKeys { key: [0; KEY_LEN], iv: [0; IV_LEN] };
+        expand_label(traffic_secret, "key", &[], &mut keys.key);
+        expand_label(traffic_secret, "iv", &[], &mut keys.iv);
+        keys
+    }
+}
+
+/// The traffic secret that follows `secret` after a KeyUpdate.
+pub(crate) fn next_traffic_secret(secret: &Secret) -> Secret {
+    derive_secret(secret, "traffic upd", &[])
+}
+
+/// The verify_data of a Finished message sent by the owner of `base_key`.
+pub(crate) fn finished_verify_data(base_key: &Secret, transcript_hash: &[u8]) -> [u8; HASH_LEN] {
+    let mut finished_key = [0u8; HASH_LEN];
+    expand_label(base_key, "finished", &[], &mut finished_key);
+    let mut mac = Hmac::<Sha256>::new_from_slice(&finished_key).expect("HMAC takes any key length");
+    mac.update(transcript_hash);
+    let mut out = [0u8; HASH_LEN];
+    out.copy_from_slice(&mac.finalize().into_bytes());
+    out
+}
+
+/// A running hash of the handshake messages.
+#[derive(Clone)]
+pub(crate) struct Transcript {
+    hash: Sha256,
+}
+
+impl Transcript {
+    pub(crate) fn new() -> Transcript {
+        Transcript { hash: Sha256::new() }
+    }
+    pub(crate) fn add(&mut self, message: &[u8]) {
+        self.hash.update(message);
+    }
+    pub(crate) fn current(&self) -> [u8; HASH_LEN] {
+        self.hash.clone().finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// The key schedule of the simple 1-RTT handshake of RFC 8448 section 3, from the x25519
+    /// shared secret and the transcript hashes
+    #[test]
+    fn rfc8448_simple_1rtt() {
+        let shared = hex("8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d");
+        let handshake_secret = handshake_secret(&shared);
+        assert_eq!(handshake_secret.to_vec(), hex("1dc826e93606aa6fdc0aadc12f741b01046aa6b99f691ed221a9f0ca043fbeac"));
+
+        // ClientHello..ServerHello
+        let hello_hash = hex("860c06edc07858ee8e78f0e7428c58edd6b43f2ca3e6e95f02ed063cf0e1cad8");
+        let client_hs = derive_secret(&handshake_secret, "c hs traffic", &hello_hash);
+        let server_hs = derive_secret(&handshake_secret, "s hs traffic", &hello_hash);
+        assert_eq!(client_hs.to_vec(), hex("b3eddb126e067f35a780b3abf45e2d8f3b1a950738f52e9600746a0e27a55a21"));
+        assert_eq!(server_hs.to_vec(), hex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38"));
+        let keys = TrafficKeys::new(&server_hs);
+        assert_eq!(keys.key.to_vec(), hex("3fce516009c21727d0f2e4e86ee403bc"));
+        assert_eq!(keys.iv.to_vec(), hex("5d313eb2671276ee13000b30"));
+        let keys = TrafficKeys::new(&client_hs);
+        assert_eq!(keys.key.to_vec(), hex("dbfaa693d1762c5b666af5d950258d01"));
+        assert_eq!(keys.iv.to_vec(), hex("5bd3c71b836e0b76bb73265f"));
+
+        // ClientHello..CertificateVerify
+        let verify_hash = hex("edb7725fa7a3473b031ec8ef65a2485493900138a2b91291407d7951a06110ed");
+        assert_eq!(finished_verify_data(&server_hs, &verify_hash).to_vec(),
+            hex("9b9b141d906337fbd2cbdce71df4deda4ab42c309572cb7fffee5454b78f0718"));
+
+        // ClientHello..server Finished
+        let finished_hash = hex("9608102a0f1ccc6db6250b7b7e417b1a000eaada3daae4777a7686c9ff83df13");
+        let master_secret = master_secret(&handshake_secret);
+        assert_eq!(master_secret.to_vec(), hex("18df06843d13a08bf2a449844c5f8a478001bc4d4c627984d5a41da8d0402919"));
+        let client_ap = derive_secret(&master_secret, "c ap traffic", &finished_hash);
+        let server_ap = derive_secret(&master_secret, "s ap traffic", &finished_hash);
+        assert_eq!(client_ap.to_vec(), hex("9e40646ce79a7f9dc05af8889bce6552875afa0b06df0087f792ebb7c17504a5"));
+        assert_eq!(server_ap.to_vec(), hex("a11af9f05531f856ad47116b45a950328204b4f44bfb6b3a4b4f1f3fcb631643"));
+        let keys = TrafficKeys::new(&server_ap);
+        assert_eq!(keys.key.to_vec(), hex("9f02283b6c9c07efc26bb9f2ac92e356"));
+        assert_eq!(keys.iv.to_vec(), hex("cf782b88dd83549aadf1e984"));
+        let keys = TrafficKeys::new(&client_ap);
+        assert_eq!(keys.key.to_vec(), hex("17422dda596ed5d9acd890e3c63f5051"));
+        assert_eq!(keys.iv.to_vec(), hex("5b78923dee08579033e523d9"));
+        assert_eq!(finished_verify_data(&client_hs, &finished_hash).to_vec(),
+            hex("a8ec436d677634ae525ac1fcebe11a039ec17694fac6e98527b642f2edd5ce61"));
+    }
+}
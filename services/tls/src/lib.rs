@@ -0,0 +1,132 @@
+//! TLS 1.3 for Xous.
+//!
+//! `TlsStream` runs TLS 1.3 over any `Read + Write` byte stream, normally a `net::TcpStream`.
+//! Only the TLS_AES_128_GCM_SHA256 cipher suite is spoken, which every TLS 1.3 peer must
+//! support. Keys are exchanged over x25519, or over secp256r1 for peers that ask for it with a
+//! HelloRetryRequest (or offer only that). AES goes through the `aes` crate, and x25519/Ed25519
+//! through the `curve25519-dalek` fork, so the hardware does that work (see the
+//! `[patch.crates-io]` section of the workspace `Cargo.toml`). SHA-256 is computed in software:
+//! the Sha512 engine only accelerates SHA-512 and SHA-512/256, which TLS 1.3 doesn't use.
+//!
+//! Server certificates are validated against a `RootStore`, which is normally loaded from the
+//! `tls.roots` dictionary of the PDDB. Session resumption, 0-RTT and client certificates are not
+//! supported.
+
+mod codec;
+mod groups;
+mod keys;
+mod messages;
+mod pddb_roots;
+mod record;
+mod roots;
+mod server;
+mod stream;
+mod verify;
+mod x509;
+
+pub use pddb_roots::*;
+pub use roots::*;
+pub use server::*;
+pub use stream::*;
+
+/// Why a handshake or connection failed.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// a message couldn't be decoded
+    Decode,
+    /// a message arrived that wasn't expected at that point
+    UnexpectedMessage,
+    /// a record failed authentication
+    BadRecordMac,
+    /// the peer doesn't speak TLS 1.3
+    ProtocolVersion,
+    /// the peer and we have no cipher suite, group or signature scheme in common
+    HandshakeFailure,
+    IllegalParameter,
+    MissingExtension,
+    BadCertificate,
+    /// the certificate uses a key type, algorithm or critical extension that isn't supported
+    UnsupportedCertificate,
+    /// the certificate, or one in its chain, isn't valid at the current time
+    CertificateExpired,
+    /// the certificate chain doesn't lead to a trusted root
+    UnknownIssuer,
+    /// the certificate isn't valid for the server name
+    NameMismatch,
+    /// the CertificateVerify signature didn't verify
+    BadSignature,
+    /// the peer's Finished message didn't verify
+    BadFinished,
+    /// the peer sent a fatal alert with this description
+    AlertReceived(u8),
+    Internal,
+}
+
+impl Error {
+    /// The description of the alert to send for this error, if any.
+    pub(crate) fn alert(&self) -> Option<u8> {
+        match self {
+            Error::Io(_) | Error::AlertReceived(_) => None,
+            Error::UnexpectedMessage => Some(10),
+            Error::BadRecordMac => Some(20),
+            Error::HandshakeFailure => Some(40),
+            Error::BadCertificate | Error::NameMismatch => Some(42),
+            Error::UnsupportedCertificate => Some(43),
+            Error::CertificateExpired => Some(45),
+            Error::IllegalParameter => Some(47),
+            Error::UnknownIssuer => Some(48),
+            Error::Decode => Some(50),
+            Error::BadSignature | Error::BadFinished => Some(51),
+            Error::ProtocolVersion => Some(70),
+            Error::Internal => Some(80),
+            Error::MissingExtension => Some(109),
+        }
+    }
+
+    /// The error for a received alert record.
+    pub(crate) fn from_alert(alert: &[u8]) -> Error {
+        match alert {
+            // close_notify during the handshake, or user_canceled
+            [_, 0] | [_, 90] => Error::Io(std::io::ErrorKind::ConnectionAborted.into()),
+            [_, description] => Error::AlertReceived(*description),
+            _ => Error::Decode,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "TLS I/O error: {}", e),
+            Error::AlertReceived(description) => write!(f, "TLS alert {} received", description),
+            e => write!(f, "TLS error: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> std::io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Opens a TLS connection to `host`, using the roots saved in the PDDB and the TRNG. Blocks until
+/// the PDDB is mounted.
+pub fn connect(host: &str, port: u16) -> Result<TlsStream<net::TcpStream>, Error> {
+    let xns = xous_names::XousNames::new().map_err(|_| Error::Internal)?;
+    let mut trng = trng::Trng::new(&xns).map_err(|_| Error::Internal)?;
+    let mut pddb = pddb::Pddb::new();
+    pddb.is_mounted_blocking(None);
+    let config = ClientConfig::new(RootStore::from_pddb(&mut pddb));
+    if config.roots.is_empty() {
+        log::warn!("there are no roots in the PDDB, so no server can be trusted");
+    }
+    let stream = net::TcpStream::connect_xous((host, port), None, None).map_err(Error::Io)?;
+    TlsStream::connect(stream, host, &config, &mut trng)
+}
@@ -0,0 +1,361 @@
+//! Encoding and decoding of the handshake messages.
+
+use crate::codec::*;
+use crate::groups::GROUPS;
+use crate::Error;
+
+pub(crate) const CLIENT_HELLO: u8 = 1;
+pub(crate) const SERVER_HELLO: u8 = 2;
+pub(crate) const NEW_SESSION_TICKET: u8 = 4;
+pub(crate) const ENCRYPTED_EXTENSIONS: u8 = 8;
+pub(crate) const CERTIFICATE: u8 = 11;
+pub(crate) const CERTIFICATE_REQUEST: u8 = 13;
+pub(crate) const CERTIFICATE_VERIFY: u8 = 15;
+pub(crate) const FINISHED: u8 = 20;
+pub(crate) const KEY_UPDATE: u8 = 24;
+/// Stands in for the first ClientHello in the transcript after a HelloRetryRequest
+pub(crate) const MESSAGE_HASH: u8 = 254;
+
+/// TLS_AES_128_GCM_SHA256, the only cipher suite spoken
+pub(crate) const TLS_AES_128_GCM_SHA256: u16 = 0x1301;
+const TLS13: u16 = 0x0304;
+
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+const EXT_COOKIE: u16 = 44;
+const EXT_KEY_SHARE: u16 = 51;
+
+/// The ServerHello random that marks a HelloRetryRequest (RFC 8446 section 4.1.3)
+const HELLO_RETRY_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// Wraps `body` in a handshake message header.
+pub(crate) fn handshake(msg_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + body.len());
+    out.push(msg_type);
+    put_vec24(&mut out, body);
+    out
+}
+
+fn extension(out: &mut Vec<u8>, ext_type: u16, body: &[u8]) {
+    put_u16(out, ext_type);
+    put_vec16(out, body);
+}
+
+/// Splits an extension block into (type, body) pairs, rejecting duplicates.
+fn extensions(data: &[u8]) -> Result<Vec<(u16, &[u8])>, Error> {
+    let mut r = Reader::new(data);
+    let mut exts: Vec<(u16, &[u8])> = Vec::new();
+    while !r.is_empty() {
+        let ext_type = r.u16()?;
+        let body = r.vec16()?;
+        if exts.iter().any(|&(t, _)| t == ext_type) {
+            return Err(Error::IllegalParameter);
+        }
+        exts.push((ext_type, body));
+    }
+    Ok(exts)
+}
+
+fn find<'a>(exts: &[(u16, &'a [u8])], ext_type: u16) -> Option<&'a [u8]> {
+    exts.iter().find(|&&(t, _)| t == ext_type).map(|&(_, body)| body)
+}
+
+fn encode_list(items: &[u16]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for &item in items {
+        put_u16(&mut list, item);
+    }
+    let mut body = Vec::new();
+    put_vec16(&mut body, &list);
+    body
+}
+
+/// `key_share` is a (group, share) pair, and `cookie` echoes the one in a HelloRetryRequest.
+pub(crate) fn client_hello(random: &[u8; 32], session_id: &[u8; 32], key_share: (u16, &[u8]), cookie: Option<&[u8]>,
+    server_name: Option<&str>, schemes: &[u16], cert_schemes: &[u16]) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_u16(&mut body, 0x0303);
+    body.extend_from_slice(random);
+    put_vec8(&mut body, session_id);
+    put_vec16(&mut body, &TLS_AES_128_GCM_SHA256.to_be_bytes());
+    put_vec8(&mut body, &[0]); // null compression
+
+    let mut exts = Vec::new();
+    if let Some(name) = server_name {
+        let mut entry = vec![0u8]; // host_name
+        put_vec16(&mut entry, name.as_bytes());
+        let mut list = Vec::new();
+        put_vec16(&mut list, &entry);
+        extension(&mut exts, EXT_SERVER_NAME, &list);
+    }
+    extension(&mut exts, EXT_SUPPORTED_VERSIONS, &[2, 0x03, 0x04]);
+    extension(&mut exts, EXT_SUPPORTED_GROUPS, &encode_list(&GROUPS));
+    extension(&mut exts, EXT_SIGNATURE_ALGORITHMS, &encode_list(schemes));
+    if cert_schemes != schemes {
+        // signature_algorithms_cert
+        extension(&mut exts, 50, &encode_list(cert_schemes));
+    }
+    let mut share = Vec::new();
+    put_u16(&mut share, key_share.0);
+    put_vec16(&mut share, key_share.1);
+    let mut shares = Vec::new();
+    put_vec16(&mut shares, &share);
+    extension(&mut exts, EXT_KEY_SHARE, &shares);
+    if let Some(cookie) = cookie {
+        let mut body = Vec::new();
+        put_vec16(&mut body, cookie);
+        extension(&mut exts, EXT_COOKIE, &body);
+    }
+    put_vec16(&mut body, &exts);
+    handshake(CLIENT_HELLO, &body)
+}
+
+/// A ServerHello, or a HelloRetryRequest asking for a second ClientHello.
+pub(crate) struct ServerHello {
+    pub session_id: Vec<u8>,
+    pub retry: bool,
+    /// the group of the server's share, or the group a HelloRetryRequest wants a share for
+    pub group: Option<u16>,
+    /// the server's share; empty in a HelloRetryRequest
+    pub key_share: Vec<u8>,
+    /// a cookie from a HelloRetryRequest, to be sent back
+    pub cookie: Option<Vec<u8>>,
+}
+
+pub(crate) fn parse_server_hello(body: &[u8]) -> Result<ServerHello, Error> {
+    let mut r = Reader::new(body);
+    r.u16()?; // legacy_version
+    let random = r.bytes(32)?;
+    let session_id = r.vec8()?.to_vec();
+    let suite = r.u16()?;
+    let compression = r.u8()?;
+    let exts = extensions(r.vec16()?)?;
+    r.finish()?;
+    match find(&exts, EXT_SUPPORTED_VERSIONS) {
+        Some(v) if v == TLS13.to_be_bytes() => (),
+        // TLS 1.2 or older: this is the only version spoken
+        _ => return Err(Error::ProtocolVersion),
+    }
+    if suite != TLS_AES_128_GCM_SHA256 || compression != 0 {
+        return Err(Error::IllegalParameter);
+    }
+    if random == HELLO_RETRY_RANDOM {
+        let mut group = None;
+        if let Some(selected) = find(&exts, EXT_KEY_SHARE) {
+            let mut r = Reader::new(selected);
+            group = Some(r.u16()?);
+            r.finish()?;
+        }
+        let mut cookie = None;
+        if let Some(body) = find(&exts, EXT_COOKIE) {
+            let mut r = Reader::new(body);
+            cookie = Some(r.vec16()?.to_vec());
+            r.finish()?;
+        }
+        return Ok(ServerHello { session_id, retry: true, group, key_share: Vec::new(), cookie });
+    }
+    let share = find(&exts, EXT_KEY_SHARE).ok_or(Error::MissingExtension)?;
+    let mut r = Reader::new(share);
+    let group = r.u16()?;
+    let key_share = r.vec16()?.to_vec();
+    r.finish()?;
+    Ok(ServerHello { session_id, retry: false, group: Some(group), key_share, cookie: None })
+}
+
+pub(crate) struct ClientHello {
+    pub session_id: Vec<u8>,
+    /// the client's (group, share) pairs, in its order of preference
+    pub key_shares: Vec<(u16, Vec<u8>)>,
+    /// the groups the client supports
+    pub groups: Vec<u16>,
+    pub schemes: Vec<u16>,
+    pub server_name: Option<String>,
+}
+
+pub(crate) fn parse_client_hello(body: &[u8]) -> Result<ClientHello, Error> {
+    let mut r = Reader::new(body);
+    r.u16()?; // legacy_version
+    r.bytes(32)?; // random
+    let session_id = r.vec8()?.to_vec();
+    let suites = r.vec16()?;
+    let compression = r.vec8()?;
+    let exts = extensions(r.vec16()?)?;
+    r.finish()?;
+    if session_id.len() > 32 || !compression.contains(&0) {
+        return Err(Error::IllegalParameter);
+    }
+    let versions = find(&exts, EXT_SUPPORTED_VERSIONS).ok_or(Error::ProtocolVersion)?;
+    let mut r = Reader::new(versions);
+    let mut v = Reader::new(r.vec8()?);
+    let mut tls13 = false;
+    while !v.is_empty() {
+        tls13 |= v.u16()? == TLS13;
+    }
+    if !tls13 {
+        return Err(Error::ProtocolVersion);
+    }
+    if !suites.chunks(2).any(|s| s == TLS_AES_128_GCM_SHA256.to_be_bytes()) {
+        return Err(Error::HandshakeFailure);
+    }
+    let mut key_shares = Vec::new();
+    if let Some(shares) = find(&exts, EXT_KEY_SHARE) {
+        let mut r = Reader::new(shares);
+        let mut r = Reader::new(r.vec16()?);
+        while !r.is_empty() {
+            let group = r.u16()?;
+            key_shares.push((group, r.vec16()?.to_vec()));
+        }
+    }
+    let mut groups = Vec::new();
+    if let Some(list) = find(&exts, EXT_SUPPORTED_GROUPS) {
+        let mut r = Reader::new(list);
+        let mut r = Reader::new(r.vec16()?);
+        while !r.is_empty() {
+            groups.push(r.u16()?);
+        }
+    }
+    let mut schemes = Vec::new();
+    let list = find(&exts, EXT_SIGNATURE_ALGORITHMS).ok_or(Error::MissingExtension)?;
+    let mut r = Reader::new(list);
+    let mut r = Reader::new(r.vec16()?);
+    while !r.is_empty() {
+        schemes.push(r.u16()?);
+    }
+    let mut server_name = None;
+    if let Some(names) = find(&exts, EXT_SERVER_NAME) {
+        let mut r = Reader::new(names);
+        let mut r = Reader::new(r.vec16()?);
+        while !r.is_empty() {
+            let name_type = r.u8()?;
+            let name = r.vec16()?;
+            if name_type == 0 {
+                server_name = Some(String::from_utf8(name.to_vec()).map_err(|_| Error::Decode)?);
+            }
+        }
+    }
+    Ok(ClientHello { session_id, key_shares, groups, schemes, server_name })
+}
+
+fn server_hello_with(random: &[u8; 32], session_id: &[u8], key_share: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_u16(&mut body, 0x0303);
+    body.extend_from_slice(random);
+    put_vec8(&mut body, session_id);
+    put_u16(&mut body, TLS_AES_128_GCM_SHA256);
+    body.push(0);
+    let mut exts = Vec::new();
+    extension(&mut exts, EXT_SUPPORTED_VERSIONS, &TLS13.to_be_bytes());
+    extension(&mut exts, EXT_KEY_SHARE, key_share);
+    put_vec16(&mut body, &exts);
+    handshake(SERVER_HELLO, &body)
+}
+
+pub(crate) fn server_hello(random: &[u8; 32], session_id: &[u8], group: u16, key_share: &[u8]) -> Vec<u8> {
+    let mut share = Vec::new();
+    put_u16(&mut share, group);
+    put_vec16(&mut share, key_share);
+    server_hello_with(random, session_id, &share)
+}
+
+/// Asks the client for a new ClientHello with a share in `group`.
+pub(crate) fn hello_retry_request(session_id: &[u8], group: u16) -> Vec<u8> {
+    server_hello_with(&HELLO_RETRY_RANDOM, session_id, &group.to_be_bytes())
+}
+
+pub(crate) fn encrypted_extensions() -> Vec<u8> {
+    handshake(ENCRYPTED_EXTENSIONS, &[0, 0])
+}
+
+pub(crate) fn parse_encrypted_extensions(body: &[u8]) -> Result<(), Error> {
+    let mut r = Reader::new(body);
+    extensions(r.vec16()?)?;
+    r.finish()
+}
+
+/// `context` is the certificate_request_context, which is empty except in replies to a
+/// CertificateRequest.
+pub(crate) fn certificate(context: &[u8], chain: &[Vec<u8>]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for cert in chain {
+        put_vec24(&mut list, cert);
+        put_u16(&mut list, 0); // no extensions
+    }
+    let mut body = Vec::new();
+    put_vec8(&mut body, context);
+    put_vec24(&mut body, &list);
+    handshake(CERTIFICATE, &body)
+}
+
+/// Returns the DER certificates, leaf first.
+pub(crate) fn parse_certificate(body: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut r = Reader::new(body);
+    r.vec8()?; // certificate_request_context
+    let mut list = Reader::new(r.vec24()?);
+    r.finish()?;
+    let mut chain = Vec::new();
+    while !list.is_empty() {
+        chain.push(list.vec24()?.to_vec());
+        list.vec16()?; // extensions, e.g. stapled OCSP responses
+    }
+    Ok(chain)
+}
+
+/// Returns the certificate_request_context.
+pub(crate) fn parse_certificate_request(body: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut r = Reader::new(body);
+    let context = r.vec8()?.to_vec();
+    extensions(r.vec16()?)?;
+    r.finish()?;
+    Ok(context)
+}
+
+pub(crate) fn certificate_verify(scheme: u16, signature: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_u16(&mut body, scheme);
+    put_vec16(&mut body, signature);
+    handshake(CERTIFICATE_VERIFY, &body)
+}
+
+pub(crate) fn parse_certificate_verify(body: &[u8]) -> Result<(u16, &[u8]), Error> {
+    let mut r = Reader::new(body);
+    let scheme = r.u16()?;
+    let signature = r.vec16()?;
+    r.finish()?;
+    Ok((scheme, signature))
+}
+
+pub(crate) fn finished(verify_data: &[u8]) -> Vec<u8> {
+    handshake(FINISHED, verify_data)
+}
+
+/// `update_requested` asks the peer to update its own keys in turn.
+pub(crate) fn key_update(update_requested: bool) -> Vec<u8> {
+    handshake(KEY_UPDATE, &[update_requested as u8])
+}
+
+/// Returns whether the peer asked for a key update in turn.
+pub(crate) fn parse_key_update(body: &[u8]) -> Result<bool, Error> {
+    match body {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(Error::Decode),
+    }
+}
+
+/// The content that CertificateVerify signs (RFC 8446 section 4.4.3).
+pub(crate) fn certificate_verify_content(server: bool, transcript_hash: &[u8]) -> Vec<u8> {
+    let mut content = vec![0x20u8; 64];
+    if server {
+        content.extend_from_slice(b"TLS 1.3, server CertificateVerify");
+    } else {
+        content.extend_from_slice(b"TLS 1.3, client CertificateVerify");
+    }
+    content.push(0);
+    content.extend_from_slice(transcript_hash);
+    content
+}
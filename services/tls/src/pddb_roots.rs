@@ -0,0 +1,62 @@
+//! Keeps the trusted roots in the PDDB, one DER certificate per key in `ROOTS_DICT`.
+
+use crate::roots::RootStore;
+use crate::Error;
+use pddb::{Pddb, PDDB_DEFAULT_SYSTEM_BASIS};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+/// The dictionary that holds the roots, in the system basis so that they're shared by all bases
+pub const ROOTS_DICT: &str = "tls.roots";
+
+/// Roots are keyed by a truncated SHA-256 of their DER, so adding one twice is harmless.
+fn key_name(der: &[u8]) -> String {
+    Sha256::digest(der)[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl RootStore {
+    /// Loads the roots saved in the PDDB. Keys that don't hold a usable certificate are skipped.
+    pub fn from_pddb(pddb: &mut Pddb) -> RootStore {
+        let mut roots = RootStore::new();
+        let keys = pddb.list_keys(ROOTS_DICT, Some(PDDB_DEFAULT_SYSTEM_BASIS)).unwrap_or(Vec::new());
+        for key in keys.iter() {
+            let mut der = Vec::new();
+            match pddb.get(ROOTS_DICT, key, Some(PDDB_DEFAULT_SYSTEM_BASIS), false, false, None, None::<fn()>) {
+                Ok(mut k) => match k.read_to_end(&mut der) {
+                    Ok(_) => {
+                        if let Err(e) = roots.add_der(&der) {
+                            log::warn!("skipping root {}: {:?}", key, e);
+                        }
+                    }
+                    Err(e) => log::warn!("couldn't read root {}: {:?}", key, e),
+                },
+                Err(e) => log::warn!("couldn't open root {}: {:?}", key, e),
+            }
+        }
+        log::debug!("{} roots loaded from the PDDB", roots.len());
+        roots
+    }
+}
+
+/// Saves a DER root certificate to the PDDB, returning the name of its key.
+pub fn save_root(pddb: &mut Pddb, der: &[u8]) -> Result<String, Error> {
+    // refuse anything that couldn't be loaded back
+    RootStore::new().add_der(der)?;
+    let key = key_name(der);
+    let mut k = pddb.get(ROOTS_DICT, &key, Some(PDDB_DEFAULT_SYSTEM_BASIS), true, true, Some(der.len()), None::<fn()>)
+        .map_err(Error::Io)?;
+    k.write_all(der).map_err(Error::Io)?;
+    pddb.sync().map_err(Error::Io)?;
+    Ok(key)
+}
+
+/// Removes the root saved under `key`.
+pub fn remove_root(pddb: &mut Pddb, key: &str) -> Result<(), Error> {
+    pddb.delete_key(ROOTS_DICT, key, Some(PDDB_DEFAULT_SYSTEM_BASIS)).map_err(Error::Io)?;
+    pddb.sync().map_err(Error::Io)
+}
+
+/// The names of the keys of the saved roots
+pub fn list_roots(pddb: &mut Pddb) -> Vec<String> {
+    pddb.list_keys(ROOTS_DICT, Some(PDDB_DEFAULT_SYSTEM_BASIS)).unwrap_or(Vec::new())
+}
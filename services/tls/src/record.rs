@@ -0,0 +1,145 @@
+//! The record layer: framing, and AES-128-GCM protection of records once keys are established.
+
+use crate::keys::{TrafficKeys, IV_LEN};
+use crate::Error;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{AeadInPlace, NewAead};
+use aes_gcm::Aes128Gcm;
+use std::io::{Read, Write};
+
+pub(crate) const CHANGE_CIPHER_SPEC: u8 = 20;
+pub(crate) const ALERT: u8 = 21;
+pub(crate) const HANDSHAKE: u8 = 22;
+pub(crate) const APPLICATION_DATA: u8 = 23;
+
+/// Largest plaintext fragment allowed in a record
+pub(crate) const MAX_FRAGMENT: usize = 1 << 14;
+/// Largest ciphertext allowed in a record: the fragment, the inner content type, padding and tag
+const MAX_CIPHERTEXT: usize = MAX_FRAGMENT + 256;
+const TAG_LEN: usize = 16;
+
+/// One direction of a protected connection.
+struct Protection {
+    aead: Aes128Gcm,
+    iv: [u8; IV_LEN],
+    seq: u64,
+}
+
+impl Protection {
+    fn new(keys: &TrafficKeys) -> Protection {
+        Protection {
+            aead: Aes128Gcm::new(GenericArray::from_slice(&keys.key)),
+            iv: keys.iv,
+            seq: 0,
+        }
+    }
+    /// The per-record nonce is the IV XORed with the record sequence number.
+    fn nonce(&mut self) -> Result<[u8; IV_LEN], Error> {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[IV_LEN - 8..].iter_mut().zip(self.seq.to_be_bytes().iter()) {
+            *n ^= s;
+        }
+        self.seq = self.seq.checked_add(1).ok_or(Error::Internal)?;
+        Ok(nonce)
+    }
+}
+
+fn header(content_type: u8, len: usize) -> [u8; 5] {
+    let len = (len as u16).to_be_bytes();
+    [content_type, 0x03, 0x03, len[0], len[1]]
+}
+
+/// Reads and writes the records of one connection.
+pub(crate) struct RecordLayer {
+    read: Option<Protection>,
+    write: Option<Protection>,
+}
+
+impl RecordLayer {
+    pub(crate) fn new() -> RecordLayer {
+        RecordLayer { read: None, write: None }
+    }
+
+    pub(crate) fn set_read_keys(&mut self, keys: &TrafficKeys) {
+        self.read = Some(Protection::new(keys));
+    }
+
+    pub(crate) fn set_write_keys(&mut self, keys: &TrafficKeys) {
+        self.write = Some(Protection::new(keys));
+    }
+
+    /// Reads one record, returning its (inner) content type and plaintext. Returns `Ok(None)` if
+    /// the stream ended cleanly before the start of a record.
+    pub(crate) fn read_record<S: Read>(&mut self, stream: &mut S) -> Result<Option<(u8, Vec<u8>)>, Error> {
+        let mut header = [0u8; 5];
+        let mut got = 0;
+        while got < header.len() {
+            match stream.read(&mut header[got..]) {
+                Ok(0) if got == 0 => return Ok(None),
+                Ok(0) => return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into())),
+                Ok(n) => got += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+        let content_type = header[0];
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        if header[1] != 0x03 || len > MAX_CIPHERTEXT {
+            return Err(Error::Decode);
+        }
+        let mut data = vec![0u8; len];
+        stream.read_exact(&mut data).map_err(Error::Io)?;
+
+        let protection = match self.read.as_mut() {
+            // CCS records are sent in the clear even after protection starts, for middlebox compatibility
+            Some(protection) if content_type != CHANGE_CIPHER_SPEC => protection,
+            _ => {
+                if len > MAX_FRAGMENT {
+                    return Err(Error::Decode);
+                }
+                return Ok(Some((content_type, data)));
+            }
+        };
+        if content_type != APPLICATION_DATA || len < TAG_LEN {
+            return Err(Error::UnexpectedMessage);
+        }
+        let nonce = protection.nonce()?;
+        protection.aead.decrypt_in_place(GenericArray::from_slice(&nonce), &header, &mut data)
+            .map_err(|_| Error::BadRecordMac)?;
+        // strip the zero padding; the last nonzero byte is the real content type
+        while let Some(&0) = data.last() {
+            data.pop();
+        }
+        let inner_type = data.pop().ok_or(Error::UnexpectedMessage)?;
+        if data.len() > MAX_FRAGMENT {
+            return Err(Error::Decode);
+        }
+        Ok(Some((inner_type, data)))
+    }
+
+    /// Writes `data` as one or more records of type `content_type`.
+    pub(crate) fn write_records<S: Write>(&mut self, stream: &mut S, content_type: u8, data: &[u8]) -> Result<(), Error> {
+        let mut out = Vec::new();
+        for fragment in data.chunks(MAX_FRAGMENT) {
+            match self.write.as_mut() {
+                Some(protection) => {
+                    let mut record = Vec::with_capacity(fragment.len() + 1 + TAG_LEN);
+                    record.extend_from_slice(fragment);
+                    record.push(content_type);
+                    let header = header(APPLICATION_DATA, record.len() + TAG_LEN);
+                    let nonce = protection.nonce()?;
+                    protection.aead.encrypt_in_place(GenericArray::from_slice(&nonce), &header, &mut record)
+                        .map_err(|_| Error::Internal)?;
+                    out.extend_from_slice(&header);
+                    out.extend_from_slice(&record);
+                }
+                None => {
+                    out.extend_from_slice(&header(content_type, fragment.len()));
+                    out.extend_from_slice(fragment);
+                }
+            }
+        }
+        stream.write_all(&out).map_err(Error::Io)?;
+        stream.flush().map_err(Error::Io)
+    }
+}
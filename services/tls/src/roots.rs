@@ -0,0 +1,84 @@
+use crate::x509::{Certificate, PublicKey};
+use crate::Error;
+
+/// A trusted root certificate. Only its subject and key are used; its validity period and
+/// extensions aren't checked.
+pub(crate) struct TrustAnchor {
+    pub subject: Vec<u8>,
+    pub public_key: PublicKey,
+    pub der: Vec<u8>,
+}
+
+/// The root certificates that server certificate chains are validated against.
+#[derive(Default)]
+pub struct RootStore {
+    anchors: Vec<TrustAnchor>,
+}
+
+impl RootStore {
+    pub fn new() -> RootStore {
+        RootStore { anchors: Vec::new() }
+    }
+
+    /// Adds a DER-encoded root certificate. Roots whose keys aren't supported are refused with
+    /// `Error::UnsupportedCertificate`.
+    pub fn add_der(&mut self, der: &[u8]) -> Result<(), Error> {
+        let (subject, public_key) = Certificate::parse_anchor(der)?;
+        if !self.anchors.iter().any(|a| a.der == der) {
+            self.anchors.push(TrustAnchor { subject, public_key, der: der.to_vec() });
+        }
+        Ok(())
+    }
+
+    /// Adds every certificate in a PEM bundle, returning how many were added. Certificates that
+    /// can't be used are skipped.
+    pub fn add_pem(&mut self, pem: &str) -> usize {
+        let mut added = 0;
+        for der in pem_certificates(pem) {
+            match self.add_der(&der) {
+                Ok(()) => added += 1,
+                Err(e) => log::debug!("skipping root certificate: {:?}", e),
+            }
+        }
+        added
+    }
+
+    pub fn len(&self) -> usize {
+        self.anchors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// The DER encodings of the roots
+    pub fn certificates(&self) -> impl Iterator<Item = &[u8]> {
+        self.anchors.iter().map(|a| a.der.as_slice())
+    }
+
+    pub(crate) fn anchors(&self) -> &[TrustAnchor] {
+        &self.anchors
+    }
+}
+
+/// Extracts the DER certificates from PEM text.
+pub fn pem_certificates(pem: &str) -> Vec<Vec<u8>> {
+    let mut certs = Vec::new();
+    let mut body: Option<String> = None;
+    for line in pem.lines() {
+        let line = line.trim();
+        if line == "-----BEGIN CERTIFICATE-----" {
+            body = Some(String::new());
+        } else if line == "-----END CERTIFICATE-----" {
+            if let Some(b64) = body.take() {
+                match base64::decode(&b64) {
+                    Ok(der) => certs.push(der),
+                    Err(e) => log::debug!("skipping bad PEM block: {:?}", e),
+                }
+            }
+        } else if let Some(b64) = body.as_mut() {
+            b64.push_str(line);
+        }
+    }
+    certs
+}
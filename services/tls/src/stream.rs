@@ -0,0 +1,674 @@
+use crate::groups::{KeyShare, GROUPS};
+use crate::keys::*;
+use crate::messages::*;
+use crate::record::*;
+use crate::roots::RootStore;
+use crate::server::ServerConfig;
+use crate::verify;
+use crate::Error;
+use rand_core::{CryptoRng, RngCore};
+use std::io::{self, Read, Write};
+
+/// What a client needs to connect.
+pub struct ClientConfig {
+    pub roots: RootStore,
+}
+
+impl ClientConfig {
+    pub fn new(roots: RootStore) -> ClientConfig {
+        ClientConfig { roots }
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Compares two MACs without leaking where they differ.
+fn same_mac(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// After a HelloRetryRequest, the first ClientHello is replaced in the transcript by a message
+/// carrying its hash (RFC 8446 section 4.4.1).
+fn replace_with_message_hash(transcript: &mut Transcript) {
+    let first_hello = transcript.current();
+    *transcript = Transcript::new();
+    transcript.add(&handshake(MESSAGE_HASH, &first_hello));
+}
+
+/// A TLS 1.3 connection over `S`, which is usually a `net::TcpStream` but can be anything that
+/// reads and writes bytes. Reads and writes carry application data once `connect` or `accept`
+/// has completed the handshake.
+pub struct TlsStream<S: Read + Write> {
+    stream: S,
+    records: RecordLayer,
+    /// records being gathered up to go out in one write
+    flight: Option<Vec<u8>>,
+    read_secret: Secret,
+    write_secret: Secret,
+    /// handshake bytes received that don't yet make up a whole message
+    handshake: Vec<u8>,
+    /// application data received but not yet read
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+    /// the peer has sent close_notify
+    eof: bool,
+    /// the underlying stream closing without close_notify is taken as the end of the data
+    lenient_eof: bool,
+    client: bool,
+    server_name: Option<String>,
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    fn new(stream: S) -> TlsStream<S> {
+        TlsStream {
+            stream,
+            records: RecordLayer::new(),
+            flight: None,
+            read_secret: [0; HASH_LEN],
+            write_secret: [0; HASH_LEN],
+            handshake: Vec::new(),
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+            eof: false,
+            lenient_eof: false,
+            client: false,
+            server_name: None,
+        }
+    }
+
+    /// Runs the client side of the handshake over `stream`, checking that the server's
+    /// certificate is valid for `server_name` and chains to one of `config.roots`.
+    pub fn connect<R: RngCore + CryptoRng>(stream: S, server_name: &str, config: &ClientConfig, rng: &mut R) -> Result<TlsStream<S>, Error> {
+        let mut tls = TlsStream::new(stream);
+        tls.client = true;
+        tls.server_name = Some(String::from(server_name));
+        match tls.client_handshake(server_name, config, rng) {
+            Ok(()) => Ok(tls),
+            Err(e) => Err(tls.fail(e)),
+        }
+    }
+
+    /// Runs the server side of the handshake over `stream`.
+    pub fn accept<R: RngCore + CryptoRng>(stream: S, config: &ServerConfig, rng: &mut R) -> Result<TlsStream<S>, Error> {
+        let mut tls = TlsStream::new(stream);
+        match tls.server_handshake(config, rng) {
+            Ok(()) => Ok(tls),
+            Err(e) => Err(tls.fail(e)),
+        }
+    }
+
+    /// On the client, the name that was connected to; on the server, the name the client asked
+    /// for, if any.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Whether the underlying stream closing without a close_notify from the peer counts as the end
+    /// of the data. By default reads fail with `UnexpectedEof` instead, because whoever is between
+    /// us and the peer can cut the connection short; callers whose data says where it ends (e.g. a
+    /// `Content-Length`) can allow it for the many servers that don't bother with close_notify.
+    pub fn set_lenient_eof(&mut self, lenient: bool) {
+        self.lenient_eof = lenient;
+    }
+
+    /// Tells the peer that no more data will be sent. The underlying stream stays open.
+    pub fn close_notify(&mut self) -> Result<(), Error> {
+        self.records.write_records(&mut self.stream, ALERT, &[1, 0])
+    }
+
+    /// Sends the alert for `e`, if it has one, and hands `e` back.
+    fn fail(&mut self, e: Error) -> Error {
+        if let Some(description) = e.alert() {
+            self.send(ALERT, &[2, description]).ok();
+        }
+        self.flush_flight().ok();
+        e
+    }
+
+    fn send(&mut self, content_type: u8, data: &[u8]) -> Result<(), Error> {
+        match self.flight.as_mut() {
+            Some(flight) => self.records.write_records(flight, content_type, data),
+            None => self.records.write_records(&mut self.stream, content_type, data),
+        }
+    }
+
+    /// Holds back what is sent until `flush_flight`, so that a flight of handshake messages goes
+    /// out in one write. A peer that gives up partway through reading it then can't cut the
+    /// connection while we are still writing, and have its alert lost.
+    fn start_flight(&mut self) {
+        self.flight = Some(Vec::new());
+    }
+
+    fn flush_flight(&mut self) -> Result<(), Error> {
+        if let Some(flight) = self.flight.take() {
+            self.stream.write_all(&flight).map_err(Error::Io)?;
+            self.stream.flush().map_err(Error::Io)?;
+        }
+        Ok(())
+    }
+
+    fn send_handshake(&mut self, transcript: &mut Transcript, message: &[u8]) -> Result<(), Error> {
+        transcript.add(message);
+        self.send(HANDSHAKE, message)
+    }
+
+    /// Returns the next whole handshake message, header and all, during the handshake.
+    fn read_handshake(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            if let Some(message) = self.take_handshake()? {
+                return Ok(message);
+            }
+            match self.records.read_record(&mut self.stream)? {
+                Some((HANDSHAKE, data)) if !data.is_empty() => self.handshake.extend_from_slice(&data),
+                Some((CHANGE_CIPHER_SPEC, data)) if data == [1] => (),
+                Some((ALERT, data)) => return Err(Error::from_alert(&data)),
+                Some(_) => return Err(Error::UnexpectedMessage),
+                None => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+    }
+
+    /// Reads a handshake message that must be of type `msg_type`, returning it whole.
+    fn expect_handshake(&mut self, msg_type: u8) -> Result<Vec<u8>, Error> {
+        let message = self.read_handshake()?;
+        if message[0] != msg_type {
+            return Err(Error::UnexpectedMessage);
+        }
+        Ok(message)
+    }
+
+    fn take_handshake(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if self.handshake.len() < 4 {
+            return Ok(None);
+        }
+        let len = (self.handshake[1] as usize) << 16 | (self.handshake[2] as usize) << 8 | self.handshake[3] as usize;
+        if len > 0x1_0000 {
+            return Err(Error::Decode);
+        }
+        if self.handshake.len() < 4 + len {
+            return Ok(None);
+        }
+        let rest = self.handshake.split_off(4 + len);
+        Ok(Some(std::mem::replace(&mut self.handshake, rest)))
+    }
+
+    /// Keys must only change on a record boundary.
+    fn check_key_change(&self) -> Result<(), Error> {
+        if self.handshake.is_empty() { Ok(()) } else { Err(Error::UnexpectedMessage) }
+    }
+
+    fn client_handshake<R: RngCore + CryptoRng>(&mut self, server_name: &str, config: &ClientConfig, rng: &mut R) -> Result<(), Error> {
+        let mut random = [0u8; 32];
+        rng.fill_bytes(&mut random);
+        // a non-empty session ID, and the CCS records below, get the handshake past middleboxes
+        let mut session_id = [0u8; 32];
+        rng.fill_bytes(&mut session_id);
+        let mut share = KeyShare::new(GROUPS[0], rng);
+        // SNI carries DNS names only
+        let sni = if server_name.parse::<std::net::IpAddr>().is_ok() { None } else { Some(server_name) };
+
+        let mut transcript = Transcript::new();
+        let hello = client_hello(&random, &session_id, (share.group(), &share.public()), None, sni,
+            &verify::SCHEMES, &verify::CERT_SCHEMES);
+        self.send_handshake(&mut transcript, &hello)?;
+
+        let mut message = self.expect_handshake(SERVER_HELLO)?;
+        let mut server_hello = parse_server_hello(&message[4..])?;
+        if server_hello.retry {
+            // a retry has to ask for something new: a share in another group we offered, or a
+            // cookie to echo (RFC 8446 section 4.1.4)
+            let group = match server_hello.group {
+                Some(group) if group == share.group() || !GROUPS.contains(&group) => return Err(Error::IllegalParameter),
+                Some(group) => group,
+                None if server_hello.cookie.is_some() => share.group(),
+                None => return Err(Error::IllegalParameter),
+            };
+            if server_hello.session_id != session_id {
+                return Err(Error::IllegalParameter);
+            }
+            replace_with_message_hash(&mut transcript);
+            transcript.add(&message);
+            if group != share.group() {
+                share = KeyShare::new(group, rng);
+            }
+            let hello = client_hello(&random, &session_id, (share.group(), &share.public()),
+                server_hello.cookie.as_deref(), sni, &verify::SCHEMES, &verify::CERT_SCHEMES);
+            self.send_handshake(&mut transcript, &hello)?;
+            message = self.expect_handshake(SERVER_HELLO)?;
+            server_hello = parse_server_hello(&message[4..])?;
+            if server_hello.retry {
+                return Err(Error::UnexpectedMessage);
+            }
+        }
+        if server_hello.session_id != session_id || server_hello.group != Some(share.group()) {
+            return Err(Error::IllegalParameter);
+        }
+        transcript.add(&message);
+        let handshake_secret = handshake_secret(&share.agree(&server_hello.key_share)?);
+        let client_hs = derive_secret(&handshake_secret, "c hs traffic", &transcript.current());
+        let server_hs = derive_secret(&handshake_secret, "s hs traffic", &transcript.current());
+        self.check_key_change()?;
+        self.records.set_read_keys(&TrafficKeys::new(&server_hs));
+        // from here on, alerts go out under the handshake keys too
+        self.send(CHANGE_CIPHER_SPEC, &[1])?;
+        self.records.set_write_keys(&TrafficKeys::new(&client_hs));
+
+        let message = self.expect_handshake(ENCRYPTED_EXTENSIONS)?;
+        parse_encrypted_extensions(&message[4..])?;
+        transcript.add(&message);
+
+        let mut message = self.read_handshake()?;
+        let mut cert_request = None;
+        if message[0] == CERTIFICATE_REQUEST {
+            cert_request = Some(parse_certificate_request(&message[4..])?);
+            transcript.add(&message);
+            message = self.read_handshake()?;
+        }
+        if message[0] != CERTIFICATE {
+            return Err(Error::UnexpectedMessage);
+        }
+        let chain = parse_certificate(&message[4..])?;
+        transcript.add(&message);
+        let server_key = verify::verify_chain(&chain, &config.roots, server_name, now())?;
+
+        let message = self.expect_handshake(CERTIFICATE_VERIFY)?;
+        let (scheme, signature) = parse_certificate_verify(&message[4..])?;
+        let content = certificate_verify_content(true, &transcript.current());
+        verify::verify_scheme(&server_key, scheme, &content, signature)?;
+        transcript.add(&message);
+
+        let message = self.expect_handshake(FINISHED)?;
+        let expected = finished_verify_data(&server_hs, &transcript.current());
+        if !same_mac(&message[4..], &expected) {
+            return Err(Error::BadFinished);
+        }
+        transcript.add(&message);
+
+        let master_secret = master_secret(&handshake_secret);
+        self.read_secret = derive_secret(&master_secret, "s ap traffic", &transcript.current());
+        self.write_secret = derive_secret(&master_secret, "c ap traffic", &transcript.current());
+        self.check_key_change()?;
+        self.records.set_read_keys(&TrafficKeys::new(&self.read_secret));
+
+        if let Some(context) = cert_request {
+            // there are no client certificates; the server decides whether to go on without one
+            self.send_handshake(&mut transcript, &certificate(&context, &[]))?;
+        }
+        let verify_data = finished_verify_data(&client_hs, &transcript.current());
+        self.send_handshake(&mut transcript, &finished(&verify_data))?;
+        self.records.set_write_keys(&TrafficKeys::new(&self.write_secret));
+        Ok(())
+    }
+
+    fn server_handshake<R: RngCore + CryptoRng>(&mut self, config: &ServerConfig, rng: &mut R) -> Result<(), Error> {
+        let mut transcript = Transcript::new();
+        let mut message = self.expect_handshake(CLIENT_HELLO)?;
+        let mut hello = parse_client_hello(&message[4..])?;
+        // take a share in the most preferred group the client sent one for, or else ask for a
+        // share in the most preferred group it supports
+        let has_share = |hello: &ClientHello, group: u16| hello.key_shares.iter().any(|&(g, _)| g == group);
+        let group = GROUPS.iter().copied().find(|&g| has_share(&hello, g))
+            .or_else(|| GROUPS.iter().copied().find(|g| hello.groups.contains(g)))
+            .ok_or(Error::HandshakeFailure)?;
+        // the CCS record for middlebox compatibility follows the first ServerHello or HelloRetryRequest
+        let mut compatibility_ccs = !hello.session_id.is_empty();
+        if !has_share(&hello, group) {
+            transcript.add(&message);
+            replace_with_message_hash(&mut transcript);
+            self.start_flight();
+            self.send_handshake(&mut transcript, &hello_retry_request(&hello.session_id, group))?;
+            if compatibility_ccs {
+                self.send(CHANGE_CIPHER_SPEC, &[1])?;
+                compatibility_ccs = false;
+            }
+            self.flush_flight()?;
+            let session_id = hello.session_id;
+            message = self.expect_handshake(CLIENT_HELLO)?;
+            hello = parse_client_hello(&message[4..])?;
+            if hello.session_id != session_id || !has_share(&hello, group) {
+                return Err(Error::IllegalParameter);
+            }
+        }
+        transcript.add(&message);
+        if !hello.schemes.contains(&config.key.scheme()) {
+            return Err(Error::HandshakeFailure);
+        }
+        let peer_share = hello.key_shares.iter().find(|&&(g, _)| g == group).map(|(_, share)| share.clone())
+            .ok_or(Error::IllegalParameter)?;
+        self.server_name = hello.server_name;
+
+        let mut random = [0u8; 32];
+        rng.fill_bytes(&mut random);
+        let share = KeyShare::new(group, rng);
+        // the whole flight, up to Finished, goes out at once
+        self.start_flight();
+        self.send_handshake(&mut transcript, &server_hello(&random, &hello.session_id, group, &share.public()))?;
+        if compatibility_ccs {
+            self.send(CHANGE_CIPHER_SPEC, &[1])?;
+        }
+
+        let handshake_secret = handshake_secret(&share.agree(&peer_share)?);
+        let client_hs = derive_secret(&handshake_secret, "c hs traffic", &transcript.current());
+        let server_hs = derive_secret(&handshake_secret, "s hs traffic", &transcript.current());
+        self.check_key_change()?;
+        self.records.set_read_keys(&TrafficKeys::new(&client_hs));
+        self.records.set_write_keys(&TrafficKeys::new(&server_hs));
+
+        self.send_handshake(&mut transcript, &encrypted_extensions())?;
+        self.send_handshake(&mut transcript, &certificate(&[], &config.chain))?;
+        let content = certificate_verify_content(true, &transcript.current());
+        let signature = config.key.sign(&content)?;
+        self.send_handshake(&mut transcript, &certificate_verify(config.key.scheme(), &signature))?;
+        let verify_data = finished_verify_data(&server_hs, &transcript.current());
+        self.send_handshake(&mut transcript, &finished(&verify_data))?;
+        self.flush_flight()?;
+
+        let master_secret = master_secret(&handshake_secret);
+        self.read_secret = derive_secret(&master_secret, "c ap traffic", &transcript.current());
+        self.write_secret = derive_secret(&master_secret, "s ap traffic", &transcript.current());
+        self.records.set_write_keys(&TrafficKeys::new(&self.write_secret));
+
+        let message = self.expect_handshake(FINISHED)?;
+        let expected = finished_verify_data(&client_hs, &transcript.current());
+        if !same_mac(&message[4..], &expected) {
+            return Err(Error::BadFinished);
+        }
+        self.check_key_change()?;
+        self.records.set_read_keys(&TrafficKeys::new(&self.read_secret));
+        Ok(())
+    }
+
+    /// Handles a handshake message that arrives after the handshake.
+    fn post_handshake(&mut self, message: &[u8]) -> Result<(), Error> {
+        match message[0] {
+            // session resumption isn't supported, so tickets are of no use
+            NEW_SESSION_TICKET if self.client => Ok(()),
+            KEY_UPDATE => {
+                let update_requested = parse_key_update(&message[4..])?;
+                self.check_key_change()?;
+                self.read_secret = next_traffic_secret(&self.read_secret);
+                self.records.set_read_keys(&TrafficKeys::new(&self.read_secret));
+                if update_requested {
+                    self.send(HANDSHAKE, &key_update(false))?;
+                    self.write_secret = next_traffic_secret(&self.write_secret);
+                    self.records.set_write_keys(&TrafficKeys::new(&self.write_secret));
+                }
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    /// Reads records until there is application data to return, or the peer closes.
+    fn fill_plaintext(&mut self) -> Result<(), Error> {
+        while self.plaintext_pos == self.plaintext.len() && !self.eof {
+            match self.records.read_record(&mut self.stream)? {
+                Some((APPLICATION_DATA, data)) => {
+                    if !self.handshake.is_empty() {
+                        return Err(Error::UnexpectedMessage);
+                    }
+                    self.plaintext = data;
+                    self.plaintext_pos = 0;
+                }
+                Some((HANDSHAKE, data)) if !data.is_empty() => {
+                    self.handshake.extend_from_slice(&data);
+                    while let Some(message) = self.take_handshake()? {
+                        self.post_handshake(&message)?;
+                    }
+                }
+                Some((ALERT, data)) if data == [1, 0] => self.eof = true,
+                Some((ALERT, data)) => return Err(Error::from_alert(&data)),
+                Some(_) => return Err(Error::UnexpectedMessage),
+                // a truncation attack looks just like this
+                None if self.lenient_eof => self.eof = true,
+                None => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Err(e) = self.fill_plaintext() {
+            return Err(self.fail(e).into());
+        }
+        let available = &self.plaintext[self.plaintext_pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.plaintext_pos += len;
+        Ok(len)
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(APPLICATION_DATA, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ServerKey;
+    use std::net::TcpStream;
+    use std::process::{Command, Stdio};
+
+    const ROOT: &str = include_str!("../testdata/root.pem");
+    // same subject as ROOT, but a different key
+    const IMPOSTOR_ROOT: &str = include_str!("../testdata/impostor-root.pem");
+    const LEAF: &str = include_str!("../testdata/localhost.pem");
+    const LEAF_KEY: &str = include_str!("../testdata/localhost.key");
+
+    /// Runs a server on a loopback port that answers one connection by echoing one read.
+    fn echo_server() -> (u16, std::thread::JoinHandle<Result<Option<String>, Error>>) {
+        let mut listener = net::TcpListener::bind_xous("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = ServerConfig::new(crate::pem_certificates(LEAF), ServerKey::from_pem(LEAF_KEY).unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut tls = TlsStream::accept(stream, &config, &mut rand::rngs::OsRng)?;
+            let mut buf = [0u8; 64];
+            let len = tls.read(&mut buf).map_err(Error::Io)?;
+            tls.write_all(&buf[..len]).map_err(Error::Io)?;
+            tls.close_notify()?;
+            Ok(tls.server_name().map(String::from))
+        });
+        (port, server)
+    }
+
+    /// Runs a server on a loopback port that sends `data` and then closes the connection without
+    /// a close_notify.
+    fn truncating_server(data: &'static [u8]) -> u16 {
+        let mut listener = net::TcpListener::bind_xous("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = ServerConfig::new(crate::pem_certificates(LEAF), ServerKey::from_pem(LEAF_KEY).unwrap());
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut tls = TlsStream::accept(stream, &config, &mut rand::rngs::OsRng).unwrap();
+            tls.write_all(data).unwrap();
+        });
+        port
+    }
+
+    fn connect<S: Read + Write>(stream: S, name: &str, root: &str) -> Result<TlsStream<S>, Error> {
+        let mut roots = RootStore::new();
+        assert_eq!(roots.add_pem(root), 1);
+        TlsStream::connect(stream, name, &ClientConfig::new(roots), &mut rand::rngs::OsRng)
+    }
+
+    fn loopback(port: u16) -> TcpStream {
+        TcpStream::connect(("127.0.0.1", port)).unwrap()
+    }
+
+    #[test]
+    fn loopback_handshake() {
+        let (port, server) = echo_server();
+        let mut tls = connect(loopback(port), "localhost", ROOT).unwrap();
+        tls.write_all(b"precursor").unwrap();
+        let mut echo = Vec::new();
+        tls.read_to_end(&mut echo).unwrap();
+        assert_eq!(echo, b"precursor");
+        assert_eq!(server.join().unwrap().unwrap().as_deref(), Some("localhost"));
+    }
+
+    #[test]
+    fn truncation() {
+        let port = truncating_server(b"precursor");
+        let mut tls = connect(loopback(port), "localhost", ROOT).unwrap();
+        let mut data = Vec::new();
+        let e = tls.read_to_end(&mut data).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(data, b"precursor");
+
+        let port = truncating_server(b"precursor");
+        let mut tls = connect(loopback(port), "localhost", ROOT).unwrap();
+        tls.set_lenient_eof(true);
+        let mut data = Vec::new();
+        tls.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"precursor");
+    }
+
+    #[test]
+    fn ip_address_name() {
+        let (port, server) = echo_server();
+        let mut tls = connect(loopback(port), "127.0.0.1", ROOT).unwrap();
+        tls.write_all(b"x").unwrap();
+        // no SNI is sent for an address
+        assert_eq!(server.join().unwrap().unwrap(), None);
+    }
+
+    #[test]
+    fn wrong_name() {
+        let (port, server) = echo_server();
+        // the socket stays open until the server has read the alert: closing it with the rest of
+        // the server's flight unread would reset the connection, and the alert with it
+        let stream = loopback(port);
+        assert!(matches!(connect(&stream, "betrusted.io", ROOT), Err(Error::NameMismatch)));
+        assert!(matches!(server.join().unwrap(), Err(Error::AlertReceived(42))));
+    }
+
+    #[test]
+    fn untrusted_root() {
+        let (port, server) = echo_server();
+        let stream = loopback(port);
+        assert!(matches!(connect(&stream, "localhost", IMPOSTOR_ROOT), Err(Error::UnknownIssuer)));
+        assert!(matches!(server.join().unwrap(), Err(Error::AlertReceived(48))));
+    }
+
+    /// An `openssl` command, if openssl is installed to stand in for an independent TLS stack.
+    fn openssl() -> Option<Command> {
+        match Command::new("openssl").arg("version").output() {
+            Ok(output) if output.status.success() => Some(Command::new("openssl")),
+            _ => {
+                eprintln!("openssl isn't installed, so interop isn't tested");
+                None
+            }
+        }
+    }
+
+    fn testdata(file: &str) -> String {
+        format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), file)
+    }
+
+    /// Fetches a page from `openssl s_server`, which will only use a share in `groups`.
+    fn fetch_from_openssl(groups: &str) {
+        let mut openssl = match openssl() {
+            Some(openssl) => openssl,
+            None => return,
+        };
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut server = openssl
+            .args(["s_server", "-tls1_3", "-naccept", "1", "-www", "-groups", groups])
+            .args(["-accept", &port.to_string(), "-key", &testdata("localhost.key"), "-cert", &testdata("localhost.pem")])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let stream = (0..100).find_map(|_| {
+            let stream = net::TcpStream::connect_xous(("127.0.0.1", port), None, None).ok();
+            if stream.is_none() {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            stream
+        });
+        let result = stream.ok_or(Error::Internal).and_then(|stream| {
+            let mut tls = connect(stream, "localhost", ROOT)?;
+            tls.write_all(b"GET / HTTP/1.0\r\n\r\n").map_err(Error::Io)?;
+            let mut page = Vec::new();
+            tls.read_to_end(&mut page).map_err(Error::Io)?;
+            Ok(page)
+        });
+        server.kill().ok();
+        server.wait().ok();
+        assert!(result.unwrap().starts_with(b"HTTP/1.0 200 ok"));
+    }
+
+    /// Has `openssl s_client`, offering `groups` and sending a share in the first, talk to the
+    /// echo server.
+    fn echo_through_openssl(groups: &str) {
+        let mut openssl = match openssl() {
+            Some(openssl) => openssl,
+            None => return,
+        };
+        let (port, server) = echo_server();
+        let mut client = openssl
+            .args(["s_client", "-tls1_3", "-quiet", "-verify_return_error", "-groups", groups])
+            .args(["-connect", &format!("127.0.0.1:{}", port), "-servername", "localhost", "-CAfile", &testdata("root.pem")])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        client.stdin.take().unwrap().write_all(b"precursor").unwrap();
+        let output = client.wait_with_output().unwrap();
+        assert_eq!(server.join().unwrap().unwrap().as_deref(), Some("localhost"));
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"precursor");
+    }
+
+    #[test]
+    fn openssl_server() {
+        fetch_from_openssl("X25519");
+    }
+
+    #[test]
+    fn openssl_server_hello_retry() {
+        // the x25519 share sent first is turned down
+        fetch_from_openssl("P-256");
+    }
+
+    #[test]
+    fn openssl_client() {
+        echo_through_openssl("X25519:P-256");
+    }
+
+    #[test]
+    fn openssl_client_hello_retry() {
+        // the P-384 share is turned down, and a P-256 one asked for
+        echo_through_openssl("P-384:P-256");
+    }
+
+    #[test]
+    fn openssl_client_secp256r1() {
+        echo_through_openssl("P-256");
+    }
+}
@@ -0,0 +1,232 @@
+//! Signature verification, and validation of certificate chains against a `RootStore`.
+
+use crate::roots::RootStore;
+use crate::x509::*;
+use crate::Error;
+use rsa::{BigUint, PaddingScheme, PublicKeyParts, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+// the SignatureScheme code points
+pub(crate) const RSA_PKCS1_SHA256: u16 = 0x0401;
+pub(crate) const RSA_PKCS1_SHA384: u16 = 0x0501;
+pub(crate) const RSA_PKCS1_SHA512: u16 = 0x0601;
+pub(crate) const ECDSA_SECP256R1_SHA256: u16 = 0x0403;
+pub(crate) const ECDSA_SECP384R1_SHA384: u16 = 0x0503;
+pub(crate) const RSA_PSS_RSAE_SHA256: u16 = 0x0804;
+pub(crate) const RSA_PSS_RSAE_SHA384: u16 = 0x0805;
+pub(crate) const RSA_PSS_RSAE_SHA512: u16 = 0x0806;
+pub(crate) const ED25519: u16 = 0x0807;
+
+/// The schemes offered for CertificateVerify. RSASSA-PKCS1-v1_5 may not be used there in TLS 1.3.
+pub(crate) const SCHEMES: [u16; 6] = [
+    ECDSA_SECP256R1_SHA256, ECDSA_SECP384R1_SHA384,
+    RSA_PSS_RSAE_SHA256, RSA_PSS_RSAE_SHA384, RSA_PSS_RSAE_SHA512, ED25519,
+];
+/// The schemes accepted in certificates
+pub(crate) const CERT_SCHEMES: [u16; 9] = [
+    ECDSA_SECP256R1_SHA256, ECDSA_SECP384R1_SHA384,
+    RSA_PSS_RSAE_SHA256, RSA_PSS_RSAE_SHA384, RSA_PSS_RSAE_SHA512, ED25519,
+    RSA_PKCS1_SHA256, RSA_PKCS1_SHA384, RSA_PKCS1_SHA512,
+];
+
+/// Longest chain accepted, not counting the root
+const MAX_CHAIN_DEPTH: usize = 6;
+
+pub(crate) fn hash(hash: Hash, message: &[u8]) -> Vec<u8> {
+    match hash {
+        Hash::Sha256 => Sha256::digest(message).to_vec(),
+        Hash::Sha384 => Sha384::digest(message).to_vec(),
+        Hash::Sha512 => Sha512::digest(message).to_vec(),
+    }
+}
+
+/// Checks a CertificateVerify signature made with `scheme`.
+pub(crate) fn verify_scheme(key: &PublicKey, scheme: u16, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let algorithm = match (scheme, key) {
+        (ECDSA_SECP256R1_SHA256, PublicKey::P256(_)) => Algorithm::Ecdsa(Hash::Sha256),
+        (ECDSA_SECP384R1_SHA384, PublicKey::P384(_)) => Algorithm::Ecdsa(Hash::Sha384),
+        (RSA_PSS_RSAE_SHA256, PublicKey::Rsa { .. }) => Algorithm::RsaPss(Hash::Sha256),
+        (RSA_PSS_RSAE_SHA384, PublicKey::Rsa { .. }) => Algorithm::RsaPss(Hash::Sha384),
+        (RSA_PSS_RSAE_SHA512, PublicKey::Rsa { .. }) => Algorithm::RsaPss(Hash::Sha512),
+        (ED25519, PublicKey::Ed25519(_)) => Algorithm::Ed25519,
+        _ => return Err(Error::IllegalParameter),
+    };
+    verify_signature(key, algorithm, message, signature)
+}
+
+pub(crate) fn verify_signature(key: &PublicKey, algorithm: Algorithm, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    use p256::ecdsa::signature::hazmat::PrehashVerifier;
+    let ok = match (key, algorithm) {
+        (PublicKey::P256(point), Algorithm::Ecdsa(h)) => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(|_| Error::BadCertificate)?;
+            let signature = p256::ecdsa::Signature::from_der(signature).map_err(|_| Error::BadSignature)?;
+            key.verify_prehash(&hash(h, message), &signature).is_ok()
+        }
+        (PublicKey::P384(point), Algorithm::Ecdsa(h)) => {
+            let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(|_| Error::BadCertificate)?;
+            let signature = p384::ecdsa::Signature::from_der(signature).map_err(|_| Error::BadSignature)?;
+            key.verify_prehash(&hash(h, message), &signature).is_ok()
+        }
+        (PublicKey::Rsa { n, e }, Algorithm::RsaPkcs1(h)) => {
+            let key = rsa_key(n, e)?;
+            let scheme = match h {
+                Hash::Sha256 => rsa::Hash::SHA2_256,
+                Hash::Sha384 => rsa::Hash::SHA2_384,
+                Hash::Sha512 => rsa::Hash::SHA2_512,
+            };
+            use rsa::PublicKey as _;
+            key.verify(PaddingScheme::new_pkcs1v15_sign(Some(scheme)), &hash(h, message), signature).is_ok()
+        }
+        (PublicKey::Rsa { n, e }, Algorithm::RsaPss(h)) => verify_pss(&rsa_key(n, e)?, h, message, signature),
+        (PublicKey::Ed25519(point), Algorithm::Ed25519) => {
+            let key = ed25519_dalek::PublicKey::from_bytes(point).map_err(|_| Error::BadCertificate)?;
+            let signature = ed25519_dalek::Signature::from_bytes(signature).map_err(|_| Error::BadSignature)?;
+            key.verify_strict(message, &signature).is_ok()
+        }
+        _ => return Err(Error::BadCertificate),
+    };
+    if ok { Ok(()) } else { Err(Error::BadSignature) }
+}
+
+/// RSA moduli outside this range are refused: smaller ones are too weak, and larger ones would let
+/// a peer make us spend ages on a single signature.
+const RSA_MIN_BITS: usize = 2048;
+const RSA_MAX_BITS: usize = 8192;
+
+fn rsa_key(n: &[u8], e: &[u8]) -> Result<RsaPublicKey, Error> {
+    let n = BigUint::from_bytes_be(n);
+    if n.bits() < RSA_MIN_BITS || n.bits() > RSA_MAX_BITS {
+        return Err(Error::UnsupportedCertificate);
+    }
+    RsaPublicKey::new(n, BigUint::from_bytes_be(e)).map_err(|_| Error::UnsupportedCertificate)
+}
+
+fn mgf1(h: Hash, seed: &[u8], len: usize) -> Vec<u8> {
+    let mut mask = Vec::with_capacity(len + 64);
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut block = seed.to_vec();
+        block.extend_from_slice(&counter.to_be_bytes());
+        mask.extend_from_slice(&hash(h, &block));
+        counter += 1;
+    }
+    mask.truncate(len);
+    mask
+}
+
+/// EMSA-PSS-VERIFY (RFC 8017 section 9.1.2) with MGF1 and a salt as long as the hash, as TLS 1.3
+/// requires. This is done here rather than by the `rsa` crate so that the hashing goes through
+/// the patched `sha2`, which puts SHA-512 on the hardware.
+fn verify_pss(key: &RsaPublicKey, h: Hash, message: &[u8], signature: &[u8]) -> bool {
+    let k = key.size();
+    if signature.len() != k {
+        return false;
+    }
+    let s = BigUint::from_bytes_be(signature);
+    if &s >= key.n() {
+        return false;
+    }
+    let m = s.modpow(key.e(), key.n()).to_bytes_be();
+    let em_bits = key.n().bits() - 1;
+    let em_len = (em_bits + 7) / 8;
+    if m.len() > em_len {
+        return false;
+    }
+    let mut em = vec![0u8; em_len - m.len()];
+    em.extend_from_slice(&m);
+
+    let m_hash = hash(h, message);
+    let h_len = m_hash.len();
+    let s_len = h_len;
+    if em_len < h_len + s_len + 2 || em[em_len - 1] != 0xbc {
+        return false;
+    }
+    let (masked_db, rest) = em.split_at(em_len - h_len - 1);
+    let hash_h = &rest[..h_len];
+    let top_bits = 8 * em_len - em_bits;
+    if top_bits > 0 && masked_db[0] >> (8 - top_bits) != 0 {
+        return false;
+    }
+    let mut db: Vec<u8> = masked_db.iter().zip(mgf1(h, hash_h, masked_db.len())).map(|(a, b)| a ^ b).collect();
+    if top_bits > 0 {
+        db[0] &= 0xff >> top_bits;
+    }
+    let pad_len = em_len - h_len - s_len - 2;
+    if db[..pad_len].iter().any(|&b| b != 0) || db[pad_len] != 0x01 {
+        return false;
+    }
+    let salt = &db[pad_len + 1..];
+    let mut m_prime = vec![0u8; 8];
+    m_prime.extend_from_slice(&m_hash);
+    m_prime.extend_from_slice(salt);
+    hash(h, &m_prime) == hash_h
+}
+
+/// Validates `chain`, leaf first, as the certificates of the server `name` at time `now` (in
+/// seconds since the Unix epoch). Returns the leaf's public key.
+pub(crate) fn verify_chain(chain: &[Vec<u8>], roots: &RootStore, name: &str, now: u64) -> Result<PublicKey, Error> {
+    let leaf_der = chain.first().ok_or(Error::BadCertificate)?;
+    let leaf = Certificate::parse(leaf_der)?;
+    if !leaf.valid_at(now) {
+        return Err(Error::CertificateExpired);
+    }
+    if !leaf.matches_name(name) {
+        return Err(Error::NameMismatch);
+    }
+    if !leaf.server_auth || leaf.key_usage.map(|u| u & KEY_USAGE_DIGITAL_SIGNATURE == 0).unwrap_or(false) {
+        return Err(Error::BadCertificate);
+    }
+    // servers may send certificates in any order after the leaf, and extra ones too
+    let intermediates: Vec<Certificate> = chain[1..].iter()
+        .filter_map(|der| Certificate::parse(der).ok())
+        .collect();
+
+    let mut current = &leaf;
+    for depth in 0..=MAX_CHAIN_DEPTH {
+        for anchor in roots.anchors() {
+            if anchor.subject == current.issuer
+            && verify_signature(&anchor.public_key, current.signature_algorithm, &current.tbs, &current.signature).is_ok() {
+                return Ok(leaf.public_key.clone());
+            }
+        }
+        if depth == MAX_CHAIN_DEPTH {
+            break;
+        }
+        let issuer = intermediates.iter().find(|cert| {
+            cert.subject == current.issuer
+            && cert.ca.map(|path_len| path_len.map(|n| depth as u32 <= n).unwrap_or(true)).unwrap_or(false)
+            && cert.key_usage.map(|u| u & KEY_USAGE_KEY_CERT_SIGN != 0).unwrap_or(true)
+            && cert.server_auth
+            && verify_signature(&cert.public_key, current.signature_algorithm, &current.tbs, &current.signature).is_ok()
+        });
+        match issuer {
+            Some(issuer) => {
+                if !issuer.valid_at(now) {
+                    return Err(Error::CertificateExpired);
+                }
+                current = issuer;
+            }
+            None => break,
+        }
+    }
+    Err(Error::UnknownIssuer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rsa_modulus_size() {
+        let e = [1, 0, 1];
+        let modulus = |bits: usize| {
+            let mut n = vec![0xffu8; bits / 8];
+            n[0] = 0xc3;
+            n
+        };
+        assert!(matches!(rsa_key(&modulus(1024), &e), Err(Error::UnsupportedCertificate)));
+        assert!(rsa_key(&modulus(2048), &e).is_ok());
+        assert!(rsa_key(&modulus(8192), &e).is_ok());
+        assert!(matches!(rsa_key(&modulus(8200), &e), Err(Error::UnsupportedCertificate)));
+    }
+}
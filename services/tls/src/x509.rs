@@ -0,0 +1,472 @@
+//! A minimal DER decoder, and just enough X.509 to validate server certificates.
+
+use crate::Error;
+
+pub(crate) const TAG_BOOLEAN: u8 = 0x01;
+pub(crate) const TAG_INTEGER: u8 = 0x02;
+pub(crate) const TAG_BIT_STRING: u8 = 0x03;
+pub(crate) const TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const TAG_OID: u8 = 0x06;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const TAG_SEQUENCE: u8 = 0x30;
+
+// object identifiers, as the bytes of their DER encoding
+pub(crate) const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+pub(crate) const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+pub(crate) const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+pub(crate) const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+pub(crate) const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+const OID_SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const OID_SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const OID_ECDSA_WITH_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x04];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const OID_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+pub(crate) const OID_SERVER_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+const OID_ANY_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25, 0x00];
+
+/// Reads DER elements from the front of a byte slice.
+pub(crate) struct Der<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Der<'a> {
+        Der { data }
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    /// Returns the tag, the contents, and the whole encoded element.
+    pub(crate) fn any(&mut self) -> Result<(u8, &'a [u8], &'a [u8]), Error> {
+        let data = self.data;
+        if data.len() < 2 || data[0] & 0x1f == 0x1f {
+            // multi-byte tags don't occur in certificates
+            return Err(Error::BadCertificate);
+        }
+        let (len, header) = match data[1] {
+            n if n < 0x80 => (n as usize, 2),
+            0x81..=0x84 => {
+                let count = (data[1] & 0x7f) as usize;
+                if data.len() < 2 + count {
+                    return Err(Error::BadCertificate);
+                }
+                let len = data[2..2 + count].iter().fold(0usize, |len, &b| len << 8 | b as usize);
+                // DER requires the shortest form
+                if len < 0x80 || data[2] == 0 {
+                    return Err(Error::BadCertificate);
+                }
+                (len, 2 + count)
+            }
+            _ => return Err(Error::BadCertificate),
+        };
+        if data.len() - header < len {
+            return Err(Error::BadCertificate);
+        }
+        self.data = &data[header + len..];
+        Ok((data[0], &data[header..header + len], &data[..header + len]))
+    }
+    /// Reads an element that must have the tag `tag`, returning its contents.
+    pub(crate) fn expect(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        match self.any()? {
+            (t, contents, _) if t == tag => Ok(contents),
+            _ => Err(Error::BadCertificate),
+        }
+    }
+    /// Reads an element if it has the tag `tag`.
+    pub(crate) fn optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>, Error> {
+        match self.data.first() {
+            Some(&t) if t == tag => Ok(Some(self.expect(tag)?)),
+            _ => Ok(None),
+        }
+    }
+    /// The contents of a BIT STRING with no unused bits
+    pub(crate) fn bit_string(&mut self) -> Result<&'a [u8], Error> {
+        match self.expect(TAG_BIT_STRING)? {
+            [0, bits @ ..] => Ok(bits),
+            _ => Err(Error::BadCertificate),
+        }
+    }
+    /// The magnitude of a non-negative INTEGER, without leading zeroes
+    pub(crate) fn unsigned(&mut self) -> Result<&'a [u8], Error> {
+        let mut int = self.expect(TAG_INTEGER)?;
+        if int.is_empty() || int[0] & 0x80 != 0 {
+            return Err(Error::BadCertificate);
+        }
+        while let [0, rest @ ..] = int {
+            int = rest;
+        }
+        Ok(int)
+    }
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::BadCertificate)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Hash {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// The signature algorithms that certificates and CertificateVerify messages can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    Ecdsa(Hash),
+    RsaPkcs1(Hash),
+    RsaPss(Hash),
+    Ed25519,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PublicKey {
+    /// an uncompressed SEC1 point
+    P256(Vec<u8>),
+    P384(Vec<u8>),
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    Ed25519([u8; 32]),
+}
+
+impl PublicKey {
+    /// Parses a SubjectPublicKeyInfo.
+    pub(crate) fn from_spki(spki: &[u8]) -> Result<PublicKey, Error> {
+        let mut outer = Der::new(spki);
+        let mut der = Der::new(outer.expect(TAG_SEQUENCE)?);
+        outer.finish()?;
+        let mut alg = Der::new(der.expect(TAG_SEQUENCE)?);
+        let key = der.bit_string()?;
+        der.finish()?;
+        let oid = alg.expect(TAG_OID)?;
+        let key = if oid == OID_EC_PUBLIC_KEY {
+            match alg.expect(TAG_OID)? {
+                OID_P256 => PublicKey::P256(key.to_vec()),
+                OID_P384 => PublicKey::P384(key.to_vec()),
+                _ => return Err(Error::UnsupportedCertificate),
+            }
+        } else if oid == OID_RSA_ENCRYPTION {
+            alg.optional(0x05)?; // NULL parameters
+            let mut outer = Der::new(key);
+            let mut rsa = Der::new(outer.expect(TAG_SEQUENCE)?);
+            let n = rsa.unsigned()?.to_vec();
+            let e = rsa.unsigned()?.to_vec();
+            rsa.finish()?;
+            PublicKey::Rsa { n, e }
+        } else if oid == OID_ED25519 {
+            let mut point = [0u8; 32];
+            if key.len() != point.len() {
+                return Err(Error::BadCertificate);
+            }
+            point.copy_from_slice(key);
+            PublicKey::Ed25519(point)
+        } else {
+            return Err(Error::UnsupportedCertificate);
+        };
+        alg.finish()?;
+        Ok(key)
+    }
+}
+
+/// The parts of a certificate that matter for validating a chain.
+#[derive(Debug)]
+pub(crate) struct Certificate {
+    /// the signed part, as encoded
+    pub tbs: Vec<u8>,
+    pub signature_algorithm: Algorithm,
+    pub signature: Vec<u8>,
+    /// the encoded issuer and subject Names
+    pub issuer: Vec<u8>,
+    pub subject: Vec<u8>,
+    /// validity period, in seconds since the Unix epoch
+    pub not_before: u64,
+    pub not_after: u64,
+    pub public_key: PublicKey,
+    /// `Some(path length constraint)` if this is a CA certificate
+    pub ca: Option<Option<u32>>,
+    /// the keyUsage bits, if restricted, with digitalSignature as bit 0
+    pub key_usage: Option<u16>,
+    /// whether the certificate may be used by a TLS server
+    pub server_auth: bool,
+    pub dns_names: Vec<String>,
+    pub ip_addresses: Vec<Vec<u8>>,
+}
+
+pub(crate) const KEY_USAGE_DIGITAL_SIGNATURE: u16 = 1 << 0;
+pub(crate) const KEY_USAGE_KEY_CERT_SIGN: u16 = 1 << 5;
+
+fn parse_signature_algorithm(der: &[u8]) -> Result<Algorithm, Error> {
+    let mut alg = Der::new(der);
+    let oid = alg.expect(TAG_OID)?;
+    let algorithm = match oid {
+        OID_SHA256_WITH_RSA => Algorithm::RsaPkcs1(Hash::Sha256),
+        OID_SHA384_WITH_RSA => Algorithm::RsaPkcs1(Hash::Sha384),
+        OID_SHA512_WITH_RSA => Algorithm::RsaPkcs1(Hash::Sha512),
+        OID_ECDSA_WITH_SHA256 => Algorithm::Ecdsa(Hash::Sha256),
+        OID_ECDSA_WITH_SHA384 => Algorithm::Ecdsa(Hash::Sha384),
+        OID_ECDSA_WITH_SHA512 => Algorithm::Ecdsa(Hash::Sha512),
+        OID_ED25519 => Algorithm::Ed25519,
+        _ => return Err(Error::UnsupportedCertificate),
+    };
+    if let Algorithm::RsaPkcs1(_) = algorithm {
+        alg.optional(0x05)?; // NULL parameters
+    }
+    alg.finish()?;
+    Ok(algorithm)
+}
+
+/// Days from 1970-01-01 to the given date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn time(der: &mut Der) -> Result<u64, Error> {
+    let (tag, contents, _) = der.any()?;
+    let digits = |s: &[u8]| -> Result<i64, Error> {
+        s.iter().try_fold(0i64, |acc, &c| {
+            if c.is_ascii_digit() { Ok(acc * 10 + (c - b'0') as i64) } else { Err(Error::BadCertificate) }
+        })
+    };
+    let (year, rest) = match (tag, contents.len()) {
+        (TAG_UTC_TIME, 13) => {
+            let yy = digits(&contents[..2])?;
+            (if yy < 50 { 2000 + yy } else { 1900 + yy }, &contents[2..])
+        }
+        (TAG_GENERALIZED_TIME, 15) => (digits(&contents[..4])?, &contents[4..]),
+        _ => return Err(Error::BadCertificate),
+    };
+    if rest[10] != b'Z' {
+        return Err(Error::BadCertificate);
+    }
+    let month = digits(&rest[0..2])?;
+    let day = digits(&rest[2..4])?;
+    let hour = digits(&rest[4..6])?;
+    let minute = digits(&rest[6..8])?;
+    let second = digits(&rest[8..10])?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(Error::BadCertificate);
+    }
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Ok(secs.max(0) as u64)
+}
+
+impl Certificate {
+    pub(crate) fn parse(der: &[u8]) -> Result<Certificate, Error> {
+        let mut outer = Der::new(der);
+        let mut cert = Der::new(outer.expect(TAG_SEQUENCE)?);
+        outer.finish()?;
+        let (tag, tbs_contents, tbs) = cert.any()?;
+        if tag != TAG_SEQUENCE {
+            return Err(Error::BadCertificate);
+        }
+        let signature_algorithm = parse_signature_algorithm(cert.expect(TAG_SEQUENCE)?)?;
+        let signature = cert.bit_string()?.to_vec();
+        cert.finish()?;
+
+        let mut tbs_der = Der::new(tbs_contents);
+        let version = match tbs_der.optional(0xa0)? {
+            Some(v) => {
+                let mut v = Der::new(v);
+                let version = v.unsigned()?;
+                v.finish()?;
+                version.first().cloned().unwrap_or(0)
+            }
+            None => 0,
+        };
+        tbs_der.expect(TAG_INTEGER)?; // serial number
+        if parse_signature_algorithm(tbs_der.expect(TAG_SEQUENCE)?)? != signature_algorithm {
+            return Err(Error::BadCertificate);
+        }
+        let (_, _, issuer) = tbs_der.any()?;
+        let mut validity = Der::new(tbs_der.expect(TAG_SEQUENCE)?);
+        let not_before = time(&mut validity)?;
+        let not_after = time(&mut validity)?;
+        validity.finish()?;
+        let (_, _, subject) = tbs_der.any()?;
+        let (_, _, spki) = tbs_der.any()?;
+        let public_key = PublicKey::from_spki(spki)?;
+        let mut cert = Certificate {
+            tbs: tbs.to_vec(),
+            signature_algorithm,
+            signature,
+            issuer: issuer.to_vec(),
+            subject: subject.to_vec(),
+            not_before,
+            not_after,
+            public_key,
+            ca: None,
+            key_usage: None,
+            server_auth: true,
+            dns_names: Vec::new(),
+            ip_addresses: Vec::new(),
+        };
+        // issuerUniqueID and subjectUniqueID are obsolete, but allowed
+        tbs_der.optional(0x81)?;
+        tbs_der.optional(0x82)?;
+        if let Some(exts) = tbs_der.optional(0xa3)? {
+            if version != 2 {
+                return Err(Error::BadCertificate);
+            }
+            let mut outer = Der::new(exts);
+            let mut exts = Der::new(outer.expect(TAG_SEQUENCE)?);
+            outer.finish()?;
+            while !exts.is_empty() {
+                let mut ext = Der::new(exts.expect(TAG_SEQUENCE)?);
+                let oid = ext.expect(TAG_OID)?;
+                let critical = ext.optional(TAG_BOOLEAN)?.map(|b| b != [0]).unwrap_or(false);
+                let value = ext.expect(TAG_OCTET_STRING)?;
+                ext.finish()?;
+                if !cert.extension(oid, value)? && critical {
+                    return Err(Error::UnsupportedCertificate);
+                }
+            }
+        }
+        tbs_der.finish()?;
+        Ok(cert)
+    }
+
+    /// Records an extension. Returns whether it was understood.
+    fn extension(&mut self, oid: &[u8], value: &[u8]) -> Result<bool, Error> {
+        let mut outer = Der::new(value);
+        match oid {
+            OID_BASIC_CONSTRAINTS => {
+                let mut bc = Der::new(outer.expect(TAG_SEQUENCE)?);
+                let ca = bc.optional(TAG_BOOLEAN)?.map(|b| b != [0]).unwrap_or(false);
+                let path_len = match bc.optional(TAG_INTEGER)? {
+                    Some(n) if n.len() <= 4 && n.first().map(|&b| b < 0x80).unwrap_or(false) =>
+                        Some(n.iter().fold(0u32, |acc, &b| acc << 8 | b as u32)),
+                    Some(_) => return Err(Error::BadCertificate),
+                    None => None,
+                };
+                bc.finish()?;
+                if ca {
+                    self.ca = Some(path_len);
+                }
+            }
+            OID_KEY_USAGE => {
+                let bits = match outer.expect(TAG_BIT_STRING)? {
+                    [_, bytes @ ..] => bytes,
+                    _ => return Err(Error::BadCertificate),
+                };
+                // bit 0 is the most significant bit of the first byte
+                let mut usage = 0u16;
+                for (i, byte) in bits.iter().take(2).enumerate() {
+                    for bit in 0..8 {
+                        if byte & (0x80 >> bit) != 0 {
+                            usage |= 1 << (i * 8 + bit);
+                        }
+                    }
+                }
+                self.key_usage = Some(usage);
+            }
+            OID_EXT_KEY_USAGE => {
+                let mut purposes = Der::new(outer.expect(TAG_SEQUENCE)?);
+                self.server_auth = false;
+                while !purposes.is_empty() {
+                    let purpose = purposes.expect(TAG_OID)?;
+                    if purpose == OID_SERVER_AUTH || purpose == OID_ANY_EXTENDED_KEY_USAGE {
+                        self.server_auth = true;
+                    }
+                }
+            }
+            OID_SUBJECT_ALT_NAME => {
+                let mut names = Der::new(outer.expect(TAG_SEQUENCE)?);
+                while !names.is_empty() {
+                    match names.any()? {
+                        (0x82, name, _) => self.dns_names.push(
+                            String::from_utf8(name.to_vec()).map_err(|_| Error::BadCertificate)?),
+                        (0x87, address, _) => self.ip_addresses.push(address.to_vec()),
+                        _ => (),
+                    }
+                }
+            }
+            _ => return Ok(false),
+        }
+        outer.finish()?;
+        Ok(true)
+    }
+
+    /// Returns just the subject and public key. Trust anchors need nothing else, and reading no
+    /// more than that accepts roots with self-signatures in obsolete algorithms.
+    pub(crate) fn parse_anchor(der: &[u8]) -> Result<(Vec<u8>, PublicKey), Error> {
+        let mut outer = Der::new(der);
+        let mut cert = Der::new(outer.expect(TAG_SEQUENCE)?);
+        outer.finish()?;
+        let mut tbs = Der::new(cert.expect(TAG_SEQUENCE)?);
+        tbs.optional(0xa0)?; // version
+        tbs.expect(TAG_INTEGER)?; // serial number
+        tbs.expect(TAG_SEQUENCE)?; // signature algorithm
+        tbs.any()?; // issuer
+        tbs.expect(TAG_SEQUENCE)?; // validity
+        let (_, _, subject) = tbs.any()?;
+        let (_, _, spki) = tbs.any()?;
+        Ok((subject.to_vec(), PublicKey::from_spki(spki)?))
+    }
+
+    pub(crate) fn valid_at(&self, now: u64) -> bool {
+        self.not_before <= now && now <= self.not_after
+    }
+
+    /// Whether the certificate names `name`, which is a DNS name or an IP address.
+    pub(crate) fn matches_name(&self, name: &str) -> bool {
+        if let Ok(ip) = name.parse::<std::net::IpAddr>() {
+            let octets = match ip {
+                std::net::IpAddr::V4(v4) => v4.octets().to_vec(),
+                std::net::IpAddr::V6(v6) => v6.octets().to_vec(),
+            };
+            return self.ip_addresses.contains(&octets);
+        }
+        let name = name.trim_end_matches('.');
+        self.dns_names.iter().any(|pattern| dns_name_matches(pattern, name))
+    }
+}
+
+/// Matches a dNSName, which may have a wildcard as its whole leftmost label.
+fn dns_name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => match name.split_once('.') {
+            Some((label, rest)) => !label.is_empty() && rest.eq_ignore_ascii_case(suffix),
+            None => false,
+        },
+        None => pattern.eq_ignore_ascii_case(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2049, 12, 31), 29219);
+        let mut der = Der::new(b"\x17\x0d491231235959Z");
+        assert_eq!(time(&mut der).unwrap(), 2524607999);
+        let mut der = Der::new(b"\x18\x0f20500101000000Z");
+        assert_eq!(time(&mut der).unwrap(), 2524608000);
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(dns_name_matches("*.betrusted.io", "ci.betrusted.io"));
+        assert!(dns_name_matches("ci.betrusted.io", "CI.Betrusted.IO"));
+        assert!(!dns_name_matches("*.betrusted.io", "betrusted.io"));
+        assert!(!dns_name_matches("*.betrusted.io", "a.ci.betrusted.io"));
+        assert!(!dns_name_matches("ci.betrusted.io", "betrusted.io"));
+    }
+}
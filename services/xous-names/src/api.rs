@@ -1,6 +1,10 @@
-#[allow(dead_code)]
 pub const AUTHENTICATE_TIMEOUT: u32 = 10_000; // time in ms that a process has to respond to an authentication request
 
+/// Number of servers described by each `Directory` call
+pub const DIRECTORY_PAGE_LEN: usize = 16;
+/// Number of requesting PIDs reported per server
+pub const DIRECTORY_MAX_PIDS: usize = 16;
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
 #[non_exhaustive]
 #[repr(C)]
@@ -50,6 +54,30 @@ pub(crate) enum Opcode {
     /// }
     /// ```
     BlockingConnect = 6,
+
+    /// Sent by a server's authenticator thread with the outcome of a signature check: the
+    /// three words of the job token, then 1 if the signature is good.
+    AuthenticateResult = 7,
+
+    /// Describe the registered servers, a page of `DirectoryPage` at a time. Only answered if the
+    /// debug policy permits introspection (see the `introspection` feature).
+    Directory = 8,
+
+    /// Sent to the name server by its own timer thread when the earliest held `AuthenticatedLookup`
+    /// is due to time out.
+    AuthenticateTimeout = 9,
+
+    /// Check whether a process registered a server name, with a `Registrant`. This lets a server
+    /// know its clients by the names they registered, which are stable where PIDs are not.
+    VerifyRegistrant = 10,
+}
+
+/// Opcodes understood by the authenticator thread that `register_name_authenticated` starts in
+/// the server's own process.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum AuthenticatorOp {
+    /// Check an `AuthenticateVerify` request
+    Verify = 0,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -76,6 +104,8 @@ pub(crate) enum Return {
 pub(crate) struct Registration {
     pub name: xous_ipc::String<64>,
     pub conn_limit: Option<u32>,
+    /// SID of the thread that checks the signatures of clients asking for an authenticated connection
+    pub authenticator: Option<[u32; 4]>,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -87,18 +117,83 @@ pub(crate) struct Disconnect {
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub(crate) struct AuthenticatedLookup {
     pub name: xous_ipc::String<64>,
-    pub pubkey_id: [u8; 20], // 160-bit pubkey ID encoded in network order (big endian)
-    pub response: [u32; 8],
+    pub pubkey: [u8; 32],    // Ed25519 public key of the client
+    pub signature: [u8; 64], // signature over `authentication_message()` of the challenge
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[repr(C)]
 pub(crate) struct AuthenticateRequest {
     pub name: xous_ipc::String<64>, // a copy of the originally requested lookup
-    pub pubkey_id: [u8; 20],        // 160-bit pubkey ID encoded in network order (big endian)
     pub challenge: [u32; 4],
 }
 
+/// Forwarded by the name server to a server's authenticator.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct AuthenticateVerify {
+    pub name: xous_ipc::String<64>,
+    pub challenge: [u32; 4],
+    pub pubkey: [u8; 32],
+    pub signature: [u8; 64],
+    /// identifies the lookup in the `AuthenticateResult`
+    pub token: [u32; 3],
+}
+
+/// What the name server knows about one registered server. The SID is never reported.
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct ServerEntry {
+    pub name: xous_ipc::String<64>,
+    /// the `max_conns` the server registered with; `None` if unlimited
+    pub conn_limit: Option<u32>,
+    /// unauthenticated connections handed out, net of disconnects
+    pub conns: u32,
+    /// whether the server takes authenticated connections
+    pub authenticated: bool,
+    /// authenticated connections handed out
+    pub auth_conns: u32,
+    /// PIDs of the processes connections were handed out to, in the order they first connected.
+    /// A PID is dropped once it has disconnected through the name server as many times as it
+    /// connected; connections closed directly with the kernel aren't seen, so it may linger.
+    pub pids: [Option<u8>; DIRECTORY_MAX_PIDS],
+    /// set if there were more PIDs than fit in `pids`
+    pub pids_truncated: bool,
+}
+impl ServerEntry {
+    pub fn pids(&self) -> impl Iterator<Item = u8> + '_ {
+        self.pids.iter().filter_map(|&pid| pid)
+    }
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct DirectoryPage {
+    /// index, in name order, of the first server to describe
+    pub start: u32,
+    /// total number of registered servers; `None` if introspection is not permitted
+    pub total: Option<u32>,
+    pub entries: [Option<ServerEntry>; DIRECTORY_PAGE_LEN],
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct Registrant {
+    pub name: xous_ipc::String<64>,
+    pub pid: u32,
+    /// filled in by the name server
+    pub registered: bool,
+}
+
+/// The message a client signs to answer `challenge` when connecting to the server `name`. The
+/// name is included so that a signature can't be replayed to get a connection to another server.
+#[allow(dead_code)]
+pub(crate) fn authentication_message(name: &str, challenge: &[u32; 4]) -> Vec<u8> {
+    let mut message = b"xous-names authenticated lookup\0".to_vec();
+    message.extend_from_slice(name.as_bytes());
+    message.push(0);
+    for word in challenge.iter() {
+        message.extend_from_slice(&word.to_le_bytes());
+    }
+    message
+}
+
 //////////////////////////////////////////////////////////////////////////////////////////////
 // We keep XousServerName around because want to be able to index off the server name, without
 // burdening the Kernel String type with the Hash32 methods
@@ -6,7 +6,7 @@ pub mod api;
 
 use api::Disconnect;
 use core::fmt::Write;
-use num_traits::ToPrimitive;
+use num_traits::{FromPrimitive, ToPrimitive};
 use xous_ipc::{Buffer, String};
 
 #[doc = include_str!("../README.md")]
@@ -56,10 +56,75 @@ impl XousNames {
         &self,
         name: &str,
         max_conns: Option<u32>,
+    ) -> Result<xous::SID, xous::Error> {
+        self.register(name, max_conns, None)
+    }
+
+    /// Registers a server that, beyond its `max_conns` unauthenticated connections, also accepts
+    /// connections from clients holding one of `allowed_keys`. A client proves it holds a key by
+    /// signing a challenge issued by the name server (see `request_authenticated_connection`).
+    ///
+    /// `verify(pubkey, message, signature)` checks the signature. It runs on a thread spawned in
+    /// the calling process, so the name server itself never depends on a signature scheme.
+    pub fn register_name_authenticated<F>(
+        &self,
+        name: &str,
+        max_conns: Option<u32>,
+        allowed_keys: Vec<[u8; 32]>,
+        verify: F,
+    ) -> Result<xous::SID, xous::Error>
+    where
+        F: Fn(&[u8; 32], &[u8], &[u8; 64]) -> bool + Send + 'static,
+    {
+        let auth_sid = xous::create_server().or(Err(xous::Error::InternalError))?;
+        let sid = match self.register(name, max_conns, Some(auth_sid.to_array())) {
+            Ok(sid) => sid,
+            Err(e) => {
+                xous::destroy_server(auth_sid).ok();
+                return Err(e);
+            }
+        };
+        std::thread::spawn(move || {
+            let xns_conn = xous::connect(xous::SID::from_bytes(b"xous-name-server").unwrap())
+                .expect("Couldn't connect to XousNames");
+            loop {
+                let msg = xous::receive_message(auth_sid).unwrap();
+                match FromPrimitive::from_usize(msg.body.id()) {
+                    Some(api::AuthenticatorOp::Verify) => {
+                        let buffer = unsafe {
+                            Buffer::from_memory_message(msg.body.memory_message().unwrap())
+                        };
+                        let request = buffer.to_original::<api::AuthenticateVerify, _>().unwrap();
+                        let verified = authenticates(&allowed_keys, &verify, &request);
+                        xous::send_message(
+                            xns_conn,
+                            xous::Message::new_scalar(
+                                api::Opcode::AuthenticateResult.to_usize().unwrap(),
+                                request.token[0] as usize,
+                                request.token[1] as usize,
+                                request.token[2] as usize,
+                                if verified { 1 } else { 0 },
+                            ),
+                        )
+                        .expect("couldn't return authentication result");
+                    }
+                    None => log::error!("unknown authenticator opcode: {:?}", msg),
+                }
+            }
+        });
+        Ok(sid)
+    }
+
+    fn register(
+        &self,
+        name: &str,
+        max_conns: Option<u32>,
+        authenticator: Option<[u32; 4]>,
     ) -> Result<xous::SID, xous::Error> {
         let mut registration = api::Registration {
             name: String::<64>::new(),
             conn_limit: max_conns,
+            authenticator,
         };
         // could also do String::from_str() but in this case we want things to fail if the string is too long.
         write!(registration.name, "{}", name).expect("name probably too long");
@@ -93,7 +158,7 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, token)) => Ok((cid, token)),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -123,7 +188,7 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, _)) => Ok(cid),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -164,10 +229,107 @@ impl XousNames {
         }
     }
 
-    // todo:
-    // pub fn authenticated_connection(&self, name: &str, key: Authkey)
-    // this function will create an authenticated connection, if such are allowed
-    // it's intended for use by dynamically-loaded third-party apps. As of Xous 0.8 this isn't supported, so it's just a "todo"
+    /// Lists the registered servers in name order, with their connection limits, the connections
+    /// handed out and the PIDs holding them. This is a debugging aid: the name server only answers
+    /// it if its debug policy permits introspection, and `AccessDenied` is returned otherwise.
+    pub fn directory(&self) -> Result<Vec<api::ServerEntry>, xous::Error> {
+        let mut servers = Vec::new();
+        loop {
+            let page = api::DirectoryPage {
+                start: servers.len() as u32,
+                total: None,
+                entries: [None; api::DIRECTORY_PAGE_LEN],
+            };
+            let mut buf = Buffer::into_buf(page).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, api::Opcode::Directory.to_u32().unwrap())
+                .or(Err(xous::Error::InternalError))?;
+            let page = buf.to_original::<api::DirectoryPage, _>().unwrap();
+            let total = page.total.ok_or(xous::Error::AccessDenied)? as usize;
+            let start = servers.len();
+            servers.extend(page.entries.iter().flatten().copied());
+            // the table can shrink between calls, so also stop on an empty page
+            if servers.len() >= total || servers.len() == start {
+                return Ok(servers);
+            }
+        }
+    }
+
+    /// Whether the process `pid` registered the server `name`. A server can use this to know a
+    /// client by a name the client registered, which stays the same across boots and builds
+    /// where its PID may not, and which no other process can claim while it's registered.
+    pub fn is_registrant(&self, name: &str, pid: xous::PID) -> Result<bool, xous::Error> {
+        let mut registrant = api::Registrant {
+            name: String::<64>::new(),
+            pid: pid.get() as u32,
+            registered: false,
+        };
+        write!(registrant.name, "{}", name).or(Err(xous::Error::InvalidString))?;
+        let mut buf = Buffer::into_buf(registrant).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::VerifyRegistrant.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+        Ok(buf.to_original::<api::Registrant, _>().unwrap().registered)
+    }
+
+    /// Connects to `name`, authenticating with `pubkey` if the server's unauthenticated connections
+    /// are used up. `sign` is handed the challenge message and returns its signature under `pubkey`.
+    /// This is intended for use by dynamically-loaded third-party apps.
+    pub fn request_authenticated_connection<F>(
+        &self,
+        name: &str,
+        pubkey: [u8; 32],
+        sign: F,
+    ) -> Result<xous::CID, xous::Error>
+    where
+        F: FnOnce(&[u8]) -> [u8; 64],
+    {
+        let mut lookup_name = xous_ipc::String::<64>::new();
+        write!(lookup_name, "{}", name).expect("name problably too long");
+        let mut buf = Buffer::into_buf(lookup_name).or(Err(xous::Error::InternalError))?;
+
+        buf.lend_mut(self.conn, api::Opcode::Lookup.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        match buf.to_original().unwrap() {
+            api::Return::CID((cid, _)) => Ok(cid),
+            api::Return::AuthenticateRequest(request) => {
+                let message = api::authentication_message(name, &request.challenge);
+                let auth_lookup = api::AuthenticatedLookup {
+                    name: request.name,
+                    pubkey,
+                    signature: sign(&message),
+                };
+                let mut buf = Buffer::into_buf(auth_lookup).or(Err(xous::Error::InternalError))?;
+                buf.lend_mut(
+                    self.conn,
+                    api::Opcode::AuthenticatedLookup.to_u32().unwrap(),
+                )
+                .or(Err(xous::Error::InternalError))?;
+                match buf.to_original().unwrap() {
+                    api::Return::CID((cid, _)) => Ok(cid),
+                    _ => Err(xous::Error::AccessDenied),
+                }
+            }
+            _ => Err(xous::Error::ServerNotFound),
+        }
+    }
+}
+
+/// Whether `request` is signed by one of `allowed_keys`, over the challenge issued for the server.
+fn authenticates<F>(
+    allowed_keys: &[[u8; 32]],
+    verify: &F,
+    request: &api::AuthenticateVerify,
+) -> bool
+where
+    F: Fn(&[u8; 32], &[u8], &[u8; 64]) -> bool,
+{
+    let name = request.name.as_str().unwrap_or("");
+    allowed_keys.contains(&request.pubkey)
+        && verify(
+            &request.pubkey,
+            &api::authentication_message(name, &request.challenge),
+            &request.signature,
+        )
 }
 
 use core::sync::atomic::{AtomicU32, Ordering};
@@ -182,3 +344,79 @@ impl Drop for XousNames {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for a signature scheme: the "signature" folds the key and message together.
+    fn toy_sign(pubkey: &[u8; 32], message: &[u8]) -> [u8; 64] {
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(pubkey);
+        for (i, b) in message.iter().enumerate() {
+            signature[32 + i % 32] ^= b;
+        }
+        signature
+    }
+
+    fn toy_verify(pubkey: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+        toy_sign(pubkey, message)[..] == signature[..]
+    }
+
+    fn request(
+        name: &str,
+        challenge: [u32; 4],
+        pubkey: [u8; 32],
+        signed_name: &str,
+    ) -> api::AuthenticateVerify {
+        let mut request_name = String::<64>::new();
+        write!(request_name, "{}", name).unwrap();
+        api::AuthenticateVerify {
+            name: request_name,
+            challenge,
+            pubkey,
+            signature: toy_sign(
+                &pubkey,
+                &api::authentication_message(signed_name, &challenge),
+            ),
+            token: [0; 3],
+        }
+    }
+
+    #[test]
+    fn test_authenticates_allowed_key() {
+        let allowed = [[1u8; 32], [2u8; 32]];
+        assert!(authenticates(
+            &allowed,
+            &toy_verify,
+            &request("app", [1, 2, 3, 4], [2u8; 32], "app")
+        ));
+    }
+
+    #[test]
+    fn test_refuses_unlisted_key() {
+        let allowed = [[1u8; 32]];
+        assert!(!authenticates(
+            &allowed,
+            &toy_verify,
+            &request("app", [1, 2, 3, 4], [3u8; 32], "app")
+        ));
+    }
+
+    #[test]
+    fn test_refuses_bad_signature() {
+        let allowed = [[1u8; 32]];
+        let mut bad = request("app", [1, 2, 3, 4], [1u8; 32], "app");
+        bad.signature[40] ^= 1;
+        assert!(!authenticates(&allowed, &toy_verify, &bad));
+        // a signature for another challenge, or for another server, doesn't carry over
+        let mut other_challenge = request("app", [1, 2, 3, 4], [1u8; 32], "app");
+        other_challenge.challenge = [1, 2, 3, 5];
+        assert!(!authenticates(&allowed, &toy_verify, &other_challenge));
+        assert!(!authenticates(
+            &allowed,
+            &toy_verify,
+            &request("app", [1, 2, 3, 4], [1u8; 32], "other")
+        ));
+    }
+}
@@ -4,13 +4,18 @@
 mod api;
 use api::*;
 
-use num_traits::FromPrimitive;
-use xous::{msg_blocking_scalar_unpack, MessageEnvelope};
+use num_traits::{FromPrimitive, ToPrimitive};
+use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack, Message, MessageEnvelope};
 use xous_ipc::{Buffer, String};
 
 use log::{error, info};
 
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Debug policy for the `Directory` call. The directory shows which processes talk to which servers,
+/// so it is only answered in hosted mode, or on device images built with the `introspection` feature.
+const INTROSPECTION_PERMITTED: bool = cfg!(any(feature = "introspection", any(windows, unix)));
 
 #[derive(PartialEq)]
 #[repr(C)]
@@ -43,6 +48,144 @@ enum ConnectSuccess {
     // AuthenticationRequest
 }
 
+/// A challenge handed out in response to a `Lookup`, which the process it was issued to can answer
+/// once with an `AuthenticatedLookup`.
+struct PendingChallenge {
+    name: XousServerName,
+    challenge: [u32; 4],
+    deadline: Instant,
+}
+
+/// An `AuthenticatedLookup` whose signature is being checked by the server's authenticator. The
+/// message is held until the `AuthenticateResult` comes back, and then returned to the client.
+struct PendingAuthentication<M> {
+    token: [u32; 3],
+    name: XousServerName,
+    pid: xous::PID,
+    /// the process whose authenticator was asked, and the only one that may answer
+    authenticator: xous::PID,
+    deadline: Instant,
+    msg: M,
+}
+
+/// Each held lookup is a blocked client thread, so a process can't have more than this many.
+const MAX_HELD_PER_PID: usize = 4;
+
+/// The challenges handed out, and the `AuthenticatedLookup`s held while their signatures are
+/// checked. `M` is the held message: a `MessageEnvelope` in the server, anything in the tests.
+struct Authentications<M> {
+    timeout: Duration,
+    /// at most one challenge per process
+    challenges: HashMap<xous::PID, PendingChallenge>,
+    held: Vec<PendingAuthentication<M>>,
+}
+impl<M> Authentications<M> {
+    fn new(timeout: Duration) -> Self {
+        Authentications {
+            timeout,
+            challenges: HashMap::new(),
+            held: Vec::new(),
+        }
+    }
+
+    /// Records `challenge` as issued to `pid` for connecting to `name`, replacing any challenge it
+    /// had outstanding.
+    fn issue(&mut self, pid: xous::PID, name: XousServerName, challenge: [u32; 4], now: Instant) {
+        self.challenges.insert(
+            pid,
+            PendingChallenge {
+                name,
+                challenge,
+                deadline: now + self.timeout,
+            },
+        );
+    }
+
+    /// Takes the challenge `pid` was issued for `name`, if it hasn't expired. A challenge can only
+    /// be answered once, and only by the process it was issued to.
+    fn answer(&mut self, pid: xous::PID, name: &XousServerName, now: Instant) -> Option<[u32; 4]> {
+        match self.challenges.remove(&pid) {
+            Some(pending) if pending.name == *name && now < pending.deadline => {
+                Some(pending.challenge)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `pid` already has as many lookups held as it may.
+    fn is_full(&self, pid: xous::PID) -> bool {
+        self.held.iter().filter(|p| p.pid == pid).count() >= MAX_HELD_PER_PID
+    }
+
+    /// Holds `msg` until the authenticator in process `authenticator` answers.
+    fn hold(
+        &mut self,
+        token: [u32; 3],
+        name: XousServerName,
+        pid: xous::PID,
+        authenticator: xous::PID,
+        msg: M,
+        now: Instant,
+    ) {
+        self.held.push(PendingAuthentication {
+            token,
+            name,
+            pid,
+            authenticator,
+            deadline: now + self.timeout,
+            msg,
+        });
+    }
+
+    /// Takes the held lookup that the authenticator answered with `token`, if the answer came from
+    /// the process that registered that authenticator.
+    fn resolve(&mut self, token: [u32; 3], from: xous::PID) -> Option<PendingAuthentication<M>> {
+        let i = self
+            .held
+            .iter()
+            .position(|p| p.token == token && p.authenticator == from)?;
+        Some(self.held.remove(i))
+    }
+
+    /// Takes the held lookups whose authenticator didn't answer in time.
+    fn expire(&mut self, now: Instant) -> Vec<PendingAuthentication<M>> {
+        let mut expired = Vec::new();
+        let mut i = 0;
+        while i < self.held.len() {
+            if now >= self.held[i].deadline {
+                expired.push(self.held.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        expired
+    }
+
+    /// When the next held lookup times out, if any are held.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.held.iter().map(|p| p.deadline).min()
+    }
+}
+
+/// Has the name server sent an `AuthenticateTimeout` once `deadline` has passed. The sleep is a
+/// ticktimer request, so held lookups time out on schedule without anything polling.
+fn arm_authentication_timer(name_server: xous::CID, deadline: Instant) {
+    std::thread::spawn(move || {
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        xous::send_message(
+            name_server,
+            Message::new_scalar(
+                api::Opcode::AuthenticateTimeout.to_usize().unwrap(),
+                0,
+                0,
+                0,
+                0,
+            ),
+        )
+        .expect("couldn't send AuthenticateTimeout");
+    });
+}
+
 #[cfg(any(target_os = "none", target_os = "xous"))]
 mod implementation {
     use utralib::generated::*;
@@ -104,14 +247,16 @@ I'll take safety over speed in this case.
 Eventually, we shall endeavor to remove Heapless entirely, once we have a `libstd` in place
 and we can use heap-allocated Rust primitives...
 */
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug)]
 struct Connection {
     pub sid: xous::SID,
     pub current_conns: u32, // number of unauthenticated (inherentely trusted) connections
     pub max_conns: Option<u32>, // if None, unlimited connections allowed
-    pub _allow_authenticate: bool,
-    pub _auth_conns: u32,        // number of authenticated connections
+    pub authenticator: Option<(xous::CID, xous::PID)>, // if Some, clients can also connect by authenticating with the registrant's authenticator
+    pub auth_conns: u32,                               // number of authenticated connections
     pub token: Option<[u32; 4]>, // a random number that must be presented to allow for disconnection for single-connection servers
+    pub pids: Vec<(xous::PID, u32)>, // processes handed connections, and how many, for introspection
+    pub registrant: xous::PID,       // the process that registered the name
 }
 #[derive(Debug)]
 struct CheckedHashMap {
@@ -128,6 +273,8 @@ impl CheckedHashMap {
         name: XousServerName,
         sid: xous::SID,
         max_conns: Option<u32>,
+        authenticator: Option<(xous::CID, xous::PID)>,
+        registrant: xous::PID,
     ) -> Result<(), xous::Error> {
         let token = if max_conns == Some(1) {
             // for the special case of 1-connection servers, provision a one-time use token for disconnects
@@ -145,9 +292,11 @@ impl CheckedHashMap {
                 sid,
                 current_conns: 0,
                 max_conns,
-                _allow_authenticate: false, // for now, we don't support authenticated connections
-                _auth_conns: 0,
+                authenticator,
+                auth_conns: 0,
                 token,
+                pids: Vec::new(),
+                registrant,
             },
         );
         Ok(())
@@ -164,7 +313,13 @@ impl CheckedHashMap {
             }
         }
         if let Some(name) = removed_name {
-            self.map.remove(&name);
+            if let Some(Connection {
+                authenticator: Some((cid, _)),
+                ..
+            }) = self.map.remove(&name)
+            {
+                unsafe { xous::disconnect(cid).ok() };
+            }
         }
 
         removed_name
@@ -174,9 +329,19 @@ impl CheckedHashMap {
         self.map.contains_key(name)
     }
 
-    pub fn connect(&mut self, name: &XousServerName) -> (Option<xous::SID>, Option<[u32; 4]>) {
+    pub fn is_registrant(&self, name: &XousServerName, pid: xous::PID) -> bool {
+        self.map
+            .get(name)
+            .map_or(false, |entry| entry.registrant == pid)
+    }
+
+    pub fn connect(
+        &mut self,
+        name: &XousServerName,
+        pid: xous::PID,
+    ) -> (Option<xous::SID>, Option<[u32; 4]>) {
         if let Some(entry) = self.map.get_mut(name) {
-            match entry.max_conns {
+            let result = match entry.max_conns {
                 // single-connection case
                 Some(1) => {
                     if entry.current_conns < 1 {
@@ -199,12 +364,38 @@ impl CheckedHashMap {
                     (*entry).current_conns += 1;
                     (Some(entry.sid), None)
                 }
+            };
+            if result.0.is_some() {
+                entry.add_pid(pid);
             }
+            result
         } else {
             (None, None)
         }
     }
 
+    /// The connection to the authenticator of a server that takes authenticated connections, and
+    /// the PID of the process it runs in.
+    pub fn authenticator(&self, name: &XousServerName) -> Option<(xous::CID, xous::PID)> {
+        self.map.get(name).and_then(|entry| entry.authenticator)
+    }
+
+    /// Counts a connection by a client that has authenticated. These don't count against `max_conns`.
+    pub fn connect_authenticated(
+        &mut self,
+        name: &XousServerName,
+        pid: xous::PID,
+    ) -> Option<xous::SID> {
+        if let Some(entry) = self.map.get_mut(name) {
+            if entry.authenticator.is_some() {
+                (*entry).auth_conns += 1;
+                entry.add_pid(pid);
+                return Some(entry.sid);
+            }
+        }
+        None
+    }
+
     pub fn trusted_init_done(&self) -> bool {
         let mut trusted_done = true;
         for (name, entry) in self.map.iter() {
@@ -228,12 +419,13 @@ impl CheckedHashMap {
     // If it does get used in security-critical routes, it should be refactored to regenerate the SID
     // and publish it to the server every time a disconnect is called, to ensure that after a disconnection
     // the caller can never talk to the server again.
-    pub fn disconnect(&mut self, sid: xous::SID) -> Option<XousServerName> {
+    pub fn disconnect(&mut self, sid: xous::SID, pid: xous::PID) -> Option<XousServerName> {
         for (name, mapping) in self.map.iter_mut() {
             if mapping.sid == sid {
                 if mapping.current_conns > 0 {
                     mapping.current_conns -= 1;
                 }
+                mapping.remove_pid(pid);
                 return Some(*name);
             }
         }
@@ -242,11 +434,17 @@ impl CheckedHashMap {
 
     // this is a safer version of disconnect. we track servers that allow exactly one connection at a time
     // and give them a one-time-use token that a connector can use to disconnect.
-    pub fn disconnect_with_token(&mut self, name: &XousServerName, token: [u32; 4]) -> bool {
+    pub fn disconnect_with_token(
+        &mut self,
+        name: &XousServerName,
+        token: [u32; 4],
+        pid: xous::PID,
+    ) -> bool {
         if let Some(entry) = self.map.get_mut(name) {
             if let Some(old_token) = entry.token {
                 if (token == old_token) && (entry.current_conns == 1) {
                     (*entry).current_conns = 0;
+                    entry.remove_pid(pid);
                     // generate the token -- we should never re-use these!
                     (*entry).token = Some(
                         xous::create_server_id()
@@ -259,6 +457,50 @@ impl CheckedHashMap {
         }
         false
     }
+
+    /// Describes up to `DIRECTORY_PAGE_LEN` servers, starting from the `start`th in name order.
+    /// Returns the total number of servers along with the page.
+    pub fn directory(&self, start: usize) -> (u32, [Option<ServerEntry>; DIRECTORY_PAGE_LEN]) {
+        let mut names: Vec<&XousServerName> = self.map.keys().collect();
+        names.sort_by(|a, b| a.to_str().cmp(b.to_str()));
+        let mut entries = [None; DIRECTORY_PAGE_LEN];
+        for (entry, name) in entries.iter_mut().zip(names.iter().skip(start)) {
+            let conn = &self.map[*name];
+            let mut pids = [None; DIRECTORY_MAX_PIDS];
+            for (dest, (pid, _)) in pids.iter_mut().zip(conn.pids.iter()) {
+                *dest = Some(pid.get());
+            }
+            *entry = Some(ServerEntry {
+                name: String::<64>::from_str(name.to_str()),
+                conn_limit: conn.max_conns,
+                conns: conn.current_conns,
+                authenticated: conn.authenticator.is_some(),
+                auth_conns: conn.auth_conns,
+                pids,
+                pids_truncated: conn.pids.len() > DIRECTORY_MAX_PIDS,
+            });
+        }
+        (names.len() as u32, entries)
+    }
+}
+
+impl Connection {
+    fn add_pid(&mut self, pid: xous::PID) {
+        match self.pids.iter_mut().find(|(p, _)| *p == pid) {
+            Some((_, count)) => *count += 1,
+            None => self.pids.push((pid, 1)),
+        }
+    }
+
+    /// Forgets one of the connections handed to `pid`, and the PID itself once none are left.
+    fn remove_pid(&mut self, pid: xous::PID) {
+        if let Some(i) = self.pids.iter().position(|(p, _)| *p == pid) {
+            self.pids[i].1 -= 1;
+            if self.pids[i].1 == 0 {
+                self.pids.remove(i);
+            }
+        }
+    }
 }
 
 fn name_from_msg(env: &MessageEnvelope) -> Result<XousServerName, ConnectError> {
@@ -296,7 +538,7 @@ fn blocking_connect(
 
     // If the server already exists, attempt to make the connection. The connection can
     // only succeed if the
-    if let (Some(server_sid), token) = name_table.connect(&name) {
+    if let (Some(server_sid), token) = name_table.connect(&name, sender_pid) {
         log::trace!("Found entry in the table (sid: {:?}, token: {:?}) -- attempting to call connect_for_process()", server_sid, token);
         let result = xous::connect_for_process(sender_pid, server_sid);
         if let Ok(xous::Result::ConnectionID(connection_id)) = result {
@@ -316,7 +558,7 @@ fn blocking_connect(
 
             // The server connection process failed inside the kernel for one reason or
             // another, so remove the entry from the `name_table` and return an error
-            name_table.disconnect(server_sid);
+            name_table.disconnect(server_sid, sender_pid);
             return Err(ConnectError::KernelConnectFailure);
         }
     }
@@ -326,6 +568,15 @@ fn blocking_connect(
     Ok(ConnectSuccess::Wait)
 }
 
+/// Returns `response` to the client of a held `AuthenticatedLookup`.
+fn respond_authentication(mut msg: MessageEnvelope, response: api::Return) {
+    let mem = msg.body.memory_message_mut().unwrap();
+    let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+    buffer
+        .replace(response)
+        .expect("AuthenticatedLookup can't serialize return value");
+}
+
 fn respond_connect_error(mut msg: MessageEnvelope, result: ConnectError) {
     let mem = msg.body.memory_message_mut().unwrap();
     let s = unsafe {
@@ -361,6 +612,7 @@ fn xmain() -> ! {
 
     let name_server = xous::create_server_with_address(b"xous-name-server")
         .expect("Couldn't create xousnames-server");
+    let name_server_cid = xous::connect(name_server).expect("couldn't connect to myself");
 
     let d11ctimeout = D11cTimeout::new();
 
@@ -372,6 +624,11 @@ fn xmain() -> ! {
     //let mut name_table = FnvIndexMap::<XousServerName, xous::SID, 128>::new();
     let mut name_table = CheckedHashMap::new();
 
+    let mut auths: Authentications<MessageEnvelope> =
+        Authentications::new(Duration::from_millis(AUTHENTICATE_TIMEOUT as u64));
+    // whether a timer is armed for the earliest held lookup
+    let mut auth_timer_armed = false;
+
     info!("started");
     loop {
         let mut msg = xous::receive_message(name_server).unwrap();
@@ -392,11 +649,36 @@ fn xmain() -> ! {
                 let mut should_connect = false;
 
                 log::trace!("registration request for '{}'", name);
-                if !name_table.contains_key(&name) {
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on Register");
+                // the authenticator SID comes from the registrant, so it may not exist; `try_connect`
+                // doesn't wait for it to show up
+                let authenticator = match registration.authenticator {
+                    Some(sid) if !name_table.contains_key(&name) => {
+                        xous::try_connect(xous::SID::from_array(sid))
+                            .map(|cid| Some((cid, sender_pid)))
+                    }
+                    _ => Ok(None),
+                };
+                if let Err(e) = authenticator {
+                    error!(
+                        "couldn't connect to the authenticator for '{}': {:?}",
+                        name, e
+                    );
+                    response = api::Return::Failure
+                } else if !name_table.contains_key(&name) {
                     let new_sid =
                         xous::create_server_id().expect("create server failed, maybe OOM?");
                     name_table
-                        .insert(name, new_sid, registration.conn_limit)
+                        .insert(
+                            name,
+                            new_sid,
+                            registration.conn_limit,
+                            authenticator.unwrap(),
+                            sender_pid,
+                        )
                         .expect("register name failure, maybe out of HashMap capacity?");
                     log::trace!("request successful, SID is {:?}", new_sid);
                     should_connect = true;
@@ -482,12 +764,12 @@ fn xmain() -> ! {
                         .expect("couldn't convert server name to string"),
                 );
                 log::trace!("Lookup request for '{}'", name);
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on Lookup");
                 let response: api::Return;
-                if let (Some(server_sid), token) = name_table.connect(&name) {
-                    let sender_pid = msg
-                        .sender
-                        .pid()
-                        .expect("can't extract sender PID on Lookup");
+                if let (Some(server_sid), token) = name_table.connect(&name, sender_pid) {
                     match xous::connect_for_process(sender_pid, server_sid)
                         .expect("can't broker connection")
                     {
@@ -504,24 +786,31 @@ fn xmain() -> ! {
                             response = api::Return::Failure
                         }
                     }
-                } else {
-                    log::debug!("Can't find request '{}' in table, dumping table:", name);
-                    for (_name, conn) in name_table.map.iter() {
-                        log::debug!("{:?}", conn);
-                    }
-                    // no authenticate remedy currently supported, but we'd put that code somewhere around here eventually.
+                } else if name_table.authenticator(&name).is_some() {
+                    // the unauthenticated connections are used up, but the server takes authenticated ones
                     let (c1, c2, c3, c4) = xous::create_server_id().unwrap().to_u32();
+                    let challenge = [c1, c2, c3, c4];
+                    log::trace!(
+                        "Lookup of '{}' needs authentication, issuing a challenge",
+                        name
+                    );
+                    auths.issue(sender_pid, name, challenge, Instant::now());
                     let auth_request = AuthenticateRequest {
                         name: String::<64>::from_str(
                             name_string
                                 .as_str()
                                 .expect("couldn't convert server name to string"),
                         ),
-                        pubkey_id: [0; 20], // placeholder
-                        challenge: [c1, c2, c3, c4],
+                        challenge,
                     };
+                    response = api::Return::AuthenticateRequest(auth_request)
+                } else {
+                    log::debug!("Can't find request '{}' in table, dumping table:", name);
+                    for (_name, conn) in name_table.map.iter() {
+                        log::debug!("{:?}", conn);
+                    }
                     d11ctimeout.hosted_delay();
-                    response = api::Return::AuthenticateRequest(auth_request) // this code just exists to exercise the return path
+                    response = api::Return::Failure
                 }
                 buffer
                     .replace(response)
@@ -529,11 +818,127 @@ fn xmain() -> ! {
             }
             Some(api::Opcode::AuthenticatedLookup) => {
                 let mem = msg.body.memory_message_mut().unwrap();
-                let buffer = unsafe { Buffer::from_memory_message_mut(mem) };
-                let auth_lookup: AuthenticatedLookup = buffer.to_original().unwrap();
-                info!("AuthenticatedLookup request {:?}", auth_lookup);
-                error!("AuthenticatedLookup not yet implemented");
-                unimplemented!("AuthenticatedLookup not yet implemented");
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let auth_lookup = buffer.to_original::<AuthenticatedLookup, _>().unwrap();
+                let name = XousServerName::from_str(
+                    auth_lookup
+                        .name
+                        .as_str()
+                        .expect("couldn't convert server name to string"),
+                );
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on AuthenticatedLookup");
+                log::trace!("AuthenticatedLookup request for '{}'", name);
+                let challenge = auths.answer(sender_pid, &name, Instant::now());
+                // the signature is checked by the server's authenticator, in the server's process; the
+                // lookup is held until it answers, so the name server never blocks on the check.
+                let mut token = None;
+                let authenticator = name_table.authenticator(&name);
+                if auths.is_full(sender_pid) {
+                    info!("too many authenticated lookups held for {:?}", sender_pid);
+                } else if let (Some(challenge), Some((authenticator, _))) =
+                    (challenge, authenticator)
+                {
+                    let (t0, t1, t2, _) = xous::create_server_id().unwrap().to_u32();
+                    let verify = AuthenticateVerify {
+                        name: auth_lookup.name,
+                        challenge,
+                        pubkey: auth_lookup.pubkey,
+                        signature: auth_lookup.signature,
+                        token: [t0, t1, t2],
+                    };
+                    let verify_buf =
+                        Buffer::into_buf(verify).expect("couldn't serialize AuthenticateVerify");
+                    if verify_buf
+                        .send(authenticator, AuthenticatorOp::Verify.to_u32().unwrap())
+                        .is_ok()
+                    {
+                        token = Some([t0, t1, t2]);
+                    } else {
+                        error!("couldn't reach the authenticator for '{}'", name);
+                    }
+                }
+                if let (Some(token), Some((_, authenticator_pid))) = (token, authenticator) {
+                    drop(buffer);
+                    auths.hold(
+                        token,
+                        name,
+                        sender_pid,
+                        authenticator_pid,
+                        msg,
+                        Instant::now(),
+                    );
+                    // held lookups all have the same timeout, so an armed timer is never late
+                    if !auth_timer_armed {
+                        auth_timer_armed = true;
+                        arm_authentication_timer(name_server_cid, auths.next_deadline().unwrap());
+                    }
+                } else {
+                    info!(
+                        "AuthenticatedLookup for '{}' denied, waiting for deterministic timeout",
+                        name
+                    );
+                    d11ctimeout.deterministic_busy_wait();
+                    buffer
+                        .replace(api::Return::Failure)
+                        .expect("AuthenticatedLookup can't serialize return value");
+                }
+            }
+            Some(api::Opcode::AuthenticateResult) => {
+                let sender_pid = msg.sender.pid();
+                msg_scalar_unpack!(msg, t0, t1, t2, verified, {
+                    let token = [t0 as u32, t1 as u32, t2 as u32];
+                    let pending = sender_pid.and_then(|pid| auths.resolve(token, pid));
+                    if let Some(pending) = pending {
+                        let mut response = api::Return::Failure;
+                        if verified != 0 {
+                            if let Some(server_sid) =
+                                name_table.connect_authenticated(&pending.name, pending.pid)
+                            {
+                                match xous::connect_for_process(pending.pid, server_sid) {
+                                    Ok(xous::Result::ConnectionID(connection_id)) => {
+                                        info!(
+                                            "authenticated connection to '{}' for process {:?}",
+                                            pending.name, pending.pid
+                                        );
+                                        response = api::Return::CID((connection_id, None));
+                                    }
+                                    result => error!(
+                                        "couldn't broker authenticated connection: {:?}",
+                                        result
+                                    ),
+                                }
+                            }
+                        } else {
+                            info!(
+                                "authentication for '{}' failed, waiting for deterministic timeout",
+                                pending.name
+                            );
+                            d11ctimeout.deterministic_busy_wait();
+                        }
+                        respond_authentication(pending.msg, response);
+                    } else {
+                        log::warn!(
+                            "AuthenticateResult from {:?} for an unknown or expired lookup, or not its own",
+                            sender_pid
+                        );
+                    }
+                })
+            }
+            Some(api::Opcode::AuthenticateTimeout) => {
+                // only one timer is armed at a time, and this was it
+                auth_timer_armed = false;
+                // don't leave clients hanging on an authenticator that never answered
+                for pending in auths.expire(Instant::now()) {
+                    info!("authentication for '{}' timed out", pending.name);
+                    respond_authentication(pending.msg, api::Return::Failure);
+                }
+                if let Some(deadline) = auths.next_deadline() {
+                    auth_timer_armed = true;
+                    arm_authentication_timer(name_server_cid, deadline);
+                }
             }
             Some(api::Opcode::TrustedInitDone) => {
                 if name_table.trusted_init_done() {
@@ -547,12 +952,49 @@ fn xmain() -> ! {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
                 let disconnect = buffer.to_original::<Disconnect, _>().unwrap();
                 let name = XousServerName::from_str(disconnect.name.as_str().unwrap());
-                let response = if name_table.disconnect_with_token(&name, disconnect.token) {
-                    api::Return::Success
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on Disconnect");
+                let response =
+                    if name_table.disconnect_with_token(&name, disconnect.token, sender_pid) {
+                        api::Return::Success
+                    } else {
+                        api::Return::Failure
+                    };
+                buffer.replace(response).expect("Can't return buffer");
+            }
+            Some(api::Opcode::Directory) => {
+                let mem = msg.body.memory_message_mut().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let mut page = buffer.to_original::<DirectoryPage, _>().unwrap();
+                if INTROSPECTION_PERMITTED {
+                    let (total, entries) = name_table.directory(page.start as usize);
+                    page.total = Some(total);
+                    page.entries = entries;
+                } else {
+                    log::warn!("Directory request denied by the debug policy");
+                    page.total = None;
+                    page.entries = [None; DIRECTORY_PAGE_LEN];
+                }
+                buffer.replace(page).expect("Can't return buffer");
+            }
+            Some(api::Opcode::VerifyRegistrant) => {
+                let mem = msg.body.memory_message_mut().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let mut registrant = buffer.to_original::<Registrant, _>().unwrap();
+                let pid = if registrant.pid <= u8::MAX as u32 {
+                    xous::PID::new(registrant.pid as u8)
                 } else {
-                    api::Return::Failure
+                    None
                 };
-                buffer.replace(response).expect("Can't return buffer");
+                registrant.registered = match (registrant.name.as_str(), pid) {
+                    (Ok(name), Some(pid)) => {
+                        name_table.is_registrant(&XousServerName::from_str(name), pid)
+                    }
+                    _ => false,
+                };
+                buffer.replace(registrant).expect("Can't return buffer");
             }
             None => {
                 error!("couldn't decode message: {:?}", msg);
@@ -566,3 +1008,263 @@ fn xmain() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0);
 }
+
+// These exercise the name table directly, so they run in hosted mode with `cargo test` and
+// don't need a kernel. Servers registered with `max_conns` of 1 are avoided, as those ask
+// the kernel for a disconnect token.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pid(id: u8) -> xous::PID {
+        xous::PID::new(id).unwrap()
+    }
+
+    fn table(names: &[(&str, Option<u32>)]) -> CheckedHashMap {
+        let mut table = CheckedHashMap::new();
+        for (i, (name, max_conns)) in names.iter().enumerate() {
+            let sid = xous::SID::from_u32(i as u32 + 1, 0, 0, 0);
+            table
+                .insert(
+                    XousServerName::from_str(name),
+                    sid,
+                    *max_conns,
+                    None,
+                    pid(1),
+                )
+                .unwrap();
+        }
+        table
+    }
+
+    fn auths() -> Authentications<u32> {
+        Authentications::new(Duration::from_millis(AUTHENTICATE_TIMEOUT as u64))
+    }
+
+    #[test]
+    fn test_challenge_answered_once() {
+        let mut auths = auths();
+        let app = XousServerName::from_str("app");
+        let now = Instant::now();
+        auths.issue(pid(5), app, [1, 2, 3, 4], now);
+        assert_eq!(auths.answer(pid(5), &app, now), Some([1, 2, 3, 4]));
+        // a replayed answer finds no challenge
+        assert_eq!(auths.answer(pid(5), &app, now), None);
+    }
+
+    #[test]
+    fn test_challenge_bound_to_pid_and_name() {
+        let mut auths = auths();
+        let app = XousServerName::from_str("app");
+        let other = XousServerName::from_str("other");
+        let now = Instant::now();
+        auths.issue(pid(5), app, [1, 2, 3, 4], now);
+        assert_eq!(auths.answer(pid(6), &app, now), None);
+        assert_eq!(auths.answer(pid(5), &other, now), None);
+        // the wrong name used the challenge up
+        assert_eq!(auths.answer(pid(5), &app, now), None);
+    }
+
+    #[test]
+    fn test_challenge_replaced_and_expired() {
+        let mut auths = auths();
+        let app = XousServerName::from_str("app");
+        let now = Instant::now();
+        auths.issue(pid(5), app, [1, 2, 3, 4], now);
+        auths.issue(pid(5), app, [5, 6, 7, 8], now);
+        assert_eq!(auths.answer(pid(5), &app, now), Some([5, 6, 7, 8]));
+
+        auths.issue(pid(5), app, [1, 2, 3, 4], now);
+        let late = now + Duration::from_millis(AUTHENTICATE_TIMEOUT as u64);
+        assert_eq!(auths.answer(pid(5), &app, late), None);
+    }
+
+    #[test]
+    fn test_held_lookup_resolved_by_token() {
+        let mut auths = auths();
+        let app = XousServerName::from_str("app");
+        let now = Instant::now();
+        auths.hold([1, 1, 1], app, pid(5), pid(9), 10, now);
+        auths.hold([2, 2, 2], app, pid(6), pid(9), 20, now);
+        assert!(auths.resolve([3, 3, 3], pid(9)).is_none());
+        let pending = auths.resolve([2, 2, 2], pid(9)).unwrap();
+        assert_eq!((pending.pid, pending.msg), (pid(6), 20));
+        // an authenticator can't answer twice
+        assert!(auths.resolve([2, 2, 2], pid(9)).is_none());
+        assert_eq!(auths.held.len(), 1);
+    }
+
+    #[test]
+    fn test_held_lookup_only_resolved_by_its_authenticator() {
+        let mut auths = auths();
+        let app = XousServerName::from_str("app");
+        auths.hold([1, 1, 1], app, pid(5), pid(9), 10, Instant::now());
+        // neither the client nor a bystander that learned the token can approve the lookup
+        assert!(auths.resolve([1, 1, 1], pid(5)).is_none());
+        assert!(auths.resolve([1, 1, 1], pid(8)).is_none());
+        assert_eq!(auths.resolve([1, 1, 1], pid(9)).unwrap().msg, 10);
+    }
+
+    #[test]
+    fn test_held_lookups_capped_per_pid() {
+        let mut auths = auths();
+        let app = XousServerName::from_str("app");
+        let now = Instant::now();
+        for i in 0..MAX_HELD_PER_PID as u32 {
+            assert!(!auths.is_full(pid(5)));
+            auths.hold([i, 0, 0], app, pid(5), pid(9), i, now);
+        }
+        assert!(auths.is_full(pid(5)));
+        assert!(!auths.is_full(pid(6)));
+        auths.resolve([0, 0, 0], pid(9));
+        assert!(!auths.is_full(pid(5)));
+    }
+
+    #[test]
+    fn test_held_lookup_times_out() {
+        let mut auths = auths();
+        let app = XousServerName::from_str("app");
+        let timeout = Duration::from_millis(AUTHENTICATE_TIMEOUT as u64);
+        let now = Instant::now();
+        assert_eq!(auths.next_deadline(), None);
+        auths.hold([1, 1, 1], app, pid(5), pid(9), 10, now);
+        auths.hold([2, 2, 2], app, pid(6), pid(9), 20, now + timeout / 2);
+        assert_eq!(auths.next_deadline(), Some(now + timeout));
+
+        assert!(auths.expire(now + timeout / 2).is_empty());
+        let expired = auths.expire(now + timeout);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].msg, 10);
+        // the timer is re-armed for the lookup still held
+        assert_eq!(auths.next_deadline(), Some(now + timeout + timeout / 2));
+        // and a late answer from the authenticator is dropped
+        assert!(auths.resolve([1, 1, 1], pid(9)).is_none());
+    }
+
+    #[test]
+    fn test_directory_reports_connections() {
+        let mut table = table(&[("ticktimer", None), ("llio", Some(3)), ("gam", Some(2))]);
+        let llio = XousServerName::from_str("llio");
+        let gam = XousServerName::from_str("gam");
+        assert!(table.connect(&llio, pid(4)).0.is_some());
+        assert!(table.connect(&llio, pid(4)).0.is_some());
+        assert!(table.connect(&llio, pid(7)).0.is_some());
+        assert!(table.connect(&gam, pid(5)).0.is_some());
+
+        let (total, entries) = table.directory(0);
+        assert_eq!(total, 3);
+        let names: Vec<&str> = entries
+            .iter()
+            .flatten()
+            .map(|e| e.name.as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["gam", "llio", "ticktimer"]);
+
+        let llio_entry = entries[1].unwrap();
+        assert_eq!(llio_entry.conn_limit, Some(3));
+        assert_eq!(llio_entry.conns, 3);
+        assert!(!llio_entry.authenticated);
+        assert_eq!(llio_entry.pids().collect::<Vec<u8>>(), [4, 7]);
+        assert!(!llio_entry.pids_truncated);
+
+        let ticktimer_entry = entries[2].unwrap();
+        assert_eq!(ticktimer_entry.conn_limit, None);
+        assert_eq!(ticktimer_entry.conns, 0);
+        assert_eq!(ticktimer_entry.pids().count(), 0);
+    }
+
+    #[test]
+    fn test_directory_refused_connection_not_recorded() {
+        let mut table = table(&[("gam", Some(2))]);
+        let gam = XousServerName::from_str("gam");
+        assert!(table.connect(&gam, pid(2)).0.is_some());
+        assert!(table.connect(&gam, pid(3)).0.is_some());
+        assert!(table.connect(&gam, pid(9)).0.is_none());
+
+        let (_, entries) = table.directory(0);
+        let entry = entries[0].unwrap();
+        assert_eq!(entry.conns, 2);
+        assert_eq!(entry.pids().collect::<Vec<u8>>(), [2, 3]);
+    }
+
+    #[test]
+    fn test_directory_disconnect_forgets_pid() {
+        let mut table = table(&[("com", None)]);
+        let com = XousServerName::from_str("com");
+        let (sid, _) = table.connect(&com, pid(2));
+        table.connect(&com, pid(3));
+        assert_eq!(table.disconnect(sid.unwrap(), pid(2)), Some(com));
+
+        let (_, entries) = table.directory(0);
+        let entry = entries[0].unwrap();
+        assert_eq!(entry.conns, 1);
+        assert_eq!(entry.pids().collect::<Vec<u8>>(), [3]);
+    }
+
+    #[test]
+    fn test_directory_keeps_pid_with_connections_left() {
+        let mut table = table(&[("com", None)]);
+        let com = XousServerName::from_str("com");
+        let (sid, _) = table.connect(&com, pid(2));
+        table.connect(&com, pid(2));
+        table.disconnect(sid.unwrap(), pid(2));
+
+        let (_, entries) = table.directory(0);
+        assert_eq!(entries[0].unwrap().pids().collect::<Vec<u8>>(), [2]);
+
+        table.disconnect(sid.unwrap(), pid(2));
+        let (_, entries) = table.directory(0);
+        assert_eq!(entries[0].unwrap().pids().count(), 0);
+    }
+
+    #[test]
+    fn test_registrant() {
+        let mut table = CheckedHashMap::new();
+        let app = XousServerName::from_str("app");
+        let sid = xous::SID::from_u32(1, 0, 0, 0);
+        table.insert(app, sid, None, None, pid(5)).unwrap();
+        assert!(table.is_registrant(&app, pid(5)));
+        assert!(!table.is_registrant(&app, pid(6)));
+        assert!(!table.is_registrant(&XousServerName::from_str("other"), pid(5)));
+        // a connection doesn't make a process the registrant
+        table.connect(&app, pid(6));
+        assert!(!table.is_registrant(&app, pid(6)));
+        table.remove(sid);
+        assert!(!table.is_registrant(&app, pid(5)));
+    }
+
+    #[test]
+    fn test_directory_paging() {
+        let names: Vec<std::string::String> = (0..DIRECTORY_PAGE_LEN + 4)
+            .map(|i| format!("server-{:02}", i))
+            .collect();
+        let registrations: Vec<(&str, Option<u32>)> =
+            names.iter().map(|n| (n.as_str(), None)).collect();
+        let table = table(&registrations);
+
+        let (total, first) = table.directory(0);
+        assert_eq!(total as usize, DIRECTORY_PAGE_LEN + 4);
+        assert!(first.iter().all(|e| e.is_some()));
+        assert_eq!(first[0].unwrap().name.as_str().unwrap(), "server-00");
+
+        let (_, second) = table.directory(DIRECTORY_PAGE_LEN);
+        assert_eq!(second.iter().flatten().count(), 4);
+        assert_eq!(second[0].unwrap().name.as_str().unwrap(), "server-16");
+
+        let (_, past_end) = table.directory(total as usize);
+        assert!(past_end.iter().all(|e| e.is_none()));
+    }
+
+    #[test]
+    fn test_directory_pid_overflow() {
+        let mut table = table(&[("ticktimer", None)]);
+        let ticktimer = XousServerName::from_str("ticktimer");
+        for id in 1..=(DIRECTORY_MAX_PIDS as u8 + 2) {
+            table.connect(&ticktimer, pid(id));
+        }
+        let (_, entries) = table.directory(0);
+        let entry = entries[0].unwrap();
+        assert_eq!(entry.pids().count(), DIRECTORY_MAX_PIDS);
+        assert!(entry.pids_truncated);
+    }
+}
@@ -0,0 +1,85 @@
+#! /usr/bin/env python3
+import argparse
+import os
+import socket
+import struct
+import sys
+
+# Host side of the shellchat `pddb backup` and `pddb restore` commands.
+#
+# `receive` waits for `pddb backup [basis] [host] [port]`. The device sends the archive as
+# length-prefixed blocks (u32 little-endian length, then data), ended by an empty block. The
+# archive is written to a temporary file, and only replaces the previous archive once the empty
+# block has arrived, so a failed or interrupted backup never clobbers a good one.
+#
+# `send` waits for `pddb restore [basis] [host] [port]` and sends the archive back as-is. The
+# device authenticates every frame of the archive, so no framing is needed in this direction.
+
+def recv_exact(conn, length):
+    data = bytearray()
+    while len(data) < length:
+        chunk = conn.recv(length - len(data))
+        if len(chunk) == 0:
+            raise EOFError("connection closed mid-block")
+        data += chunk
+    return bytes(data)
+
+def receive(conn, path):
+    tmp_path = path + '.tmp'
+    total = 0
+    with open(tmp_path, 'wb') as f:
+        try:
+            while True:
+                (length,) = struct.unpack('<I', recv_exact(conn, 4))
+                if length == 0:
+                    break
+                f.write(recv_exact(conn, length))
+                total += length
+        except EOFError as e:
+            f.close()
+            os.remove(tmp_path)
+            print("Backup failed ({}); {} left untouched".format(e, path))
+            return False
+        f.flush()
+        os.fsync(f.fileno())
+    os.replace(tmp_path, path)
+    print("Received {} bytes into {}".format(total, path))
+    return True
+
+def send(conn, path):
+    with open(path, 'rb') as f:
+        archive = f.read()
+    conn.sendall(archive)
+    print("Sent {} bytes from {}".format(len(archive), path))
+    return True
+
+def main():
+    parser = argparse.ArgumentParser(description="Receive or send PDDB backup archives over the network")
+    parser.add_argument(
+        "direction", choices=['receive', 'send'], help="receive a backup from the device, or send one to it"
+    )
+    parser.add_argument(
+        "--file", required=True, help="archive file", type=str
+    )
+    parser.add_argument(
+        "--port", required=False, help="TCP port to listen on", type=int, default=6502
+    )
+    args = parser.parse_args()
+
+    listener = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    listener.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    listener.bind(('', args.port))
+    listener.listen(1)
+    print("Waiting for the device on port {}".format(args.port))
+    conn, addr = listener.accept()
+    print("Connection from {}".format(addr[0]))
+    with conn:
+        if args.direction == 'receive':
+            ok = receive(conn, args.file)
+        else:
+            ok = send(conn, args.file)
+    listener.close()
+    sys.exit(0 if ok else 1)
+
+if __name__ == "__main__":
+    main()
@@ -87,6 +87,10 @@ def main():
                     # passing = False # not a fail, because it's the test condition that's wrong, not the code
                     passing = 'OOM'
                     proc.kill()
+                if 'fsck failed' in realtime_output:
+                    err_log.append(realtime_output)
+                    logging.debug("fsck found problems in a saved image")
+                    passing = 'FAIL FSCK'
                 if 'Decryption auth error' in realtime_output:
                     err_log.append(realtime_output)
                     logging.debug("decryption auth error")
@@ -364,8 +364,11 @@ class KeyDescriptor:
         global PRINTED_FULL
         global DO_CI_TESTS
         desc = ''
-        if self.start > 0x7e_fff02_0000:
+        if self.start >= 0xfe00_0000_0000:
             desc += indent + 'Start: 0x{:x} (lg)\n'.format(self.start)
+        elif self.start >= 0x7f_0000_0000:
+            dict = (self.start - 0x7f_0000_0000) // 0x1_fc00_0000
+            desc += indent + 'Start: 0x{:x} (md) | dict_index {}\n'.format(self.start, dict)
         else:
             dict = (self.start - 0x3f_8000_0000) // 0xFE_0000
             pool = ((self.start - 0x3f_8000_0000) - dict * 0xFE_0000) // 0xFE0
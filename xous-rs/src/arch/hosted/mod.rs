@@ -289,7 +289,10 @@ pub fn syscall(call: SysCall) -> SysCallResult {
         //     .unwrap()
         //     .insert(tid, ())
         //     .is_none());
-        send_syscall(&call);
+        if !send_syscall(&call) {
+            // The call was answered without asking the kernel
+            return Ok(Result::Ok);
+        }
 
         let result = match read_syscall_result(tid) {
             Result::Error(e) => Some(Err(e)),
@@ -452,14 +455,16 @@ fn read_syscall_result(thread_id: TID) -> Result {
     }
 }
 
-fn send_syscall(call: &crate::SysCall) {
+fn send_syscall(call: &crate::SysCall) -> bool {
     // println!("Making Syscall: {:?}", call);
     let tid = thread_id();
 
     send_syscall_from_tid(call, tid)
 }
 
-fn send_syscall_from_tid(call: &crate::SysCall, tid: TID) {
+/// Send a syscall to the kernel on behalf of `tid`. Returns `false` if the call
+/// did not need to be sent, in which case no response will arrive.
+fn send_syscall_from_tid(call: &crate::SysCall, tid: TID) -> bool {
     let args = call.as_args();
 
     // Send the packet to the server
@@ -492,8 +497,21 @@ fn send_syscall_from_tid(call: &crate::SysCall, tid: TID) {
     }
 
     let mut stream = SERVER_CONNECTION.send.lock().unwrap();
+
+    // The kernel can't see our memory, so the value is compared here instead.
+    // Doing so while holding the send lock keeps it atomic: a `WakeAddress` from
+    // a thread that changed the value either goes out after this call, or its
+    // change is visible here.
+    if let crate::SysCall::WaitOnAddress(addr, expected, _) = call {
+        let value = unsafe { &*(addr.get() as *const core::sync::atomic::AtomicUsize) };
+        if value.load(core::sync::atomic::Ordering::SeqCst) != *expected {
+            return false;
+        }
+    }
+
     if let Err(e) = stream.write_all(&pkt) {
         eprintln!("Server shut down: {}", e);
         std::process::exit(0);
     }
+    true
 }
@@ -0,0 +1,122 @@
+use crate::program::{for_each_segment, SEGMENT_EXECUTE, SEGMENT_WRITE};
+use crate::{Error, MemoryAddress, MemoryRange, ProcessInit};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Processes get their stack right below this address, the same as the
+/// ones the loader starts at boot.
+const STACK_TOP: usize = 0x8000_0000;
+const STACK_SIZE: usize = 131_072;
+
+/// Programs may not be loaded above this address.
+const USER_AREA_END: usize = 0xff00_0000;
+
+const FLAG_R: usize = 0b0000_0010;
+const FLAG_W: usize = 0b0000_0100;
+const FLAG_X: usize = 0b0000_1000;
+
+/// Lay out the program in `image` in a fresh area of memory in this process, so
+/// that it can be started with `create_process()`. The kernel moves the memory
+/// into the new process, so it does not have to be freed once the process has
+/// been created.
+///
+/// `image` is either an ELF file, from which the `PT_LOAD` segments are used,
+/// or a MiniELF payload: an `IniE` tag as it appears in the kernel arguments,
+/// with its load offset counted from the start of `image`. Every page is
+/// mapped readable, and writable or executable only if a segment or section
+/// on it asks for it. The kernel keeps these permissions when it moves the
+/// pages into the new process.
+///
+/// # Errors
+///
+/// * **BadAddress**: The image is malformed, or doesn't fit in the user area
+/// * **OutOfMemory**: There isn't enough memory to lay the program out
+pub fn load_program(image: &[u8]) -> core::result::Result<ProcessInit, Error> {
+    // Find out how much memory the program spans
+    let mut low = usize::MAX;
+    let mut high = 0;
+    let entry = for_each_segment(image, |virt, size, _, _| {
+        let end = virt
+            .checked_add(size)
+            .filter(|end| *end <= USER_AREA_END)
+            .ok_or(Error::BadAddress)?;
+        low = low.min(virt);
+        high = high.max(end);
+        Ok(())
+    })?;
+    if low >= high {
+        return Err(Error::BadAddress);
+    }
+    let base = low & !(PAGE_SIZE - 1);
+    let len = (high - base + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let text_destination = MemoryAddress::new(base).ok_or(Error::BadAddress)?;
+    let start = MemoryAddress::new(entry).ok_or(Error::BadAddress)?;
+    if entry < base
+        || entry >= base + len
+        || (base < STACK_TOP && STACK_TOP - STACK_SIZE < base + len)
+    {
+        return Err(Error::BadAddress);
+    }
+
+    // The program is written in place, so it starts out with every permission,
+    // and is cut down to what each page needs once it has been copied.
+    let text = crate::map_memory(None, None, len, memory_flags(FLAG_R | FLAG_W | FLAG_X))?;
+
+    // Memory is only allocated when it's first touched, but every page has to be
+    // backed before it can be handed over. New pages come zeroed.
+    let dest = text.as_mut_ptr();
+    for offset in (0..len).step_by(PAGE_SIZE) {
+        unsafe { dest.add(offset).write_volatile(0) };
+    }
+    for_each_segment(image, |virt, _, data, _| {
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), dest.add(virt - base), data.len()) };
+        Ok(())
+    })
+    .and_then(|_| restrict_pages(image, base, &text))
+    .map_err(|e| {
+        crate::unmap_memory(text).ok();
+        e
+    })?;
+    super::cache_flush();
+
+    Ok(ProcessInit {
+        stack: unsafe { MemoryRange::new(STACK_TOP - STACK_SIZE, STACK_SIZE)? },
+        text,
+        text_destination,
+        start,
+    })
+}
+
+/// Drop the write and execute permissions from every page of `text` that no
+/// segment covering it asks for.
+fn restrict_pages(
+    image: &[u8],
+    base: usize,
+    text: &MemoryRange,
+) -> core::result::Result<(), Error> {
+    for offset in (0..text.len()).step_by(PAGE_SIZE) {
+        let page_start = base + offset;
+        let page_end = page_start + PAGE_SIZE;
+        let mut flags = FLAG_R;
+        for_each_segment(image, |virt, size, _, segment_flags| {
+            if virt < page_end && page_start < virt + size {
+                if segment_flags & SEGMENT_WRITE != 0 {
+                    flags |= FLAG_W;
+                }
+                if segment_flags & SEGMENT_EXECUTE != 0 {
+                    flags |= FLAG_X;
+                }
+            }
+            Ok(())
+        })?;
+        if flags != FLAG_R | FLAG_W | FLAG_X {
+            let page = unsafe { MemoryRange::new(text.as_ptr() as usize + offset, PAGE_SIZE)? };
+            crate::update_memory_flags(page, memory_flags(flags))?;
+        }
+    }
+    Ok(())
+}
+
+fn memory_flags(bits: usize) -> crate::MemoryFlags {
+    crate::definitions::from_bits(bits).unwrap()
+}
@@ -1,6 +1,7 @@
-use crate::{MemoryRange, PID, TID};
-use core::convert::TryInto;
+use crate::{MemoryAddress, MemoryRange, PID, TID};
 
+mod loader;
+pub use loader::*;
 mod mem;
 pub use mem::*;
 mod syscall;
@@ -9,6 +10,23 @@ pub use syscall::*;
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ProcessArgs {
     name: [u8; 16],
+    init: ProcessInit,
+}
+
+impl ProcessArgs {
+    /// Prepare to start the program in `image`, which is either an ELF file or a
+    /// MiniELF payload. The program is laid out in memory right away, see
+    /// `load_program()` for details.
+    pub fn new(name: &str, image: &[u8]) -> core::result::Result<ProcessArgs, crate::Error> {
+        let mut name_bytes = [0u8; 16];
+        for (dest, src) in name_bytes.iter_mut().zip(name.as_bytes()) {
+            *dest = *src;
+        }
+        Ok(ProcessArgs {
+            name: name_bytes,
+            init: load_program(image)?,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -67,16 +85,34 @@ impl ProcessKey {
     }
 }
 
+/// Describes a new process to the kernel. The program must already be laid out in
+/// memory in the calling process, which gives it up when the process is created.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInit {
-    pub key: ProcessKey,
+    /// Stack of the initial thread, in the new process
+    pub stack: MemoryRange,
+
+    /// The program, in the calling process. Every page must be backed by memory.
+    pub text: MemoryRange,
+
+    /// Where the program will be in the new process
+    pub text_destination: MemoryAddress,
+
+    /// Address of the first instruction to run, in the new process
+    pub start: MemoryAddress,
 }
 
 pub struct WaitHandle<T> {
     tid: TID,
     data: core::marker::PhantomData<T>,
 }
-pub struct ProcessHandle(());
+pub struct ProcessHandle(PID);
+impl ProcessHandle {
+    /// The ID of the process this handle refers to
+    pub fn pid(&self) -> PID {
+        self.0
+    }
+}
 
 pub fn thread_to_args(syscall: usize, init: &ThreadInit) -> [usize; 8] {
     [
@@ -143,26 +179,34 @@ pub fn wait_thread<T>(joiner: WaitHandle<T>) -> crate::SysCallResult {
 pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
     [
         call,
-        u32::from_le_bytes(init.key.0[0..4].try_into().unwrap()) as _,
-        u32::from_le_bytes(init.key.0[4..8].try_into().unwrap()) as _,
-        0,
-        0,
-        0,
-        0,
+        init.stack.as_ptr() as _,
+        init.stack.len(),
+        init.text.as_ptr() as _,
+        init.text.len(),
+        init.text_destination.get(),
+        init.start.get(),
         0,
     ]
 }
 
+/// This code is executed inside the kernel. It takes the list of args
+/// that were passed via registers and converts them into a `ProcessInit`
+/// struct with enough information to start the new process.
 pub fn args_to_process(
-    _a1: usize,
-    _a2: usize,
-    _a3: usize,
-    _a4: usize,
-    _a5: usize,
-    _a6: usize,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
     _a7: usize,
 ) -> core::result::Result<ProcessInit, crate::Error> {
-    todo!()
+    Ok(ProcessInit {
+        stack: unsafe { MemoryRange::new(a1, a2).map_err(|_| crate::Error::InvalidSyscall) }?,
+        text: unsafe { MemoryRange::new(a3, a4).map_err(|_| crate::Error::InvalidSyscall) }?,
+        text_destination: MemoryAddress::new(a5).ok_or(crate::Error::InvalidSyscall)?,
+        start: MemoryAddress::new(a6).ok_or(crate::Error::InvalidSyscall)?,
+    })
 }
 
 pub fn create_thread_0_pre<U>(f: &fn() -> U) -> core::result::Result<ThreadInit, crate::Error>
@@ -329,18 +373,18 @@ where
     })
 }
 
-/// If no connection exists, create a new connection to the server. This means
-/// our parent PID will be PID1. Otherwise, reuse the same connection.
-pub fn create_process_pre(_args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
-    todo!()
+/// The program was already laid out when the `ProcessArgs` were created, so all
+/// that's left is to hand it to the kernel.
+pub fn create_process_pre(args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
+    Ok(args.init)
 }
 
 pub fn create_process_post(
     _args: ProcessArgs,
     _init: ProcessInit,
-    _pid: PID,
+    pid: PID,
 ) -> core::result::Result<ProcessHandle, crate::Error> {
-    todo!()
+    Ok(ProcessHandle(pid))
 }
 
 pub fn create_thread_n_pre(
@@ -16,6 +16,7 @@ pub mod definitions;
 mod messages;
 
 pub mod process;
+mod program;
 pub mod string;
 pub mod stringbuffer;
 pub mod syscall;
@@ -46,6 +47,21 @@ pub fn init() {
     }));
 }
 
+static mut PROGRAM_NAME: &str = "";
+
+/// Records the name of the running program. `#[xous_main]` calls this with the name of the binary
+/// before the entry point's body runs, while it is still the only thread.
+#[doc(hidden)]
+pub fn set_program_name(name: &'static str) {
+    unsafe { PROGRAM_NAME = name };
+}
+
+/// The name of the binary this program was built as (e.g. `shellchat`), or an empty string if its
+/// entry point wasn't declared with `#[xous_main]`.
+pub fn program_name() -> &'static str {
+    unsafe { PROGRAM_NAME }
+}
+
 /// Convert a four-letter string into a 32-bit int.
 #[macro_export]
 macro_rules! make_name {
@@ -132,10 +148,10 @@ macro_rules! maybe_main {
                 }
 
                 let mut pw = PanicWriter { conn };
-                // Send the "We're panicking" message (1000).
+                // Send the "We're panicking" message (1000), along with the thread that panicked.
                 let panic_start_msg = ScalarMessage {
                     id: 1000,
-                    arg1: 0,
+                    arg1: xous::current_tid().unwrap_or(0),
                     arg2: 0,
                     arg3: 0,
                     arg4: 0,
@@ -0,0 +1,274 @@
+//! Parsing of the program images that can be handed to `create_process()`.
+//! See `load_program()` for how they are laid out in memory.
+#![cfg_attr(not(any(target_os = "none", target_os = "xous")), allow(dead_code))]
+
+use crate::Error;
+use core::convert::TryInto;
+
+/// The chunk of the program is to be mapped writable
+pub(crate) const SEGMENT_WRITE: usize = 1 << 0;
+/// The chunk of the program is to be mapped executable
+pub(crate) const SEGMENT_EXECUTE: usize = 1 << 1;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: usize = 0xf3;
+const PT_LOAD: usize = 1;
+const PF_X: usize = 1 << 0;
+const PF_W: usize = 1 << 1;
+
+/// Name of the kernel argument tag that describes a MiniELF program.
+const INIE_TAG: &[u8] = b"IniE";
+/// Size and flags of a MiniELF section share a word, with the flags in the top byte.
+const MINIELF_SIZE_MASK: usize = 0x00ff_ffff;
+const MINIELF_FLAG_WRITE: usize = 1 << 24;
+const MINIELF_FLAG_NOCOPY: usize = 1 << 25;
+const MINIELF_FLAG_EXECUTE: usize = 1 << 26;
+
+/// Call `f` with the address, size in memory, initial contents and `SEGMENT_*`
+/// flags of every chunk of the program in `image`, and return the program's
+/// entrypoint.
+///
+/// `image` is either an ELF file, from which the `PT_LOAD` segments are used,
+/// or a MiniELF payload: an `IniE` tag as it appears in the kernel arguments,
+/// with its load offset counted from the start of `image` just as the loader
+/// counts it from the start of the arguments.
+pub(crate) fn for_each_segment<F>(image: &[u8], f: F) -> core::result::Result<usize, Error>
+where
+    F: FnMut(usize, usize, &[u8], usize) -> core::result::Result<(), Error>,
+{
+    if image.starts_with(ELF_MAGIC) {
+        for_each_elf_segment(image, f)
+    } else if image.starts_with(INIE_TAG) {
+        for_each_minielf_section(image, f)
+    } else {
+        Err(Error::BadAddress)
+    }
+}
+
+fn for_each_elf_segment<F>(image: &[u8], mut f: F) -> core::result::Result<usize, Error>
+where
+    F: FnMut(usize, usize, &[u8], usize) -> core::result::Result<(), Error>,
+{
+    if image.get(4) != Some(&ELFCLASS32)
+        || image.get(5) != Some(&ELFDATA2LSB)
+        || read_u16(image, 0x12)? != EM_RISCV
+    {
+        return Err(Error::BadAddress);
+    }
+    let entry = read_u32(image, 0x18)?;
+    let phoff = read_u32(image, 0x1c)?;
+    let phentsize = read_u16(image, 0x2a)?;
+    let phnum = read_u16(image, 0x2c)?;
+
+    for index in 0..phnum {
+        let header = index
+            .checked_mul(phentsize)
+            .and_then(|offset| offset.checked_add(phoff))
+            .ok_or(Error::BadAddress)?;
+        if read_u32(image, header)? != PT_LOAD {
+            continue;
+        }
+        let offset = read_u32(image, header + 4)?;
+        let virt = read_u32(image, header + 8)?;
+        let file_size = read_u32(image, header + 16)?;
+        let mem_size = read_u32(image, header + 20)?;
+        let p_flags = read_u32(image, header + 24)?;
+        if file_size > mem_size {
+            return Err(Error::BadAddress);
+        }
+        if mem_size == 0 {
+            continue;
+        }
+        let mut flags = 0;
+        if p_flags & PF_W != 0 {
+            flags |= SEGMENT_WRITE;
+        }
+        if p_flags & PF_X != 0 {
+            flags |= SEGMENT_EXECUTE;
+        }
+        f(virt, mem_size, slice(image, offset, file_size)?, flags)?;
+    }
+    Ok(entry)
+}
+
+fn for_each_minielf_section<F>(image: &[u8], mut f: F) -> core::result::Result<usize, Error>
+where
+    F: FnMut(usize, usize, &[u8], usize) -> core::result::Result<(), Error>,
+{
+    // The tag header is the name, then the size of the tag data in words and its CRC16
+    let tag_len = read_u16(image, 4)? * 4;
+    if tag_len < 8 || (tag_len - 8) % 8 != 0 {
+        return Err(Error::BadAddress);
+    }
+    let tag = slice(image, 8, tag_len)?;
+    let load_offset = read_u32(tag, 0)?;
+    let entry = read_u32(tag, 4)?;
+    let mut data = image.get(load_offset..).ok_or(Error::BadAddress)?;
+
+    for section in (8..tag_len).step_by(8) {
+        let virt = read_u32(tag, section)?;
+        let size_and_flags = read_u32(tag, section + 4)?;
+        let size = size_and_flags & MINIELF_SIZE_MASK;
+        // NOCOPY sections are zero-filled, and have no data in the image
+        let contents = if size_and_flags & MINIELF_FLAG_NOCOPY != 0 {
+            &data[..0]
+        } else {
+            let contents = data.get(..size).ok_or(Error::BadAddress)?;
+            data = &data[size..];
+            contents
+        };
+        let mut flags = 0;
+        if size_and_flags & MINIELF_FLAG_WRITE != 0 {
+            flags |= SEGMENT_WRITE;
+        }
+        if size_and_flags & MINIELF_FLAG_EXECUTE != 0 {
+            flags |= SEGMENT_EXECUTE;
+        }
+        f(virt, size, contents, flags)?;
+    }
+    Ok(entry)
+}
+
+fn slice(image: &[u8], offset: usize, len: usize) -> core::result::Result<&[u8], Error> {
+    offset
+        .checked_add(len)
+        .and_then(|end| image.get(offset..end))
+        .ok_or(Error::BadAddress)
+}
+
+fn read_u16(image: &[u8], offset: usize) -> core::result::Result<usize, Error> {
+    Ok(u16::from_le_bytes(slice(image, offset, 2)?.try_into().unwrap()) as usize)
+}
+
+fn read_u32(image: &[u8], offset: usize) -> core::result::Result<usize, Error> {
+    Ok(u32::from_le_bytes(slice(image, offset, 4)?.try_into().unwrap()) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address, size in memory, initial contents and flags of a chunk of the program
+    type Segment = (usize, usize, Vec<u8>, usize);
+
+    fn segments(image: &[u8]) -> Result<(usize, Vec<Segment>), Error> {
+        let mut found = Vec::new();
+        let entry = for_each_segment(image, |virt, size, data, flags| {
+            found.push((virt, size, data.to_vec(), flags));
+            Ok(())
+        })?;
+        Ok((entry, found))
+    }
+
+    /// An `IniE` tag for `sections`, followed by `padding` bytes and then the section data
+    fn minielf(entry: u32, sections: &[(u32, u32, &[u8])], padding: usize) -> Vec<u8> {
+        let tag_len = 8 + 8 * sections.len();
+        let load_offset = 8 + tag_len + padding;
+        let mut image = Vec::new();
+        image.extend_from_slice(INIE_TAG);
+        image.extend_from_slice(&((tag_len / 4) as u16).to_le_bytes());
+        image.extend_from_slice(&0u16.to_le_bytes());
+        image.extend_from_slice(&(load_offset as u32).to_le_bytes());
+        image.extend_from_slice(&entry.to_le_bytes());
+        for (virt, size_and_flags, _) in sections {
+            image.extend_from_slice(&virt.to_le_bytes());
+            image.extend_from_slice(&size_and_flags.to_le_bytes());
+        }
+        image.resize(load_offset, 0xaa);
+        for (_, _, data) in sections {
+            image.extend_from_slice(data);
+        }
+        image
+    }
+
+    #[test]
+    fn minielf_load_offset_is_absolute() {
+        let text = [1u8, 2, 3, 4];
+        let data = [5u8, 6];
+        let flags_text = (MINIELF_FLAG_EXECUTE) as u32;
+        let flags_data = (MINIELF_FLAG_WRITE) as u32;
+        let flags_bss = (MINIELF_FLAG_WRITE | MINIELF_FLAG_NOCOPY) as u32;
+        // the section data doesn't have to follow the tag directly
+        let image = minielf(
+            0x2000_0000,
+            &[
+                (0x2000_0000, 4 | flags_text, &text),
+                (0x2000_1000, 2 | flags_data, &data),
+                (0x2000_2000, 0x100 | flags_bss, &[]),
+            ],
+            64,
+        );
+        let (entry, found) = segments(&image).unwrap();
+        assert_eq!(entry, 0x2000_0000);
+        assert_eq!(
+            found,
+            vec![
+                (0x2000_0000, 4, text.to_vec(), SEGMENT_EXECUTE),
+                (0x2000_1000, 2, data.to_vec(), SEGMENT_WRITE),
+                (0x2000_2000, 0x100, vec![], SEGMENT_WRITE),
+            ]
+        );
+    }
+
+    #[test]
+    fn minielf_rejects_truncated_images() {
+        let image = minielf(0x2000_0000, &[(0x2000_0000, 8, &[0u8; 8])], 0);
+        assert_eq!(
+            segments(&image[..image.len() - 1]).unwrap_err(),
+            Error::BadAddress
+        );
+        let mut bad_offset = image.clone();
+        bad_offset[8..12].copy_from_slice(&(image.len() as u32 + 1).to_le_bytes());
+        assert_eq!(segments(&bad_offset).unwrap_err(), Error::BadAddress);
+        // a tag that claims more sections than the image holds
+        let mut bad_len = image.clone();
+        bad_len[4..6].copy_from_slice(&64u16.to_le_bytes());
+        assert_eq!(segments(&bad_len).unwrap_err(), Error::BadAddress);
+        assert_eq!(segments(b"junk").unwrap_err(), Error::BadAddress);
+    }
+
+    #[test]
+    fn elf_segments_and_flags() {
+        // ELF header, then two program headers: a read-execute text segment, and a
+        // read-write data segment whose tail is bss
+        let mut image = vec![0u8; 0x34 + 2 * 0x20];
+        image[..4].copy_from_slice(ELF_MAGIC);
+        image[4] = ELFCLASS32;
+        image[5] = ELFDATA2LSB;
+        image[0x12..0x14].copy_from_slice(&(EM_RISCV as u16).to_le_bytes());
+        image[0x18..0x1c].copy_from_slice(&0x2000_0010u32.to_le_bytes());
+        image[0x1c..0x20].copy_from_slice(&0x34u32.to_le_bytes());
+        image[0x2a..0x2c].copy_from_slice(&0x20u16.to_le_bytes());
+        image[0x2c..0x2e].copy_from_slice(&2u16.to_le_bytes());
+        let text_offset = image.len();
+        image.extend_from_slice(&[0x13, 0, 0, 0]);
+        let data_offset = image.len();
+        image.extend_from_slice(&[7, 8]);
+        for (index, (offset, virt, file_size, mem_size, p_flags)) in [
+            (text_offset, 0x2000_0000u32, 4u32, 4u32, (PF_X | 4) as u32),
+            (data_offset, 0x2000_1000, 2, 0x40, (PF_W | 4) as u32),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let header = 0x34 + index * 0x20;
+            image[header..header + 4].copy_from_slice(&(PT_LOAD as u32).to_le_bytes());
+            image[header + 4..header + 8].copy_from_slice(&(*offset as u32).to_le_bytes());
+            image[header + 8..header + 12].copy_from_slice(&virt.to_le_bytes());
+            image[header + 16..header + 20].copy_from_slice(&file_size.to_le_bytes());
+            image[header + 20..header + 24].copy_from_slice(&mem_size.to_le_bytes());
+            image[header + 24..header + 28].copy_from_slice(&p_flags.to_le_bytes());
+        }
+        let (entry, found) = segments(&image).unwrap();
+        assert_eq!(entry, 0x2000_0010);
+        assert_eq!(
+            found,
+            vec![
+                (0x2000_0000, 4, vec![0x13, 0, 0, 0], SEGMENT_EXECUTE),
+                (0x2000_1000, 0x40, vec![7, 8], SEGMENT_WRITE),
+            ]
+        );
+    }
+}
@@ -425,6 +425,38 @@ pub enum SysCall {
         usize, /* stack pointer */
     ),
 
+    /// Puts the current thread to sleep if the word at the given address still
+    /// holds the expected value. Checking the value and going to sleep happen
+    /// atomically with respect to `WakeAddress`, so a wakeup can't be missed in
+    /// between. The thread sleeps until another thread in this process calls
+    /// `WakeAddress` on the same address, or until the timeout (in milliseconds)
+    /// passes. A timeout of 0 waits forever. Timeouts are noticed on the next
+    /// preemption tick, so they may run late by up to one tick.
+    ///
+    /// # Returns
+    ///
+    /// * **Ok**: The thread was woken up, or the value did not match
+    ///
+    /// # Errors
+    ///
+    /// * **BadAlignment**: The address is not aligned to a word
+    /// * **BadAddress**: The address is not readable
+    /// * **OutOfMemory**: Too many threads are already waiting
+    /// * **Timeout**: The timeout passed before the thread was woken up
+    WaitOnAddress(
+        MemoryAddress,
+        usize, /* expected value */
+        usize, /* timeout in ms */
+    ),
+
+    /// Wakes up at most `count` threads in this process that are waiting on
+    /// the given address, longest-waiting first.
+    ///
+    /// # Returns
+    ///
+    /// * **Scalar1**: The number of threads that were woken up
+    WakeAddress(MemoryAddress, usize /* count */),
+
     /// This syscall does not exist. It captures all possible
     /// arguments so detailed analysis can be performed.
     Invalid(usize, usize, usize, usize, usize, usize, usize),
@@ -468,6 +500,8 @@ pub enum SysCallNumber {
     Disconnect = 35,
     JoinThread = 36,
     SetExceptionHandler = 37,
+    WaitOnAddress = 38,
+    WakeAddress = 39,
     Invalid,
 }
 
@@ -511,6 +545,8 @@ impl SysCallNumber {
             35 => Disconnect,
             36 => JoinThread,
             37 => SetExceptionHandler,
+            38 => WaitOnAddress,
+            39 => WakeAddress,
             _ => Invalid,
         }
     }
@@ -852,6 +888,26 @@ impl SysCall {
                 0,
                 0,
             ],
+            SysCall::WaitOnAddress(addr, expected, timeout) => [
+                SysCallNumber::WaitOnAddress as usize,
+                addr.get(),
+                *expected,
+                *timeout,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::WakeAddress(addr, count) => [
+                SysCallNumber::WakeAddress as usize,
+                addr.get(),
+                *count,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
             SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7) => [
                 SysCallNumber::Invalid as usize,
                 *a1,
@@ -1015,6 +1071,14 @@ impl SysCall {
             SysCallNumber::Disconnect => SysCall::Disconnect(a1 as _),
             SysCallNumber::JoinThread => SysCall::JoinThread(a1 as _),
             SysCallNumber::SetExceptionHandler => SysCall::SetExceptionHandler(a1 as _, a2 as _),
+            SysCallNumber::WaitOnAddress => SysCall::WaitOnAddress(
+                MemoryAddress::new(a1).ok_or(Error::InvalidSyscall)?,
+                a2,
+                a3,
+            ),
+            SysCallNumber::WakeAddress => {
+                SysCall::WakeAddress(MemoryAddress::new(a1).ok_or(Error::InvalidSyscall)?, a2)
+            }
             SysCallNumber::Invalid => SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
         })
     }
@@ -1747,6 +1811,52 @@ pub fn join_thread(tid: TID) -> core::result::Result<usize, Error> {
         }
     })
 }
+
+/// Block the current thread for as long as `addr` holds `expected`, until
+/// another thread calls `wake_address()` on it or `timeout_ms` milliseconds
+/// pass. A `timeout_ms` of 0 waits forever. Returns right away if `addr`
+/// already holds a different value. Being woken up doesn't guarantee that
+/// the value has changed, so callers should check it again.
+///
+/// # Errors
+///
+/// * **Timeout**: The timeout passed before the thread was woken up
+/// * **OutOfMemory**: Too many threads are already waiting
+pub fn wait_on_address(
+    addr: &core::sync::atomic::AtomicUsize,
+    expected: usize,
+    timeout_ms: usize,
+) -> core::result::Result<(), Error> {
+    let addr = MemoryAddress::new(addr as *const _ as usize).ok_or(Error::BadAddress)?;
+    rsyscall(SysCall::WaitOnAddress(addr, expected, timeout_ms)).and_then(|result| {
+        if let Result::Ok = result {
+            Ok(())
+        } else if let Result::Error(e) = result {
+            Err(e)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Wake up at most `count` threads that are blocked in `wait_on_address()`
+/// on `addr`. Returns the number of threads that were woken up.
+pub fn wake_address(
+    addr: &core::sync::atomic::AtomicUsize,
+    count: usize,
+) -> core::result::Result<usize, Error> {
+    let addr = MemoryAddress::new(addr as *const _ as usize).ok_or(Error::BadAddress)?;
+    rsyscall(SysCall::WakeAddress(addr, count)).and_then(|result| {
+        if let Result::Scalar1(woken) = result {
+            Ok(woken)
+        } else if let Result::Error(e) = result {
+            Err(e)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
 /* https://github.com/betrusted-io/xous-core/issues/90
 static EXCEPTION_HANDLER: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
 fn handle_exception(exception_type: usize, arg1: usize, arg2: usize) -> isize {
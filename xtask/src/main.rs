@@ -45,6 +45,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "shellchat",
         "ime-frontend",
         "ime-plugin-shell",
+        "ime-plugin-dict",
         "graphics-server",
         "ticktimer-server",
         "log-server",
@@ -64,6 +65,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "dns",
         "pddb",
         "modals",
+        "alarms",
     ];
     let app_pkgs = [
         // "standard" demo apps